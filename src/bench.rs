@@ -1,6 +1,5 @@
 // RUN: cargo bench --features "unstable"
 
-#![feature(test)]
 #![cfg_attr(feature = "unstable", feature(test))]
 #[cfg(all(feature = "unstable", test))]
 mod bench {
@@ -23,25 +22,93 @@ mod bench {
         })
     }
 
+    #[bench]
+    fn fact10_bytecode_bench(b: &mut Bencher) {
+        set_engine(Engine::Bytecode);
+        let env_ref = default_env();
+        run_with_env("(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))", env_ref.clone());
+
+        b.iter(|| {
+            run_with_env("(fact 20)", env_ref.clone());
+        });
+        set_engine(Engine::TreeWalker);
+    }
+
+    #[bench]
+    fn map_lambda_bench(b: &mut Bencher) {
+        let env_ref = default_env();
+        run_with_env("(define numbers (list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20))", env_ref.clone());
+
+        b.iter(|| {
+            run_with_env("(map (lambda (x) (if (< x 10) (* x x) (+ x 1))) numbers)", env_ref.clone());
+        })
+    }
+
+    // 500 definitions of 11 lists and vectors each.
+    fn large_source() -> String {
+        let definition = "(define (f x y) (if (< x y) (list x y (quote (a b c)) #(1 2 3)) (f (- x 1) (+ y 1))))\n";
+        definition.repeat(500)
+    }
+
+    #[bench]
+    fn parse_large_source_bench(b: &mut Bencher) {
+        let source = large_source();
+
+        b.iter(|| {
+            parse_program(&source)
+        })
+    }
+
+    // Sums a list of `length` elements by walking down it with `cdr`, which takes
+    // time in proportion to the length when the rest of a list is not copied.
+    fn walk_list(b: &mut Bencher, length: usize) {
+        let env_ref = default_env();
+        run_with_env("(define (sum l total) (if (null? l) total (sum (cdr l) (+ total (car l)))))", env_ref.clone());
+        run_with_env(&format!("(define numbers (vector->list (make-vector {} 1)))", length), env_ref.clone());
+
+        b.iter(|| {
+            run_with_env("(sum numbers 0)", env_ref.clone());
+        })
+    }
+
+    #[bench]
+    fn walk_list_1000_bench(b: &mut Bencher) {
+        walk_list(b, 1000);
+    }
+
+    #[bench]
+    fn walk_list_4000_bench(b: &mut Bencher) {
+        walk_list(b, 4000);
+    }
+
+    #[bench]
+    fn list_tail_bench(b: &mut Bencher) {
+        let env_ref = default_env();
+        run_with_env("(define numbers (vector->list (make-vector 1000 1)))", env_ref.clone());
+        run_with_env("(define (tails n) (if (= n 0) 0 (begin (list-tail numbers n) (member 2 numbers) (tails (- n 1)))))", env_ref.clone());
+
+        b.iter(|| {
+            run_with_env("(tails 1000)", env_ref.clone());
+        })
+    }
+
     #[derive(Debug)]
     struct TestResult {
-        value: Result<Option<DataType>, &'static str>,
+        value: Result<Value, SchemeError>,
         env: Rc<RefCell<Env>>
     }
 
     fn default_env() -> Rc<RefCell<Env>> {
-        let local = RefCell::new(setup());
-        let env = Env {
-            local,
-            parent: None
-        };
+        let mut builtins = setup();
+        define_prelude(&mut builtins).unwrap();
+        let env = Env::new(builtins, None);
 
         let env_ref = Rc::new(RefCell::new(env));
         env_ref
     }
 
     fn run_with_env(s: &str, env_ref: Rc<RefCell<Env>>) -> TestResult {
-        env_logger::init();
+        let _ = env_logger::init();
         let result = parse(s)
             .and_then(|ast| eval(Some(ast.result), env_ref.clone()));
 
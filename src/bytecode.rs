@@ -0,0 +1,493 @@
+use std::fmt;
+use std::rc::Rc;
+
+use super::{list_items, source_map, SchemeError, Span, SymbolId, Value};
+
+/// Where a variable is found at run time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Address {
+    // a slot of the frame `depth` frames out from the current one
+    Local { depth: usize, slot: usize, name: SymbolId },
+    // looked up by name, as global variables and those of code that was not compiled
+    Free(SymbolId)
+}
+
+impl Address {
+    pub(crate) fn name(&self) -> SymbolId {
+        match *self {
+            Address::Local { name, .. } | Address::Free(name) => name
+        }
+    }
+}
+
+/// One instruction of the virtual machine. Instructions work on a stack of values:
+/// they pop their operands and push their result.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Op {
+    Constant(Value),
+    Quasiquote(Rc<Value>),
+    Variable(Address),
+    // the procedure of a call, which must be bound before the arguments are evaluated,
+    // and is on the call stack while they are
+    Callee(Address),
+    // puts the procedure of `((...) arguments)` on the call stack before it is evaluated
+    EnterCall(SymbolId),
+    // checks that the procedure of `((...) arguments)` can be applied
+    Operator,
+    Define(SymbolId),
+    // `define` of a variable that has a slot in the current frame
+    DefineLocal(usize, SymbolId),
+    // binds a variable of the current frame, as `letrec` does
+    Bind(usize, SymbolId),
+    DefineRecordType(Rc<Value>),
+    Lambda(Rc<Template>),
+    // a frame for the variables of `letrec`
+    EnterScope(Rc<[SymbolId]>),
+    LeaveScope,
+    Pop,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Call { arguments: usize, name: SymbolId },
+    // a call whose value is the value of the procedure making it, which replaces the
+    // frame of that procedure
+    TailCall { arguments: usize, name: SymbolId },
+    Return,
+    // forms with the wrong shape fail when they are reached, not when compiled
+    Fail(SchemeError)
+}
+
+/// What a `lambda` form creates procedures from.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Template {
+    pub(crate) params: Rc<[Value]>,
+    // kept for the tree-walking evaluator, which may call the procedure as well
+    pub(crate) body: Rc<Value>,
+    pub(crate) code: Rc<Bytecode>,
+    // how many frames out from the one the lambda is evaluated in its procedures
+    // refer to: the frames in between have no variable the body uses, and are not
+    // kept alive by the procedures
+    pub(crate) outer: usize
+}
+
+/// A form compiled for the virtual machine, with the span of the innermost form read
+/// by `parse` that each instruction belongs to.
+#[derive(Debug, PartialEq)]
+pub struct Bytecode {
+    pub(crate) ops: Vec<Op>,
+    pub(crate) spans: Vec<Option<Span>>,
+    // the variables with a slot in the frame the code runs in: the parameters of a
+    // lambda, then the variables its body defines
+    pub(crate) locals: Rc<[SymbolId]>
+}
+
+// The variables of a frame known when compiling. A scope is open when its code binds
+// variables whose names cannot be told beforehand, so that the variables of the
+// scopes around it have to be looked up by name.
+#[derive(Clone)]
+struct Scope {
+    names: Vec<SymbolId>,
+    open: bool
+}
+
+impl Scope {
+    // The variables of a frame for `names` in which `forms` are evaluated.
+    fn new<'a, I>(mut names: Vec<SymbolId>, forms: I) -> Scope where I: IntoIterator<Item=&'a Value> {
+        let mut open = false;
+        for form in forms {
+            definitions(form, &mut names, &mut open);
+        }
+        Scope { names, open }
+    }
+}
+
+// Adds the names `form` defines in the frame it is evaluated in to `names`, leaving
+// out the forms that get a frame of their own.
+pub(crate) fn definitions(form: &Value, names: &mut Vec<SymbolId>, open: &mut bool) {
+    let list = match form {
+        Value::List(list) => list,
+        _ => return
+    };
+    match list.first() {
+        Some(Value::Symbol(name)) => match &*name.name() {
+            "quote" | "quasiquote" | "lambda" | "letrec" | "letrec*" => return,
+            "define-record-type" => {
+                *open = true;
+                return;
+            }
+            "define" => if let Some(&Value::Symbol(id)) = list.get(1) {
+                if !names.contains(&id) {
+                    names.push(id);
+                }
+            },
+            _ => {}
+        },
+        Some(operator) => definitions(operator, names, open),
+        None => {}
+    }
+    for form in &list[1..] {
+        definitions(form, names, open);
+    }
+}
+
+// Adds the variables `form` refers to that are not in `bound` to `free`. Gives None
+// when `form` looks variables up by names that are not known before it runs, as
+// quasiquote templates and record type definitions do.
+fn free_variables(form: &Value, bound: &mut Vec<SymbolId>, free: &mut Vec<SymbolId>) -> Option<()> {
+    let list = match *form {
+        Value::Symbol(id) => {
+            if !bound.contains(&id) && !free.contains(&id) {
+                free.push(id);
+            }
+            return Some(());
+        }
+        Value::List(ref list) => list,
+        _ => return Some(())
+    };
+    let forms = match list[0] {
+        Value::Symbol(id) => match &*id.name() {
+            "quote" => return Some(()),
+            "quasiquote" | "define-record-type" => return None,
+            "lambda" => return match (list.get(1).and_then(list_items), list.get(2)) {
+                (Some(params), Some(body)) => {
+                    let names = params.iter().filter_map(|param| match *param {
+                        Value::Symbol(id) => Some(id),
+                        _ => None
+                    }).collect();
+                    bound_in(names, Some(body), bound, free)
+                }
+                _ => Some(())
+            },
+            "letrec" | "letrec*" => return match list.get(1).and_then(list_items) {
+                Some(bindings) => {
+                    let names = bindings.iter().filter_map(|binding| match binding {
+                        Value::List(parts) if parts.len() == 2 => match parts[0] {
+                            Value::Symbol(id) => Some(id),
+                            _ => None
+                        },
+                        _ => None
+                    }).collect();
+                    let inits = bindings.iter().filter_map(|binding| match binding {
+                        Value::List(parts) => parts.get(1),
+                        _ => None
+                    });
+                    bound_in(names, inits.chain(&list[2..]), bound, free)
+                }
+                None => Some(())
+            },
+            "define" => &list[2..],
+            "if" => &list[1..],
+            _ => &list[..]
+        },
+        _ => &list[..]
+    };
+    forms.iter().try_for_each(|form| free_variables(form, bound, free))
+}
+
+// `free_variables` of `forms`, evaluated in a frame of their own binding `names`.
+fn bound_in<'a, I>(mut names: Vec<SymbolId>, forms: I, bound: &mut Vec<SymbolId>, free: &mut Vec<SymbolId>) -> Option<()>
+    where I: IntoIterator<Item=&'a Value> + Clone {
+    let mut open = false;
+    for form in forms.clone() {
+        definitions(form, &mut names, &mut open);
+    }
+    if open {
+        return None;
+    }
+    let mark = bound.len();
+    bound.extend(names);
+    let result = forms.into_iter().try_for_each(|form| free_variables(form, bound, free));
+    bound.truncate(mark);
+    result
+}
+
+struct Compiler {
+    ops: Vec<Op>,
+    spans: Vec<Option<Span>>,
+    span: Option<Span>,
+    // innermost last
+    scopes: Vec<Scope>
+}
+
+impl Compiler {
+    fn new(span: Option<Span>, scopes: Vec<Scope>) -> Compiler {
+        Compiler { ops: vec![], spans: vec![], span, scopes }
+    }
+
+    fn resolve(&self, name: SymbolId) -> Address {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.names.iter().rposition(|&n| n == name) {
+                return Address::Local { depth, slot, name };
+            }
+            if scope.open {
+                break;
+            }
+        }
+        Address::Free(name)
+    }
+
+    // The number of innermost frames with no variable the body of a lambda with
+    // `params` uses, which its procedures need not refer to.
+    fn unused_frames(&self, params: &[SymbolId], body: &Value) -> usize {
+        let mut free = vec![];
+        if bound_in(params.to_vec(), Some(body), &mut vec![], &mut free).is_none() {
+            return 0;
+        }
+        // the frames out to the innermost one a free variable may be found in, as
+        // `resolve` finds them
+        let used = free.iter().filter_map(|name| self.scopes.iter().rposition(|scope| scope.names.contains(name) || scope.open)).max();
+        self.scopes.len() - used.map_or(0, |i| i + 1)
+    }
+
+    // Where `define` binds `name`: the slot the current frame has for it, if any.
+    fn definition(&self, name: SymbolId) -> Option<usize> {
+        self.scopes.last().and_then(|scope| scope.names.iter().rposition(|&n| n == name))
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.spans.push(self.span);
+        self.ops.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.ops.len();
+        match self.ops[at] {
+            Op::Jump(ref mut to) | Op::JumpIfFalse(ref mut to) => *to = target,
+            _ => unreachable!()
+        }
+    }
+
+    fn finish(self) -> Rc<Bytecode> {
+        let locals = self.scopes.last().map_or(Rc::from(&[][..]), |scope| Rc::from(scope.names.as_slice()));
+        Rc::new(Bytecode { ops: self.ops, spans: self.spans, locals })
+    }
+
+    // Emits the instructions pushing the value of `form`. In tail position, calls
+    // replace the frame they are made from.
+    fn form(&mut self, form: &Value, tail: bool) {
+        let outer = self.span;
+        if let Some(span) = source_map::span_of(form) {
+            self.span = Some(span);
+        }
+        match form {
+            Value::List(list) => self.list(list, form, tail),
+            atom => self.atom(atom)
+        }
+        self.span = outer;
+    }
+
+    fn atom(&mut self, value: &Value) {
+        let op = match value {
+            Value::Symbol(id) => Op::Variable(self.resolve(*id)),
+            Value::Nil => Op::Fail(SchemeError::syntax("syntax error")),
+            Value::Pair(_) => Op::Fail(SchemeError::syntax("datum labels and dotted lists can only be used in quoted data")),
+            Value::List(_) => unreachable!(),
+            _ => Op::Constant(value.clone())
+        };
+        self.emit(op);
+    }
+
+    fn list(&mut self, list: &[Value], form: &Value, tail: bool) {
+        let id = match list.first() {
+            Some(Value::Symbol(id)) => *id,
+            Some(operator @ Value::List(_)) => {
+                self.emit(Op::EnterCall(SymbolId::intern("lambda")));
+                self.form(operator, false);
+                self.emit(Op::Operator);
+                return self.call(SymbolId::intern("lambda"), &list[1..], tail);
+            }
+            _ => return self.fail(SchemeError::syntax("syntax error"))
+        };
+        match &*id.name() {
+            "quote" => match list.get(1) {
+                Some(datum) => {
+                    self.emit(Op::Constant(datum.clone()));
+                }
+                None => self.fail(SchemeError::syntax("wrong number of parts"))
+            },
+            "quasiquote" => match list {
+                [_, template] => {
+                    self.emit(Op::Quasiquote(Rc::new(template.clone())));
+                }
+                _ => self.fail(SchemeError::syntax("wrong number of parts"))
+            },
+            "if" if list.len() >= 4 => {
+                self.form(&list[1], false);
+                let otherwise = self.emit(Op::JumpIfFalse(0));
+                self.form(&list[2], tail);
+                let end = self.emit(Op::Jump(0));
+                self.patch(otherwise);
+                self.form(&list[3], tail);
+                self.patch(end);
+            }
+            "if" => self.fail(SchemeError::syntax("wrong syntax for if expression")),
+            "define" => match (list.get(1), list.get(2)) {
+                (Some(&Value::Symbol(id)), Some(value)) => {
+                    self.form(value, false);
+                    self.emit(match self.definition(id) {
+                        Some(slot) => Op::DefineLocal(slot, id),
+                        None => Op::Define(id)
+                    });
+                }
+                _ => self.fail(SchemeError::syntax("wrong syntax for define expression"))
+            },
+            "lambda" => self.lambda(list),
+            "define-record-type" => {
+                self.emit(Op::DefineRecordType(Rc::new(form.clone())));
+            }
+            "letrec" | "letrec*" => match (list.get(1).and_then(list_items), list.get(2)) {
+                (Some(bindings), Some(_)) => self.letrec(bindings, &list[2..], tail),
+                _ => self.fail(SchemeError::syntax("wrong syntax for letrec expression"))
+            },
+            _ => {
+                self.emit(Op::Callee(self.resolve(id)));
+                self.call(id, &list[1..], tail);
+            }
+        }
+    }
+
+    fn fail(&mut self, error: SchemeError) {
+        self.emit(Op::Fail(error));
+    }
+
+    fn call(&mut self, name: SymbolId, arguments: &[Value], tail: bool) {
+        for argument in arguments {
+            self.form(argument, false);
+        }
+        let arguments = arguments.len();
+        self.emit(if tail { Op::TailCall { arguments, name } } else { Op::Call { arguments, name } });
+    }
+
+    fn lambda(&mut self, list: &[Value]) {
+        let (args, body) = match (list.get(1).and_then(list_items), list.get(2)) {
+            (Some(args), Some(body @ Value::List(_))) => (args, body),
+            _ => return self.fail(SchemeError::syntax("syntax error"))
+        };
+        let params = args.iter().map(|arg| match *arg {
+            Value::Symbol(id) => Ok(id),
+            _ => Err(SchemeError::syntax("lambda argument must be a symbol"))
+        }).collect::<Result<Vec<SymbolId>, SchemeError>>();
+        match params {
+            Ok(params) => {
+                let outer = self.unused_frames(&params, body);
+                let mut scopes = self.scopes[..self.scopes.len() - outer].to_vec();
+                scopes.push(Scope::new(params.clone(), Some(body)));
+                let params = params.into_iter().map(Value::Symbol).collect();
+                let mut compiler = Compiler::new(self.span, scopes);
+                compiler.form(body, true);
+                compiler.emit(Op::Return);
+                self.emit(Op::Lambda(Rc::new(Template { params, body: Rc::new(body.clone()), code: compiler.finish(), outer })));
+            }
+            Err(error) => self.fail(error)
+        }
+    }
+
+    // Every init is evaluated in the new scope, so that the lambdas it creates can
+    // refer to each other regardless of their order.
+    fn letrec(&mut self, bindings: &[Value], body: &[Value], tail: bool) {
+        // the names of the bindings up to the first malformed one, which fails
+        let names = bindings.iter().map_while(|binding| match binding {
+            Value::List(parts) if parts.len() == 2 => match parts[0] {
+                Value::Symbol(id) => Some(id),
+                _ => None
+            },
+            _ => None
+        }).collect::<Vec<SymbolId>>();
+        let inits = bindings[..names.len()].iter().filter_map(|binding| match binding {
+            Value::List(parts) => parts.get(1),
+            _ => None
+        });
+        let scope = Scope::new(names, inits.chain(body));
+        self.emit(Op::EnterScope(Rc::from(scope.names.as_slice())));
+        self.scopes.push(scope);
+        self.letrec_body(bindings, body, tail);
+        self.scopes.pop();
+    }
+
+    fn letrec_body(&mut self, bindings: &[Value], body: &[Value], tail: bool) {
+        for (slot, binding) in bindings.iter().enumerate() {
+            match binding {
+                Value::List(parts) if parts.len() == 2 => match parts[0] {
+                    Value::Symbol(id) => {
+                        self.form(&parts[1], false);
+                        self.emit(Op::Bind(slot, id));
+                    }
+                    _ => return self.fail(SchemeError::syntax("letrec binding name must be a symbol"))
+                },
+                _ => return self.fail(SchemeError::syntax("wrong syntax for letrec binding"))
+            }
+        }
+        for (i, form) in body.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop);
+            }
+            self.form(form, tail && i == body.len() - 1);
+        }
+        self.emit(Op::LeaveScope);
+    }
+}
+
+/// Compiles `form` for the virtual machine. Compiling does not fail: forms with the
+/// wrong shape compile to instructions raising the error the tree-walking evaluator
+/// would raise when it got to them.
+pub fn compile(form: &Value) -> Rc<Bytecode> {
+    let mut compiler = Compiler::new(None, vec![]);
+    compiler.form(form, true);
+    compiler.emit(Op::Return);
+    compiler.finish()
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Local { depth, slot, name } => write!(f, "{} {}:{}", name, depth, slot),
+            Address::Free(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Constant(data) => write!(f, "constant {}", data),
+            Op::Quasiquote(template) => write!(f, "quasiquote {}", super::unparse(template)),
+            Op::Variable(address) => write!(f, "variable {}", address),
+            Op::Callee(address) => write!(f, "callee {}", address),
+            Op::EnterCall(id) => write!(f, "enter-call {}", id),
+            Op::Operator => write!(f, "operator"),
+            Op::Define(id) => write!(f, "define {}", id),
+            Op::DefineLocal(slot, id) => write!(f, "define-local {} {}", id, slot),
+            Op::Bind(slot, id) => write!(f, "bind {} {}", id, slot),
+            Op::DefineRecordType(_) => write!(f, "define-record-type"),
+            Op::Lambda(template) if template.outer > 0 => write!(f, "lambda {} outer {}", template.params.len(), template.outer),
+            Op::Lambda(template) => write!(f, "lambda {}", template.params.len()),
+            Op::EnterScope(names) => write!(f, "enter-scope {}", names.len()),
+            Op::LeaveScope => write!(f, "leave-scope"),
+            Op::Pop => write!(f, "pop"),
+            Op::Jump(to) => write!(f, "jump {}", to),
+            Op::JumpIfFalse(to) => write!(f, "jump-if-false {}", to),
+            Op::Call { arguments, name } => write!(f, "call {} {}", name, arguments),
+            Op::TailCall { arguments, name } => write!(f, "tail-call {} {}", name, arguments),
+            Op::Return => write!(f, "return"),
+            Op::Fail(error) => write!(f, "fail {:?}", error.message())
+        }
+    }
+}
+
+/// Lists the instructions one per line, numbered, followed by the bodies of the
+/// lambdas they create.
+impl fmt::Display for Bytecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(f, "{:4} {}", i, op)?;
+        }
+        for op in &self.ops {
+            if let Op::Lambda(template) = op {
+                writeln!(f, "lambda {}:", super::unparse(&template.body))?;
+                write!(f, "{}", template.code)?;
+            }
+        }
+        Ok(())
+    }
+}
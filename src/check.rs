@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::{define_prelude, expand, list_items, parse_program, record, setup, unparse, Env, Span, SymbolId, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+/// A problem found by `check`. Problems inside a top-level form are reported with
+/// the span of the whole form, since the reader keeps no positions below it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String
+}
+
+/// One-based line and column of a byte offset into `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// `message` prefixed with the line and column where `span` starts in `source`, as in
+/// `error at 3:14: unexpected )`.
+pub fn error_at(source: &str, span: Span, message: &str) -> String {
+    let (line, column) = line_column(source, span.start);
+    format!("error at {}:{}: {}", line, column, message)
+}
+
+/// Reads and lints a whole program without evaluating it: syntax errors, malformed
+/// special forms, references to variables that are never bound and imports of
+/// unknown libraries. The standard libraries are known, and so are the libraries the
+/// program defines with `define-library` before importing them. Forms that could be
+/// read are linted even when others have syntax errors, once they are expanded like
+/// `eval` expands them.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let mut builtins = setup();
+    if let Err(e) = define_prelude(&mut builtins) {
+        return vec![Diagnostic { span: Span { start: 0, end: 0 }, severity: Severity::Error, message: format!("prelude: {}", e) }];
+    }
+    lint(source, &Rc::new(RefCell::new(Env::new(builtins, None)))).0
+}
+
+// The variables `source` refers to without binding them, when it is evaluated in the
+// global environment `env`, each with the span of the top-level form using it.
+pub(crate) fn unbound_variables(source: &str, env: &Rc<RefCell<Env>>) -> Vec<(SymbolId, Span)> {
+    lint(source, env).1
+}
+
+fn lint(source: &str, env: &Rc<RefCell<Env>>) -> (Vec<Diagnostic>, Vec<(SymbolId, Span)>) {
+    let (forms, errors) = parse_program(source);
+    let mut diagnostics = errors.into_iter()
+        .map(|e| Diagnostic { span: e.span, severity: Severity::Error, message: e.message.to_string() })
+        .collect::<Vec<Diagnostic>>();
+
+    // the macros of earlier forms are kept for the later ones, in a frame of their own
+    // so that `env` is left as it is
+    let scope = Rc::new(RefCell::new(Env::new(HashMap::new(), Some(env.clone()))));
+    let forms = forms.into_iter().filter_map(|(mut form, span)| match expand(&mut form, &scope) {
+        Ok(()) => Some((form, span)),
+        Err(e) => {
+            diagnostics.push(Diagnostic { span, severity: Severity::Error, message: e.message().to_string() });
+            None
+        }
+    }).collect::<Vec<(Value, Span)>>();
+
+    // the names `env` binds are builtins to the program, the prelude included
+    let bound = env.borrow().bindings().into_iter().map(|(id, _)| id).collect::<HashSet<SymbolId>>();
+    let mut linter = Linter { builtins: bound.clone(), bound, libraries: HashMap::new(), diagnostics: vec![], unbound: vec![] };
+    for (form, span) in forms.iter() {
+        linter.collect_definitions(form, *span);
+    }
+    for (form, span) in forms.iter() {
+        linter.lint(form, *span, &mut vec![]);
+    }
+    diagnostics.append(&mut linter.diagnostics);
+    diagnostics.sort_by_key(|d| d.span.start);
+    (diagnostics, linter.unbound)
+}
+
+struct Linter {
+    // the global variables and every name defined anywhere in the program
+    bound: HashSet<SymbolId>,
+    builtins: HashSet<SymbolId>,
+    // the names each library defined by the program exports
+    libraries: HashMap<String, Vec<SymbolId>>,
+    diagnostics: Vec<Diagnostic>,
+    unbound: Vec<(SymbolId, Span)>
+}
+
+fn symbol(value: &Value) -> Option<SymbolId> {
+    match *value {
+        Value::Symbol(id) => Some(id),
+        _ => None
+    }
+}
+
+// The libraries of R7RS, which export builtins.
+const STANDARD_LIBRARIES: &[&str] = &[
+    "(scheme base)", "(scheme case-lambda)", "(scheme char)", "(scheme complex)", "(scheme cxr)", "(scheme eval)",
+    "(scheme file)", "(scheme inexact)", "(scheme lazy)", "(scheme load)", "(scheme process-context)", "(scheme read)",
+    "(scheme repl)", "(scheme time)", "(scheme write)", "(scheme r5rs)"
+];
+
+// The name of the symbol a form starts with.
+fn keyword(list: &[Value]) -> Option<Rc<str>> {
+    list.first().and_then(symbol).map(|id| id.name())
+}
+
+impl Linter {
+    fn report(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic { span, severity: Severity::Error, message });
+    }
+
+    fn collect_definitions(&mut self, form: &Value, span: Span) {
+        if let Value::List(list) = form {
+            match keyword(list).as_deref() {
+                Some("quote") => return,
+                Some("define") => {
+                    if let Some(id) = list.get(1).and_then(symbol) {
+                        if self.builtins.contains(&id) {
+                            self.diagnostics.push(Diagnostic {
+                                span,
+                                severity: Severity::Warning,
+                                message: format!("definition of {} shadows a builtin", id)
+                            });
+                        }
+                        self.bound.insert(id);
+                    }
+                }
+                Some("define-record-type") => match record::define_record_type(list) {
+                    Ok(bindings) => self.bound.extend(bindings.iter().map(|(name, _)| SymbolId::intern(name))),
+                    Err(e) => self.report(span, e.to_string())
+                },
+                Some("import") => return self.import(&list[1..], span),
+                Some("define-library") => return self.define_library(list, span),
+                _ => {}
+            }
+            for child in list {
+                self.collect_definitions(child, span);
+            }
+        }
+    }
+
+    // Binds the names of the import sets `sets`, or reports the libraries they name
+    // that are not known.
+    fn import(&mut self, sets: &[Value], span: Span) {
+        for set in sets {
+            match self.import_set(set) {
+                Ok(names) => self.bound.extend(names),
+                Err(message) => self.report(span, message)
+            }
+        }
+    }
+
+    // The names an import set binds.
+    fn import_set(&self, set: &Value) -> Result<Vec<SymbolId>, String> {
+        let list = match set {
+            Value::List(list) if !list.is_empty() => list,
+            _ => return Err(format!("wrong syntax for import set {}", unparse(set)))
+        };
+        let names = |values: &[Value]| values.iter().filter_map(symbol).collect::<Vec<SymbolId>>();
+        match (keyword(list).as_deref(), list.get(1)) {
+            (Some("only"), Some(inner @ Value::List(_))) => {
+                let only = names(&list[2..]);
+                Ok(self.import_set(inner)?.into_iter().filter(|id| only.contains(id)).collect())
+            }
+            (Some("except"), Some(inner @ Value::List(_))) => {
+                let except = names(&list[2..]);
+                Ok(self.import_set(inner)?.into_iter().filter(|id| !except.contains(id)).collect())
+            }
+            (Some("prefix"), Some(inner @ Value::List(_))) => {
+                let prefix = list.get(2).and_then(symbol).map(|id| id.name()).unwrap_or_else(|| "".into());
+                Ok(self.import_set(inner)?.into_iter().map(|id| SymbolId::intern(&format!("{}{}", prefix, id))).collect())
+            }
+            (Some("rename"), Some(inner @ Value::List(_))) => {
+                let renames = list[2..].iter().filter_map(list_items).filter(|pair| pair.len() == 2)
+                    .filter_map(|pair| Some((symbol(&pair[0])?, symbol(&pair[1])?)))
+                    .collect::<Vec<(SymbolId, SymbolId)>>();
+                Ok(self.import_set(inner)?.into_iter()
+                    .map(|id| renames.iter().find(|(from, _)| *from == id).map_or(id, |(_, to)| *to))
+                    .collect())
+            }
+            _ => {
+                let name = unparse(set);
+                if STANDARD_LIBRARIES.contains(&name.as_str()) {
+                    Ok(self.builtins.iter().cloned().collect())
+                } else {
+                    self.libraries.get(&name).cloned().ok_or_else(|| format!("unknown library {}", name))
+                }
+            }
+        }
+    }
+
+    // Makes the library known to the imports after it, with the names it exports. Its
+    // own definitions are bound like those of the program.
+    fn define_library(&mut self, list: &[Value], span: Span) {
+        let mut exports = vec![];
+        for declaration in list.iter().skip(2).filter_map(list_items) {
+            match keyword(declaration).as_deref() {
+                Some("export") => exports.extend(declaration[1..].iter().filter_map(|spec| match list_items(spec) {
+                    Some(rename) if rename.len() == 3 => rename.get(2).and_then(symbol),
+                    _ => symbol(spec)
+                })),
+                Some("import") => self.import(&declaration[1..], span),
+                Some("begin") => {
+                    for form in &declaration[1..] {
+                        self.collect_definitions(form, span);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match list.get(1) {
+            Some(name @ Value::List(_)) => {
+                self.libraries.insert(unparse(name), exports);
+            }
+            _ => self.report(span, "wrong syntax for define-library expression".to_string())
+        }
+    }
+
+    fn lint_all(&mut self, list: &[Value], span: Span, scopes: &mut Vec<Vec<SymbolId>>) {
+        for form in list {
+            self.lint(form, span, scopes);
+        }
+    }
+
+    // Binds `names` around the expressions of a lambda or letrec.
+    fn lint_scope(&mut self, names: &[Value], body: &[Value], span: Span, scopes: &mut Vec<Vec<SymbolId>>, form: &str) {
+        let mut scope = vec![];
+        for name in names {
+            match symbol(name) {
+                Some(id) => scope.push(id),
+                None => self.report(span, format!("wrong syntax for {} expression", form))
+            }
+        }
+        scopes.push(scope);
+        self.lint_all(body, span, scopes);
+        scopes.pop();
+    }
+
+    fn lint_quasiquote(&mut self, template: &Value, depth: usize, span: Span, scopes: &mut Vec<Vec<SymbolId>>) {
+        let list = match template {
+            Value::List(list) => list,
+            Value::Vector(vector) => return self.lint_template_elements(&vector.borrow().clone(), depth, span, scopes),
+            Value::Pair(pair) => return self.lint_template_elements(&[pair.car(), pair.cdr()], depth, span, scopes),
+            _ => return
+        };
+        match (keyword(list).as_deref(), list.len()) {
+            (Some("unquote"), 2) | (Some("unquote-splicing"), 2) if depth == 1 => self.lint(&list[1], span, scopes),
+            (Some("unquote"), 2) | (Some("unquote-splicing"), 2) => self.lint_quasiquote(&list[1], depth - 1, span, scopes),
+            (Some("quasiquote"), 2) => self.lint_quasiquote(&list[1], depth + 1, span, scopes),
+            _ => self.lint_template_elements(list, depth, span, scopes)
+        }
+    }
+
+    fn lint_template_elements(&mut self, elements: &[Value], depth: usize, span: Span, scopes: &mut Vec<Vec<SymbolId>>) {
+        for element in elements {
+            self.lint_quasiquote(element, depth, span, scopes);
+        }
+    }
+
+    fn lint(&mut self, form: &Value, span: Span, scopes: &mut Vec<Vec<SymbolId>>) {
+        let list = match *form {
+            Value::Symbol(id) => {
+                if !self.bound.contains(&id) && !scopes.iter().any(|scope| scope.contains(&id)) {
+                    self.report(span, format!("unbound variable {}", id));
+                    self.unbound.push((id, span));
+                }
+                return;
+            }
+            Value::List(ref list) => list,
+            _ => return
+        };
+        match keyword(list).as_deref() {
+            Some("quote") => {
+                if list.len() != 2 {
+                    self.report(span, "wrong syntax for quote expression".to_string());
+                }
+            }
+            Some("quasiquote") => match list.get(1) {
+                Some(template) if list.len() == 2 => self.lint_quasiquote(template, 1, span, scopes),
+                _ => self.report(span, "wrong syntax for quasiquote expression".to_string())
+            },
+            Some("if") => {
+                if list.len() != 4 {
+                    self.report(span, "wrong syntax for if expression".to_string());
+                }
+                self.lint_all(&list[1..], span, scopes);
+            }
+            Some("define") => match list.get(1).and_then(symbol) {
+                Some(_) if list.len() == 3 => self.lint(&list[2], span, scopes),
+                _ => self.report(span, "wrong syntax for define expression".to_string())
+            },
+            Some("lambda") => match (list.get(1).and_then(list_items), list.get(2)) {
+                (Some(params), Some(body @ Value::List(_))) if list.len() == 3 => {
+                    self.lint_scope(params, std::slice::from_ref(body), span, scopes, "lambda")
+                }
+                _ => self.report(span, "wrong syntax for lambda expression".to_string())
+            },
+            Some(form @ "letrec") | Some(form @ "letrec*") => match list.get(1).and_then(list_items) {
+                Some(bindings) if list.len() > 2 => {
+                    let mut names = vec![];
+                    let mut expressions = vec![];
+                    for binding in bindings {
+                        match binding {
+                            Value::List(pair) if pair.len() == 2 => {
+                                names.push(pair[0].clone());
+                                expressions.push(pair[1].clone());
+                            }
+                            _ => self.report(span, format!("wrong syntax for {} expression", form))
+                        }
+                    }
+                    expressions.extend(list[2..].iter().cloned());
+                    self.lint_scope(&names, &expressions, span, scopes, form);
+                }
+                _ => self.report(span, format!("wrong syntax for {} expression", form))
+            },
+            Some("define-record-type") | Some("import") => {}
+            Some("define-library") => {
+                for declaration in list.iter().skip(2).filter_map(list_items) {
+                    if keyword(declaration).as_deref() == Some("begin") {
+                        self.lint_all(&declaration[1..], span, scopes);
+                    }
+                }
+            }
+            _ => self.lint_all(list, span, scopes)
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::{dump, frames, gc, interrupt, source_map, symbol, watch, Evaluation, Value, EvalObserver, Engine, NumberFormatter, OptLevel, OverflowMode, ReaderLimits};
+use port::Ports;
+
+// Everything an interpreter keeps between evaluations: its settings, the current
+// ports and observer, the objects the cycle collector tracks, the watches, what the
+// last evaluation left behind, the literals it interned and the storage it keeps for
+// reuse, all of which are dropped with it. Each thread has a context of its own,
+// which the free functions of this crate evaluate in, and each `Interpreter`
+// another, which is the current one of the thread while the interpreter evaluates.
+#[derive(Default)]
+pub(crate) struct Context {
+    pub overflow_mode: Cell<OverflowMode>,
+    pub engine: Cell<Engine>,
+    pub opt_level: Cell<OptLevel>,
+    pub fuel: Cell<Option<usize>>,
+    pub allocation_limit: Cell<Option<usize>>,
+    pub depth_limit: Cell<Option<usize>>,
+    pub reader_limits: Cell<ReaderLimits>,
+    pub number_formatter: RefCell<Option<Rc<NumberFormatter>>>,
+    pub ports: RefCell<Ports>,
+    pub observer: RefCell<Option<Rc<dyn EvalObserver>>>,
+    pub evaluation: Evaluation,
+    pub error: RefCell<Option<dump::ErrorContext>>,
+    pub spans: RefCell<source_map::Spans>,
+    pub heap: gc::Heap,
+    pub watches: watch::Watches,
+    pub interrupts: interrupt::Interrupts,
+    pub interner: symbol::Interner,
+    pub free_frames: frames::FreeFrames,
+    // the stack of the last read, kept for the next one
+    pub read_elements: RefCell<Vec<Value>>,
+    // the stacks of frames builtins are running, nested in one another
+    pub runs: Cell<usize>
+}
+
+thread_local! {
+    static CURRENT: RefCell<Rc<Context>> = RefCell::new(Rc::new(Context::default()));
+}
+
+impl Context {
+    // A context with the settings, ports, observer and handlers of the current one,
+    // and none of its state.
+    pub fn inherit() -> Context {
+        with(|current| {
+            let context = Context {
+                overflow_mode: Cell::new(current.overflow_mode.get()),
+                engine: Cell::new(current.engine.get()),
+                opt_level: Cell::new(current.opt_level.get()),
+                fuel: Cell::new(current.fuel.get()),
+                allocation_limit: Cell::new(current.allocation_limit.get()),
+                depth_limit: Cell::new(current.depth_limit.get()),
+                reader_limits: Cell::new(current.reader_limits.get()),
+                number_formatter: RefCell::new(current.number_formatter.borrow().clone()),
+                ports: RefCell::new(current.ports.borrow().clone()),
+                observer: RefCell::new(current.observer.borrow().clone()),
+                ..Context::default()
+            };
+            context.heap.inherit(&current.heap);
+            context.watches.inherit(&current.watches);
+            context.interrupts.inherit(&current.interrupts);
+            context
+        })
+    }
+}
+
+// The current context is cloned out of the cell before `f` is given it, so that `f`
+// may drop values whose host code evaluates with another interpreter.
+pub(crate) fn with<R, F: FnOnce(&Context) -> R>(f: F) -> R {
+    let context = CURRENT.with(|c| c.borrow().clone());
+    f(&context)
+}
+
+// The context that was current before `install`, put back when dropped.
+pub(crate) struct Installed(Option<Rc<Context>>);
+
+// Makes `context` the current one of the thread until the guard returned is dropped.
+pub(crate) fn install(context: &Rc<Context>) -> Installed {
+    Installed(Some(CURRENT.with(|c| c.replace(context.clone()))))
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        if let Some(context) = self.0.take() {
+            CURRENT.with(|c| *c.borrow_mut() = context);
+        }
+    }
+}
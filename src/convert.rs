@@ -0,0 +1,270 @@
+use std::any::Any;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use super::{SchemeError, Value};
+
+/// Rust data that can be handed to Scheme as a value. A struct implements it by
+/// converting each of its fields, which is what a derive would generate.
+///
+/// ```
+/// use scheme_rs::{FromScheme, Interpreter, SchemeError, ToScheme, Value};
+///
+/// struct Point { x: i64, y: i64 }
+///
+/// impl ToScheme for Point {
+///     fn to_scheme(&self) -> Value {
+///         vec![self.x, self.y].to_scheme()
+///     }
+/// }
+///
+/// impl FromScheme for Point {
+///     fn from_scheme(value: &Value) -> Result<Point, SchemeError> {
+///         match Vec::<i64>::from_scheme(value)?.as_slice() {
+///             [x, y] => Ok(Point { x: *x, y: *y }),
+///             _ => Err(SchemeError::wrong_type("expected a point"))
+///         }
+///     }
+/// }
+///
+/// let interpreter = Interpreter::new().unwrap();
+/// interpreter.define_fn("flip", |args: &[Value]| {
+///     let point = Point::from_scheme(&args[0])?;
+///     Ok(Point { x: point.y, y: point.x }.to_scheme())
+/// });
+/// let flipped = interpreter.eval_str("(flip (list 1 2))").unwrap();
+/// assert_eq!((2, 1), { let p = Point::from_scheme(&flipped).unwrap(); (p.x, p.y) });
+/// ```
+pub trait ToScheme {
+    fn to_scheme(&self) -> Value;
+}
+
+/// Rust data that can be read back from a Scheme value. Values of another kind
+/// give a type error, and numbers that do not fit give a range error.
+pub trait FromScheme: Sized {
+    fn from_scheme(value: &Value) -> Result<Self, SchemeError>;
+}
+
+impl ToScheme for Value {
+    fn to_scheme(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl FromScheme for Value {
+    fn from_scheme(value: &Value) -> Result<Value, SchemeError> {
+        Ok(value.clone())
+    }
+}
+
+// For results that are not needed, whatever their type.
+impl FromScheme for () {
+    fn from_scheme(_: &Value) -> Result<(), SchemeError> {
+        Ok(())
+    }
+}
+
+impl ToScheme for bool {
+    fn to_scheme(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl FromScheme for bool {
+    fn from_scheme(value: &Value) -> Result<bool, SchemeError> {
+        value.as_bool().ok_or(SchemeError::wrong_type("expected a boolean"))
+    }
+}
+
+impl ToScheme for f64 {
+    fn to_scheme(&self) -> Value {
+        Value::Number(*self)
+    }
+}
+
+// Exact numbers are read as the nearest float, as `exact->inexact` does.
+impl FromScheme for f64 {
+    fn from_scheme(value: &Value) -> Result<f64, SchemeError> {
+        value.as_f64().ok_or(SchemeError::wrong_type("expected a real number"))
+    }
+}
+
+impl ToScheme for char {
+    fn to_scheme(&self) -> Value {
+        Value::Char(*self)
+    }
+}
+
+impl FromScheme for char {
+    fn from_scheme(value: &Value) -> Result<char, SchemeError> {
+        value.as_char().ok_or(SchemeError::wrong_type("expected a character"))
+    }
+}
+
+impl ToScheme for str {
+    fn to_scheme(&self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl ToScheme for String {
+    fn to_scheme(&self) -> Value {
+        Value::String(self.as_str().into())
+    }
+}
+
+impl FromScheme for String {
+    fn from_scheme(value: &Value) -> Result<String, SchemeError> {
+        value.as_str().map(str::to_string).ok_or(SchemeError::wrong_type("expected a string"))
+    }
+}
+
+// Integers of every width are fixnums when they fit, and big integers otherwise.
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {$(
+        impl ToScheme for $int {
+            fn to_scheme(&self) -> Value {
+                match i64::try_from(*self) {
+                    Ok(i) => Value::Integer(i),
+                    Err(_) => Value::integer((*self).into())
+                }
+            }
+        }
+
+        impl FromScheme for $int {
+            fn from_scheme(value: &Value) -> Result<$int, SchemeError> {
+                match value {
+                    Value::Integer(_) | Value::BigInt(_) => value.as_i64()
+                        .and_then(|i| <$int>::try_from(i).ok())
+                        .ok_or(SchemeError::out_of_range("integer out of range")),
+                    _ => Err(SchemeError::wrong_type("expected an exact integer"))
+                }
+            }
+        }
+
+        impl From<$int> for Value {
+            fn from(n: $int) -> Value {
+                n.to_scheme()
+            }
+        }
+    )*}
+}
+
+integer_conversions!(i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+
+/// A list of the elements converted one by one.
+impl<T: ToScheme> ToScheme for [T] {
+    fn to_scheme(&self) -> Value {
+        Value::list(self.iter().map(ToScheme::to_scheme).collect())
+    }
+}
+
+impl<T: ToScheme> ToScheme for Vec<T> {
+    fn to_scheme(&self) -> Value {
+        self.as_slice().to_scheme()
+    }
+}
+
+/// The elements of a proper list or a vector, converted one by one.
+impl<T: FromScheme> FromScheme for Vec<T> {
+    fn from_scheme(value: &Value) -> Result<Vec<T>, SchemeError> {
+        let elements = match value {
+            Value::Vector(vector) => vector.borrow().clone(),
+            _ => value.as_list().ok_or(SchemeError::wrong_type("expected a list"))?
+        };
+        elements.iter().map(T::from_scheme).collect()
+    }
+}
+
+/// Foreign data, shared with the value it was read from.
+impl<T: Any> FromScheme for Rc<T> {
+    fn from_scheme(value: &Value) -> Result<Rc<T>, SchemeError> {
+        match value {
+            Value::Foreign(f) => f.downcast().ok_or(SchemeError::wrong_type("expected foreign data of another type")),
+            _ => Err(SchemeError::wrong_type("expected foreign data"))
+        }
+    }
+}
+
+/// A pair, so that a `Vec` of tuples is an association list.
+impl<A: ToScheme, B: ToScheme> ToScheme for (A, B) {
+    fn to_scheme(&self) -> Value {
+        Value::cons(self.0.to_scheme(), self.1.to_scheme())
+    }
+}
+
+impl<A: FromScheme, B: FromScheme> FromScheme for (A, B) {
+    fn from_scheme(value: &Value) -> Result<(A, B), SchemeError> {
+        match value {
+            Value::Pair(p) => Ok((A::from_scheme(&p.car())?, B::from_scheme(&p.cdr())?)),
+            Value::List(l) => Ok((A::from_scheme(&l[0])?, B::from_scheme(&l.tail(1).map_or(Value::Nil, Value::List))?)),
+            _ => Err(SchemeError::wrong_type("expected a pair"))
+        }
+    }
+}
+
+impl<T: ToScheme + ?Sized> ToScheme for &T {
+    fn to_scheme(&self) -> Value {
+        (**self).to_scheme()
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Number(f)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<char> for Value {
+    fn from(c: char) -> Value {
+        Value::Char(c)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s.into())
+    }
+}
+
+impl<T: ToScheme> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Value {
+        elements.to_scheme()
+    }
+}
+
+// `TryFrom` for every type `FromScheme` reads, which cannot be one generic impl
+// since the standard library already has one for types with `From<Value>`.
+macro_rules! try_from_value {
+    ($($t:ty),*) => {$(
+        impl TryFrom<Value> for $t {
+            type Error = SchemeError;
+
+            fn try_from(value: Value) -> Result<$t, SchemeError> {
+                <$t>::from_scheme(&value)
+            }
+        }
+    )*}
+}
+
+try_from_value!(bool, f64, char, String, i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+
+impl<T: FromScheme> TryFrom<Value> for Vec<T> {
+    type Error = SchemeError;
+
+    fn try_from(value: Value) -> Result<Vec<T>, SchemeError> {
+        Vec::from_scheme(&value)
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use super::{context, datatype2str, error_environment, escape_string, parse_datum, watch, Env, SchemeError, SymbolId, Value};
+
+// Printed values longer than this are cut in the environment summaries.
+const MAX_VALUE_LENGTH: usize = 80;
+
+// The message of the last error and the procedures being applied when it happened.
+pub(crate) type ErrorContext = (String, Vec<String>);
+
+/// State of the interpreter when the last top-level evaluation failed. It prints
+/// as a datum that can be attached to a bug report and read back with `Dump::parse`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dump {
+    pub error: String,
+    /// Procedures being applied when the error happened, outermost first.
+    pub stack: Vec<String>,
+    /// Bindings in scope at the error, innermost frame first. Native procedures are
+    /// left out and long values are cut.
+    pub frames: Vec<Vec<(String, String)>>,
+    /// Procedures applied most recently, oldest first.
+    pub trace: Vec<String>
+}
+
+pub(crate) fn record_error(error: &SchemeError) {
+    let stack = watch::call_stack();
+    context::with(|c| *c.error.borrow_mut() = Some((error.message().to_string(), stack)));
+}
+
+pub(crate) fn clear_error() {
+    context::with(|c| *c.error.borrow_mut() = None);
+}
+
+fn summary(value: &Value) -> String {
+    let printed = datatype2str(value);
+    match printed.char_indices().nth(MAX_VALUE_LENGTH) {
+        Some((i, _)) => format!("{}...", &printed[..i]),
+        None => printed
+    }
+}
+
+fn frames(env: Rc<RefCell<Env>>) -> Vec<Vec<(String, String)>> {
+    let mut frames = vec![];
+    let mut current = Some(env);
+    while let Some(env) = current {
+        let env = env.borrow();
+        let mut bindings = env.bindings().iter()
+            .filter(|(_, value)| !matches!(value, Value::Proc(_)))
+            .map(|(name, value)| (name.to_string(), summary(value)))
+            .collect::<Vec<(String, String)>>();
+        bindings.sort();
+        frames.push(bindings);
+        current = env.parent.as_ref().map(|parent| (**parent).clone());
+    }
+    frames
+}
+
+/// The dump of the last failed top-level evaluation in the current context, if it
+/// failed.
+pub fn error_dump() -> Option<Dump> {
+    let (error, stack) = context::with(|c| c.error.borrow().clone())?;
+    Some(Dump {
+        error: error.to_string(),
+        stack,
+        frames: error_environment().map(frames).unwrap_or_default(),
+        trace: watch::trace()
+    })
+}
+
+fn quoted(strings: &[String]) -> String {
+    strings.iter().map(|s| format!(" \"{}\"", escape_string(s))).collect()
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "(dump")?;
+        writeln!(f, "  (error \"{}\")", escape_string(&self.error))?;
+        writeln!(f, "  (stack{})", quoted(&self.stack))?;
+        for frame in &self.frames {
+            let bindings = frame.iter()
+                .map(|(name, value)| format!(" (\"{}\" \"{}\")", escape_string(name), escape_string(value)))
+                .collect::<String>();
+            writeln!(f, "  (frame{})", bindings)?;
+        }
+        write!(f, "  (trace{}))", quoted(&self.trace))
+    }
+}
+
+fn strings(values: &[Value]) -> Result<Vec<String>, SchemeError> {
+    values.iter().map(|value| match value {
+        Value::String(s) => Ok(s.to_string()),
+        _ => Err(SchemeError::read("malformed dump"))
+    }).collect()
+}
+
+impl Dump {
+    /// Reads back a dump written with `to_string`.
+    pub fn parse(text: &str) -> Result<Dump, SchemeError> {
+        let sections = match parse_datum(text)?.0 {
+            Value::List(ref list) if list.first() == Some(&Value::Symbol(SymbolId::intern("dump"))) => list[1..].to_vec(),
+            _ => return Err(SchemeError::read("malformed dump"))
+        };
+        let mut dump = Dump::default();
+        for section in &sections {
+            let (name, items) = match section {
+                Value::List(list) => match list.split_first() {
+                    Some((Value::Symbol(name), items)) => (name.name(), items),
+                    _ => return Err(SchemeError::read("malformed dump"))
+                },
+                _ => return Err(SchemeError::read("malformed dump"))
+            };
+            match &*name {
+                "error" => dump.error = strings(items)?.concat(),
+                "stack" => dump.stack = strings(items)?,
+                "trace" => dump.trace = strings(items)?,
+                "frame" => dump.frames.push(items.iter().map(|binding| match binding {
+                    Value::List(pair) => match strings(pair)?.as_slice() {
+                        [name, value] => Ok((name.clone(), value.clone())),
+                        _ => Err(SchemeError::read("malformed dump"))
+                    },
+                    _ => Err(SchemeError::read("malformed dump"))
+                }).collect::<Result<Vec<(String, String)>, SchemeError>>()?),
+                _ => return Err(SchemeError::read("malformed dump"))
+            }
+        }
+        Ok(dump)
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{watch, Span, SyntaxError};
+
+/// Why reading or evaluating failed. Every kind carries the span of the innermost
+/// form read by `parse` that failed with it, when there is one, and the backtrace of
+/// the procedures being applied when it was raised.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemeError {
+    /// The source text is not a sequence of data.
+    ReadError { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// A special form is used with the wrong shape, like `(if)`.
+    Syntax { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// A variable is referenced before anything is bound to it.
+    UnboundVariable { name: String, span: Option<Span>, backtrace: Backtrace },
+    /// A value is not of the type a procedure or form needs.
+    WrongType { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// A procedure is called with too many or too few arguments.
+    Arity { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// An index, range or key is not in a string, vector, list or table.
+    OutOfRange { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// Any other failure, like a division by zero or a closed port.
+    Other { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// Evaluation used up what it was allowed of a resource, like the steps of
+    /// `set_fuel`. Exception handlers do not catch it.
+    BudgetExceeded { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// Evaluation was stopped by an `InterruptHandle` or ran out of time.
+    Interrupted { message: &'static str, span: Option<Span>, backtrace: Backtrace },
+    /// A value cannot be converted to or from Rust data, for the reason in `detail`.
+    // Errors are returned all through the recursive parts of the interpreter, whose
+    // frames the size of an error adds to. The detail is not a `String`: the name of
+    // `UnboundVariable` is the only one, and the variant is told by its capacity, which
+    // no smaller variant overlaps. A second `String` would need a tag of its own.
+    Conversion { detail: Box<str>, span: Option<Span>, backtrace: Backtrace }
+}
+
+impl SchemeError {
+    pub fn read(message: &'static str) -> SchemeError {
+        SchemeError::ReadError { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn syntax(message: &'static str) -> SchemeError {
+        SchemeError::Syntax { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn unbound(name: &str) -> SchemeError {
+        SchemeError::UnboundVariable { name: name.to_string(), span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn wrong_type(message: &'static str) -> SchemeError {
+        SchemeError::WrongType { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn arity(message: &'static str) -> SchemeError {
+        SchemeError::Arity { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn out_of_range(message: &'static str) -> SchemeError {
+        SchemeError::OutOfRange { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn other(message: &'static str) -> SchemeError {
+        SchemeError::Other { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn budget_exceeded(message: &'static str) -> SchemeError {
+        SchemeError::BudgetExceeded { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn interrupted(message: &'static str) -> SchemeError {
+        SchemeError::Interrupted { message, span: None, backtrace: Backtrace::default() }
+    }
+
+    pub fn conversion(detail: &str) -> SchemeError {
+        SchemeError::Conversion { detail: detail.into(), span: None, backtrace: Backtrace::default() }
+    }
+
+    /// The message of the error, without the name of an unbound variable or the
+    /// reason a conversion failed.
+    pub fn message(&self) -> &'static str {
+        match self {
+            SchemeError::UnboundVariable { .. } => "symbol is not defined.",
+            SchemeError::Conversion { .. } => "value cannot be converted",
+            SchemeError::ReadError { message, .. } | SchemeError::Syntax { message, .. } | SchemeError::WrongType { message, .. }
+            | SchemeError::Arity { message, .. } | SchemeError::OutOfRange { message, .. } | SchemeError::Other { message, .. }
+            | SchemeError::BudgetExceeded { message, .. } | SchemeError::Interrupted { message, .. } => message
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            SchemeError::ReadError { span, .. } | SchemeError::Syntax { span, .. } | SchemeError::UnboundVariable { span, .. }
+            | SchemeError::WrongType { span, .. } | SchemeError::Arity { span, .. } | SchemeError::OutOfRange { span, .. }
+            | SchemeError::Other { span, .. } | SchemeError::BudgetExceeded { span, .. }
+            | SchemeError::Interrupted { span, .. } | SchemeError::Conversion { span, .. } => *span
+        }
+    }
+
+    /// The same error placed at `span`.
+    pub fn with_span(mut self, at: Span) -> SchemeError {
+        match &mut self {
+            SchemeError::ReadError { span, .. } | SchemeError::Syntax { span, .. } | SchemeError::UnboundVariable { span, .. }
+            | SchemeError::WrongType { span, .. } | SchemeError::Arity { span, .. } | SchemeError::OutOfRange { span, .. }
+            | SchemeError::Other { span, .. } | SchemeError::BudgetExceeded { span, .. }
+            | SchemeError::Interrupted { span, .. } | SchemeError::Conversion { span, .. } => *span = Some(at)
+        }
+        self
+    }
+
+    pub fn backtrace(&self) -> &Backtrace {
+        match self {
+            SchemeError::ReadError { backtrace, .. } | SchemeError::Syntax { backtrace, .. } | SchemeError::UnboundVariable { backtrace, .. }
+            | SchemeError::WrongType { backtrace, .. } | SchemeError::Arity { backtrace, .. } | SchemeError::OutOfRange { backtrace, .. }
+            | SchemeError::Other { backtrace, .. } | SchemeError::BudgetExceeded { backtrace, .. }
+            | SchemeError::Interrupted { backtrace, .. } | SchemeError::Conversion { backtrace, .. } => backtrace
+        }
+    }
+
+    // The same error with the procedures being applied now as its backtrace, unless
+    // it already has one from where it was raised first.
+    pub(crate) fn traced(mut self) -> SchemeError {
+        match &mut self {
+            SchemeError::ReadError { backtrace, .. } | SchemeError::Syntax { backtrace, .. } | SchemeError::UnboundVariable { backtrace, .. }
+            | SchemeError::WrongType { backtrace, .. } | SchemeError::Arity { backtrace, .. } | SchemeError::OutOfRange { backtrace, .. }
+            | SchemeError::Other { backtrace, .. } | SchemeError::BudgetExceeded { backtrace, .. }
+            | SchemeError::Interrupted { backtrace, .. } | SchemeError::Conversion { backtrace, .. } if backtrace.is_empty() => backtrace.frames = Some(Arc::new(watch::call_stack())),
+            _ => {}
+        }
+        self
+    }
+}
+
+/// The names of the procedures being applied when an error was raised, outermost
+/// first, as `Dump::stack` has them. Errors compare equal whatever their backtraces
+/// are, so that an error can be matched by its kind, message and span alone.
+#[derive(Clone, Debug, Default)]
+pub struct Backtrace {
+    // behind one pointer, so that it takes a word of an error, and copies of the
+    // error share it
+    frames: Option<Arc<Vec<String>>>
+}
+
+impl Backtrace {
+    pub fn frames(&self) -> &[String] {
+        self.frames.as_ref().map_or(&[], |frames| frames.as_slice())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames().is_empty()
+    }
+}
+
+impl PartialEq for Backtrace {
+    fn eq(&self, _: &Backtrace) -> bool {
+        true
+    }
+}
+
+// Innermost frame first, one per line, as Rust prints its own backtraces.
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, name) in self.frames().iter().rev().enumerate() {
+            writeln!(f, "{:>4}: {}", i, name)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<SyntaxError> for SchemeError {
+    fn from(error: SyntaxError) -> SchemeError {
+        SchemeError::ReadError { message: error.message, span: Some(error.span), backtrace: Backtrace::default() }
+    }
+}
+
+impl fmt::Display for SchemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemeError::UnboundVariable { name, .. } => write!(f, "symbol is not defined: {}", name),
+            SchemeError::Conversion { detail, .. } => write!(f, "value cannot be converted: {}", detail),
+            _ => write!(f, "{}", self.message())
+        }
+    }
+}
+
+impl Error for SchemeError {}
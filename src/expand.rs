@@ -0,0 +1,990 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use super::{list_items, read_list, source_map, to_data, Env, SchemeError, Span, SymbolId, Value};
+use labels::Labels;
+
+// The forms the evaluator knows, which are kept as they are.
+const CORE: &[&str] = &["quote", "quasiquote", "if", "define", "lambda", "define-record-type", "letrec", "letrec*", "define-syntax"];
+
+// The forms rewritten into core forms, unless a variable of the same name is in scope.
+const DERIVED: &[&str] = &["let", "let*", "cond", "case", "and", "or", "when", "unless", "do"];
+
+// Bounds on the work of macros that keep expanding into forms using them.
+const MAX_EXPANSIONS: usize = 100_000;
+const MAX_DEPTH: usize = 1024;
+
+/// A macro defined with `define-syntax` and `syntax-rules`. The variables its
+/// templates bind with `lambda`, `let`, `let*`, `letrec`, `letrec*` and `do` are
+/// renamed in each expansion, so that they neither capture nor shadow the names of
+/// the forms the macro is used on. Other names are inserted as they are.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Macro {
+    literals: Vec<SymbolId>,
+    ellipsis: SymbolId,
+    // the patterns, and the templates of the forms they match
+    rules: Vec<(Value, Value)>
+}
+
+// A form being built out of the parts of the one expanded, each with the span it
+// was read with.
+#[derive(Clone)]
+enum Form {
+    Node(Value, Option<Span>),
+    List(Vec<Form>, Option<Span>)
+}
+
+// What the name at the head of a list stands for where the list is.
+enum Meaning {
+    Variable,
+    Core,
+    Derived,
+    Macro(Rc<Macro>)
+}
+
+struct Expander<'a> {
+    env: &'a Rc<RefCell<Env>>,
+    // the names bound around the form being expanded, innermost last: None for
+    // variables, the macro for macros. The first scope is the top level of `env`.
+    scopes: Vec<HashMap<SymbolId, Option<Rc<Macro>>>>,
+    expansions: usize,
+    depth: usize,
+    // the names made up for the variables of derived forms so far
+    fresh: usize
+}
+
+/// Rewrites `form`, a program about to be evaluated in `env`, into the core forms
+/// the evaluator knows: `let`, `let*`, `cond`, `case`, `and`, `or`, `when`,
+/// `unless`, `do`, `(define (f x) ...)` and the macros defined with
+/// `define-syntax` become `lambda`, `letrec`, `if` and calls. Macros defined at
+/// the top level are kept in `env` for later programs. The forms moved keep their
+/// spans, and forms introduced have the span of the form they replace.
+pub fn expand(form: &mut Value, env: &Rc<RefCell<Env>>) -> Result<(), SchemeError> {
+    Expander { env, scopes: vec![HashMap::new()], expansions: 0, depth: 0, fresh: 0 }.form(form)
+}
+
+// Makes the lists of a quoted datum out of pairs. Kept out of `parts`, which is on
+// the stack once for every level of nesting of the form expanded.
+#[inline(never)]
+fn quoted(datum: &mut Value) {
+    *datum = to_data(datum);
+}
+
+fn fail(message: &'static str, span: Option<Span>) -> SchemeError {
+    let error = SchemeError::syntax(message);
+    match span {
+        Some(span) => error.with_span(span),
+        None => error
+    }
+}
+
+fn symbol(name: &str) -> Form {
+    Form::Node(Value::Symbol(SymbolId::intern(name)), None)
+}
+
+fn boolean(b: bool) -> Form {
+    Form::Node(Value::Bool(b), None)
+}
+
+// The parts of `list`, which are moved out of it.
+fn parts(list: Vec<Value>) -> Vec<Form> {
+    let spans = list.iter().map(source_map::take_span).collect::<Vec<Option<Span>>>();
+    list.into_iter().zip(spans).map(|(value, span)| Form::Node(value, span)).collect()
+}
+
+// The parts of the list `form`, which is left empty.
+fn take_parts(form: &mut Value) -> Vec<Form> {
+    source_map::take_span(form);
+    match mem::replace(form, Value::Nil) {
+        Value::List(list) => parts(list.into_vec()),
+        _ => unreachable!()
+    }
+}
+
+// A copy of `form` whose nodes have the spans of those of `form`.
+fn copy(form: &Value) -> Form {
+    match form {
+        Value::List(list) => Form::List(list.iter().map(copy).collect(), source_map::span_of(form)),
+        _ => Form::Node(form.clone(), source_map::span_of(form))
+    }
+}
+
+// Forgets the spans of `form` and its parts, which are about to be dropped.
+fn forget(form: &Value) {
+    source_map::take_span(form);
+    if let Value::List(list) = form {
+        list.iter().for_each(forget);
+    }
+}
+
+// Whether lists in `form` are nested more than `depth` deep.
+fn deeper_than(form: &Value, depth: usize) -> bool {
+    match form {
+        Value::List(list) => depth == 0 || list.iter().any(|form| deeper_than(form, depth - 1)),
+        _ => false
+    }
+}
+
+// The elements of the list `value`, and the tail after them if it is a dotted list.
+fn elements(value: &Value) -> Option<(Cow<'_, [Value]>, Option<Value>)> {
+    if let Value::Pair(_) = value {
+        let mut elements = vec![];
+        let mut tail = value.clone();
+        while let Value::Pair(p) = tail {
+            elements.push(p.car());
+            tail = p.cdr();
+        }
+        let tail = match tail {
+            Value::List(list) => {
+                elements.extend(list);
+                None
+            }
+            Value::Nil => None,
+            tail => Some(tail)
+        };
+        return Some((Cow::Owned(elements), tail));
+    }
+    list_items(value).map(|list| (Cow::Borrowed(list), None))
+}
+
+impl Form {
+    fn symbol(&self) -> Option<Rc<str>> {
+        match self {
+            Form::Node(Value::Symbol(id), _) => Some(id.name()),
+            _ => None
+        }
+    }
+
+    fn is_list(&self) -> bool {
+        matches!(self, Form::List(..) | Form::Node(Value::List(_) | Value::Nil, _))
+    }
+
+    fn parts(self) -> Option<Vec<Form>> {
+        match self {
+            Form::List(forms, _) => Some(forms),
+            Form::Node(Value::List(list), _) => Some(parts(list.into_vec())),
+            Form::Node(Value::Nil, _) => Some(vec![]),
+            _ => None
+        }
+    }
+
+    fn span(&self) -> Option<Span> {
+        match self {
+            Form::Node(_, span) | Form::List(_, span) => *span
+        }
+    }
+
+    // The form with `span`, unless it has one.
+    fn spanned(self, span: Option<Span>) -> Form {
+        match self {
+            Form::List(forms, None) => Form::List(forms, span),
+            Form::Node(value, None) => Form::Node(value, span),
+            form => form
+        }
+    }
+
+    fn deeper_than(&self, depth: usize) -> bool {
+        match self {
+            Form::List(forms, _) => depth == 0 || forms.iter().any(|form| form.deeper_than(depth - 1)),
+            Form::Node(value, _) => deeper_than(value, depth)
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Form::Node(value, _) => value,
+            Form::List(forms, _) => read_list(forms.into_iter().map(Form::into_value).collect())
+        }
+    }
+
+    // Puts the form in `slot`, where its nodes are at the addresses their spans are
+    // recorded for.
+    fn place(self, slot: &mut Value) {
+        match self {
+            Form::Node(value, span) => {
+                *slot = value;
+                source_map::put_span(slot, span);
+            }
+            Form::List(forms, span) => {
+                let mut list = Vec::with_capacity(forms.len());
+                list.resize_with(forms.len(), || Value::Nil);
+                *slot = read_list(list);
+                if let Value::List(list) = slot {
+                    for (form, slot) in forms.into_iter().zip(list.iter_mut()) {
+                        form.place(slot);
+                    }
+                }
+                source_map::put_span(slot, span);
+            }
+        }
+    }
+}
+
+impl<'a> Expander<'a> {
+    fn meaning(&self, id: SymbolId) -> Meaning {
+        let name = id.name();
+        if CORE.contains(&&*name) {
+            return Meaning::Core;
+        }
+        for scope in self.scopes.iter().rev() {
+            match scope.get(&id) {
+                Some(Some(m)) => return Meaning::Macro(m.clone()),
+                Some(None) => return Meaning::Variable,
+                None => {}
+            }
+        }
+        let env = self.env.borrow();
+        match env.macro_named(id) {
+            Some(m) => Meaning::Macro(m),
+            None if DERIVED.contains(&&*name) && env.get(id).is_none() => Meaning::Derived,
+            None => Meaning::Variable
+        }
+    }
+
+    fn bind(&mut self, name: &Value, m: Option<Rc<Macro>>) {
+        if let Value::Symbol(id) = *name {
+            if self.scopes.len() == 1 {
+                let env = self.env.borrow();
+                match m {
+                    Some(ref m) => env.macros.borrow_mut().insert(id, m.clone()),
+                    None => env.macros.borrow_mut().remove(&id)
+                };
+            }
+            self.scopes.last_mut().unwrap().insert(id, m);
+        }
+    }
+
+    fn fresh(&mut self, name: &str) -> Form {
+        self.fresh += 1;
+        symbol(&format!("%{}{}", name, self.fresh))
+    }
+
+    fn form(&mut self, form: &mut Value) -> Result<(), SchemeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(fail("expanded form nested too deeply", source_map::span_of(form)));
+        }
+        self.depth += 1;
+        let result = self.expand_form(form);
+        self.depth -= 1;
+        result
+    }
+
+    fn all(&mut self, list: &mut [Value]) -> Result<(), SchemeError> {
+        for form in list {
+            self.form(form)?;
+        }
+        Ok(())
+    }
+
+    // Expands `form` until it is a core form or a call, then its parts.
+    fn expand_form(&mut self, form: &mut Value) -> Result<(), SchemeError> {
+        while self.expand_once(form)? {}
+        self.parts(form)
+    }
+
+    // Replaces `form` by the form it is rewritten into, if any. The form is taken apart
+    // here rather than in `expand_form`, which keeps what is on the stack while its
+    // parts are expanded small.
+    fn expand_once(&mut self, form: &mut Value) -> Result<bool, SchemeError> {
+        let rewritten = match self.rewrite(form)? {
+            Some(rewritten) => rewritten,
+            None => return Ok(false)
+        };
+        self.expansions += 1;
+        if self.expansions == MAX_EXPANSIONS {
+            return Err(fail("macro expansion does not terminate", rewritten.span()));
+        }
+        rewritten.place(form);
+        Ok(true)
+    }
+
+    // The form replacing `form`, unless it is a core form or a call already. Its
+    // parts are left for `parts`.
+    fn rewrite(&mut self, form: &mut Value) -> Result<Option<Form>, SchemeError> {
+        let (id, list) = match &*form {
+            Value::List(list) => match list.first() {
+                Some(&Value::Symbol(id)) => (id, list),
+                _ => return Ok(None)
+            },
+            _ => return Ok(None)
+        };
+        let span = source_map::span_of(form);
+        match self.meaning(id) {
+            Meaning::Variable => Ok(None),
+            Meaning::Core => match (&*id.name(), list.get(1)) {
+                ("define", Some(Value::List(_) | Value::Nil)) => {
+                    let parts = take_parts(form);
+                    self.define_procedure(parts, span).map(Some)
+                }
+                ("define-syntax", _) => {
+                    let m = match &list[..] {
+                        [_, name @ Value::Symbol(_), Value::List(spec)] => Macro::new(spec).map(|m| (name.clone(), m)),
+                        _ => None
+                    };
+                    let (name, m) = m.ok_or_else(|| fail("wrong syntax for define-syntax expression", span))?;
+                    self.bind(&name, Some(Rc::new(m)));
+                    forget(form);
+                    Ok(Some(Form::List(vec![symbol("begin")], span)))
+                }
+                // a body of several forms, or of one that is not a list, is made one list
+                ("lambda", Some(_)) if list.len() > 3 || list.len() == 3 && !matches!(list[2], Value::List(_) | Value::Nil) => {
+                    let mut parts = take_parts(form);
+                    let body = parts.split_off(2);
+                    parts.push(lambda_body(body, span));
+                    Ok(Some(Form::List(parts, span)))
+                }
+                // the template is copied as it is where nothing is unquoted, which
+                // would not end on a cycle
+                ("quasiquote", Some(template)) if Labels::new(template).has_cycles() => Err(fail("circular quasiquote template", span)),
+                _ => Ok(None)
+            },
+            Meaning::Macro(m) => {
+                let expanded = m.transcribe(form, &mut self.fresh).map_err(|message| fail(message, span))?;
+                if expanded.deeper_than(MAX_DEPTH - self.depth) {
+                    return Err(fail("expanded form nested too deeply", span));
+                }
+                forget(form);
+                Ok(Some(expanded.spanned(span)))
+            }
+            Meaning::Derived => {
+                let parts = take_parts(form);
+                self.derived(parts, span).map(Some)
+            }
+        }
+    }
+
+    // Expands the parts of a core form or a call.
+    fn parts(&mut self, form: &mut Value) -> Result<(), SchemeError> {
+        let list = match form {
+            Value::List(list) => list,
+            // a vector evaluates to itself, so its lists are data like quoted ones
+            Value::Vector(_) => {
+                quoted(form);
+                return Ok(());
+            }
+            _ => return Ok(())
+        };
+        let name = match list.first() {
+            Some(Value::Symbol(id)) if CORE.contains(&&*id.name()) => id.name(),
+            _ => return self.all(list)
+        };
+        match &*name {
+            // quoted lists are made of pairs once, when the program is expanded, so
+            // that the program gets the same data every time it evaluates them
+            "quote" if list.len() == 2 => {
+                quoted(&mut list[1]);
+                Ok(())
+            }
+            "quasiquote" if list.len() == 2 => self.quasiquote(&mut list[1], 1),
+            "if" => self.all(&mut list[1..]),
+            "define" if list.len() > 1 => {
+                self.bind(&list[1], None);
+                self.all(&mut list[2..])
+            }
+            "lambda" => match list.get(1).and_then(list_items) {
+                Some(params) => {
+                    let params = params.iter().filter_map(|param| match *param {
+                        Value::Symbol(id) => Some((id, None)),
+                        _ => None
+                    }).collect();
+                    self.scopes.push(params);
+                    let result = self.all(&mut list[2..]);
+                    self.scopes.pop();
+                    result
+                }
+                _ => Ok(())
+            },
+            "letrec" | "letrec*" => match list.get(1).and_then(list_items) {
+                Some(bindings) => {
+                    let names = bindings.iter().filter_map(|binding| match binding {
+                        Value::List(parts) => match parts.first() {
+                            Some(&Value::Symbol(id)) => Some((id, None)),
+                            _ => None
+                        },
+                        _ => None
+                    }).collect();
+                    self.scopes.push(names);
+                    let result = self.letrec(list);
+                    self.scopes.pop();
+                    result
+                }
+                _ => Ok(())
+            },
+            // quote, define-record-type and malformed forms, which fail when evaluated
+            _ => Ok(())
+        }
+    }
+
+    fn letrec(&mut self, list: &mut [Value]) -> Result<(), SchemeError> {
+        if let Value::List(bindings) = &mut list[1] {
+            for binding in bindings.iter_mut() {
+                if let Value::List(parts) = binding {
+                    self.all(&mut parts[1..])?;
+                }
+            }
+        }
+        self.all(&mut list[2..])
+    }
+
+    // Expands the unquoted parts of a quasiquote template, `depth` quasiquotes deep.
+    fn quasiquote(&mut self, template: &mut Value, depth: usize) -> Result<(), SchemeError> {
+        match template {
+            Value::List(list) => {
+                let name = match list.first() {
+                    Some(Value::Symbol(id)) if list.len() == 2 => Some(id.name()),
+                    _ => None
+                };
+                let depth = match name.as_deref() {
+                    Some("unquote") | Some("unquote-splicing") => match depth {
+                        1 => return self.form(&mut list[1]),
+                        _ => depth - 1
+                    },
+                    Some("quasiquote") => depth + 1,
+                    _ => depth
+                };
+                list.iter_mut().try_for_each(|part| self.quasiquote(part, depth))
+            }
+            Value::Vector(v) => v.borrow_mut().iter_mut().try_for_each(|part| self.quasiquote(part, depth)),
+            // a dotted template, whose tail may be unquoted as in `(a . ,b)`
+            Value::Pair(p) => {
+                let mut pair = p.clone();
+                loop {
+                    self.quasiquote(&mut pair.car.borrow_mut(), depth)?;
+                    let next = match *pair.cdr.borrow_mut() {
+                        Value::Pair(ref next) => next.clone(),
+                        ref mut tail => return self.quasiquote(tail, depth)
+                    };
+                    pair = next;
+                }
+            }
+            _ => Ok(())
+        }
+    }
+
+    fn derived(&mut self, parts: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let keyword = parts[0].symbol().unwrap_or_default();
+        let mut parts = parts.into_iter().skip(1).collect::<Vec<Form>>();
+        let list = |forms: Vec<Form>| Form::List(forms, span);
+        match &*keyword {
+            "let" => self.derive_let(parts, span),
+            "let*" => {
+                let error = || fail("wrong syntax for let* expression", span);
+                if parts.len() < 2 {
+                    return Err(error());
+                }
+                let body = parts.split_off(1);
+                let mut bindings = parts.pop().unwrap().parts().ok_or_else(error)?;
+                if bindings.len() < 2 {
+                    let mut form = vec![symbol("let"), list(bindings)];
+                    form.extend(body);
+                    return Ok(list(form));
+                }
+                let rest = bindings.split_off(1);
+                let mut inner = vec![symbol("let*"), list(rest)];
+                inner.extend(body);
+                Ok(list(vec![symbol("let"), list(bindings), list(inner)]))
+            }
+            "and" => Ok(match parts.len() {
+                0 => boolean(true),
+                1 => parts.pop().unwrap(),
+                _ => {
+                    let rest = parts.split_off(1);
+                    let mut and = vec![symbol("and")];
+                    and.extend(rest);
+                    list(vec![symbol("if"), parts.pop().unwrap(), list(and), boolean(false)])
+                }
+            }),
+            "or" => Ok(match parts.len() {
+                0 => boolean(false),
+                1 => parts.pop().unwrap(),
+                _ => {
+                    let rest = parts.split_off(1);
+                    let mut or = vec![symbol("or")];
+                    or.extend(rest);
+                    let value = self.fresh("or");
+                    let test = list(vec![symbol("if"), value.clone(), value.clone(), list(or)]);
+                    let lambda = list(vec![symbol("lambda"), list(vec![value]), test]);
+                    list(vec![lambda, parts.pop().unwrap()])
+                }
+            }),
+            "when" | "unless" => {
+                if parts.len() < 2 {
+                    return Err(fail(if &*keyword == "when" { "wrong syntax for when expression" } else { "wrong syntax for unless expression" }, span));
+                }
+                let body = sequence(parts.split_off(1), span);
+                let test = parts.pop().unwrap();
+                let nothing = list(vec![symbol("begin")]);
+                Ok(match &*keyword {
+                    "when" => list(vec![symbol("if"), test, body, nothing]),
+                    _ => list(vec![symbol("if"), test, nothing, body])
+                })
+            }
+            "cond" => self.derive_cond(parts, span),
+            "case" => self.derive_case(parts, span),
+            "do" => self.derive_do(parts, span),
+            _ => unreachable!()
+        }
+    }
+
+    // `(define (name params...) body...)`, which defines a procedure.
+    fn define_procedure(&mut self, parts: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let error = || fail("wrong syntax for define expression", span);
+        let mut parts = parts.into_iter().skip(1).collect::<Vec<Form>>();
+        let body = parts.split_off(1);
+        if body.is_empty() {
+            return Err(error());
+        }
+        let mut params = parts.pop().unwrap().parts().ok_or_else(error)?;
+        if params.is_empty() {
+            return Err(error());
+        }
+        let name = params.remove(0);
+        if name.symbol().is_none() {
+            return Err(error());
+        }
+        let lambda = Form::List(vec![symbol("lambda"), Form::List(params, span), lambda_body(body, span)], span);
+        Ok(Form::List(vec![symbol("define"), name, lambda], span))
+    }
+
+    fn derive_let(&mut self, mut parts: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let error = || fail("wrong syntax for let expression", span);
+        let list = |forms: Vec<Form>| Form::List(forms, span);
+        let named = parts.first().and_then(Form::symbol).is_some();
+        let name = if named { Some(parts.remove(0)) } else { None };
+        if parts.len() < 2 {
+            return Err(error());
+        }
+        let body = parts.split_off(1);
+        let mut names = vec![];
+        let mut inits = vec![];
+        for binding in parts.pop().unwrap().parts().ok_or_else(error)? {
+            match binding.parts() {
+                Some(mut pair) if pair.len() == 2 && pair[0].symbol().is_some() => {
+                    inits.push(pair.pop().unwrap());
+                    names.push(pair.pop().unwrap());
+                }
+                _ => return Err(error())
+            }
+        }
+        let lambda = list(vec![symbol("lambda"), list(names), lambda_body(body, span)]);
+        let procedure = match name {
+            // `(let loop ((n 0)) ...)` calls a procedure bound to `loop` in its body
+            Some(name) => list(vec![symbol("letrec"), list(vec![list(vec![name.clone(), lambda])]), name]),
+            None => lambda
+        };
+        let mut call = vec![procedure];
+        call.extend(inits);
+        Ok(list(call))
+    }
+
+    fn derive_cond(&mut self, clauses: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let error = || fail("wrong syntax for cond expression", span);
+        let list = |forms: Vec<Form>| Form::List(forms, span);
+        let last = clauses.len().saturating_sub(1);
+        let mut form = list(vec![symbol("begin")]);
+        for (i, clause) in clauses.into_iter().enumerate().rev() {
+            let mut clause = clause.parts().filter(|clause| !clause.is_empty()).ok_or_else(error)?;
+            let mut body = clause.split_off(1);
+            let test = clause.pop().unwrap();
+            form = if test.symbol().as_deref() == Some("else") {
+                if i != last || body.is_empty() {
+                    return Err(error());
+                }
+                sequence(body, span)
+            } else if body.first().and_then(Form::symbol).as_deref() == Some("=>") {
+                if body.len() != 2 {
+                    return Err(error());
+                }
+                let value = self.fresh("cond");
+                let receiver = body.pop().unwrap();
+                let test_value = list(vec![symbol("if"), value.clone(), list(vec![receiver, value.clone()]), form]);
+                list(vec![list(vec![symbol("lambda"), list(vec![value]), test_value]), test])
+            } else if body.is_empty() {
+                list(vec![symbol("or"), test, form])
+            } else {
+                list(vec![symbol("if"), test, sequence(body, span), form])
+            };
+        }
+        Ok(form)
+    }
+
+    fn derive_case(&mut self, mut parts: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let error = || fail("wrong syntax for case expression", span);
+        let list = |forms: Vec<Form>| Form::List(forms, span);
+        if parts.is_empty() {
+            return Err(error());
+        }
+        let clauses = parts.split_off(1);
+        let key = self.fresh("case");
+        let mut cond = vec![symbol("cond")];
+        for clause in clauses {
+            let mut clause = clause.parts().filter(|clause| clause.len() >= 2).ok_or_else(error)?;
+            let mut body = clause.split_off(1);
+            let data = clause.pop().unwrap();
+            // `=>` hands the key to a procedure
+            if body.first().and_then(Form::symbol).as_deref() == Some("=>") {
+                if body.len() != 2 {
+                    return Err(error());
+                }
+                body = vec![list(vec![body.pop().unwrap(), key.clone()])];
+            }
+            let test = match data.symbol().as_deref() {
+                Some("else") => data,
+                _ if data.is_list() => list(vec![symbol("memv"), key.clone(), list(vec![symbol("quote"), data])]),
+                _ => return Err(error())
+            };
+            let mut clause = vec![test];
+            clause.extend(body);
+            cond.push(list(clause));
+        }
+        let lambda = list(vec![symbol("lambda"), list(vec![key]), list(cond)]);
+        Ok(list(vec![lambda, parts.pop().unwrap()]))
+    }
+
+    fn derive_do(&mut self, mut parts: Vec<Form>, span: Option<Span>) -> Result<Form, SchemeError> {
+        let error = || fail("wrong syntax for do expression", span);
+        let list = |forms: Vec<Form>| Form::List(forms, span);
+        if parts.len() < 2 {
+            return Err(error());
+        }
+        let commands = parts.split_off(2);
+        let mut exit = parts.pop().unwrap().parts().filter(|exit| !exit.is_empty()).ok_or_else(error)?;
+        let results = exit.split_off(1);
+        let test = exit.pop().unwrap();
+        let (mut names, mut inits, mut steps) = (vec![], vec![], vec![]);
+        for binding in parts.pop().unwrap().parts().ok_or_else(error)? {
+            match binding.parts() {
+                Some(mut binding) if (binding.len() == 2 || binding.len() == 3) && binding[0].symbol().is_some() => {
+                    let step = if binding.len() == 3 { binding.pop().unwrap() } else { binding[0].clone() };
+                    steps.push(step);
+                    inits.push(binding.pop().unwrap());
+                    names.push(binding.pop().unwrap());
+                }
+                _ => return Err(error())
+            }
+        }
+        let name = self.fresh("do");
+        let mut next = vec![name.clone()];
+        next.extend(steps);
+        let mut body = commands;
+        body.push(list(next));
+        let test = list(vec![symbol("if"), test, sequence(results, span), sequence(body, span)]);
+        let lambda = list(vec![symbol("lambda"), list(names), test]);
+        let procedure = list(vec![symbol("letrec"), list(vec![list(vec![name.clone(), lambda])]), name]);
+        let mut call = vec![procedure];
+        call.extend(inits);
+        Ok(list(call))
+    }
+}
+
+// The forms evaluated one after the other, with the value of the last one.
+fn sequence(mut forms: Vec<Form>, span: Option<Span>) -> Form {
+    match forms.len() {
+        1 => forms.pop().unwrap(),
+        _ => {
+            forms.insert(0, symbol("begin"));
+            Form::List(forms, span)
+        }
+    }
+}
+
+// The body of a lambda evaluating `forms`, which has to be a single list.
+fn lambda_body(forms: Vec<Form>, span: Option<Span>) -> Form {
+    match forms.len() {
+        1 if forms[0].is_list() => sequence(forms, span),
+        _ => {
+            let mut body = vec![symbol("begin")];
+            body.extend(forms);
+            Form::List(body, span)
+        }
+    }
+}
+
+// What a pattern variable matched.
+#[derive(Clone)]
+enum Binding<'a> {
+    One(&'a Value),
+    // the parts of a list after those matched by the patterns before a dot
+    Rest(&'a [Value]),
+    // what each of the parts matched by a pattern followed by an ellipsis matched
+    Many(Vec<Binding<'a>>),
+    // what was matched in a vector, copied out of it as the vector is only borrowed
+    // while it is matched
+    Copied(Form)
+}
+
+type Bindings<'a> = HashMap<SymbolId, Binding<'a>>;
+
+impl<'a> Binding<'a> {
+    fn copied<'b>(self) -> Binding<'b> {
+        match self {
+            Binding::One(form) => Binding::Copied(copy(form)),
+            Binding::Rest(forms) => Binding::Copied(Form::List(forms.iter().map(copy).collect(), None)),
+            Binding::Many(each) => Binding::Many(each.into_iter().map(Binding::copied).collect()),
+            Binding::Copied(form) => Binding::Copied(form)
+        }
+    }
+}
+
+impl Macro {
+    // A macro for `(syntax-rules (literals...) (pattern template)...)`, with an
+    // ellipsis of its own before the literals if it has one.
+    fn new(spec: &[Value]) -> Option<Macro> {
+        match spec.first() {
+            Some(Value::Symbol(id)) if &*id.name() == "syntax-rules" => {}
+            _ => return None
+        }
+        let (ellipsis, spec) = match spec.get(1) {
+            Some(&Value::Symbol(ellipsis)) => (ellipsis, &spec[2..]),
+            _ => (SymbolId::intern("..."), &spec[1..])
+        };
+        let literals = spec.first().and_then(list_items)?.iter().map(|literal| match *literal {
+            Value::Symbol(id) => Some(id),
+            _ => None
+        }).collect::<Option<Vec<SymbolId>>>()?;
+        // rules on a cycle would be matched and instantiated without end
+        let rules = spec[1..].iter().map(|rule| match rule {
+            Value::List(parts) if parts.len() == 2 && matches!(parts[0], Value::List(_) | Value::Pair(_)) && !Labels::new(rule).has_cycles() =>
+                Some((parts[0].clone(), parts[1].clone())),
+            _ => None
+        }).collect::<Option<Vec<(Value, Value)>>>()?;
+        Some(Macro { literals, ellipsis, rules })
+    }
+
+    fn is_ellipsis(&self, value: &Value) -> bool {
+        matches!(*value, Value::Symbol(id) if id == self.ellipsis)
+    }
+
+    // The form given by the template of the first rule whose pattern matches `form`.
+    // `fresh` counts the names made up so far.
+    fn transcribe(&self, form: &Value, fresh: &mut usize) -> Result<Form, &'static str> {
+        let parts = match form {
+            Value::List(list) => &list[1..],
+            _ => unreachable!()
+        };
+        for (pattern, template) in &self.rules {
+            let (patterns, rest) = elements(pattern).unwrap();
+            let mut bindings = HashMap::new();
+            if self.bind_all(&patterns[1..], rest.as_ref(), parts, &mut bindings) {
+                // the variables the template binds are pattern variables matching a
+                // name of their own
+                let mut introduced = vec![];
+                self.introduced(template, &mut introduced);
+                for id in introduced {
+                    if let Entry::Vacant(entry) = bindings.entry(id) {
+                        *fresh += 1;
+                        entry.insert(Binding::Copied(symbol(&format!("%{}{}", id.name(), fresh))));
+                    }
+                }
+                return self.instantiate(template, &bindings, false);
+            }
+        }
+        Err("no syntax rule matches the form")
+    }
+
+    // Adds the variables `template` binds to `names`, pattern variables included.
+    fn introduced(&self, template: &Value, names: &mut Vec<SymbolId>) {
+        let (parts, rest) = match template {
+            Value::List(_) | Value::Pair(_) => elements(template).unwrap(),
+            Value::Vector(parts) => return parts.borrow().iter().for_each(|part| self.introduced(part, names)),
+            _ => return
+        };
+        let mut bound = |value: &Value| if let Value::Symbol(id) = *value {
+            if &*id.name() != "_" && !self.is_ellipsis(value) && !names.contains(&id) {
+                names.push(id);
+            }
+        };
+        match (parts.first(), parts.get(1)) {
+            (Some(Value::Symbol(id)), Some(params)) if &*id.name() == "lambda" => match elements(params) {
+                Some((params, rest)) => params.iter().chain(&rest).for_each(&mut bound),
+                None => bound(params)
+            },
+            (Some(Value::Symbol(id)), Some(second)) if ["let", "let*", "letrec", "letrec*", "do"].contains(&&*id.name()) => {
+                // a named let binds its name, and then takes the bindings
+                let bindings = match second {
+                    Value::Symbol(_) if &*id.name() == "let" => {
+                        bound(second);
+                        parts.get(2)
+                    }
+                    _ => Some(second)
+                };
+                if let Some((bindings, _)) = bindings.and_then(elements) {
+                    bindings.iter().filter_map(|binding| elements(binding)?.0.first().cloned()).for_each(|name| bound(&name));
+                }
+            }
+            _ => {}
+        }
+        parts.iter().chain(&rest).for_each(|part| self.introduced(part, names));
+    }
+
+    // The pattern variables of `pattern`, or the names of `template`.
+    fn variables(&self, pattern: &Value) -> Vec<SymbolId> {
+        match *pattern {
+            Value::Symbol(id) if &*id.name() == "_" || self.is_ellipsis(pattern) || self.literals.contains(&id) => vec![],
+            Value::Symbol(id) => vec![id],
+            Value::List(_) | Value::Pair(_) => match elements(pattern) {
+                Some((parts, rest)) => parts.iter().chain(&rest).flat_map(|part| self.variables(part)).collect(),
+                None => vec![]
+            },
+            Value::Vector(ref parts) => parts.borrow().iter().flat_map(|part| self.variables(part)).collect(),
+            _ => vec![]
+        }
+    }
+
+    fn bind<'a>(&self, pattern: &Value, form: &'a Value, bindings: &mut Bindings<'a>) -> bool {
+        match (pattern, form) {
+            (Value::Symbol(id), _) if &*id.name() == "_" => true,
+            (Value::Symbol(id), _) if self.literals.contains(id) => pattern == form,
+            (&Value::Symbol(id), _) => {
+                bindings.insert(id, Binding::One(form));
+                true
+            }
+            (Value::List(_) | Value::Pair(_), _) => match (elements(pattern), list_items(form)) {
+                (Some((patterns, rest)), Some(forms)) => self.bind_all(&patterns, rest.as_ref(), forms, bindings),
+                _ => false
+            },
+            (Value::Vector(patterns), Value::Vector(forms)) => {
+                let forms = forms.borrow();
+                let mut matched = HashMap::new();
+                if !self.bind_all(&patterns.borrow(), None, &forms, &mut matched) {
+                    return false;
+                }
+                bindings.extend(matched.into_iter().map(|(name, binding)| (name, binding.copied())));
+                true
+            }
+            _ => pattern == form
+        }
+    }
+
+    // Matches `forms` against `patterns`, followed by the pattern `rest` for the
+    // parts left in a dotted pattern.
+    fn bind_all<'a>(&self, patterns: &[Value], rest: Option<&Value>, forms: &'a [Value], bindings: &mut Bindings<'a>) -> bool {
+        if let Some(rest) = rest {
+            // an ellipsis takes every part there is, and leaves none to the rest
+            let end = if patterns.iter().any(|pattern| self.is_ellipsis(pattern)) { forms.len() } else { patterns.len() };
+            if forms.len() < end || !self.bind_all(patterns, None, &forms[..end], bindings) {
+                return false;
+            }
+            return match *rest {
+                Value::Symbol(id) if &*id.name() != "_" => {
+                    bindings.insert(id, Binding::Rest(&forms[end..]));
+                    true
+                }
+                _ => true
+            };
+        }
+        let n = patterns.len();
+        let e = match patterns.iter().position(|pattern| self.is_ellipsis(pattern)) {
+            Some(0) => return false,
+            Some(e) => e,
+            None => return n == forms.len() && patterns.iter().zip(forms).all(|(pattern, form)| self.bind(pattern, form, bindings))
+        };
+        let (before, repeated, after) = (&patterns[..e - 1], &patterns[e - 1], &patterns[e + 1..]);
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let end = forms.len() - after.len();
+        if !before.iter().zip(forms).all(|(pattern, form)| self.bind(pattern, form, bindings))
+            || !after.iter().zip(&forms[end..]).all(|(pattern, form)| self.bind(pattern, form, bindings)) {
+            return false;
+        }
+        let mut matches = vec![];
+        for form in &forms[before.len()..end] {
+            let mut matched = HashMap::new();
+            if !self.bind(repeated, form, &mut matched) {
+                return false;
+            }
+            matches.push(matched);
+        }
+        for name in self.variables(repeated) {
+            let each = matches.iter_mut().map(|matched| matched.remove(&name).unwrap()).collect();
+            bindings.insert(name, Binding::Many(each));
+        }
+        true
+    }
+
+    // `template` with the pattern variables replaced by what they matched. In an
+    // escaped template, `(... template)`, ellipses are names like the others.
+    fn instantiate(&self, template: &Value, bindings: &Bindings, escaped: bool) -> Result<Form, &'static str> {
+        match template {
+            Value::Symbol(id) => match bindings.get(id) {
+                Some(Binding::One(form)) => Ok(copy(form)),
+                Some(Binding::Rest(forms)) => Ok(Form::List(forms.iter().map(copy).collect(), None)),
+                Some(Binding::Copied(form)) => Ok(form.clone()),
+                Some(Binding::Many(_)) => Err("pattern variable used without an ellipsis"),
+                None => Ok(Form::Node(template.clone(), None))
+            },
+            Value::List(list) if !escaped && list.len() == 2 && self.is_ellipsis(&list[0]) => self.instantiate(&list[1], bindings, true),
+            Value::List(list) => Ok(Form::List(self.instantiate_all(list, bindings, escaped)?, None)),
+            Value::Pair(_) => {
+                let (templates, tail) = elements(template).unwrap();
+                let mut forms = self.instantiate_all(&templates, bindings, escaped)?;
+                let tail = match tail {
+                    Some(tail) => self.instantiate(&tail, bindings, escaped)?,
+                    None => return Ok(Form::List(forms, None))
+                };
+                // a tail that is a list, as the rest of a dotted pattern is, makes a list
+                if tail.is_list() {
+                    forms.extend(tail.parts().unwrap());
+                    return Ok(Form::List(forms, None));
+                }
+                let pairs = forms.into_iter().rev().fold(tail.into_value(), |tail, form| Value::cons(form.into_value(), tail));
+                Ok(Form::Node(pairs, None))
+            }
+            Value::Vector(list) => {
+                let forms = self.instantiate_all(&list.borrow(), bindings, escaped)?;
+                let vector = forms.into_iter().map(Form::into_value).collect();
+                Ok(Form::Node(Value::Vector(Rc::new(RefCell::new(vector))), None))
+            }
+            _ => Ok(Form::Node(template.clone(), None))
+        }
+    }
+
+    fn instantiate_all(&self, templates: &[Value], bindings: &Bindings, escaped: bool) -> Result<Vec<Form>, &'static str> {
+        let mut forms = vec![];
+        let mut i = 0;
+        while i < templates.len() {
+            let depth = match escaped {
+                true => 0,
+                false => templates[i + 1..].iter().take_while(|template| self.is_ellipsis(template)).count()
+            };
+            match depth {
+                0 => forms.push(self.instantiate(&templates[i], bindings, escaped)?),
+                _ => self.repeat(&templates[i], bindings, depth, &mut forms)?
+            }
+            i += 1 + depth;
+        }
+        Ok(forms)
+    }
+
+    // Instantiates `template`, followed by `depth` ellipses, once for each of the
+    // parts its pattern variables matched.
+    fn repeat(&self, template: &Value, bindings: &Bindings, depth: usize, forms: &mut Vec<Form>) -> Result<(), &'static str> {
+        let repeated = self.variables(template).into_iter().filter_map(|name| match bindings.get(&name) {
+            Some(Binding::Many(each)) => Some((name, each)),
+            _ => None
+        }).collect::<Vec<(SymbolId, &Vec<Binding>)>>();
+        let count = match repeated.first() {
+            Some((_, each)) => each.len(),
+            None => return Err("ellipsis follows a template without pattern variables")
+        };
+        if repeated.iter().any(|(_, each)| each.len() != count) {
+            return Err("pattern variables matched different numbers of forms");
+        }
+        for i in 0..count {
+            let mut bindings = bindings.clone();
+            for (name, each) in &repeated {
+                bindings.insert(*name, each[i].clone());
+            }
+            match depth {
+                1 => forms.push(self.instantiate(template, &bindings, false)?),
+                _ => self.repeat(template, &bindings, depth - 1, forms)?
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::any::Any;
+use std::fmt;
+use std::rc::Rc;
+
+/// Rust data of the host program in a value, like a database handle or a game
+/// entity. Scripts can only pass it around; the native functions that know its type
+/// get it back with `downcast_ref` or `downcast`. Copies share the data, and are
+/// `eq?` to each other only.
+#[derive(Clone)]
+pub struct Foreign(Rc<dyn Any>);
+
+impl Foreign {
+    pub fn new<T: Any>(data: T) -> Foreign {
+        Foreign(Rc::new(data))
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.0.is::<T>()
+    }
+
+    /// The data, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+
+    /// The data, shared with this value, if it is a `T`.
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        self.0.clone().downcast().ok()
+    }
+
+    pub fn ptr_eq(&self, other: &Foreign) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub(crate) fn identity(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const u8 as usize
+    }
+}
+
+impl From<Rc<dyn Any>> for Foreign {
+    fn from(data: Rc<dyn Any>) -> Foreign {
+        Foreign(data)
+    }
+}
+
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Foreign) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Foreign").field(&(self.identity() as *const u8)).finish()
+    }
+}
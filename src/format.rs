@@ -0,0 +1,128 @@
+use std::rc::Rc;
+
+use super::{context, datatype2str, unparse, Label, Labels, SchemeError, Value};
+
+/// Host hook that renders a number (`Number` or `BigInt`) for `display` and `format`.
+/// Returning `None` falls back to the default rendering.
+pub type NumberFormatter = dyn Fn(&Value) -> Option<String>;
+
+/// Installs the number formatting hook of the current context, or removes it with
+/// `None`.
+pub fn set_number_formatter(formatter: Option<Rc<NumberFormatter>>) {
+    context::with(|c| *c.number_formatter.borrow_mut() = formatter);
+}
+
+/// Decimal and digit grouping separators of a locale, e.g. `1.234.567,5` for German.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberLocale {
+    pub decimal_separator: char,
+    pub grouping_separator: Option<char>
+}
+
+impl NumberLocale {
+    pub fn format(&self, value: &Value) -> Option<String> {
+        let digits = match value {
+            Value::Number(f) if f.is_finite() => f.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::BigInt(b) => b.to_string(),
+            _ => return None
+        };
+        let (sign, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", digits.as_str())
+        };
+        let (integer, fraction) = match unsigned.find('.') {
+            Some(i) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+            None => (unsigned, None)
+        };
+
+        let mut result = sign.to_string();
+        for (i, c) in integer.chars().enumerate() {
+            if let Some(separator) = self.grouping_separator {
+                if i > 0 && (integer.len() - i) % 3 == 0 {
+                    result.push(separator);
+                }
+            }
+            result.push(c);
+        }
+        if let Some(fraction) = fraction {
+            result.push(self.decimal_separator);
+            result.push_str(fraction);
+        }
+        Some(result)
+    }
+
+    pub fn formatter(self) -> Rc<NumberFormatter> {
+        Rc::new(move |value: &Value| self.format(value))
+    }
+}
+
+// Human-readable rendering used by `display` and the `~a` directive: strings and
+// characters are written raw and numbers go through the installed hook.
+pub(crate) fn display_string(value: &Value) -> String {
+    display_datum(value, &mut Labels::new(value))
+}
+
+fn display_datum(value: &Value, labels: &mut Labels) -> String {
+    let label = match labels.label(value) {
+        Label::Reference(n) => return format!("#{}#", n),
+        Label::Define(n) => format!("#{}=", n),
+        Label::None => String::new()
+    };
+    match value {
+        Value::Number(_) | Value::Integer(_) | Value::BigInt(_) | Value::Rational(_) => {
+            let formatted = context::with(|c| c.number_formatter.borrow().clone()).and_then(|hook| hook(value));
+            formatted.unwrap_or_else(|| datatype2str(value))
+        }
+        Value::String(s) => s.to_string(),
+        Value::Char(c) => c.to_string(),
+        Value::Symbol(s) => s.to_string(),
+        Value::Pair(_) | Value::List(_) | Value::Nil => {
+            let mut elements = vec![];
+            let mut tail = value.clone();
+            loop {
+                match tail {
+                    // a pair with a label other than the first is displayed as a dotted tail
+                    Value::Pair(ref p) if elements.is_empty() || !labels.is_cyclic(&tail) => {
+                        elements.push(display_datum(&p.car(), labels));
+                        tail = p.cdr();
+                    }
+                    Value::List(ref l) => {
+                        elements.extend(l.iter().map(|x| display_datum(x, labels)));
+                        return format!("{}({})", label, elements.join(" "));
+                    }
+                    Value::Nil => return format!("{}({})", label, elements.join(" ")),
+                    _ => return format!("{}({} . {})", label, elements.join(" "), display_datum(&tail, labels))
+                }
+            }
+        }
+        Value::Vector(v) => format!("{}#({})", label, v.borrow().iter().map(|x| display_datum(x, labels)).collect::<Vec<String>>().join(" ")),
+        _ => datatype2str(value)
+    }
+}
+
+// SRFI 28 style templates: `~a` displays the next argument, `~s` writes it like
+// `write` does, `~%` is a newline and `~~` a literal tilde.
+pub(crate) fn format_template(template: &str, arguments: &[Value]) -> Result<String, SchemeError> {
+    let mut result = String::new();
+    let mut remaining = arguments.iter();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => result.push_str(&display_string(remaining.next().ok_or(SchemeError::arity("format function has too few arguments"))?)),
+            Some('s') => result.push_str(&unparse(remaining.next().ok_or(SchemeError::arity("format function has too few arguments"))?)),
+            Some('%') => result.push('\n'),
+            Some('~') => result.push('~'),
+            _ => return Err(SchemeError::other("unknown format directive"))
+        }
+    }
+    if remaining.next().is_some() {
+        return Err(SchemeError::arity("format function has too many arguments"));
+    }
+    Ok(result)
+}
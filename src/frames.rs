@@ -0,0 +1,38 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{context, Env, SymbolId, Value};
+
+// Most frames are dropped as soon as the procedure returns, so their tables are kept
+// in the context evaluating for the next call instead of being freed and allocated
+// again. This is all the evaluator does towards allocating its temporaries apart:
+// values are reference counted one by one and may escape through any of them, so
+// there is no nursery or arena they could be freed from together. Reusing tables
+// saves an allocation a call, about a tenth of those a small procedure call makes.
+const MAX_FREE_FRAMES: usize = 64;
+
+// The tables of the frames a context has released, dropped with it.
+#[derive(Default)]
+pub(crate) struct FreeFrames(RefCell<Vec<HashMap<SymbolId, Value>>>);
+
+/// An empty binding table, reusing the one of a frame released earlier if possible.
+pub(crate) fn take() -> HashMap<SymbolId, Value> {
+    context::with(|c| c.free_frames.0.borrow_mut().pop()).unwrap_or_default()
+}
+
+/// Takes back the table of a procedure frame once the call has returned. A frame
+/// that escaped, for example into a closure created by the call or into the error
+/// environment, is still referenced elsewhere and is left alone.
+pub(crate) fn release(env: Rc<RefCell<Env>>) {
+    if let Ok(env) = Rc::try_unwrap(env) {
+        let mut local = env.into_inner().local.into_inner();
+        local.clear();
+        context::with(|c| {
+            let mut free = c.free_frames.0.borrow_mut();
+            if free.len() < MAX_FREE_FRAMES {
+                free.push(local);
+            }
+        });
+    }
+}
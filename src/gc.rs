@@ -0,0 +1,371 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use super::{context, Closure, Env, HashTable, Pair, PersistentMap, Record, Value};
+use hash_table::HashKey;
+
+/// What the cycle collector of the current context has done.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapStatistics {
+    /// Collections run, by `(gc)` or because enough objects changed since the last one.
+    pub collections: usize,
+    /// Objects freed by every collection so far.
+    pub freed: usize,
+    /// Objects looked at by the last collection.
+    pub traced: usize,
+    /// Objects that changed since they were created and may be on a cycle.
+    pub tracked: usize
+}
+
+// The objects a context tracks and what its collections have done.
+pub(crate) struct Heap {
+    // keyed by address, so that an object changed again is tracked once
+    tracked: RefCell<HashMap<usize, Handle>>,
+    statistics: Cell<HeapStatistics>,
+    changes: Cell<usize>,
+    threshold: Cell<Option<usize>>
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap { tracked: RefCell::default(), statistics: Cell::default(), changes: Cell::new(0), threshold: Cell::new(Some(10_000)) }
+    }
+}
+
+impl Heap {
+    pub fn inherit(&self, heap: &Heap) {
+        self.threshold.set(heap.threshold.get());
+    }
+}
+
+/// Changes of objects after which a collection runs by itself, if that many are more
+/// than the objects the last collection looked at; None when collections only run
+/// on `(gc)`.
+pub fn gc_threshold() -> Option<usize> {
+    context::with(|c| c.heap.threshold.get())
+}
+
+pub fn set_gc_threshold(threshold: Option<usize>) {
+    context::with(|c| c.heap.threshold.set(threshold));
+}
+
+pub fn heap_statistics() -> HeapStatistics {
+    context::with(|c| {
+        let mut stats = c.heap.statistics.get();
+        stats.tracked = c.heap.tracked.borrow().values().filter(|handle| handle.is_alive()).count();
+        stats
+    })
+}
+
+// An object that can refer to others, and so be on a cycle of references.
+#[derive(Clone)]
+enum Node {
+    Env(Rc<RefCell<Env>>),
+    Pair(Rc<Pair>),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    // the elements shared by copies of a list
+    List(Rc<Vec<Value>>),
+    Closure(Rc<Closure>),
+    Record(Rc<Record>),
+    HashTable(HashTable)
+}
+
+// A tracked object, which tracking does not keep alive.
+enum Handle {
+    Env(Weak<RefCell<Env>>),
+    Pair(Weak<Pair>),
+    Vector(Weak<RefCell<Vec<Value>>>),
+    Record(Weak<Record>),
+    HashTable(Weak<RefCell<HashMap<HashKey, (Value, Value)>>>)
+}
+
+impl Handle {
+    fn is_alive(&self) -> bool {
+        match self {
+            Handle::Env(w) => w.strong_count() > 0,
+            Handle::Pair(w) => w.strong_count() > 0,
+            Handle::Vector(w) => w.strong_count() > 0,
+            Handle::Record(w) => w.strong_count() > 0,
+            Handle::HashTable(w) => w.strong_count() > 0
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Handle::Env(w) => w.upgrade().map(Node::Env),
+            Handle::Pair(w) => w.upgrade().map(Node::Pair),
+            Handle::Vector(w) => w.upgrade().map(Node::Vector),
+            Handle::Record(w) => w.upgrade().map(Node::Record),
+            Handle::HashTable(w) => w.upgrade().map(Node::HashTable)
+        }
+    }
+}
+
+impl Node {
+    fn of(value: &Value) -> Option<Node> {
+        match value {
+            Value::Pair(p) => Some(Node::Pair(p.clone())),
+            Value::Vector(v) => Some(Node::Vector(v.clone())),
+            Value::List(l) => Some(Node::List(l.storage().clone())),
+            Value::Lambda(p) => Some(Node::Closure(p.closure.clone())),
+            Value::Record(r) => Some(Node::Record(r.clone())),
+            Value::HashTable(t) => Some(Node::HashTable(t.clone())),
+            _ => None
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Node::Env(e) => Rc::as_ptr(e) as *const u8 as usize,
+            Node::Pair(p) => Rc::as_ptr(p) as *const u8 as usize,
+            Node::Vector(v) => Rc::as_ptr(v) as *const u8 as usize,
+            Node::List(l) => Rc::as_ptr(l) as *const u8 as usize,
+            Node::Closure(c) => Rc::as_ptr(c) as *const u8 as usize,
+            Node::Record(r) => Rc::as_ptr(r) as *const u8 as usize,
+            Node::HashTable(t) => Rc::as_ptr(t) as *const u8 as usize
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(e) => Rc::strong_count(e),
+            Node::Pair(p) => Rc::strong_count(p),
+            Node::Vector(v) => Rc::strong_count(v),
+            Node::List(l) => Rc::strong_count(l),
+            Node::Closure(c) => Rc::strong_count(c),
+            Node::Record(r) => Rc::strong_count(r),
+            Node::HashTable(t) => Rc::strong_count(t)
+        }
+    }
+
+    fn handle(&self) -> Option<Handle> {
+        match self {
+            Node::Env(e) => Some(Handle::Env(Rc::downgrade(e))),
+            Node::Pair(p) => Some(Handle::Pair(Rc::downgrade(p))),
+            Node::Vector(v) => Some(Handle::Vector(Rc::downgrade(v))),
+            Node::Record(r) => Some(Handle::Record(Rc::downgrade(r))),
+            Node::HashTable(t) => Some(Handle::HashTable(Rc::downgrade(t))),
+            Node::List(_) | Node::Closure(_) => None
+        }
+    }
+
+    // Adds the objects this one refers to to `children`, once for each reference.
+    // Gives false when the object is borrowed, and what it refers to cannot be seen.
+    fn children(&self, children: &mut Vec<Node>) -> bool {
+        match self {
+            Node::Env(e) => match e.try_borrow() {
+                Ok(env) => match (env.local.try_borrow(), env.slots.try_borrow()) {
+                    (Ok(local), Ok(slots)) => {
+                        local.values().chain(slots.iter().flatten()).for_each(|value| refers_to(value, children));
+                        if let Some(ref table) = env.persistent {
+                            table.iter().for_each(|(_, value)| refers_to(value, children));
+                        }
+                        children.extend(env.parent.as_deref().cloned().map(Node::Env));
+                        true
+                    }
+                    _ => false
+                },
+                Err(_) => false
+            },
+            Node::Pair(p) => match (p.car.try_borrow(), p.cdr.try_borrow()) {
+                (Ok(car), Ok(cdr)) => {
+                    refers_to(&car, children);
+                    refers_to(&cdr, children);
+                    true
+                }
+                _ => false
+            },
+            Node::Vector(v) => match v.try_borrow() {
+                Ok(items) => {
+                    items.iter().for_each(|value| refers_to(value, children));
+                    true
+                }
+                Err(_) => false
+            },
+            Node::List(l) => {
+                l.iter().for_each(|value| refers_to(value, children));
+                true
+            }
+            Node::Closure(c) => {
+                children.push(Node::Env(c.env.clone()));
+                true
+            }
+            Node::Record(r) => match r.fields.try_borrow() {
+                Ok(fields) => {
+                    fields.iter().for_each(|value| refers_to(value, children));
+                    true
+                }
+                Err(_) => false
+            },
+            Node::HashTable(t) => match t.try_borrow() {
+                Ok(entries) => {
+                    for (key, value) in entries.values() {
+                        refers_to(key, children);
+                        refers_to(value, children);
+                    }
+                    true
+                }
+                Err(_) => false
+            }
+        }
+    }
+
+    // Drops what the object refers to, which breaks the cycles it is on.
+    fn clear(&self) {
+        match self {
+            Node::Env(e) => if let Ok(mut env) = e.try_borrow_mut() {
+                env.parent = None;
+                env.local.get_mut().clear();
+                env.slots.get_mut().iter_mut().for_each(|slot| *slot = None);
+                if let Some(ref mut table) = env.persistent {
+                    *table = PersistentMap::new();
+                }
+                env.macros.get_mut().clear();
+            },
+            Node::Pair(p) => {
+                if let Ok(mut car) = p.car.try_borrow_mut() {
+                    *car = Value::Nil;
+                }
+                if let Ok(mut cdr) = p.cdr.try_borrow_mut() {
+                    *cdr = Value::Nil;
+                }
+            }
+            Node::Vector(v) => if let Ok(mut items) = v.try_borrow_mut() {
+                items.clear();
+            },
+            // a list cannot be changed, and is on a cycle through a pair, vector or
+            // frame, which is cleared; a closure is on one through its frame
+            Node::List(_) | Node::Closure(_) => {}
+            Node::Record(r) => if let Ok(mut fields) = r.fields.try_borrow_mut() {
+                fields.iter_mut().for_each(|field| *field = Value::Unspecified);
+            },
+            Node::HashTable(t) => if let Ok(mut entries) = t.try_borrow_mut() {
+                entries.clear();
+            }
+        }
+    }
+}
+
+// Adds the object `value` is, if any, to `children`.
+fn refers_to(value: &Value, children: &mut Vec<Node>) {
+    children.extend(Node::of(value));
+}
+
+// Whether storing `value` in an object can make a cycle.
+fn can_refer(value: &Value) -> bool {
+    match value {
+        Value::List(items) => items.iter().any(can_refer),
+        Value::Pair(_) | Value::Vector(_) | Value::Lambda(_) | Value::Record(_) | Value::HashTable(_) => true,
+        _ => false
+    }
+}
+
+fn track_node(node: Node) {
+    let due = context::with(|c| {
+        let heap = &c.heap;
+        if let Some(handle) = node.handle() {
+            heap.tracked.borrow_mut().insert(node.address(), handle);
+        }
+        heap.changes.set(heap.changes.get() + 1);
+        let due = heap.threshold.get().map(|threshold| threshold.max(heap.statistics.get().traced));
+        due.is_some_and(|due| heap.changes.get() >= due)
+    });
+    drop(node);
+    if due {
+        collect_garbage();
+    }
+}
+
+/// Notes that `value` was stored in `container`, a pair, vector, record or hash
+/// table, which may have closed a cycle through it.
+pub(crate) fn track(container: &Value, value: &Value) {
+    if can_refer(value) {
+        if let Some(node) = Node::of(container) {
+            track_node(node);
+        }
+    }
+}
+
+/// Notes that `value` was bound in the frame `env`, as `define` does.
+pub(crate) fn track_env(env: &Rc<RefCell<Env>>, value: &Value) {
+    if can_refer(value) {
+        track_node(Node::Env(env.clone()));
+    }
+}
+
+// What a collection knows of an object it came across.
+struct Vertex {
+    node: Node,
+    // references to the object from the other objects the collection came across
+    internal: usize,
+    // whether what the object refers to could be seen
+    opaque: bool,
+    reachable: bool
+}
+
+/// Frees the objects that are only referred to from cycles of references between
+/// them, and gives how many there were. Starting from the tracked objects, every
+/// object they lead to is counted the references the others hold to it: objects
+/// with more references than that are held from elsewhere, and what they lead to
+/// is still in use.
+pub fn collect_garbage() -> usize {
+    let tracked = context::with(|c| {
+        c.heap.changes.set(0);
+        let mut tracked = c.heap.tracked.borrow_mut();
+        tracked.retain(|_, handle| handle.is_alive());
+        tracked.values().filter_map(Handle::upgrade).collect::<Vec<Node>>()
+    });
+    let mut vertices = HashMap::new();
+    let mut pending = vec![];
+    for node in tracked {
+        let address = node.address();
+        if let Entry::Vacant(entry) = vertices.entry(address) {
+            entry.insert(Vertex { node, internal: 0, opaque: false, reachable: false });
+            pending.push(address);
+        }
+    }
+    let mut children = vec![];
+    while let Some(address) = pending.pop() {
+        let vertex = vertices.get_mut(&address).unwrap();
+        vertex.opaque = !vertex.node.children(&mut children);
+        for child in children.drain(..) {
+            match vertices.entry(child.address()) {
+                Entry::Occupied(mut entry) => entry.get_mut().internal += 1,
+                Entry::Vacant(entry) => {
+                    pending.push(*entry.key());
+                    entry.insert(Vertex { node: child, internal: 1, opaque: false, reachable: false });
+                }
+            }
+        }
+    }
+    // the collection holds one reference to every object itself
+    let mut pending = vertices.iter()
+        .filter(|(_, vertex)| vertex.opaque || vertex.node.strong_count() > vertex.internal + 1)
+        .map(|(&address, _)| address)
+        .collect::<Vec<usize>>();
+    while let Some(address) = pending.pop() {
+        let vertex = vertices.get_mut(&address).unwrap();
+        if vertex.reachable {
+            continue;
+        }
+        vertex.reachable = true;
+        vertex.node.children(&mut children);
+        pending.extend(children.drain(..).map(|child| child.address()));
+    }
+    let garbage = vertices.values().filter(|vertex| !vertex.reachable).collect::<Vec<&Vertex>>();
+    for vertex in &garbage {
+        vertex.node.clear();
+    }
+    // lists go with the objects holding them, and are not counted
+    let freed = garbage.iter().filter(|vertex| !matches!(vertex.node, Node::List(_))).count();
+    context::with(|c| {
+        let mut stats = c.heap.statistics.get();
+        stats.collections += 1;
+        stats.freed += freed;
+        stats.traced = vertices.len();
+        c.heap.statistics.set(stats);
+    });
+    freed
+}
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use super::{list_elements, SymbolId, Value};
+use labels::Labels;
+
+/// Key equality of hash tables: numbers, characters, symbols, strings and lists
+/// compare by value (like `equal?`), while mutable and procedure values compare
+/// by identity. An exact integer and the float with the same value are different keys.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum HashKey {
+    Bool(bool),
+    Number(u64),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Char(char),
+    Symbol(SymbolId),
+    String(Rc<str>),
+    List(Vec<HashKey>),
+    Pair(Box<HashKey>, Box<HashKey>),
+    Eof,
+    Unspecified,
+    Identity(usize)
+}
+
+/// Entries are stored under their key along with the original key value.
+pub type HashTable = Rc<RefCell<HashMap<HashKey, (Value, Value)>>>;
+
+impl HashKey {
+    pub fn of(value: &Value) -> HashKey {
+        match value {
+            Value::Bool(b) => HashKey::Bool(*b),
+            // -0.0 and 0.0 are the same number, and every NaN is the same key
+            Value::Number(f) if *f == 0.0 => HashKey::Number(0),
+            Value::Number(f) if f.is_nan() => HashKey::Number(f64::NAN.to_bits()),
+            Value::Number(f) => HashKey::Number(f.to_bits()),
+            Value::Integer(i) => HashKey::Integer(*i),
+            Value::BigInt(b) => match b.to_i64() {
+                Some(i) => HashKey::Integer(i),
+                None => HashKey::BigInt((**b).clone())
+            },
+            Value::Rational(r) => HashKey::Rational((**r).clone()),
+            Value::Char(c) => HashKey::Char(*c),
+            Value::Symbol(s) => HashKey::Symbol(*s),
+            Value::String(s) => HashKey::String(s.clone()),
+            // a proper list is the same key whether it is built from pairs or not
+            Value::List(l) => HashKey::List(l.iter().map(HashKey::of).collect()),
+            Value::Nil => HashKey::List(vec![]),
+            Value::Pair(p) => match list_elements(value) {
+                Some(l) => HashKey::List(l.iter().map(HashKey::of).collect()),
+                // circular data would make a key without end
+                None if Labels::new(value).has_cycles() => HashKey::Identity(Rc::as_ptr(p) as usize),
+                None => HashKey::Pair(Box::new(HashKey::of(&p.car())), Box::new(HashKey::of(&p.cdr())))
+            },
+            Value::Vector(v) => HashKey::Identity(Rc::as_ptr(v) as *const u8 as usize),
+            Value::Bytevector(b) => HashKey::Identity(Rc::as_ptr(b) as *const u8 as usize),
+            Value::HashTable(t) => HashKey::Identity(Rc::as_ptr(t) as *const u8 as usize),
+            Value::Record(r) => HashKey::Identity(Rc::as_ptr(r) as *const u8 as usize),
+            Value::Port(p) => HashKey::Identity(Rc::as_ptr(p) as *const u8 as usize),
+            Value::Foreign(f) => HashKey::Identity(f.identity()),
+            Value::Eof => HashKey::Eof,
+            Value::Unspecified => HashKey::Unspecified,
+            Value::Proc(f) => HashKey::Identity(f.identity()),
+            Value::Lambda(p) => HashKey::Identity(p.identity())
+        }
+    }
+}
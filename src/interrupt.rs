@@ -0,0 +1,128 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{context, SchemeError};
+
+// The deadline is only looked at every so many steps, reading the clock costing
+// more than a step does.
+const CLOCK_INTERVAL: usize = 256;
+
+// The interruptions of a context: the handle stopping its evaluations, and when the
+// one running has to stop by.
+pub(crate) struct Interrupts {
+    handle: InterruptHandle,
+    timeout: Cell<Option<Duration>>,
+    deadline: Cell<Option<Instant>>
+}
+
+impl Default for Interrupts {
+    fn default() -> Interrupts {
+        Interrupts { handle: InterruptHandle::new(), timeout: Cell::new(None), deadline: Cell::new(None) }
+    }
+}
+
+impl Interrupts {
+    pub fn inherit(&self, interrupts: &Interrupts) {
+        self.timeout.set(interrupts.timeout.get());
+    }
+
+    pub fn set_timeout(&self, duration: Option<Duration>) {
+        self.timeout.set(duration);
+    }
+}
+
+/// Stops the evaluations of the interpreter it was taken from, from any thread, like
+/// one handling Ctrl-C or watching over a pool of interpreters. The evaluation
+/// running stops at its next safe point, a form evaluated, a procedure called or one
+/// of the points where the long builtins `set_timeout` lists look for it, with
+/// a `SchemeError::Interrupted` that exception handlers can catch; an interruption
+/// coming when none is running is dropped, every evaluation starting uninterrupted.
+///
+/// ```
+/// use std::thread;
+/// use std::time::Duration;
+/// use scheme_rs::{InterpreterThread, SchemeError};
+///
+/// let interpreter = InterpreterThread::spawn().unwrap();
+/// let handle = interpreter.interrupt_handle();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(50));
+///     handle.interrupt();
+/// });
+/// let result = interpreter.eval::<()>("(define (forever) (forever)) (forever)");
+/// assert!(matches!(result, Err(SchemeError::Interrupted { .. })));
+/// ```
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>
+}
+
+impl InterruptHandle {
+    pub(crate) fn new() -> InterruptHandle {
+        InterruptHandle { interrupted: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The handle interrupting the evaluations of the current context. Each interpreter
+/// has a handle of its own, `Interpreter::interrupt_handle`.
+pub fn interrupt_handle() -> InterruptHandle {
+    context::with(|c| c.interrupts.handle.clone())
+}
+
+pub fn timeout() -> Option<Duration> {
+    context::with(|c| c.interrupts.timeout.get())
+}
+
+/// Interrupts every later top-level evaluation in the current context once it has
+/// run for `duration`. There is no timeout by default.
+///
+/// The clock is only read every 256 steps, and between the multiplications of
+/// `expt`, `factorial` and `binomial` on exact numbers or every few thousand strings
+/// `string-join` joins. Any other builtin runs to the end of its call, so that an
+/// evaluation can go on after its deadline for as long as one call of a builtin
+/// takes.
+pub fn set_timeout(duration: Option<Duration>) {
+    context::with(|c| c.interrupts.timeout.set(duration));
+}
+
+// Starts the clock of a top-level evaluation, and drops the interruptions that came
+// before it.
+pub(crate) fn start() {
+    context::with(|c| {
+        let interrupts = &c.interrupts;
+        interrupts.handle.interrupted.store(false, Ordering::Relaxed);
+        interrupts.deadline.set(interrupts.timeout.get().map(|timeout| Instant::now() + timeout));
+    });
+}
+
+// Fails if the evaluation, `steps` steps into it, was interrupted or has run out of
+// time. An interruption is reported once.
+pub(crate) fn check(steps: usize) -> Result<(), SchemeError> {
+    look(steps.is_multiple_of(CLOCK_INTERVAL))
+}
+
+// `check` reading the clock whatever the step, for builtins that run long without
+// taking any.
+pub(crate) fn poll() -> Result<(), SchemeError> {
+    look(true)
+}
+
+fn look(clock: bool) -> Result<(), SchemeError> {
+    context::with(|c| {
+        let interrupts = &c.interrupts;
+        let interrupted = &interrupts.handle.interrupted;
+        if interrupted.load(Ordering::Relaxed) && interrupted.swap(false, Ordering::Relaxed) {
+            return Err(SchemeError::interrupted("evaluation was interrupted"));
+        }
+        match interrupts.deadline.get() {
+            Some(deadline) if clock && Instant::now() >= deadline => Err(SchemeError::interrupted("evaluation timed out")),
+            _ => Ok(())
+        }
+    })
+}
@@ -0,0 +1,102 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::Value;
+
+/// How a value is printed when it may be part of a cycle.
+pub(crate) enum Label {
+    None,
+    /// First time a value on a cycle is printed, as `#n=` in front of it.
+    Define(usize),
+    /// Later times, as `#n#` in place of it.
+    Reference(usize)
+}
+
+/// The datum labels for printing one value: pairs, vectors and records that can be
+/// reached from themselves get a label, so that printing them ends.
+pub(crate) struct Labels {
+    cyclic: HashSet<usize>,
+    // labels in the order they are printed
+    assigned: HashMap<usize, usize>
+}
+
+fn address(value: &Value) -> Option<usize> {
+    match value {
+        Value::Pair(p) => Some(Rc::as_ptr(p) as usize),
+        Value::Vector(v) => Some(Rc::as_ptr(v) as *const u8 as usize),
+        Value::Record(r) => Some(Rc::as_ptr(r) as usize),
+        _ => None
+    }
+}
+
+enum Visit {
+    Enter(Value),
+    Exit(usize)
+}
+
+impl Labels {
+    pub(crate) fn new(value: &Value) -> Labels {
+        let mut cyclic = HashSet::new();
+        // a value met again while its elements are being visited is on a cycle; the
+        // search keeps its own stack so that long lists do not exhaust the real one
+        let mut active = HashSet::new();
+        let mut done = HashSet::new();
+        let mut stack = vec![Visit::Enter(value.clone())];
+        while let Some(visit) = stack.pop() {
+            let value = match visit {
+                Visit::Enter(value) => value,
+                Visit::Exit(a) => {
+                    active.remove(&a);
+                    done.insert(a);
+                    continue;
+                }
+            };
+            if let Some(a) = address(&value) {
+                if active.contains(&a) {
+                    cyclic.insert(a);
+                    continue;
+                }
+                if done.contains(&a) {
+                    continue;
+                }
+                active.insert(a);
+                stack.push(Visit::Exit(a));
+            }
+            match value {
+                Value::Pair(p) => {
+                    stack.push(Visit::Enter(p.cdr()));
+                    stack.push(Visit::Enter(p.car()));
+                }
+                Value::List(l) => stack.extend(l.into_iter().rev().map(Visit::Enter)),
+                Value::Vector(v) => stack.extend(v.borrow().iter().rev().cloned().map(Visit::Enter)),
+                Value::Record(r) => stack.extend(r.fields.borrow().iter().rev().cloned().map(Visit::Enter)),
+                _ => {}
+            }
+        }
+        Labels { cyclic, assigned: HashMap::new() }
+    }
+
+    /// Whether any value reachable from the one the labels are for is on a cycle.
+    pub(crate) fn has_cycles(&self) -> bool {
+        !self.cyclic.is_empty()
+    }
+
+    pub(crate) fn is_cyclic(&self, value: &Value) -> bool {
+        address(value).is_some_and(|a| self.cyclic.contains(&a))
+    }
+
+    /// The label of `value` at this point of printing.
+    pub(crate) fn label(&mut self, value: &Value) -> Label {
+        match address(value) {
+            Some(a) if self.cyclic.contains(&a) => match self.assigned.get(&a) {
+                Some(&n) => Label::Reference(n),
+                None => {
+                    let n = self.assigned.len();
+                    self.assigned.insert(a, n);
+                    Label::Define(n)
+                }
+            },
+            _ => Label::None
+        }
+    }
+}
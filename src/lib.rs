@@ -1,13 +1,101 @@
 #[macro_use]
 extern crate log;
 extern crate env_logger;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+mod bytecode;
+mod check;
+mod context;
+mod convert;
+mod dump;
+mod error;
+mod expand;
+mod foreign;
+mod format;
+mod frames;
+mod gc;
+mod hash_table;
+mod interrupt;
+mod labels;
+mod list;
+mod machine;
+mod number_theory;
+mod observe;
+mod optimize;
+mod path;
+mod persistent;
+mod port;
+mod prelude;
+mod pretty;
+mod reader;
+mod record;
+mod sandbox;
+#[cfg(feature = "serde")]
+mod serialization;
+mod sort;
+mod source_map;
+mod strings;
+mod symbol;
+mod syntax;
+pub mod testing;
+mod vm;
+mod watch;
+mod worker;
 
 //use std;
-use std::collections::HashMap;
-use std::cell::RefCell;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::mem;
 use std::rc::Rc;
+use std::time::Duration;
 use std::fmt;
 use std::f64;
+use std::cmp::Ordering;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+pub use bytecode::{compile, Bytecode};
+pub use check::{check, error_at, line_column, Diagnostic, Severity};
+pub use convert::{FromScheme, ToScheme};
+pub use dump::{error_dump, Dump};
+pub use error::{Backtrace, SchemeError};
+pub use expand::expand;
+pub use foreign::Foreign;
+pub use format::{set_number_formatter, NumberFormatter, NumberLocale};
+pub use gc::{collect_garbage, gc_threshold, heap_statistics, set_gc_threshold, HeapStatistics};
+pub use hash_table::{HashKey, HashTable};
+pub use interrupt::{interrupt_handle, set_timeout, timeout, InterruptHandle};
+pub use list::List;
+pub use observe::{set_eval_observer, EvalObserver};
+pub use persistent::PersistentMap;
+pub use prelude::define_prelude;
+pub use port::{current_error_port, current_input_port, current_output_port, set_current_error_port, set_current_input_port, set_current_output_port, Port};
+pub use optimize::optimize;
+pub use reader::Reader;
+pub use record::{Record, RecordType};
+pub use sandbox::{BuiltinGroup, Sandbox};
+#[cfg(feature = "serde")]
+pub use serialization::{from_value, to_value};
+pub use symbol::{intern_string, interner_statistics, InternerStatistics, SymbolId};
+pub use syntax::{parse_all, Syntax, SyntaxKind};
+pub use vm::execute;
+pub use watch::{set_watch_handler, unwatch, unwatch_span, watch, watch_span, WatchAccess, WatchEvent, WatchHandler};
+pub use worker::InterpreterThread;
+use context::Context;
+use expand::Macro;
+use labels::{Label, Labels};
+use machine::Code;
+use port::Ports;
 
 #[macro_export]
 macro_rules! tuplet {
@@ -21,10 +109,11 @@ macro_rules! tuplet {
         tuplet!( $v ; $j+1 ; ($($x),*) ; ($($a),*,$v.get($j)) ) };
     { $v:expr ; $j:expr ; () ; ($($a:expr),*) } => {
         {
-            if $v.len() >= $j {
-                let remain = $v.len() - $j;
+            let j: usize = $j;
+            if $v.len() >= j {
+                let remain = $v.len() - j;
                 if remain > 0 {
-                    ($($a),*, Some(&$v[$j..]))
+                    ($($a),*, Some(&$v[j..]))
                 } else {
                     ($($a),*, None)
                 }
@@ -38,1245 +127,5071 @@ macro_rules! tuplet {
 #[macro_export]
 macro_rules! define_comparison {
     ($proc:ident, $name:pat, $func:expr) => {
-        let $proc = DataType::Proc(Function( Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
+        let $proc = Value::Proc(Function::new(Arity::at_least(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
                 debug!("Function - name: {:?} - Args: {:?}", stringify!($name), vec);
-                if vec.len() != 2 {
-                    return Err("function requires 2 arguments only");
+                if vec.len() < 2 {
+                    return Err(SchemeError::arity("function requires at least 2 arguments"));
                 }
-                tuplet!((a,b) = vec);
-
-                if let (Some(&DataType::Number(ref a0)), Some(&DataType::Number(ref b0))) = (a, b) {
-                    let a1: f64 = a0.clone().into();
-                    let b1: f64 = b0.clone().into();
-                    let desc = format!("{} {} {}", a1, stringify!($name), b1);
-                    debug!("Description: {}", desc);
-                    Ok(Some(DataType::Bool($func(a1, b1))))
-                } else {
-                    return Err("wrong argument datatype");
+                if vec.iter().any(|x| number_to_f64(x).is_none()) {
+                    return Err(SchemeError::wrong_type("wrong argument datatype"));
                 }
-
+                debug!("Description: {} {:?}", stringify!($name), vec);
+                Ok(Value::Bool(vec.windows(2).all(|pair| compare_numbers(&pair[0], &pair[1]).is_some_and($func))))
             })));
     };
 }
 
-#[derive(Clone)]
-#[derive(Debug)]
-#[derive(PartialEq)]
-pub enum AST {
-    Integer(i64),
-    Float(f64),
-    Symbol(String),
-    Children(Vec<AST>)
-}
-
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct ReadFromTokenResult {
     pub remain: Vec<String>,
-    pub result: AST
+    pub result: Value
 }
 
+/// A procedure created by `lambda`. Copies of it, such as the one `define` names,
+/// share its closure.
 #[derive(Clone)]
-#[derive(PartialEq)]
 pub struct Procedure {
-    body: AST,
-    params: Vec<DataType>,
-    env: Rc<RefCell<Env>>
+    closure: Rc<Closure>,
+    // the variable the lambda was first bound to with `define`
+    name: Option<SymbolId>
+}
+
+// What evaluating a `lambda` form creates: the parts of the form, shared with the
+// other procedures made from it, and the frame the procedure is called in, which is
+// referred to rather than copied.
+struct Closure {
+    // shared with the frames evaluating it
+    body: Rc<Value>,
+    params: Rc<[Value]>,
+    // the frame the lambda was evaluated in, or one further out when the compiler
+    // found that the body uses none of the variables of the frames in between
+    env: Rc<RefCell<Env>>,
+    // the body compiled for the virtual machine, when the lambda was created by it
+    code: Option<Rc<Bytecode>>
+}
+
+impl Procedure {
+    fn new(params: Rc<[Value]>, body: Rc<Value>, env: Rc<RefCell<Env>>, code: Option<Rc<Bytecode>>) -> Procedure {
+        count_allocation(mem::size_of::<Closure>());
+        Procedure { closure: Rc::new(Closure { body, params, env, code }), name: None }
+    }
+
+    pub fn name(&self) -> Option<SymbolId> {
+        self.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        Arity::exactly(self.closure.params.len())
+    }
+
+    // Procedures are told apart by the closure they were created with.
+    pub(crate) fn identity(&self) -> usize {
+        Rc::as_ptr(&self.closure) as *const u8 as usize
+    }
+}
+
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Procedure) -> bool {
+        Rc::ptr_eq(&self.closure, &other.closure) && self.name == other.name
+    }
 }
 
 impl fmt::Debug for Procedure {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let env_raw = &self.env as *const _;
+        let env_raw = Rc::as_ptr(&self.closure.env);
 
         f.debug_struct("Procedure")
-            .field("body", &self.body)
-            .field("params", &self.params)
+            .field("body", &self.closure.body)
+            .field("params", &self.closure.params)
             .field("env", &env_raw)
+            .field("name", &self.name)
             .finish()
     }
 }
 
-pub struct Function(pub Rc<Fn(Vec<DataType>, Rc<RefCell<Env>>) -> Result<Option<DataType>, &'static str>>);
+pub type NativeFunction = dyn Fn(Vec<Value>, Rc<RefCell<Env>>) -> Result<Value, SchemeError>;
+
+/// Numbers of arguments a procedure accepts; `max` is None when there is no limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>
+}
+
+impl Arity {
+    pub fn exactly(n: usize) -> Arity {
+        Arity { min: n, max: Some(n) }
+    }
+
+    pub fn at_least(n: usize) -> Arity {
+        Arity { min: n, max: None }
+    }
+
+    pub fn between(min: usize, max: usize) -> Arity {
+        Arity { min, max: Some(max) }
+    }
+}
+
+/// A procedure implemented in Rust. Builtins are named after the binding `setup` puts
+/// them in, other functions when they are first bound with `define`.
+pub struct Function {
+    native: Rc<Native>,
+    name: Option<SymbolId>
+}
+
+// What the copies of a function `named` gives share, kept behind one pointer so that
+// a function takes no more room in a value than a pair does.
+struct Native {
+    body: Rc<NativeFunction>,
+    arity: Arity
+}
 
 impl Function {
-    fn call(&self, arguments: Vec<DataType>, env: Rc<RefCell<Env>>) -> Result<Option<DataType>, &'static str> {
-        (self.0)(arguments, env)
+    pub fn new(arity: Arity, body: Rc<NativeFunction>) -> Function {
+        Function { native: Rc::new(Native { body, arity }), name: None }
+    }
+
+    pub fn named(mut self, name: SymbolId) -> Function {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn name(&self) -> Option<SymbolId> {
+        self.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.native.arity
+    }
+
+    pub(crate) fn identity(&self) -> usize {
+        Rc::as_ptr(&self.native.body) as *const u8 as usize
+    }
+
+    fn call(&self, arguments: Vec<Value>, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+        (self.native.body)(arguments, env)
     }
 }
 
 impl Clone for Function {
     fn clone(&self) -> Self {
-        Function(self.0.clone())
+        Function { native: self.native.clone(), name: self.name }
     }
 }
 
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let raw = &self.0 as *const _;
-        f.debug_tuple("Function").field(&raw).finish()
+        f.debug_tuple("Function").field(&self.name).field(&(self.identity() as *const u8)).finish()
     }
 }
 
 impl std::cmp::PartialEq for Function {
     fn eq(&self, other: &Function) -> bool {
-        let self_raw = &self.0 as *const _;
-        let other_raw = &other.0 as *const _;
-        self_raw == other_raw
+        Rc::ptr_eq(&self.native.body, &other.native.body)
     }
 }
 
-trait FloatIterExt {
-    fn float_min(&mut self) -> f64;
-    fn float_max(&mut self) -> f64;
-}
-
-impl<T> FloatIterExt for T where T: Iterator<Item=f64> {
-    fn float_max(&mut self) -> f64 {
-        self.fold(f64::NAN, f64::max)
+// Booleans `#t`/`#true`/`#f`/`#false`, characters such as `#\a`, `#\space` or
+// `#\newline`, and numbers with radix and exactness prefixes such as `#x1F`, `#e1.5`
+// or `#i#b101`.
+fn hash_literal(s: &str) -> Result<Value, &'static str> {
+    if let Some(name) = s.strip_prefix("#\\") {
+        let mut chars = name.chars();
+        return match (name, chars.next(), chars.next()) {
+            ("space", _, _) => Ok(Value::Char(' ')),
+            ("newline", _, _) => Ok(Value::Char('\n')),
+            ("tab", _, _) => Ok(Value::Char('\t')),
+            (_, Some(c), None) => Ok(Value::Char(c)),
+            _ => Err("unknown character name")
+        };
     }
-
-    fn float_min(&mut self) -> f64 {
-        self.fold(f64::NAN, f64::min)
+    match s {
+        "#t" | "#true" => return Ok(Value::Bool(true)),
+        "#f" | "#false" => return Ok(Value::Bool(false)),
+        _ => {}
     }
+    let (radix, exactness, digits) = number_prefixes(s).ok_or("syntax error")?;
+    let exact = || if radix == 10 { parse_exact(digits).map(Value::rational) } else { parse_number(digits, radix) };
+    let value = match exactness {
+        Some('e') => exact(),
+        Some(_) => exact().and_then(|n| number_to_f64(&n)).map(Value::Number),
+        None => parse_number(digits, radix)
+    };
+    value.ok_or("bad number literal")
 }
 
-#[derive(Clone, Debug)]
-#[derive(PartialEq)]
-pub enum DataType {
-    Bool(bool),
-    Pair((Box<DataType>, Box<DataType>)),
-    Number(f64),
-    Symbol(String),
-    String(String),
-    Proc(Function),
-    List(Vec<DataType>),
-    Lambda(Procedure)
-}
-
-#[derive(Debug)]
-#[derive(Clone)]
-#[derive(PartialEq)]
-pub struct Env {
-    pub local: RefCell<HashMap<String, DataType>>,
-    pub parent: Option<Box<Rc<RefCell<Env>>>>
+// Splits the radix prefix `#x`, `#o`, `#b` or `#d` and the exactness prefix `#e` or
+// `#i`, in either order and each at most once, from the digits that follow them.
+fn number_prefixes(s: &str) -> Option<(u32, Option<char>, &str)> {
+    let (mut radix, mut exactness, mut rest) = (None, None, s);
+    while let Some(prefix) = rest.strip_prefix('#') {
+        let mut chars = prefix.chars();
+        match chars.next()?.to_ascii_lowercase() {
+            c @ ('e' | 'i') if exactness.is_none() => exactness = Some(c),
+            'x' if radix.is_none() => radix = Some(16),
+            'o' if radix.is_none() => radix = Some(8),
+            'b' if radix.is_none() => radix = Some(2),
+            'd' if radix.is_none() => radix = Some(10),
+            _ => return None
+        }
+        rest = chars.as_str();
+    }
+    Some((radix.unwrap_or(10), exactness, rest))
 }
 
-impl Env {
-    fn get(&self, key: &String) -> Option<DataType> {
-        match self.local.borrow().get::<str>(key) {
-            Some(&DataType::Bool(b)) => Some(DataType::Bool(b)),
-            Some(&DataType::Pair(ref p)) => Some(DataType::Pair(p.clone())),
-            Some(&DataType::Number(f)) => Some(DataType::Number(f)),
-            Some(&DataType::Symbol(ref ss)) => Some(DataType::Symbol(ss.clone())),
-            Some(&DataType::String(ref ss)) => Some(DataType::String(ss.clone())),
-            Some(&DataType::Proc(ref p)) => Some(DataType::Proc(p.clone())),
-            Some(&DataType::List(ref l)) => Some(DataType::List(l.clone())),
-            Some(&DataType::Lambda(ref p)) => Some(DataType::Lambda(p.clone())),
-            None => {
-                match self.parent {
-                    Some(ref some_parent) => {
-                        let parent_borrow = some_parent.borrow();
-                        parent_borrow.get(key)
-                    }
-                    None => None
-                }
-            }
+// The exact value of a decimal numeral like `-12.5e3`, or of a ratio like `1/3`.
+fn parse_exact(s: &str) -> Option<BigRational> {
+    let is_integer = |digits: &str| {
+        let unsigned = digits.strip_prefix(['+', '-']).unwrap_or(digits);
+        !unsigned.is_empty() && unsigned.chars().all(|c| c.is_ascii_digit())
+    };
+    if let Some((numerator, denominator)) = s.split_once('/') {
+        if !is_integer(numerator) || !denominator.chars().all(|c| c.is_ascii_digit()) {
+            return None;
         }
+        let denominator = denominator.parse::<BigInt>().ok()?;
+        if denominator.is_zero() {
+            return None;
+        }
+        return Some(BigRational::new(numerator.parse().ok()?, denominator));
+    }
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+        None => (s, 0)
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", integer, fraction);
+    if !is_integer(&digits) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
     }
+    let value = BigRational::from_integer(digits.parse().ok()?);
+    let scale = exponent - fraction.len() as i32;
+    let power = BigRational::from_integer(num_traits::pow(BigInt::from(10), scale.unsigned_abs() as usize));
+    Some(if scale < 0 { value / power } else { value * power })
 }
 
-pub fn parse(program: &str) -> Result<ReadFromTokenResult, &'static str> {
-    debug!("program: {}", program);
-    let wrap_program = format!("(begin {})", program);
+// Radix argument of `number->string` and `string->number`, 10 if absent.
+fn radix_argument(value: Option<&Value>) -> Option<u32> {
+    match value.map(integer_argument) {
+        None => Some(10),
+        Some(radix) => radix.and_then(|r| r.to_u32()).filter(|r| [2, 8, 10, 16].contains(r))
+    }
+}
 
-    let tokens = tokenize(&wrap_program);
-    debug!("tokens: {:?}", tokens);
-    let ast = read_from_tokens(tokens.clone());
-    debug!("ast: {:?}", ast);
-    return ast;
-}
-
-fn tokenize(program: &str) -> Vec<String>
-{
-    let iterator = Box::new(program.chars());
-    let count = iterator.clone().count();
-    let vec = iterator.fold(Vec::with_capacity(count), |mut acc, x| {
-        if x == '(' || x == ')' {
-            acc.extend(vec![' ', x, ' '])
-        } else {
-            acc.push(x)
+// The number written as `s` in `radix`, following the reader: exact integers and
+// ratios in any radix, decimals and `#e`/`#i` prefixes in radix 10 only.
+fn parse_number(s: &str, radix: u32) -> Option<Value> {
+    let integer = |digits: &str| {
+        let unsigned = digits.strip_prefix(['+', '-']).unwrap_or(digits);
+        if unsigned.is_empty() || !unsigned.chars().all(|c| c.is_digit(radix)) {
+            return None;
         }
-        acc
-    });
-    let s: String = vec.into_iter().collect();
-    let ss: Vec<String> = s.split_whitespace().map(|x| x.to_string()).collect();
-    ss
+        BigInt::parse_bytes(digits.as_bytes(), radix)
+    };
+    if let Some((numerator, denominator)) = s.split_once('/') {
+        if denominator.starts_with(['+', '-']) {
+            return None;
+        }
+        let denominator = integer(denominator).filter(|d| !d.is_zero())?;
+        return Some(Value::rational(BigRational::new(integer(numerator)?, denominator)));
+    }
+    if let Some(n) = integer(s) {
+        return Some(Value::integer(n));
+    }
+    if radix != 10 {
+        return None;
+    }
+    if s.starts_with('#') {
+        return hash_literal(s).ok().filter(|n| number_to_f64(n).is_some());
+    }
+    float_literal(s).map(Value::Number)
 }
 
-fn read_from_tokens(mut tokens: Vec<String>) -> Result<ReadFromTokenResult, &'static str> {
-    if tokens.len() > 0 {
-        let token = tokens.remove(0);
+// A decimal like `-1.5` or `1e-3`, or one of `+inf.0`, `-inf.0` and `+nan.0`. Words
+// that Rust would read as floats, like `inf` or `NaN`, are left to be symbols.
+fn float_literal(s: &str) -> Option<f64> {
+    match s {
+        "+inf.0" => return Some(f64::INFINITY),
+        "-inf.0" => return Some(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => return Some(f64::NAN),
+        _ => {}
+    }
+    if !s.contains(|c: char| c.is_ascii_digit()) || !s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+        return None;
+    }
+    s.parse::<f64>().ok()
+}
 
-        if token == "(" {
-            let mut vec: Vec<AST> = vec![];
-            let mut tmp_tokens = tokens.clone();
+// The shortest text the reader turns back into `f`: integral floats keep a `.0` so
+// that they are not read as exact integers, and infinities and NaN are written the
+// way the reader reads them.
+fn float_to_string(f: f64) -> String {
+    if f.is_nan() {
+        "+nan.0".to_string()
+    } else if f.is_infinite() {
+        (if f > 0.0 { "+inf.0" } else { "-inf.0" }).to_string()
+    } else {
+        format!("{:?}", f)
+    }
+}
 
-            if tmp_tokens.is_empty() {
-                return Err("syntax error");
-            }
+// `value` written in `radix`; inexact numbers can only be written in radix 10.
+fn number_to_string(value: &Value, radix: u32) -> Option<String> {
+    match value {
+        Value::Integer(i) => Some(BigInt::from(*i).to_str_radix(radix)),
+        Value::BigInt(b) => Some(b.to_str_radix(radix)),
+        Value::Rational(r) => Some(format!("{}/{}", r.numer().to_str_radix(radix), r.denom().to_str_radix(radix))),
+        Value::Number(_) if radix == 10 => Some(datatype2str(value)),
+        _ => None
+    }
+}
 
-            while !tmp_tokens.is_empty() {
-                if tmp_tokens.first().unwrap() == ")" {
-                    break
-                } else {
-                    let start_quote_option = match tmp_tokens.clone().first() {
-                        Some(first_word) => {
-                            if first_word.starts_with('\"') {
-                                debug!("detect a start quote of string");
-                                Some(tmp_tokens.clone())
-                            } else {
-                                None
-                            }
-                        }
-                        None => None
-                    };
-                    if let Some(rest_str) = start_quote_option {
-                        debug!("rest_str: {:?}", rest_str);
-                        match rest_str.iter().position(|string_tag| if string_tag.ends_with('\"') { true } else { false }) {
-                            Some(i) => {
-                                debug!("detect an end quote of string");
-                                let str_result = (rest_str[0..i + 1]).join(" ");
-                                let rest_tokens = (rest_str[i + 1..]).iter().map(|&ref x| x.to_string()).collect::<Vec<String>>();
-                                debug!("str_result: {:?}", str_result);
-                                debug!("rest_tokens: {:?}", rest_tokens);
-                                vec.push(AST::Symbol(str_result));
-                                tmp_tokens = rest_tokens.clone();
-                            }
-                            None => { return Err("can not find an end quote"); }
-                        }
-                    } else {
-                        match read_from_tokens(tmp_tokens.clone()) {
-                            Ok(data) => {
-                                vec.push(data.result);
-                                tmp_tokens = data.remain.clone();
-                            }
-                            Err(e) => { return Err(e); }
-                        }
-                    }
-                }
-            }
-            if tmp_tokens.is_empty() {
-                return Err("syntax error");
+// Order of two numbers. An exact number and a float are compared exactly, so that
+// large integers are not rounded to the float's precision; NaN is unordered.
+fn compare_numbers(a: &Value, b: &Value) -> Option<Ordering> {
+    if let (Value::Integer(a), Value::Integer(b)) = (a, b) {
+        return Some(a.cmp(b));
+    }
+    match (exact_rational(a), exact_rational(b)) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        (Some(_), None) => compare_numbers(b, a).map(Ordering::reverse),
+        (None, Some(b)) => {
+            let a = number_to_f64(a)?;
+            if a.is_infinite() {
+                return Some(if a > 0.0 { Ordering::Greater } else { Ordering::Less });
             }
-            tmp_tokens.remove(0);
-            Ok(
-                ReadFromTokenResult {
-                    remain: tmp_tokens,
-                    result: AST::Children(vec)
-                }
-            )
-        } else if token == ")" {
-            Err("unexpected )")
-        } else {
-            Ok(
-                ReadFromTokenResult {
-                    remain: tokens,
-                    result: atom(&token)
-                }
-            )
+            BigRational::from_float(a).map(|a| a.cmp(&b))
         }
-    } else {
-        Err("unexpected EOF while reading")
+        (None, None) => number_to_f64(a)?.partial_cmp(&number_to_f64(b)?)
     }
 }
 
-fn atom(token: &str) -> AST {
-    let to_int = token.parse::<i64>();
-    let to_float = token.parse::<f64>();
+fn number_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(f) => Some(*f),
+        Value::Integer(i) => Some(*i as f64),
+        Value::Rational(r) => r.to_f64(),
+        Value::BigInt(b) => b.to_f64(),
+        _ => None
+    }
+}
 
-    if to_int.is_ok() {
-        AST::Integer(to_int.unwrap_or_default())
-    } else if to_float.is_ok() {
-        AST::Float(to_float.unwrap_or_default())
-    } else {
-        AST::Symbol(token.to_string())
+fn exact_integer(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Integer(i) => Some(BigInt::from(*i)),
+        Value::BigInt(b) => Some((**b).clone()),
+        _ => None
     }
 }
 
-pub fn eval(ast_option: Option<AST>, env: Rc<RefCell<Env>>) -> Result<Option<DataType>, &'static str> {
-    debug!("eval");
-    debug!("{:?}", ast_option);
-    match ast_option.clone() {
-        Some(AST::Symbol(s)) => {
-            debug!("ast is a symbol: {:?}", s);
-            if s.starts_with("#") {
-                if s.len() != 2 {
-                    return Err("syntax error");
-                }
-                let c_option = s.chars().nth(1);
-                if let Some('t') = c_option {
-                    Ok(Some(DataType::Bool(true)))
-                } else if let Some('f') = c_option {
-                    Ok(Some(DataType::Bool(false)))
-                } else {
-                    Err("syntax error")
-                }
-            } else if s.len() > 1 && s.starts_with("'") {
-                let slice = &s[1..s.len()];
-                Ok(Some(DataType::Symbol(slice.to_string())))
-            } else if s.starts_with("\"") && s.ends_with("\"") {
-                Ok(Some(DataType::String((&s[1..s.len() - 1]).to_string())))
-            } else {
-                match env.borrow().get(&s) {
-                    Some(data) => Ok(Some(data)),
-                    None => Err("symbol is not defined.")
-                }
-            }
-        }
-        Some(AST::Children(list)) => {
-            debug!("ast is a children: {:?}", list);
+fn exact_rational(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::Rational(r) => Some((**r).clone()),
+        _ => exact_integer(value).map(BigRational::from_integer)
+    }
+}
 
-            if list.is_empty() {
-                return Err("syntax error");
-            }
+// Exact integers stay exact as long as every operand is exact; an empty
+// argument list has no exact operand and falls back to inexact numbers.
+fn exact_integers(vec: &[Value]) -> Option<Vec<BigInt>> {
+    vec.iter().map(exact_integer).collect()
+}
 
-            tuplet!((s0,s1,s2,s3) = list);
+/// What arithmetic on fixnums does when the result does not fit in 64 bits: move on
+/// to a bignum, or fail with an "integer overflow" error.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowMode {
+    #[default]
+    Promote,
+    Error
+}
 
-            if let Some(&AST::Symbol(ref s0)) = s0 {
-                match s0.as_str() {
-                    "quote" => {
-                        debug!("quote-expression");
-                        tuplet!((s0_option,s1_option,*rest_option) = list);
+pub fn overflow_mode() -> OverflowMode {
+    context::with(|c| c.overflow_mode.get())
+}
 
-                        match s1_option {
-                            Some(ref ast) => {
-                                match ast2datatype(ast) {
-                                    Ok(data) => Ok(Some(data)),
-                                    Err(e) => { return Err(e); }
-                                }
-                            }
-                            None => { return Err("wrong number of parts"); }
-                        }
-                    }
-                    "if" => {
-                        debug!("if-expression");
-                        if let (Some(&ref cond), Some(&ref conseq), Some(&ref alt)) = (s1, s2, s3) {
-                            match eval(Some(cond.clone()), env.clone()) {
-                                Ok(Some(DataType::Bool(b))) => {
-                                    match b {
-                                        true => eval(Some(conseq.clone()), env.clone()),
-                                        false => eval(Some(alt.clone()), env.clone())
-                                    }
-                                }
-                                Ok(_) => { return Err("syntax error"); }
-                                Err(e) => { return Err(e); }
-                            }
-                        } else {
-                            return Err("wrong syntax for if expression");
-                        }
-                    }
-                    "define" => {
-                        if let (Some(&AST::Symbol(ref s1)), Some(&ref a2)) = (s1, s2) {
-                            match a2.clone() {
-                                AST::Integer(i) => {
-                                    let env_borrow_mut = env.borrow_mut();
-                                    env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::Number(i as f64));
-                                }
-                                AST::Float(f) => {
-                                    let env_borrow_mut = env.borrow_mut();
-                                    env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::Number(f));
-                                }
-                                AST::Symbol(ref s) => {
-                                    if s.len() > 1 && s.starts_with("#") {
-                                        let c_option = s.chars().nth(1);
-                                        if let Some('t') = c_option {
-                                            let env_borrow_mut = env.borrow_mut();
-                                            env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::Bool(true));
-                                        } else if let Some('f') = c_option {
-                                            let env_borrow_mut = env.borrow_mut();
-                                            env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::Bool(false));
-                                        } else {
-                                            return Err("syntax error");
-                                        }
-                                    } else if s.starts_with("\"") && s.ends_with("\"") {
-                                        let env_borrow_mut = env.borrow_mut();
-                                        env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::String((&s[1..s.len() - 1]).to_string()));
-                                    } else {
-                                        let data_option = env.borrow().get(&s);
-                                        if let Some(data) = data_option {
-                                            let env_borrow_mut = env.borrow_mut();
-                                            env_borrow_mut.local.borrow_mut().insert(s1.clone(), data);
-                                        } else {
-                                            return Err("symbol is not defined");
-                                        }
-                                    }
-                                }
-                                AST::Children(ref v) => {
-                                    debug!("children: {:?}", v);
-
-                                    let data_option = eval(Some(a2.clone()), env.clone());
-                                    if let Ok(Some(DataType::Lambda(ref p))) = data_option {
-                                        let env_borrow_mut = env.borrow_mut();
-                                        env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::Lambda(p.clone()));
-                                    } else if let Ok(Some(DataType::List(ref v))) = data_option {
-                                        let env_borrow_mut = env.borrow_mut();
-                                        env_borrow_mut.local.borrow_mut().insert(s1.clone(), DataType::List(v.clone()));
-                                    } else if let Err(e) = data_option {
-                                        return Err(e);
-                                    }
-                                }
-                            }
-                            return Ok(None);
-                        }
-                        return Err("wrong syntax for define expression");
-                    }
-                    "lambda" => {
-                        debug!("lambda-expression");
-                        if let (Some(&AST::Children(ref args)), Some(&AST::Children(ref body))) = (s1, s2) {
-                            debug!("ENV: {:?}", env);
-                            debug!("args: {:?}", args);
-                            debug!("body: {:?}", body);
-
-                            // convert args AST to Datatype symbol
-                            let args_result: Result<Vec<_>, _> = args.iter().map(|ref arg|
-                                match arg {
-                                    &&AST::Symbol(ref arg_string) => Ok(DataType::Symbol(arg_string.to_string())),
-                                    _ => Err("lambda argument must be a symbol")
-                                }
-                            ).collect();
-
-                            if let Result::Err(ref e) = args_result { return Err(e); }
-
-                            let args_meta = args_result.unwrap().iter()
-                                .map(|ref mut x| x.clone())
-                                .collect::<Vec<DataType>>();
-
-                            let local = RefCell::new(HashMap::new());
-                            let parent_env_box = Box::new(env.clone());
-                            let procedure_env = Env {
-                                local,
-                                parent: Some(parent_env_box)
-                            };
-
-                            debug!("procedure_env: {:?}", procedure_env);
-                            let procedure = Procedure {
-                                body: AST::Children(body.clone()),
-                                params: args_meta,
-                                env: Rc::new(RefCell::new(procedure_env))
-                            };
-                            debug!("procedure: {:?}", procedure);
-
-                            Ok(Some(DataType::Lambda(procedure)))
-                        } else {
-                            Err("syntax error")
-                        }
-                    }
-                    _ => {
-                        debug!("Some(AST::Symbol) but not define");
-                        debug!("proc_key : {}", s0);
-                        debug!("ENV: {:?}", env);
+/// Replaces the overflow behavior of every later evaluation in the current context.
+pub fn set_overflow_mode(mode: OverflowMode) {
+    context::with(|c| c.overflow_mode.set(mode));
+}
 
-                        let mut data_option = match env.borrow().get(s0) {
-                            Some(d) => Some(d.clone()),
-                            None => None
-                        };
+// Called when the fixnum fast path gave up: fails if that was because of an overflow
+// and overflows are errors.
+fn check_overflow(vec: &[Value]) -> Result<(), SchemeError> {
+    if overflow_mode() == OverflowMode::Error && !vec.is_empty() && vec.iter().all(|x| matches!(x, Value::Integer(_))) {
+        return Err(SchemeError::other("integer overflow"));
+    }
+    Ok(())
+}
 
-                        debug!("data_option: {:?}", data_option);
+// Fast path of the arithmetic functions, folding the operands in place: None when
+// an operand is not a fixnum or when the result overflows.
+fn fold_fixnums<F>(vec: &[Value], f: F) -> Option<i64> where F: Fn(i64, i64) -> Option<i64> {
+    let fixnum = |x: &Value| if let Value::Integer(i) = *x { Some(i) } else { None };
+    let (first, rest) = vec.split_first()?;
+    rest.iter().try_fold(fixnum(first)?, |acc, x| f(acc, fixnum(x)?))
+}
 
-                        match data_option {
-                            Some(DataType::Proc(ref f)) => {
-                                let slice = &list[1..list.len()];
-                                execute(f, slice, env)
-                            }
-                            Some(DataType::Lambda(ref mut p)) => {
-                                debug!("first elm symbol - lambda: {:?}", p);
-                                let slice = &list[1..list.len()];
-                                match prepare_arguments(slice, env.clone()) {
-                                    Ok(args) => {
-                                        debug!("first elm symbol - procedure params: {:?}", p.params);
-                                        let procedure_local = p.env.borrow_mut().local.clone();
-
-                                        for (name_ref, value_ref) in p.params.iter().zip(args.into_iter()) {
-                                            debug!("first elm symbol - procedure params - name: {:?} value: {:?}", name_ref, value_ref);
-                                            if let (Some(&DataType::Symbol(ref name)), Some(ref value)) = (Some(name_ref), Some(value_ref)) {
-                                                procedure_local.borrow_mut().insert(name.to_string(), value.clone());
-                                            } else {
-                                                unreachable!()
-                                            }
-                                        }
-
-                                        let proc_env = Env {
-                                            local: procedure_local,
-                                            parent: p.env.borrow_mut().parent.clone()
-                                        };
-
-                                        debug!("proc_env: {:?}", proc_env);
-                                        return eval(Some(p.body.clone()), Rc::new(RefCell::new(proc_env)));
-                                    }
-                                    Err(e) => return Err(e)
-                                }
-                            }
-                            Some(_) | None => Err("symbol is not defined.")
-                        }
-                    }
-                }
-            } else {
-                debug!("first ast is not a symbol");
-                debug!("proc_key : {:?}", s0);
-
-                tuplet!((s0_option,*rest_option) = list);
-
-                if let Some(&AST::Children(_)) = s0_option {
-                    match eval(Some(list.first().unwrap().clone()), env.clone()) {
-                        Ok(Some(DataType::Proc(ref f))) => {
-                            debug!("first elm function - function: {:?}", f);
-                            match rest_option {
-                                Some(rest) => execute(f, rest, env),
-                                None => execute(f, &vec![], env)
-                            }
-                        }
-                        Ok(Some(DataType::Lambda(ref mut p))) => {
-                            debug!("first elm lambda - lambda: {:?} - procedure params: {:?}", p, p.params);
-                            let proc_env = match rest_option {
-                                Some(rest) => {
-                                    match prepare_arguments(rest, env.clone()) {
-                                        Ok(args) => {
-                                            let p_env_borrow_mut = p.env.borrow_mut();
-                                            for (name_ref, value_ref) in p.params.iter().zip(args.into_iter()) {
-                                                debug!("first elm lambda - procedure params - name: {:?} value: {:?}", name_ref, value_ref);
-                                                if let (Some(&DataType::Symbol(ref name)), Some(ref value)) = (Some(name_ref), Some(value_ref)) {
-                                                    p_env_borrow_mut.local.borrow_mut().insert(name.to_string(), value.clone());
-                                                } else {
-                                                    unreachable!()
-                                                }
-                                            }
-                                            Env {
-                                                local: p_env_borrow_mut.local.clone(),
-                                                parent: p_env_borrow_mut.parent.clone()
-                                            }
-                                        }
-                                        Err(e) => return Err(e)
-                                    }
-                                }
-                                None => {
-                                    let p_env_borrow_mut = p.env.borrow_mut();
-                                    Env {
-                                        local: p_env_borrow_mut.local.clone(),
-                                        parent: p_env_borrow_mut.parent.clone()
-                                    }
-                                }
-                            };
-                            debug!("proc_env: {:?}", proc_env);
-                            return eval(Some(p.body.clone()), Rc::new(RefCell::new(proc_env)));
-                        }
-                        Ok(_) => { return Err("unsupported data type on first element"); }
-                        Err(e) => { return Err(e); }
-                    }
-                } else {
-                    return Err("syntax error");
-                }
-            }
-        }
-        Some(_) | None => {
-            debug!("ast is not a symbol/children");
-            let data = match ast_option {
-                Some(AST::Integer(i)) => Some(DataType::Number(i as f64)),
-                Some(AST::Float(f)) => Some(DataType::Number(f)),
-                Some(_) => unreachable!(),
-                None => None
-            };
-            Ok(data)
-        }
-    }
+fn exact_rationals(vec: &[Value]) -> Option<Vec<BigRational>> {
+    vec.iter().map(exact_rational).collect()
 }
 
-fn prepare_arguments(arguments: &[AST], env: Rc<RefCell<Env>>) -> Result<Vec<DataType>, &'static str> {
-    let args_result: Result<Vec<_>, _> = arguments.iter()
-        .map(|x| eval(Some(x.clone()), env.clone()))
-        .collect();
-    debug!("args: {:?}", args_result);
-    if let Result::Err(ref e) = args_result { return Err(e); }
+fn inexact_numbers(vec: &[Value]) -> Option<Vec<f64>> {
+    vec.iter().map(number_to_f64).collect()
+}
 
-    let args = args_result.unwrap().iter()
-        .filter(|x| x.is_some())
-        .flat_map(|ref mut x| x.clone())
-        .collect::<Vec<DataType>>();
-    Ok(args)
+// Integer arguments of `quotient`, `gcd` and the like, with whether they are all exact:
+// integral floats are accepted and make the result inexact.
+fn integer_arguments(vec: &[Value]) -> Option<(Vec<BigInt>, bool)> {
+    let integers = vec.iter().map(integer_argument).collect::<Option<Vec<BigInt>>>()?;
+    Some((integers, vec.iter().all(|x| exact_integer(x).is_some())))
 }
 
-fn execute(f: &Function, arguments: &[AST], env: Rc<RefCell<Env>>) -> Result<Option<DataType>, &'static str> {
-    match prepare_arguments(arguments, env.clone()) {
-        Ok(args) => {
-            f.call(args, env.clone()).and_then(|r| {
-                match r {
-                    Some(data) => Ok(Some(data)),
-                    None => Ok(None)
-                }
-            })
-        }
-        Err(e) => return Err(e)
+fn integer_result(n: BigInt, exact: bool) -> Value {
+    if exact {
+        Value::integer(n)
+    } else {
+        Value::Number(n.to_f64().unwrap_or(f64::NAN))
     }
 }
 
-pub fn setup() -> HashMap<String, DataType> {
-    let mut map = HashMap::new();
-    map.insert("pi".to_string(), DataType::Number(std::f64::consts::PI));
-
-    map.insert("+".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "+", vec);
-        let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
-        if !is_numbers {
-            return Err("wrong argument datatype");
+// `quotient`, `modulo` and the other integer divisions, which only differ in how they round.
+fn integer_division(name: &'static str, f: fn(&BigInt, &BigInt) -> BigInt) -> Value {
+    Value::Proc(Function::new(Arity::exactly(2), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", name, vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("integer division requires two arguments"));
+        }
+        match integer_arguments(&vec) {
+            Some((ref n, _)) if n[1].is_zero() => Err(SchemeError::other("division by zero")),
+            Some((n, exact)) => Ok(integer_result(f(&n[0], &n[1]), exact)),
+            None => Err(SchemeError::wrong_type("integer division requires arguments of type 'integer'"))
         }
+    })))
+}
 
-        let desc = vec.iter().map(|&ref x|
-            match x {
-                &DataType::Number(f) => f.to_string(),
-                _ => unreachable!(),
+// `floor/` and `truncate/`, whose quotient and remainder come back as a two-element
+// list until procedures can return multiple values.
+fn integer_division_pair(name: &'static str, f: fn(&BigInt, &BigInt) -> (BigInt, BigInt)) -> Value {
+    Value::Proc(Function::new(Arity::exactly(2), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", name, vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("integer division requires two arguments"));
+        }
+        match integer_arguments(&vec) {
+            Some((ref n, _)) if n[1].is_zero() => Err(SchemeError::other("division by zero")),
+            Some((n, exact)) => {
+                let (quotient, remainder) = f(&n[0], &n[1]);
+                Ok(Value::list(vec![integer_result(quotient, exact), integer_result(remainder, exact)]))
             }
-        ).collect::<Vec<String>>().join(" + ");
-        debug!("Description: {}", desc);
-        let numbers = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } });
-        let data: f64 = numbers.map(|x| {
-            let y: f64 = x.clone().into();
-            y
-        }).sum();
-        Ok(Some(DataType::Number(data)))
-    }))));
+            None => Err(SchemeError::wrong_type("integer division requires arguments of type 'integer'"))
+        }
+    })))
+}
 
-    map.insert("-".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "+", vec);
-        let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
+// `floor`, `ceiling`, `round` and `truncate`: exact numbers round to exact integers,
+// inexact numbers to integral floats.
+fn rounding(name: &'static str, exact: fn(&BigRational) -> BigRational, inexact: fn(f64) -> f64) -> Value {
+    Value::Proc(Function::new(Arity::exactly(1), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", name, vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("rounding requires one argument only"));
+        }
+        match vec[0] {
+            Value::Integer(_) | Value::BigInt(_) => Ok(vec[0].clone()),
+            Value::Rational(ref r) => Ok(Value::rational(exact(r))),
+            Value::Number(f) => Ok(Value::Number(inexact(f))),
+            _ => Err(SchemeError::wrong_type("rounding requires an argument of type 'number'"))
+        }
+    })))
+}
 
-        if !is_numbers {
-            return Err("wrong argument datatype");
+// `sin`, `exp` and the other functions of one real argument. `exact` is the one exact
+// argument with an exact result, like 0 for `sin` or 1 for `log`.
+fn transcendental(name: &'static str, f: fn(f64) -> f64, exact: (i64, i64)) -> Value {
+    Value::Proc(Function::new(Arity::exactly(1), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", name, vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("math function requires one argument only"));
+        }
+        if vec[0] == Value::Integer(exact.0) {
+            return Ok(Value::Integer(exact.1));
+        }
+        match number_to_f64(&vec[0]) {
+            Some(x) => Ok(Value::Number(f(x))),
+            None => Err(SchemeError::wrong_type("math function requires an argument of type 'number'"))
         }
+    })))
+}
 
-        let desc = vec.iter().map(|&ref x|
-            match x {
-                &DataType::Number(f) => f.to_string(),
-                _ => unreachable!(),
-            }
-        ).collect::<Vec<String>>().join(" - ");
-        debug!("Description: {}", desc);
+// The exact square root of `r`, if it has one.
+fn exact_sqrt(r: &BigRational) -> Option<BigRational> {
+    if r.is_negative() {
+        return None;
+    }
+    let root = |n: &BigInt| Some(n.sqrt()).filter(|root| root * root == *n);
+    Some(BigRational::new(root(r.numer())?, root(r.denom())?))
+}
 
-        let value: f64 = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } })
-            .map(|x| {
-                let y: f64 = x.clone().into();
-                y
-            })
-            .fold(0.0, |mut acc, x| {
-                if acc == 0.0 { acc = x; } else { acc = acc - x; }
-                acc
-            });
-        Ok(Some(DataType::Number(value)))
+// Rounds to the nearest integer, and to the even one when `r` is halfway between two.
+fn round_half_even(r: &BigRational) -> BigRational {
+    let floor = r.floor();
+    let one = BigRational::one();
+    match (r - &floor).cmp(&BigRational::new(1.into(), 2.into())) {
+        Ordering::Less => floor,
+        Ordering::Greater => floor + one,
+        Ordering::Equal if floor.to_integer().is_even() => floor,
+        Ordering::Equal => floor + one
+    }
+}
 
-    }))));
+// Byte argument of the bytevector functions, an integer from 0 to 255.
+fn byte_argument(value: &Value) -> Option<u8> {
+    integer_argument(value).and_then(|n| n.to_u8())
+}
 
-    map.insert("*".to_string(), DataType::Proc(
-        Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-            debug!("Function - name: {:?} - Args: {:?}", "*", vec);
-            let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
-            if !is_numbers {
-                return Err("wrong argument datatype");
-            }
+// Integer argument of the number theory functions, which also accept integral floats.
+fn integer_argument(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Integer(i) => Some(BigInt::from(*i)),
+        Value::BigInt(b) => Some((**b).clone()),
+        Value::Number(f) if f.fract() == 0.0 => BigInt::from_f64(*f),
+        _ => None
+    }
+}
 
-            let desc = vec.iter().map(|&ref x|
-                match x {
-                    &DataType::Number(f) => f.to_string(),
-                    _ => panic!("Something went wrong"),
-                }
-            ).collect::<Vec<String>>().join(" x ");
-            debug!("Description: {}", desc);
-
-            let numbers = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } });
-            let data: f64 = numbers.map(|x| {
-                let y: f64 = x.clone().into();
-                y
-            }).product();
-            Ok(Some(DataType::Number(data)))
-        }))));
+// Optional port argument of the input functions, the current input port if absent.
+fn input_port(value: Option<&Value>) -> Result<Rc<Port>, SchemeError> {
+    match value {
+        None => Ok(current_input_port()),
+        Some(Value::Port(p)) if p.is_input() => Ok(p.clone()),
+        Some(_) => Err(SchemeError::wrong_type("port argument must be an input port"))
+    }
+}
 
-    map.insert("/".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "/", vec);
-        let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
+// Optional port argument of the output functions, the current output port if absent.
+fn output_port(value: Option<&Value>) -> Result<Rc<Port>, SchemeError> {
+    match value {
+        None => Ok(current_output_port()),
+        Some(Value::Port(p)) if p.is_output() => Ok(p.clone()),
+        Some(_) => Err(SchemeError::wrong_type("port argument must be an output port"))
+    }
+}
 
-        if !is_numbers {
-            return Err("wrong argument datatype");
-        }
+trait FloatIterExt {
+    fn float_min(&mut self) -> f64;
+    fn float_max(&mut self) -> f64;
+}
 
-        let desc = vec.iter().map(|&ref x|
-            match x {
-                &DataType::Number(f) => f.to_string(),
-                _ => unreachable!(),
-            }
-        ).collect::<Vec<String>>().join(" / ");
-        debug!("Description: {}", desc);
+impl<T> FloatIterExt for T where T: Iterator<Item=f64> {
+    fn float_max(&mut self) -> f64 {
+        self.fold(f64::NAN, f64::max)
+    }
 
-        let value: f64 = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } })
-            .map(|x| {
-                let y: f64 = x.clone().into();
-                y
-            })
-            .fold(0.0, |mut acc, x| {
-                if acc == 0.0 { acc = x; } else { acc = acc / x; }
-                acc
-            });
-        Ok(Some(DataType::Number(value)))
-    }))));
+    fn float_min(&mut self) -> f64 {
+        self.fold(f64::NAN, f64::min)
+    }
+}
 
-    define_comparison!(gt, ">", |a,b| { a > b });
-    map.insert(">".to_string(), gt);
+// Both halves of a cons cell can be replaced in place by the destructive list operations.
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Pair {
+    car: RefCell<Value>,
+    cdr: RefCell<Value>
+}
 
-    define_comparison!(lt, "<", |a,b| { a < b });
-    map.insert("<".to_string(), lt);
+impl Pair {
+    pub fn car(&self) -> Value {
+        self.car.borrow().clone()
+    }
 
-    define_comparison!(eq, "=", |a,b| { a == b });
-    map.insert("=".to_string(), eq);
+    pub fn cdr(&self) -> Value {
+        self.cdr.borrow().clone()
+    }
 
-    define_comparison!(ge, ">=", |a,b| { a >= b });
-    map.insert(">=".to_string(), ge);
+    pub fn set_car(&self, value: Value) {
+        *self.car.borrow_mut() = value;
+    }
+
+    pub fn set_cdr(&self, value: Value) {
+        *self.cdr.borrow_mut() = value;
+    }
+}
+
+// The halves are printed as values are, which stops at the pairs that lead back to
+// this one.
+impl fmt::Debug for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pair").field("car", &self.car()).field("cdr", &self.cdr()).finish()
+    }
+}
+
+// Takes the cdrs no other value shares off one at a time, so that dropping a long
+// list does not recurse once for each pair of it.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = mem::replace(self.cdr.get_mut(), Value::Nil);
+        while let Value::Pair(p) = next {
+            next = match Rc::try_unwrap(p) {
+                Ok(mut pair) => mem::replace(pair.cdr.get_mut(), Value::Nil),
+                Err(_) => break
+            };
+        }
+    }
+}
+
+/// An s-expression: what the reader produces, what programs are made of and what
+/// they evaluate to. Programs are made of the `List`s the reader makes, which are
+/// never changed; the lists of data, quoted ones included, are made of pairs, which
+/// programs can change.
+///
+/// Having both is a deliberate departure from one representation of lists. The
+/// evaluator indexes into the forms it runs, which pairs would make it walk, while
+/// data must be pairs for `set-car!` and `set-cdr!`. `List` stays on the side of
+/// code: the expander makes the lists of quoted data and of vector literals into
+/// pairs, so no program, and no host evaluating one, gets a `List` back. Only the
+/// data `parse_datum` and the other readers return, before they are evaluated, and
+/// the forms `expand` rewrites are made of them.
+#[derive(Clone)]
+pub enum Value {
+    Bool(bool),
+    Pair(Rc<Pair>),
+    Number(f64),
+    Integer(i64),
+    // numbers too big for a fixnum are shared between copies instead of copied
+    BigInt(Rc<BigInt>),
+    Rational(Rc<BigRational>),
+    Char(char),
+    Symbol(SymbolId),
+    String(Rc<str>),
+    Proc(Function),
+    /// A list of a program, as the reader makes them for the expander and the
+    /// evaluator. Programs and their results only hold pairs.
+    List(List),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Bytevector(Rc<RefCell<Vec<u8>>>),
+    HashTable(HashTable),
+    Record(Rc<Record>),
+    Port(Rc<Port>),
+    /// Data of the host program, which scripts can only pass around and give back to
+    /// the native functions that know its type.
+    Foreign(Foreign),
+    Eof,
+    Lambda(Procedure),
+    /// The empty list. `List` is never empty.
+    Nil,
+    /// Result of expressions evaluated only for their effect, such as `define`.
+    Unspecified
+}
+
+/// The name `Value` had before programs were read as values.
+pub type DataType = Value;
+
+impl Value {
+    /// An exact integer, as a fixnum when it fits in 64 bits.
+    pub fn integer(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::BigInt(Rc::new(n))
+        }
+    }
+
+    /// An exact ratio, as an integer when the denominator is 1.
+    pub fn rational(r: BigRational) -> Value {
+        if r.is_integer() {
+            Value::integer(r.to_integer())
+        } else {
+            Value::Rational(Rc::new(r))
+        }
+    }
+
+    /// A proper list of `elements` made of pairs, which programs can change with
+    /// `set-car!` and `set-cdr!`; `Nil` when there are none.
+    pub fn list(elements: Vec<Value>) -> Value {
+        elements.into_iter().rev().fold(Value::Nil, |tail, element| Value::cons(element, tail))
+    }
+
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        count_allocation(mem::size_of::<Pair>());
+        Value::Pair(Rc::new(Pair { car: RefCell::new(car), cdr: RefCell::new(cdr) }))
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::String(s.into())
+    }
+
+    pub fn symbol(name: &str) -> Value {
+        Value::Symbol(SymbolId::intern(name))
+    }
+
+    pub fn vector(elements: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn bytevector(bytes: Vec<u8>) -> Value {
+        Value::Bytevector(Rc::new(RefCell::new(bytes)))
+    }
+
+    /// Rust data for scripts to pass around, as a `Foreign`.
+    pub fn foreign<T: Any>(data: T) -> Value {
+        Value::Foreign(Foreign::new(data))
+    }
+
+    /// Every value but `#f` counts as true, as in `if`.
+    pub fn is_truthy(&self) -> bool {
+        *self != Value::Bool(false)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    /// The value of an exact integer that fits in 64 bits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            Value::BigInt(b) => b.to_i64(),
+            _ => None
+        }
+    }
+
+    /// The value of any real number, exact ones rounded to the nearest float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            Value::BigInt(b) => b.to_f64(),
+            Value::Rational(r) => r.to_f64(),
+            _ => None
+        }
+    }
+
+    pub fn as_char(&self) -> Option<char> {
+        match *self {
+            Value::Char(c) => Some(c),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_symbol(&self) -> Option<SymbolId> {
+        match *self {
+            Value::Symbol(id) => Some(id),
+            _ => None
+        }
+    }
+
+    /// The foreign data of the value, if it is a `T`.
+    pub fn as_foreign<T: Any>(&self) -> Option<&T> {
+        match self {
+            Value::Foreign(f) => f.downcast_ref(),
+            _ => None
+        }
+    }
+
+    /// The elements of a proper list, whether it was read, built by `list` or made
+    /// of pairs. Improper and circular lists have none.
+    pub fn as_list(&self) -> Option<Vec<Value>> {
+        list_elements(self)
+    }
+
+    /// The value in Scheme syntax, as `unparse` writes it: strings are quoted,
+    /// characters written as `#\a` and improper lists dotted, so that reading the text
+    /// gives back an equal value.
+    pub fn to_scheme_string(&self) -> String {
+        unparse(self)
+    }
+
+    pub fn write_to(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        out.write_str(&unparse(self))
+    }
+
+    /// Like `to_scheme_string`, with the lists and vectors that do not fit in `width`
+    /// columns broken over indented lines.
+    ///
+    /// ```
+    /// use scheme_rs::parse_datum;
+    ///
+    /// let (value, _) = parse_datum("(define (f x) (if (< x 0) \"negative\" (list x #\\a)))").unwrap();
+    /// assert_eq!("(define (f x)\n  (if (< x 0)\n    \"negative\"\n    (list x #\\a)))", value.to_pretty_string(20));
+    /// ```
+    pub fn to_pretty_string(&self, width: usize) -> String {
+        pretty::pretty(self, width)
+    }
+
+    pub fn write_pretty_to(&self, out: &mut dyn fmt::Write, width: usize) -> fmt::Result {
+        out.write_str(&pretty::pretty(self, width))
+    }
+}
+
+// Lists are equal whether they are made of pairs or were read, so that the data a
+// program builds compare equal to the same data written in Rust.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Proc(a), Value::Proc(b)) => a == b,
+            (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Bytevector(a), Value::Bytevector(b)) => a == b,
+            (Value::HashTable(a), Value::HashTable(b)) => a == b,
+            (Value::Record(a), Value::Record(b)) => a == b,
+            (Value::Port(a), Value::Port(b)) => a == b,
+            (Value::Foreign(a), Value::Foreign(b)) => a == b,
+            (Value::Lambda(a), Value::Lambda(b)) => a == b,
+            (Value::Eof, Value::Eof) | (Value::Nil, Value::Nil) | (Value::Unspecified, Value::Unspecified) => true,
+            (Value::List(_) | Value::Pair(_), Value::List(_) | Value::Pair(_)) => lists_equal(self, other),
+            _ => false
+        }
+    }
+}
+
+// Walks along the cdrs, so that comparing long lists does not grow the stack.
+fn lists_equal(a: &Value, b: &Value) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    loop {
+        match (&a, &b) {
+            (Value::List(x), Value::List(y)) => return x == y,
+            (Value::Pair(x), Value::Pair(y)) if Rc::ptr_eq(x, y) => return true,
+            _ => {}
+        }
+        match (split_list(&a), split_list(&b)) {
+            (Some((a_car, a_cdr)), Some((b_car, b_cdr))) if a_car == b_car => {
+                a = a_cdr;
+                b = b_cdr;
+            }
+            (Some(_), Some(_)) => return false,
+            _ => return a == b
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", datatype2str(self))
+    }
+}
+
+// Data can be cyclic, so values are written with datum labels as `Display` writes
+// them, inside the name of their variant.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let variant = match self {
+            Value::Bool(_) => "Bool",
+            Value::Pair(_) => "Pair",
+            Value::Number(_) => "Number",
+            Value::Integer(_) => "Integer",
+            Value::BigInt(_) => "BigInt",
+            Value::Rational(_) => "Rational",
+            Value::Char(_) => "Char",
+            Value::Symbol(_) => "Symbol",
+            Value::String(_) => "String",
+            Value::Proc(_) => "Proc",
+            Value::List(_) => "List",
+            Value::Vector(_) => "Vector",
+            Value::Bytevector(_) => "Bytevector",
+            Value::HashTable(_) => "HashTable",
+            Value::Record(_) => "Record",
+            Value::Port(_) => "Port",
+            Value::Foreign(_) => "Foreign",
+            Value::Lambda(_) => "Lambda",
+            Value::Eof => return f.write_str("Eof"),
+            Value::Nil => return f.write_str("Nil"),
+            Value::Unspecified => return f.write_str("Unspecified")
+        };
+        write!(f, "{}({})", variant, datatype2str(self))
+    }
+}
+
+/// A variable as `Env::visible_bindings` lists it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub name: SymbolId,
+    pub value: Value,
+    /// The frame the variable is bound in, counted out from the one it is seen from
+    /// as `Env::frame_of` does.
+    pub frame: usize
+}
+
+/// The bindings of a frame at one point, taken by `Env::snapshot`. The values are
+/// shared with the frame, not copied: data changed in place since, like a vector
+/// given to `vector-set!`, stay changed when the snapshot is restored. The snapshot
+/// of a frame made by `Env::persistent` shares its bindings with the frame too.
+#[derive(Clone, Debug)]
+pub struct EnvSnapshot {
+    local: HashMap<SymbolId, Value>,
+    slots: Vec<Option<Value>>,
+    persistent: Option<PersistentMap<SymbolId, Value>>,
+    macros: HashMap<SymbolId, Rc<Macro>>
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Env {
+    pub local: RefCell<HashMap<SymbolId, Value>>,
+    pub parent: Option<Box<Rc<RefCell<Env>>>>,
+    // variables that compiled code finds by position instead of by name; a slot is
+    // empty until its variable is bound
+    names: Rc<[SymbolId]>,
+    slots: RefCell<Vec<Option<Value>>>,
+    // the bindings of a frame made by `Env::persistent`, kept there instead of in `local`;
+    // binding a variable swaps in a new map rather than changing this one
+    persistent: Option<PersistentMap<SymbolId, Value>>,
+    // macros defined with `define-syntax` at the top level of programs evaluated here
+    macros: RefCell<HashMap<SymbolId, Rc<Macro>>>
+}
+
+impl Env {
+    pub fn new(local: HashMap<SymbolId, Value>, parent: Option<Rc<RefCell<Env>>>) -> Env {
+        Env { local: RefCell::new(local), parent: parent.map(Box::new), names: Rc::from(&[][..]), slots: RefCell::new(vec![]), persistent: None, macros: RefCell::new(HashMap::new()) }
+    }
+
+    /// A frame keeping its bindings in a persistent map instead of `local`, so that
+    /// cloning the frame, forking its bindings or taking a snapshot of them copies
+    /// none of them. The map itself is never changed: binding a variable swaps in a
+    /// new one. The values are reference counted without atomics, so the bindings of
+    /// a frame stay on the thread that made them.
+    pub fn persistent(bindings: PersistentMap<SymbolId, Value>, parent: Option<Rc<RefCell<Env>>>) -> Env {
+        Env { persistent: Some(bindings), ..Env::new(HashMap::new(), parent) }
+    }
+
+    // A frame for compiled code, with an empty slot for each of `names`.
+    pub(crate) fn with_slots(names: Rc<[SymbolId]>, parent: Rc<RefCell<Env>>) -> Env {
+        let slots = RefCell::new(vec![None; names.len()]);
+        Env { local: RefCell::new(HashMap::new()), parent: Some(Box::new(parent)), names, slots, persistent: None, macros: RefCell::new(HashMap::new()) }
+    }
+
+    /// The bindings of a frame made by `persistent` as they are now, which the
+    /// definitions made in the frame later leave alone.
+    pub fn persistent_bindings(&self) -> Option<PersistentMap<SymbolId, Value>> {
+        self.persistent.clone()
+    }
+
+    // Whether `id` is bound in this frame by name rather than in a slot.
+    fn has_named(&self, id: SymbolId) -> bool {
+        self.local.borrow().contains_key(&id) || self.persistent.as_ref().is_some_and(|table| table.contains_key(&id))
+    }
+
+    pub(crate) fn slot(&self, i: usize) -> Option<Value> {
+        self.slots.borrow()[i].clone()
+    }
+
+    pub(crate) fn set_slot(&self, i: usize, value: Value) {
+        self.slots.borrow_mut()[i] = Some(value);
+    }
+
+    /// Binds `id` in this frame, in its slot if compiled code gave it one.
+    pub(crate) fn bind(&mut self, id: SymbolId, value: Value) {
+        match self.names.iter().rposition(|&name| name == id) {
+            Some(i) => self.set_slot(i, value),
+            None => match self.persistent {
+                Some(ref mut table) => {
+                    *table = table.insert(id, value);
+                }
+                None => {
+                    self.local.borrow_mut().insert(id, value);
+                }
+            }
+        }
+    }
+
+    /// The variables bound in this frame alone, in no particular order.
+    pub fn bindings(&self) -> Vec<(SymbolId, Value)> {
+        let slots = self.slots.borrow();
+        let bound = self.names.iter().zip(slots.iter()).filter_map(|(&name, value)| value.clone().map(|value| (name, value)));
+        let mut bindings = bound.chain(self.local.borrow().iter().map(|(&name, value)| (name, value.clone()))).collect::<Vec<(SymbolId, Value)>>();
+        if let Some(ref table) = self.persistent {
+            bindings.extend(table.iter().map(|(&name, value)| (name, value.clone())));
+        }
+        bindings
+    }
+
+    /// Whether `id` is bound in this frame or one of its parents.
+    pub fn is_bound(&self, id: SymbolId) -> bool {
+        self.frame_of(id).is_some()
+    }
+
+    /// How many frames out from this one `id` is bound: 0 for this frame, 1 for its
+    /// parent and so on.
+    pub fn frame_of(&self, id: SymbolId) -> Option<usize> {
+        let in_slot = self.names.iter().zip(self.slots.borrow().iter()).any(|(&name, value)| name == id && value.is_some());
+        if in_slot || self.has_named(id) {
+            return Some(0);
+        }
+        self.parent.as_ref().and_then(|parent| parent.borrow().frame_of(id)).map(|frame| frame + 1)
+    }
+
+    /// The variables seen from this frame, innermost frame first and by name within
+    /// a frame. Variables shadowed by closer ones are left out.
+    pub fn visible_bindings(&self) -> Vec<Binding> {
+        let mut seen = HashSet::new();
+        let mut visible = vec![];
+        let mut bindings = self.bindings();
+        let mut parent = self.parent.as_deref().cloned();
+        let mut frame = 0;
+        loop {
+            bindings.sort_by_key(|(name, _)| name.name());
+            for (name, value) in bindings {
+                if seen.insert(name) {
+                    visible.push(Binding { name, value, frame });
+                }
+            }
+            let env = match parent {
+                Some(env) => env,
+                None => return visible
+            };
+            bindings = env.borrow().bindings();
+            parent = env.borrow().parent.as_deref().cloned();
+            frame += 1;
+        }
+    }
+
+    /// The bindings of this frame, not of its parents, for `restore` to put back.
+    /// Only a frame made by `Env::persistent`, like the global environment of an
+    /// interpreter built with `InterpreterBuilder::persistent_environment`, shares
+    /// its bindings with the snapshot. Other frames copy their table of bindings, in
+    /// time proportional to its size, and so does `restore`.
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            local: self.local.borrow().clone(),
+            slots: self.slots.borrow().clone(),
+            persistent: self.persistent_bindings(),
+            macros: self.macros.borrow().clone()
+        }
+    }
+
+    /// Puts back the bindings of this frame `snapshot` was taken of: variables and
+    /// macros defined since are unbound again, and those redefined get their old
+    /// values back.
+    pub fn restore(&mut self, snapshot: &EnvSnapshot) {
+        self.local.borrow_mut().clone_from(&snapshot.local);
+        for (slot, value) in self.slots.borrow_mut().iter_mut().zip(&snapshot.slots) {
+            slot.clone_from(value);
+        }
+        if let (Some(table), Some(bindings)) = (&mut self.persistent, &snapshot.persistent) {
+            *table = bindings.clone();
+        }
+        self.macros.borrow_mut().clone_from(&snapshot.macros);
+    }
+
+    /// Whether a variable or macro of this frame was bound, or bound to another value,
+    /// since `snapshot` was taken of it.
+    pub fn changed_since(&self, snapshot: &EnvSnapshot) -> bool {
+        let local = self.local.borrow();
+        let macros = self.macros.borrow();
+        local.len() != snapshot.local.len()
+            || local.iter().any(|(id, value)| !snapshot.local.get(id).is_some_and(|old| is_eqv(old, value)))
+            || self.slots.borrow().iter().zip(&snapshot.slots).any(|(slot, old)| match (slot, old) {
+                (Some(value), Some(old)) => !is_eqv(old, value),
+                (slot, old) => slot.is_some() != old.is_some()
+            })
+            || match (&self.persistent, &snapshot.persistent) {
+                (Some(table), Some(old)) => {
+                    !table.ptr_eq(old) && (table.len() != old.len() || table.iter().any(|(id, value)| !old.get(id).is_some_and(|old| is_eqv(old, value))))
+                }
+                _ => false
+            }
+            || macros.len() != snapshot.macros.len()
+            || macros.iter().any(|(id, m)| !snapshot.macros.get(id).is_some_and(|old| Rc::ptr_eq(old, m)))
+    }
+
+    // The macro `id` names here, unless a variable of that name is closer.
+    fn macro_named(&self, id: SymbolId) -> Option<Rc<Macro>> {
+        if let Some(m) = self.macros.borrow().get(&id) {
+            return Some(m.clone());
+        }
+        if self.names.contains(&id) || self.has_named(id) {
+            return None;
+        }
+        self.parent.as_ref().and_then(|parent| parent.borrow().macro_named(id))
+    }
+
+    fn get(&self, key: SymbolId) -> Option<Value> {
+        if let Some(value) = self.names.iter().rposition(|&name| name == key).and_then(|i| self.slot(i)) {
+            return Some(value);
+        }
+        if let Some(value) = self.persistent.as_ref().and_then(|table| table.get(&key).cloned()) {
+            return Some(value);
+        }
+        match self.local.borrow().get(&key) {
+            Some(&Value::Bool(b)) => Some(Value::Bool(b)),
+            Some(Value::Pair(p)) => Some(Value::Pair(p.clone())),
+            Some(&Value::Number(f)) => Some(Value::Number(f)),
+            Some(&Value::Integer(i)) => Some(Value::Integer(i)),
+            Some(Value::BigInt(b)) => Some(Value::BigInt(b.clone())),
+            Some(Value::Rational(r)) => Some(Value::Rational(r.clone())),
+            Some(&Value::Char(c)) => Some(Value::Char(c)),
+            Some(&Value::Symbol(id)) => Some(Value::Symbol(id)),
+            Some(Value::String(ss)) => Some(Value::String(ss.clone())),
+            Some(Value::Proc(p)) => Some(Value::Proc(p.clone())),
+            Some(Value::List(l)) => Some(Value::List(l.clone())),
+            Some(Value::Vector(v)) => Some(Value::Vector(v.clone())),
+            Some(Value::Bytevector(b)) => Some(Value::Bytevector(b.clone())),
+            Some(Value::HashTable(t)) => Some(Value::HashTable(t.clone())),
+            Some(Value::Record(r)) => Some(Value::Record(r.clone())),
+            Some(Value::Port(p)) => Some(Value::Port(p.clone())),
+            Some(Value::Foreign(f)) => Some(Value::Foreign(f.clone())),
+            Some(Value::Eof) => Some(Value::Eof),
+            Some(Value::Nil) => Some(Value::Nil),
+            Some(Value::Unspecified) => Some(Value::Unspecified),
+            Some(Value::Lambda(p)) => Some(Value::Lambda(p.clone())),
+            None => {
+                match self.parent {
+                    Some(ref some_parent) => {
+                        let parent_borrow = some_parent.borrow();
+                        parent_borrow.get(key)
+                    }
+                    None => None
+                }
+            }
+        }
+    }
+}
+
+pub fn parse(program: &str) -> Result<ReadFromTokenResult, SchemeError> {
+    debug!("program: {}", program);
+    // the newline ends a line comment on the last line before the closing parenthesis
+    let wrap_program = format!("(begin {}\n)", program);
+
+    let spanned = tokenize_with_spans(&wrap_program).map_err(|e| read_error(program, e.message))?;
+    let tokens = spanned.iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+    debug!("tokens: {:?}", tokens);
+    let mut position = 0;
+    let result = read_from_tokens(&tokens, &mut position).map_err(|message| read_error(program, message))?;
+    // tokens after the end of the `begin` follow a parenthesis the program closed once
+    // too often, which is where the error is reported, and would be lost
+    if position < tokens.len() {
+        let offset = "(begin ".len();
+        let span = spanned[position - 1].1;
+        return Err(SchemeError::read("unexpected )").with_span(Span { start: span.start - offset, end: span.end - offset }));
+    }
+    debug!("datum: {:?}", result);
+    source_map::record(&result, &spanned, "(begin ".len());
+    Ok(ReadFromTokenResult { remain: tokens[position..].iter().map(|&token| token.to_string()).collect(), result })
+}
+
+/// Byte range of a datum in the source text it was read from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+/// Reads the first datum of `source` without evaluating it, along with the span it covers.
+pub fn parse_datum(source: &str) -> Result<(Value, Span), SchemeError> {
+    let spanned = tokenize_with_spans(source)?;
+    let tokens = spanned.iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+    let mut position = 0;
+    let result = read_from_tokens(&tokens, &mut position).map_err(|message| read_error(source, message))?;
+    let span = Span {
+        start: spanned[0].1.start,
+        end: spanned[position - 1].1.end
+    };
+    Ok((result, span))
+}
+
+// A read error of `source`, placed at the first syntax error `parse_program` finds
+// in it. `parse` reads the program wrapped in a `begin`, which can move the error.
+fn read_error(source: &str, message: &'static str) -> SchemeError {
+    match parse_program(source).1.first() {
+        Some(error) => SchemeError::read(message).with_span(error.span),
+        None => SchemeError::read(message)
+    }
+}
+
+/// Problem found while reading source text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxError {
+    pub span: Span,
+    pub message: &'static str
+}
+
+/// Reads every datum of `source` without evaluating it, going on after syntax errors
+/// so that tools see all of them. A malformed form is skipped up to the parenthesis
+/// that closes it, or up to the next parenthesis opened at the start of a line when
+/// it is never closed. Returns the forms that could be read and the errors.
+pub fn parse_program(source: &str) -> (Vec<(Value, Span)>, Vec<SyntaxError>) {
+    let (tokens, mut errors) = scan(source);
+    let mut forms = vec![];
+    let mut start = 0;
+    while start < tokens.len() {
+        let end = form_end(source, &tokens, start);
+        let span = Span { start: tokens[start].1.start, end: tokens[end - 1].1.end };
+        let group = tokens[start..end].iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+        match read_from_tokens(&group, &mut 0) {
+            Ok(form) => forms.push((form, span)),
+            Err(message) => errors.push(SyntaxError { span, message })
+        }
+        start = end;
+    }
+    errors.sort_by_key(|e| e.span.start);
+    (forms, errors)
+}
+
+// Reads the first form of `source` like `parse_program` does, returning it or its
+// errors, and the length of the text up to where the next form starts. A source
+// holding only comments gives no form and no errors.
+pub(crate) fn read_form(source: &str) -> (Result<Value, Vec<SyntaxError>>, usize) {
+    let (tokens, errors) = scan(source);
+    if tokens.is_empty() {
+        return (Err(errors), source.len());
+    }
+    let end = form_end(source, &tokens, 0);
+    let span = Span { start: tokens[0].1.start, end: tokens[end - 1].1.end };
+    let next = tokens.get(end).map_or(source.len(), |(_, next)| next.start);
+    let inside = errors.into_iter().filter(|e| e.span.start < span.end).collect::<Vec<SyntaxError>>();
+    if !inside.is_empty() {
+        return (Err(inside), next);
+    }
+    let group = tokens[..end].iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+    match read_from_tokens(&group, &mut 0) {
+        Ok(form) => (Ok(form), span.end),
+        Err(message) => (Err(vec![SyntaxError { span, message }]), next)
+    }
+}
+
+// Index just past the tokens of the datum starting at `start`. A list that is never
+// closed ends before the next parenthesis opened at the start of a line.
+fn form_end(source: &str, tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, (token, _)) in tokens.iter().enumerate().skip(start) {
+        if opens_list(token) {
+            depth += 1;
+        } else if token == ")" && depth > 0 {
+            depth -= 1;
+        } else if is_prefix(token) {
+            continue;
+        }
+        if depth == 0 {
+            return i + 1;
+        }
+    }
+    tokens.iter().enumerate().skip(start + 1)
+        .find(|(_, (token, span))| opens_list(token) && source[..span.start].ends_with('\n'))
+        .map_or(tokens.len(), |(i, _)| i)
+}
+
+fn opens_list(token: &str) -> bool {
+    token.ends_with('(') && !token.starts_with("#\\")
+}
+
+/// What reading input that may go on later gives, such as the lines a REPL has read
+/// so far.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PartialParse {
+    /// Every datum of the input is complete.
+    Complete(Vec<Value>),
+    /// The input ends inside a datum, with `missing_parens` lists still open.
+    Incomplete { missing_parens: usize },
+    /// The input has a syntax error that no more input can fix.
+    Invalid(SyntaxError)
+}
+
+/// Reads `source` like `parse_program`, but tells input that ends too early, like an
+/// unbalanced parenthesis or an unterminated string, from a syntax error.
+pub fn parse_partial(source: &str) -> PartialParse {
+    let sink = scan_to_end(source);
+    if sink.errors_before_end > 0 {
+        return PartialParse::Invalid(sink.errors[0].clone());
+    }
+    let mut forms = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+    for (i, (token, span)) in sink.tokens.iter().enumerate() {
+        let (token, span) = (token.as_ref(), *span);
+        if opens_list(token) {
+            depth += 1;
+        } else if token == ")" {
+            if depth == 0 {
+                return PartialParse::Invalid(SyntaxError { span, message: "unexpected )" });
+            }
+            depth -= 1;
+        }
+        if depth == 0 && !is_prefix(token) {
+            let group = sink.tokens[start..=i].iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+            match read_from_tokens(&group, &mut 0) {
+                Ok(form) => forms.push(form),
+                Err(message) => return PartialParse::Invalid(SyntaxError { span: Span { start: sink.tokens[start].1.start, end: span.end }, message })
+            }
+            start = i + 1;
+        }
+    }
+    if sink.incomplete() {
+        PartialParse::Incomplete { missing_parens: depth }
+    } else {
+        PartialParse::Complete(forms)
+    }
+}
+
+/// Writes a value back as source text that `parse_datum` reads into an equal value:
+/// lists without the quotes `Display` puts in front of them, and values on a cycle
+/// with datum labels.
+pub fn unparse(value: &Value) -> String {
+    unparse_datum(value, &mut Labels::new(value))
+}
+
+fn unparse_datum(value: &Value, labels: &mut Labels) -> String {
+    let label = match labels.label(value) {
+        Label::Reference(n) => return format!("#{}#", n),
+        Label::Define(n) => format!("#{}=", n),
+        Label::None => String::new()
+    };
+    match value {
+        Value::Bool(b) => if *b { "#t" } else { "#f" }.to_string(),
+        Value::Symbol(s) => symbol_text(&s.name()).into_owned(),
+        Value::Pair(_) | Value::List(_) | Value::Nil => {
+            let mut elements = vec![];
+            let mut tail = value.clone();
+            loop {
+                match tail {
+                    // a pair with a label other than the first is written as a dotted tail
+                    Value::Pair(ref p) if elements.is_empty() || !labels.is_cyclic(&tail) => {
+                        elements.push(unparse_datum(&p.car(), labels));
+                        tail = p.cdr();
+                    }
+                    Value::List(ref l) => {
+                        elements.extend(l.iter().map(|x| unparse_datum(x, labels)));
+                        return format!("{}({})", label, elements.join(" "));
+                    }
+                    Value::Nil => return format!("{}({})", label, elements.join(" ")),
+                    _ => return format!("{}({} . {})", label, elements.join(" "), unparse_datum(&tail, labels))
+                }
+            }
+        }
+        Value::Vector(v) => format!("{}#({})", label, v.borrow().iter().map(|x| unparse_datum(x, labels)).collect::<Vec<String>>().join(" ")),
+        _ => datatype2str(value)
+    }
+}
+
+fn tokenize_with_spans(program: &str) -> Result<Vec<Token<'_>>, SyntaxError> {
+    let (tokens, errors) = scan(program);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(tokens)
+    }
+}
+
+/// Bounds on the source text the reader accepts, so that hostile input is rejected
+/// while it is tokenized, before it can exhaust the stack or memory. Nesting counts
+/// lists, vectors and quote abbreviations.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReaderLimits {
+    pub max_depth: usize,
+    pub max_datums: usize,
+    pub max_token_length: usize
+}
+
+const DEFAULT_READER_LIMITS: ReaderLimits = ReaderLimits {
+    max_depth: 512,
+    max_datums: 1_000_000,
+    max_token_length: 1 << 20
+};
+
+impl Default for ReaderLimits {
+    fn default() -> ReaderLimits {
+        DEFAULT_READER_LIMITS
+    }
+}
+
+pub fn reader_limits() -> ReaderLimits {
+    context::with(|c| c.reader_limits.get())
+}
+
+/// Replaces the limits of every later read in the current context.
+pub fn set_reader_limits(limits: ReaderLimits) {
+    context::with(|c| c.reader_limits.set(limits));
+}
+
+type Token<'a> = (Cow<'a, str>, Span);
+
+#[derive(Default)]
+struct TokenSink<'a> {
+    // tokens are slices of the source, unless `#!fold-case` changed them
+    tokens: Vec<Token<'a>>,
+    errors: Vec<SyntaxError>,
+    limits: ReaderLimits,
+    // nesting added by each open list, including the abbreviations in front of it
+    open: Vec<usize>,
+    depth: usize,
+    prefixes: usize,
+    datums: usize,
+    exceeded: bool,
+    // `#;` comments still waiting for their datum: where the datum's tokens start, the
+    // depth it is read at and the span of the `#;`
+    datum_comments: Vec<(usize, usize, Span)>,
+    // errors found before the end of the input; those after it only say the input ends early
+    errors_before_end: usize,
+    fold_case: bool
+}
+
+impl<'a> TokenSink<'a> {
+    // Records a token, or the error if it breaks a limit; tokens after that are dropped.
+    fn push(&mut self, token: &'a str, span: Span) {
+        if self.exceeded {
+            return;
+        }
+        // directives are read like comments, and change how the tokens after them are read
+        match token {
+            "#!fold-case" => return self.fold_case = true,
+            "#!no-fold-case" => return self.fold_case = false,
+            _ => {}
+        }
+        let opens = opens_list(token);
+        let error = if token.len() > self.limits.max_token_length {
+            Some("token too long")
+        } else if token == ")" {
+            self.depth -= self.open.pop().unwrap_or(0);
+            self.prefixes = 0;
+            None
+        } else if is_prefix(token) {
+            self.prefixes += 1;
+            if self.depth + self.prefixes > self.limits.max_depth { Some("datum nested too deeply") } else { None }
+        } else {
+            self.datums += 1;
+            if opens {
+                self.open.push(self.prefixes + 1);
+                self.depth += self.prefixes + 1;
+            }
+            self.prefixes = 0;
+            if self.datums > self.limits.max_datums {
+                Some("too many datums")
+            } else if self.depth > self.limits.max_depth {
+                Some("datum nested too deeply")
+            } else {
+                None
+            }
+        };
+        let completes_datum = !opens && !is_prefix(token);
+        match error {
+            Some(message) => {
+                self.errors.push(SyntaxError { span, message });
+                self.exceeded = true;
+            }
+            None if self.fold_case && folds_case(token) => self.tokens.push((Cow::Owned(token.to_lowercase()), span)),
+            None => self.tokens.push((Cow::Borrowed(token), span))
+        }
+        if completes_datum {
+            self.end_datum_comments();
+        }
+    }
+
+    // Records the token of `program` that started at `current`, if any, and ends at `end`.
+    fn end_token(&mut self, program: &'a str, current: &mut Option<usize>, end: usize) {
+        if let Some(start) = current.take() {
+            self.push(&program[start..end], Span { start, end });
+        }
+    }
+
+    fn start_datum_comment(&mut self, span: Span) {
+        self.datum_comments.push((self.tokens.len(), self.depth, span));
+    }
+
+    // Drops the datum of the innermost `#;` once it is complete. A list closed before
+    // the datum started leaves the comment without one.
+    fn end_datum_comments(&mut self) {
+        while let Some(&(start, depth, span)) = self.datum_comments.last() {
+            if self.depth > depth {
+                return;
+            }
+            self.datum_comments.pop();
+            if self.depth < depth {
+                self.errors.push(SyntaxError { span, message: "missing datum after #;" });
+                continue;
+            }
+            self.tokens.truncate(start);
+            // a finished comment does not finish the datum of an enclosing one
+            return;
+        }
+    }
+
+    // Ends the input, which stopped inside a string or comment if there is an error
+    // about it, and reports the `#;` comments still waiting for their datum.
+    fn finish(mut self, unterminated: Option<SyntaxError>) -> TokenSink<'a> {
+        self.errors_before_end = self.errors.len();
+        self.errors.extend(unterminated);
+        for (_, _, span) in self.datum_comments.drain(..) {
+            self.errors.push(SyntaxError { span, message: "missing datum after #;" });
+        }
+        self
+    }
+
+    // Whether more input could complete the data read so far: a list, string or block
+    // comment is still open, or an abbreviation or `#;` waits for its datum.
+    fn incomplete(&self) -> bool {
+        !self.open.is_empty() || self.prefixes > 0 || self.errors.len() > self.errors_before_end
+    }
+}
+
+// Tokens are slices of the program, found in a single pass over it. A string literal
+// is a single token that keeps its surrounding quotes and escape sequences, so that
+// whitespace and parentheses survive; unknown escape sequences are reported here.
+fn scan(program: &str) -> (Vec<Token<'_>>, Vec<SyntaxError>) {
+    let sink = scan_to_end(program);
+    (sink.tokens, sink.errors)
+}
+
+fn scan_to_end(program: &str) -> TokenSink<'_> {
+    let mut sink = TokenSink { limits: reader_limits(), ..TokenSink::default() };
+    // start of the token being read, which runs up to the next delimiter
+    let mut current = None;
+    let mut chars = program.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if sink.exceeded {
+            break;
+        }
+        let text = current.map_or("", |start| &program[start..i]);
+        // the character after `#\` belongs to the token even if it is a delimiter
+        if c == '\\' && text == "#" {
+            chars.next();
+            continue;
+        }
+        match c {
+            ';' if text == "#" => {
+                sink.start_datum_comment(Span { start: i - 1, end: i + 1 });
+                current = None;
+            }
+            '|' if text == "#" => {
+                // block comments nest
+                let mut depth = 1;
+                let mut previous = None;
+                while depth > 0 {
+                    let next = chars.next().map(|(_, x)| x);
+                    match (previous, next) {
+                        (Some('|'), Some('#')) => { depth -= 1; previous = None; continue; }
+                        (Some('#'), Some('|')) => { depth += 1; previous = None; continue; }
+                        (_, None) => {
+                            return sink.finish(Some(SyntaxError { span: Span { start: i - 1, end: program.len() }, message: "unterminated block comment" }));
+                        }
+                        _ => previous = next
+                    }
+                }
+                current = None;
+            }
+            ';' => {
+                sink.end_token(program, &mut current, i);
+                for (_, x) in chars.by_ref() {
+                    if x == '\n' {
+                        break;
+                    }
+                }
+            }
+            '=' if datum_label(&program[current.unwrap_or(i)..i + 1], '=').is_some() => {
+                sink.end_token(program, &mut current, i + 1);
+            }
+            '(' if text == "#" || text == "#u8" => {
+                sink.end_token(program, &mut current, i + 1);
+            }
+            '(' | ')' => {
+                sink.end_token(program, &mut current, i);
+                sink.push(&program[i..i + 1], Span { start: i, end: i + 1 });
+            }
+            // strings, and symbols written between bars like `|two words|`
+            '"' | '|' => {
+                sink.end_token(program, &mut current, i);
+                let (unknown_escape, unterminated) = if c == '"' {
+                    ("unknown escape sequence in string", "can not find an end quote")
+                } else {
+                    ("unknown escape sequence in symbol", "can not find an end bar")
+                };
+                let end = loop {
+                    match chars.next() {
+                        Some((j, x)) if x == c => break j + 1,
+                        Some((j, '\\')) => match chars.next() {
+                            Some((_, 'n')) | Some((_, 't')) | Some((_, '"')) | Some((_, '\\')) | Some((_, '|')) => {}
+                            // `\x41;` is the character with that hexadecimal code point
+                            Some((_, 'x')) => {
+                                let digits = chars.clone().take_while(|&(_, h)| h.is_ascii_hexdigit()).count();
+                                let code = u32::from_str_radix(&program[j + 2..j + 2 + digits], 16).ok().and_then(char::from_u32);
+                                chars.by_ref().take(digits).for_each(drop);
+                                match chars.clone().next() {
+                                    Some((_, ';')) if code.is_some() => { chars.next(); }
+                                    _ => sink.errors.push(SyntaxError { span: Span { start: j, end: j + 2 + digits }, message: "bad hexadecimal escape" })
+                                }
+                            }
+                            Some((_, x)) => sink.errors.push(SyntaxError { span: Span { start: j, end: j + 1 + x.len_utf8() }, message: unknown_escape }),
+                            None => {
+                                return sink.finish(Some(SyntaxError { span: Span { start: i, end: program.len() }, message: unterminated }));
+                            }
+                        },
+                        Some(_) => {}
+                        None => {
+                            return sink.finish(Some(SyntaxError { span: Span { start: i, end: program.len() }, message: unterminated }));
+                        }
+                    }
+                };
+                sink.push(&program[i..end], Span { start: i, end });
+            }
+            '\'' | '`' | ',' if current.is_none() => {
+                let mut end = i + 1;
+                if c == ',' && chars.clone().next().map(|(_, x)| x) == Some('@') {
+                    chars.next();
+                    end += 1;
+                }
+                sink.push(&program[i..end], Span { start: i, end });
+            }
+            _ if c.is_whitespace() => sink.end_token(program, &mut current, i),
+            _ => if current.is_none() {
+                current = Some(i);
+            }
+        }
+    }
+    sink.end_token(program, &mut current, program.len());
+    sink.finish(None)
+}
+
+// What reading a datum keeps between its parts: the datum labels defined so far, and
+// the elements of the lists still open, on one stack that all of them share. A list
+// is then allocated once, at its size, when it is closed, instead of growing as its
+// elements are read. This is as far as the reader goes towards an arena: values are
+// reference counted one by one, so the lists of a parse can not share one buffer
+// that would only be freed with all of them.
+struct Reading {
+    labels: HashMap<usize, Value>,
+    elements: Vec<Value>
+}
+
+// Elements the stack of a read keeps room for when it is given back for the next one.
+const MAX_KEPT_ELEMENTS: usize = 1 << 16;
+
+// Reads the datum at `position` in `tokens` and moves `position` past it.
+fn read_from_tokens(tokens: &[&str], position: &mut usize) -> Result<Value, &'static str> {
+    let elements = context::with(|c| mem::take(&mut *c.read_elements.borrow_mut()));
+    let mut reading = Reading { labels: HashMap::new(), elements };
+    let result = read_datum(tokens, position, &mut reading);
+    reading.elements.clear();
+    if reading.elements.capacity() <= MAX_KEPT_ELEMENTS {
+        context::with(|c| *c.read_elements.borrow_mut() = reading.elements);
+    }
+    result
+}
+
+// Reads a datum, with the labels and the open lists of the read it is part of.
+fn read_datum(tokens: &[&str], position: &mut usize, reading: &mut Reading) -> Result<Value, &'static str> {
+    let token = *tokens.get(*position).ok_or("unexpected EOF while reading")?;
+    *position += 1;
+
+    if token == "(" || token == "#(" || token == "#u8(" {
+        let (elements, tail) = read_elements(token, tokens, position, reading)?;
+        Ok(match token {
+            "#(" => Value::Vector(Rc::new(RefCell::new(elements))),
+            "#u8(" => Value::Bytevector(Rc::new(RefCell::new(bytes(&elements)?))),
+            _ => match tail {
+                Some(tail) => elements.into_iter().rev().fold(tail, |tail, element| Value::cons(element, tail)),
+                None => read_list(elements)
+            }
+        })
+    } else if let Some(name) = abbreviation(token) {
+        // 'datum is read as (quote datum), `datum as (quasiquote datum) and so on
+        let datum = read_datum(tokens, position, reading)?;
+        Ok(read_list(vec![Value::Symbol(SymbolId::intern(name)), datum]))
+    } else if let Some(n) = datum_label(token, '=') {
+        labeled(n, tokens, position, reading)
+    } else if let Some(n) = datum_label(token, '#') {
+        reading.labels.get(&n).cloned().ok_or("undefined datum label")
+    } else if token == ")" {
+        Err("unexpected )")
+    } else {
+        atom(token)
+    }
+}
+
+// Reads the elements of the list, vector or bytevector that `token` opened, up to the
+// closing parenthesis, and the tail after the dot of a dotted list.
+fn read_elements(token: &str, tokens: &[&str], position: &mut usize, reading: &mut Reading) -> Result<(Vec<Value>, Option<Value>), &'static str> {
+    let start = reading.elements.len();
+    let mut dots = vec![];
+    loop {
+        match tokens.get(*position) {
+            Some(&")") => break,
+            Some(&".") => {
+                *position += 1;
+                dots.push(reading.elements.len() - start);
+            }
+            Some(_) => {
+                let element = read_datum(tokens, position, reading)?;
+                reading.elements.push(element);
+            }
+            None => return Err("syntax error")
+        }
+    }
+    *position += 1;
+    let mut elements = reading.elements.split_off(start);
+    // a dot may only appear once, right before the last datum of a non-empty list
+    match dots.as_slice() {
+        [] => Ok((elements, None)),
+        &[dot] if token == "(" && dot > 0 && dot == elements.len() - 1 => {
+            let tail = elements.pop();
+            Ok((elements, tail))
+        }
+        _ => Err("unexpected .")
+    }
+}
+
+fn bytes(elements: &[Value]) -> Result<Vec<u8>, &'static str> {
+    elements.iter().map(|element| match *element {
+        Value::Integer(i) if (0..256).contains(&i) => Ok(i as u8),
+        _ => Err("bytevector element must be a byte")
+    }).collect()
+}
+
+// Reads the datum after the label `#n=`. A list or vector is made before its
+// elements, which may refer to it.
+fn labeled(n: usize, tokens: &[&str], position: &mut usize, reading: &mut Reading) -> Result<Value, &'static str> {
+    let token = *tokens.get(*position).ok_or("unexpected EOF while reading")?;
+    if (token != "(" && token != "#(") || tokens.get(*position + 1) == Some(&")") {
+        let value = read_datum(tokens, position, reading)?;
+        reading.labels.insert(n, value.clone());
+        return Ok(value);
+    }
+    *position += 1;
+    if token == "#(" {
+        let vector = Rc::new(RefCell::new(vec![]));
+        reading.labels.insert(n, Value::Vector(vector.clone()));
+        let (elements, _) = read_elements(token, tokens, position, reading)?;
+        *vector.borrow_mut() = elements;
+        // the datum can refer to itself through its label
+        let value = Value::Vector(vector);
+        gc::track(&value, &value);
+        return Ok(value);
+    }
+    let first = Rc::new(Pair { car: RefCell::new(Value::Nil), cdr: RefCell::new(Value::Nil) });
+    reading.labels.insert(n, Value::Pair(first.clone()));
+    let (elements, tail) = read_elements(token, tokens, position, reading)?;
+    let mut elements = elements.into_iter();
+    first.set_car(elements.next().ok_or("unexpected .")?);
+    let mut last = first.clone();
+    for element in elements {
+        let next = Rc::new(Pair { car: RefCell::new(element), cdr: RefCell::new(Value::Nil) });
+        last.set_cdr(Value::Pair(next.clone()));
+        last = next;
+    }
+    last.set_cdr(tail.unwrap_or(Value::Nil));
+    let value = Value::Pair(first);
+    gc::track(&value, &value);
+    Ok(value)
+}
+
+fn abbreviation(token: &str) -> Option<&'static str> {
+    match token {
+        "'" => Some("quote"),
+        "`" => Some("quasiquote"),
+        "," => Some("unquote"),
+        ",@" => Some("unquote-splicing"),
+        _ => None
+    }
+}
+
+// The number of a datum label `#n=`, or of a reference `#n#` to it, as `end` says.
+fn datum_label(token: &str, end: char) -> Option<usize> {
+    let digits = token.strip_prefix('#')?.strip_suffix(end)?;
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+// Whether `#!fold-case` changes `token`: identifiers and the names of characters and
+// other `#` literals are read in lower case, but not strings, symbols between bars or
+// characters written as themselves, like `#\\A`.
+fn folds_case(token: &str) -> bool {
+    let literal = token.starts_with(['"', '|']) || (token.starts_with("#\\") && token.chars().count() == 3);
+    !literal && token.chars().any(char::is_uppercase)
+}
+
+// Tokens that stand in front of a datum: quote abbreviations and datum labels.
+fn is_prefix(token: &str) -> bool {
+    abbreviation(token).is_some() || datum_label(token, '=').is_some()
+}
+
+// Resolves the escape sequences of a string literal or a symbol between bars. Unknown
+// ones were reported by the tokenizer and are left out.
+fn unescape(s: &str) -> Cow<'_, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some(e @ '"') | Some(e @ '\\') | Some(e @ '|') => unescaped.push(e),
+                Some('x') => {
+                    let code = chars.by_ref().take_while(|&h| h != ';').collect::<String>();
+                    unescaped.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+                }
+                _ => {}
+            },
+            c => unescaped.push(c)
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+fn atom(token: &str) -> Result<Value, &'static str> {
+    if token.len() > 1 && token.starts_with('|') && token.ends_with('|') {
+        return Ok(Value::Symbol(SymbolId::intern(&unescape(&token[1..token.len() - 1]))));
+    }
+    if token.starts_with('#') {
+        return hash_literal(token);
+    }
+    let to_int = token.parse::<i64>();
+    let to_float = float_literal(token);
+
+    // an exact literal that is not a fixnum, or that starts like a number and has a
+    // slash, is a big integer or a ratio, and malformed if it cannot be read as one
+    let numeric = token.strip_prefix(['+', '-']).unwrap_or(token).starts_with(|c: char| c.is_ascii_digit());
+    if to_int.is_err() && numeric && (token.contains('/') || token[1..].chars().all(|c| c.is_ascii_digit())) {
+        return parse_exact(token).map(Value::rational).ok_or("bad number literal");
+    }
+    Ok(if let Ok(i) = to_int {
+        Value::Integer(i)
+    } else if let Some(f) = to_float {
+        Value::Number(f)
+    } else if token.len() > 1 && token.starts_with('"') && token.ends_with('"') {
+        Value::String(intern_string(&unescape(&token[1..token.len() - 1])))
+    } else {
+        Value::Symbol(SymbolId::intern(token))
+    })
+}
+
+/// Cost of a top-level evaluation: every `eval` call is a step, and every cons cell,
+/// list and environment frame created by the interpreter is an allocation. `bytes`
+/// is the room these take, worked out from the number of their elements, whether or
+/// not they are still alive at the end.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvalStatistics {
+    pub steps: usize,
+    pub allocations: usize,
+    pub bytes: usize
+}
+
+// What a context knows of the evaluations it runs.
+#[derive(Default)]
+pub(crate) struct Evaluation {
+    depth: Cell<usize>,
+    max_depth: Cell<usize>,
+    statistics: Cell<EvalStatistics>,
+    last_statistics: Cell<EvalStatistics>,
+    error_environment: RefCell<Option<Rc<RefCell<Env>>>>
+}
+
+fn count_allocation(bytes: usize) {
+    context::with(|c| {
+        let statistics = &c.evaluation.statistics;
+        let mut stats = statistics.get();
+        stats.allocations += 1;
+        stats.bytes = stats.bytes.saturating_add(bytes);
+        statistics.set(stats);
+    });
+}
+
+fn size_of_values(count: usize) -> usize {
+    count.saturating_mul(mem::size_of::<Value>())
+}
+
+// One level of `evaluation_depth`: a top-level evaluation or a procedure call in
+// progress.
+struct DepthGuard;
+
+impl DepthGuard {
+    fn enter() -> DepthGuard {
+        let depth = context::with(|c| {
+            let evaluation = &c.evaluation;
+            let depth = evaluation.depth.get() + 1;
+            evaluation.depth.set(depth);
+            if depth > evaluation.max_depth.get() {
+                evaluation.max_depth.set(depth);
+            }
+            let mut stats = if depth == 1 { EvalStatistics::default() } else { evaluation.statistics.get() };
+            stats.steps += 1;
+            evaluation.statistics.set(stats);
+            depth
+        });
+        if depth == 1 {
+            forget_error();
+            interrupt::start();
+        }
+        DepthGuard
+    }
+}
+
+// Counts a step of the evaluation in progress that does not go a level deeper.
+fn count_step() {
+    context::with(|c| {
+        let statistics = &c.evaluation.statistics;
+        let mut stats = statistics.get();
+        stats.steps += 1;
+        statistics.set(stats);
+    });
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        context::with(|c| {
+            let evaluation = &c.evaluation;
+            evaluation.depth.set(evaluation.depth.get() - 1);
+            if evaluation.depth.get() == 0 {
+                evaluation.last_statistics.set(evaluation.statistics.get());
+            }
+        });
+    }
+}
+
+/// Number of evaluations and procedure calls in progress in the current context: 1
+/// for a top-level `eval`, plus one for each call still waiting for its value. Calls
+/// in tail position take the place of the call they return from, and both engines
+/// count the same way at every optimization level.
+pub fn evaluation_depth() -> usize {
+    context::with(|c| c.evaluation.depth.get())
+}
+
+/// Deepest `evaluation_depth` reached since the last `reset_max_evaluation_depth`.
+pub fn max_evaluation_depth() -> usize {
+    context::with(|c| c.evaluation.max_depth.get())
+}
+
+/// Statistics of the most recent top-level `eval` that finished in the current context.
+pub fn last_eval_statistics() -> EvalStatistics {
+    context::with(|c| c.evaluation.last_statistics.get())
+}
+
+pub fn reset_max_evaluation_depth() {
+    context::with(|c| c.evaluation.max_depth.set(c.evaluation.depth.get()));
+}
+
+/// Environment of the innermost evaluation that failed during the last top-level `eval`,
+/// so that a host can inspect and evaluate expressions in the scope of the failure.
+pub fn error_environment() -> Option<Rc<RefCell<Env>>> {
+    context::with(|c| c.evaluation.error_environment.borrow().clone())
+}
+
+/// The evaluator `eval` runs programs with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    /// Walks the tree read by `parse`, with a stack of frames instead of recursion.
+    /// A form in tail position takes the place of the frame waiting for it, so that
+    /// calls in tail position do not make the stack grow.
+    #[default]
+    TreeWalker,
+    /// Compiles the tree to bytecode and runs it on a virtual machine, where calls in
+    /// tail position do not make the stack grow either. Evaluation depth and steps
+    /// count the calls of lambdas instead of every form.
+    Bytecode
+}
+
+pub fn engine() -> Engine {
+    context::with(|c| c.engine.get())
+}
+
+/// Replaces the evaluator of every later evaluation in the current context.
+pub fn set_engine(engine: Engine) {
+    context::with(|c| c.engine.set(engine));
+}
+
+pub fn fuel() -> Option<usize> {
+    context::with(|c| c.fuel.get())
+}
+
+/// Limits every later top-level evaluation in the current context to `steps` steps,
+/// as `EvalStatistics` counts them, after which it fails with a
+/// `SchemeError::BudgetExceeded`. There is no limit by default.
+pub fn set_fuel(steps: Option<usize>) {
+    context::with(|c| c.fuel.set(steps));
+}
+
+pub fn allocation_limit() -> Option<usize> {
+    context::with(|c| c.allocation_limit.get())
+}
+
+/// Limits the bytes every later top-level evaluation in the current context can
+/// allocate to `bytes`, as `EvalStatistics` counts them, after which it fails with a
+/// `SchemeError::BudgetExceeded`. This is a budget of allocation, not a bound on the
+/// memory in use: everything the evaluation allocates counts, even once it is freed,
+/// and memory that earlier evaluations hold on to does not. There is no limit by
+/// default.
+pub fn set_allocation_limit(bytes: Option<usize>) {
+    context::with(|c| c.allocation_limit.set(bytes));
+}
+
+pub fn depth_limit() -> Option<usize> {
+    context::with(|c| c.depth_limit.get())
+}
+
+/// Limits the `evaluation_depth` of every later evaluation in the current context to
+/// `depth`, beyond which it fails with a `SchemeError::BudgetExceeded`. Deep
+/// recursion does not use up the Rust stack, so there is no limit by default but the
+/// allocation limit.
+pub fn set_depth_limit(depth: Option<usize>) {
+    context::with(|c| c.depth_limit.set(depth));
+}
+
+// Fails if allocating `bytes` more would go over the allocation limit, for the builtins
+// that make objects of any size they are asked for before counting them.
+fn reserve(bytes: usize) -> Result<(), SchemeError> {
+    context::with(|c| match c.allocation_limit.get() {
+        Some(limit) if c.evaluation.statistics.get().bytes.saturating_add(bytes) > limit => Err(SchemeError::budget_exceeded("evaluation allocated more than its limit")),
+        _ => Ok(())
+    })
+}
+
+// Fails if allocating `bytes` more would go over the allocation limit, and counts them
+// otherwise, for the builtins making objects as large as their arguments ask for.
+fn allocate(bytes: usize) -> Result<(), SchemeError> {
+    reserve(bytes)?;
+    count_allocation(bytes);
+    Ok(())
+}
+
+// The bytes of an exact integer or ratio of about `bits` bits.
+fn size_of_bits(bits: u64) -> usize {
+    (bits / 8).to_usize().unwrap_or(usize::MAX)
+}
+
+// `allocate` for a result of the number theory functions of at most `bits` bits,
+// unless it is one of those they refuse to compute.
+fn allocate_bits(bits: u64) -> Result<(), SchemeError> {
+    match bits > number_theory::MAX_RESULT_BITS {
+        true => Ok(()),
+        false => allocate(size_of_bits(bits))
+    }
+}
+
+// `base` to the power `power`, by squaring its numerator and denominator, which stay
+// coprime. Interruptions are looked for between the multiplications, each of which
+// can take long on large numbers.
+fn exact_power(base: &BigRational, power: usize) -> Result<BigRational, SchemeError> {
+    Ok(BigRational::new_raw(integer_power(base.numer(), power)?, integer_power(base.denom(), power)?))
+}
+
+fn integer_power(base: &BigInt, mut power: usize) -> Result<BigInt, SchemeError> {
+    let (mut result, mut square) = (BigInt::one(), base.clone());
+    while power > 0 {
+        interrupt::poll()?;
+        if power % 2 == 1 {
+            result *= &square;
+        }
+        power /= 2;
+        if power > 0 {
+            square = &square * &square;
+        }
+    }
+    Ok(result)
+}
+
+// Builtins working through many elements look for interruptions every so many of
+// them.
+const POLL_INTERVAL: usize = 4096;
+
+// The bits of the product of `numbers`, at most.
+fn product_bits(numbers: &[BigRational]) -> u64 {
+    numbers.iter().fold(0u64, |bits, n| bits.saturating_add(n.numer().bits()).saturating_add(n.denom().bits()))
+}
+
+// Where an evaluation can be stopped: it fails once it has taken more steps than its
+// fuel allows, more memory than its limit or is nested deeper than its limit, or has
+// been interrupted.
+fn safe_point() -> Result<(), SchemeError> {
+    let steps = context::with(|c| {
+        let steps = c.evaluation.statistics.get().steps;
+        match (c.fuel.get(), c.depth_limit.get()) {
+            (Some(fuel), _) if steps > fuel => Err(SchemeError::budget_exceeded("evaluation ran out of fuel")),
+            (_, Some(limit)) if c.evaluation.depth.get() > limit => Err(SchemeError::budget_exceeded("evaluation nested too deeply")),
+            _ => Ok(steps)
+        }
+    })?;
+    reserve(0).and_then(|_| interrupt::check(steps))
+}
+
+/// How much `eval` rewrites programs before evaluating them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OptLevel {
+    /// Programs are evaluated as they were read.
+    #[default]
+    None,
+    /// Programs go through `optimize` first.
+    Simple
+}
+
+pub fn opt_level() -> OptLevel {
+    context::with(|c| c.opt_level.get())
+}
+
+/// Replaces the optimization level of every later evaluation in the current context.
+pub fn set_opt_level(level: OptLevel) {
+    context::with(|c| c.opt_level.set(level));
+}
+
+/// Evaluates the program `form` in `env`, once `expand` has rewritten it into core forms.
+pub fn eval(mut form: Option<Value>, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    if let Some(form) = form.as_mut() {
+        if let Err(error) = expand(form, &env) {
+            let _depth = DepthGuard::enter();
+            observe::error(&error);
+            return Err(error);
+        }
+        if opt_level() == OptLevel::Simple {
+            optimize(form, &env);
+        }
+    }
+    eval_ref(form.as_ref(), env)
+}
+
+// Drops what was recorded about the last error, once it has been handled.
+fn forget_error() {
+    context::with(|c| *c.evaluation.error_environment.borrow_mut() = None);
+    dump::clear_error();
+}
+
+// Keeps the environment of the innermost evaluation that failed with `error`, and
+// what was being applied at that moment, unless a deeper one failed first.
+fn record_failure(env: &Rc<RefCell<Env>>, error: &SchemeError) {
+    let first = context::with(|c| {
+        let mut error_env = c.evaluation.error_environment.borrow_mut();
+        let first = error_env.is_none();
+        if first {
+            *error_env = Some(env.clone());
+        }
+        first
+    });
+    if first {
+        dump::record_error(error);
+        observe::error(error);
+    }
+}
+
+// Evaluation borrows the tree, so subexpressions are not copied at every step.
+fn eval_ref(form: Option<&Value>, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    match form {
+        Some(form) => match engine() {
+            Engine::TreeWalker => machine::run(Code::program(form), env),
+            Engine::Bytecode => {
+                observe::enter_form(form);
+                vm::execute(&compile(form), env)
+            }
+        },
+        None => {
+            let _depth = DepthGuard::enter();
+            Ok(Value::Unspecified)
+        }
+    }
+}
+
+// A new frame for a call of `p`, binding its parameters to `args`.
+fn bind_arguments(p: &Procedure, args: Vec<Value>) -> Rc<RefCell<Env>> {
+    let mut local = frames::take();
+    for (name_ref, value) in p.closure.params.iter().zip(args) {
+        if let Value::Symbol(name) = *name_ref {
+            watch::notify(name, WatchAccess::Write, &value, || None);
+            local.insert(name, value);
+        } else {
+            unreachable!()
+        }
+    }
+
+    let proc_env = Rc::new(RefCell::new(Env::new(local, Some(p.closure.env.clone()))));
+    debug!("proc_env: {:?}", proc_env);
+    count_allocation(mem::size_of::<Env>());
+    proc_env
+}
+
+// Evaluates the body of a lambda in a new frame binding its parameters to `args`.
+// The frame is handed back to the free frames when the body has not captured it.
+fn call_lambda(p: &Procedure, args: Vec<Value>) -> Result<Value, SchemeError> {
+    if p.closure.code.is_some() || engine() == Engine::Bytecode {
+        return vm::call(p, args);
+    }
+    let proc_env = bind_arguments(p, args);
+    let result = machine::run(Code::body(p.closure.body.clone()), proc_env.clone());
+    frames::release(proc_env);
+    result
+}
+
+fn is_procedure(value: &Value) -> bool {
+    matches!(value, Value::Proc(_) | Value::Lambda(_))
+}
+
+// Whether the Scheme comparison procedure `less` orders `a` before `b`.
+fn is_less(less: &Value, a: &Value, b: &Value, env: Rc<RefCell<Env>>) -> Result<bool, SchemeError> {
+    apply_procedure(less, vec![a.clone(), b.clone()], env).map(|result| result != Value::Bool(false))
+}
+
+// Calls `procedure` with the first elements of every list, then the second ones and so
+// on until the shortest list runs out, passing each result to `f`.
+fn apply_elementwise<F>(procedure: &Value, lists: &[Vec<Value>], env: Rc<RefCell<Env>>, mut f: F) -> Result<(), SchemeError>
+    where F: FnMut(Value) {
+    let length = lists.iter().map(Vec::len).min().unwrap_or(0);
+    for i in 0..length {
+        let arguments = lists.iter().map(|list| list[i].clone()).collect();
+        f(apply_procedure(procedure, arguments, env.clone())?);
+    }
+    Ok(())
+}
+
+/// Calls a builtin or a lambda with arguments that are already evaluated, for
+/// builtins that take procedures as arguments.
+pub fn apply_procedure(procedure: &Value, args: Vec<Value>, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    if is_procedure(procedure) {
+        observe::apply(procedure, &args);
+    }
+    match procedure {
+        Value::Proc(f) => f.call(args, env),
+        Value::Lambda(p) => call_lambda(p, args),
+        _ => Err(SchemeError::wrong_type("wrong type to apply"))
+    }
+}
+
+/// A global environment holding the builtins, and the programs evaluated in it, for
+/// crates that embed the interpreter.
+///
+/// ```
+/// use scheme_rs::{Interpreter, Value};
+///
+/// let interpreter = Interpreter::new().unwrap();
+/// interpreter.eval_str("(define (square x) (* x x))").unwrap();
+/// assert_eq!(Ok(Value::Integer(49)), interpreter.eval_str("(square 7)"));
+/// ```
+///
+/// Clones share the global environment, so that a function given to `define_fn` can
+/// keep one to call back into Scheme; the environment then lives as long as the
+/// function does.
+///
+/// Interpreters share no state a program can observe, on the same thread or not.
+/// Besides its environment, each interpreter has a context of its own: its settings,
+/// such as the fuel, its current ports, observer and watches, the objects its cycle
+/// collector tracks, what its last evaluation left behind, and the literals it
+/// interned, which are given up when it is dropped. Only the table of symbol names is
+/// per thread, so that a symbol has the same id in every interpreter on it and in
+/// the values a host keeps after the interpreter is gone. The functions of this crate reading or changing these,
+/// like `set_fuel` or `heap_statistics`, act on the current context, which is that
+/// of the interpreter evaluating, and outside of any the thread's own, where `eval`
+/// and the other free functions evaluate. A new interpreter starts with the settings,
+/// ports and observer of the current context, and changing those afterwards leaves
+/// it alone.
+#[derive(Clone)]
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+    context: Rc<Context>,
+    strictness: Strictness
+}
+
+impl Interpreter {
+    /// An interpreter with every builtin and the procedures of the prelude, which
+    /// fails if the prelude does.
+    pub fn new() -> Result<Interpreter, SchemeError> {
+        InterpreterBuilder::new().build()
+    }
+
+    /// Runs `f` in the context of this interpreter, so that the functions of this
+    /// crate `f` calls act on the settings and state of the interpreter rather than
+    /// on those of the thread.
+    ///
+    /// ```
+    /// use scheme_rs::{fuel, set_fuel, Interpreter};
+    ///
+    /// let interpreter = Interpreter::new().unwrap();
+    /// interpreter.enter(|| set_fuel(Some(1_000)));
+    /// assert!(interpreter.eval_str("(define (forever) (forever)) (forever)").is_err());
+    /// assert_eq!(None, fuel());
+    /// ```
+    pub fn enter<F, R>(&self, f: F) -> R where F: FnOnce() -> R {
+        let _context = context::install(&self.context);
+        f()
+    }
+
+    /// Reads and evaluates every expression of `source` in the global environment,
+    /// returning the value of the last.
+    pub fn eval_str(&self, source: &str) -> Result<Value, SchemeError> {
+        self.enter(|| {
+            let ast = parse(source)?;
+            if self.strictness == Strictness::Strict {
+                if let Some(&(id, span)) = check::unbound_variables(source, &self.env).first() {
+                    let error = SchemeError::unbound(&id.name()).with_span(span);
+                    observe::error(&error);
+                    return Err(error);
+                }
+            }
+            eval(Some(ast.result), self.env.clone())
+        })
+    }
+
+    /// Makes `port` the current input port of the evaluations of this interpreter
+    /// and its clones, or standard input again with None.
+    pub fn set_input_port(&self, port: Option<Rc<Port>>) {
+        self.context.ports.borrow_mut().input = port;
+    }
+
+    /// Makes `port` the current output port of the evaluations of this interpreter
+    /// and its clones, or standard output again with None.
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use scheme_rs::{Interpreter, Port};
+    ///
+    /// let interpreter = Interpreter::new().unwrap();
+    /// interpreter.set_output_port(Some(Rc::new(Port::output(Box::new(std::io::sink())))));
+    /// interpreter.eval_str("(display \"nobody sees this\")").unwrap();
+    /// ```
+    pub fn set_output_port(&self, port: Option<Rc<Port>>) {
+        self.context.ports.borrow_mut().output = port;
+    }
+
+    /// Makes `port` the current error port of the evaluations of this interpreter
+    /// and its clones, or standard error again with None.
+    pub fn set_error_port(&self, port: Option<Rc<Port>>) {
+        self.context.ports.borrow_mut().error = port;
+    }
+
+    /// The handle interrupting the evaluations of this interpreter and its clones.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.enter(interrupt_handle)
+    }
+
+    /// Starts reporting the evaluations of this interpreter and its clones to
+    /// `observer`, or stops with None.
+    pub fn set_eval_observer(&self, observer: Option<Rc<dyn EvalObserver>>) {
+        self.enter(|| set_eval_observer(observer));
+    }
+
+    /// The environment programs are evaluated in, where they define their names.
+    pub fn global_env(&self) -> Rc<RefCell<Env>> {
+        self.env.clone()
+    }
+
+    /// Binds `name` in the global environment to a procedure calling `f` with its
+    /// arguments, replacing a builtin of the same name. `f` checks the number and
+    /// types of the arguments itself, and may keep state of the host program.
+    ///
+    /// ```
+    /// use scheme_rs::{Interpreter, SchemeError, Value};
+    ///
+    /// let interpreter = Interpreter::new().unwrap();
+    /// interpreter.define_fn("double", |args: &[Value]| match args {
+    ///     [Value::Integer(n)] => Ok(Value::Integer(n * 2)),
+    ///     _ => Err(SchemeError::wrong_type("double function requires an integer"))
+    /// });
+    /// assert_eq!(Ok(Value::Integer(42)), interpreter.eval_str("(double 21)"));
+    /// ```
+    pub fn define_fn<F>(&self, name: &str, f: F)
+        where F: Fn(&[Value]) -> Result<Value, SchemeError> + 'static {
+        let id = self.enter(|| SymbolId::intern(name));
+        let body = Rc::new(move |args: Vec<Value>, _: Rc<RefCell<Env>>| f(&args));
+        self.env.borrow_mut().bind(id, Value::Proc(Function::new(Arity::at_least(0), body).named(id)));
+    }
+
+    /// Calls `procedure`, a lambda or a builtin, with `args` as they are, without
+    /// evaluating them.
+    ///
+    /// ```
+    /// use scheme_rs::{Interpreter, Value};
+    ///
+    /// let interpreter = Interpreter::new().unwrap();
+    /// let on_save = interpreter.eval_str("(lambda (name size) (> size 100))").unwrap();
+    /// let args = [Value::string("notes.txt"), Value::Integer(250)];
+    /// assert_eq!(Ok(Value::Bool(true)), interpreter.call(&on_save, &args));
+    /// ```
+    pub fn call(&self, procedure: &Value, args: &[Value]) -> Result<Value, SchemeError> {
+        self.enter(|| apply_procedure(procedure, args.to_vec(), self.env.clone()))
+    }
+}
+
+// The procedures of the prelude and those a program defines are on cycles through
+// the frames binding them, which only the collector of the interpreter frees. The
+// last handle of an interpreter, once it has let go of the global environment, runs
+// it to free them, unless something else still holds the environment.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.context) == 1 {
+            let env = mem::replace(&mut self.env, Rc::new(RefCell::new(Env::new(HashMap::new(), None))));
+            let _context = context::install(&self.context);
+            drop(env);
+            collect_garbage();
+        }
+    }
+}
+
+/// How much `Interpreter::eval_str` checks a program before evaluating it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strictness {
+    /// Errors are found as the program runs into them, so that a mistake in a branch
+    /// that is never taken goes unnoticed.
+    #[default]
+    Lenient,
+    /// A program referring to a variable that neither it nor the interpreter binds
+    /// fails before any of it is evaluated, as `check` would report it.
+    Strict
+}
+
+// The settings `InterpreterBuilder` gives an interpreter. Those it has none of are
+// left as the current context has them when the interpreter is built.
+#[derive(Clone, Copy, Debug, Default)]
+struct Settings {
+    engine: Option<Engine>,
+    opt_level: Option<OptLevel>,
+    reader_limits: Option<ReaderLimits>,
+    overflow_mode: Option<OverflowMode>,
+    fuel: Option<usize>,
+    allocation_limit: Option<usize>,
+    timeout: Option<Duration>,
+    depth_limit: Option<usize>,
+    strictness: Strictness
+}
+
+impl Settings {
+    fn apply(&self, context: &Context) {
+        if let Some(engine) = self.engine {
+            context.engine.set(engine);
+        }
+        if let Some(level) = self.opt_level {
+            context.opt_level.set(level);
+        }
+        if let Some(limits) = self.reader_limits {
+            context.reader_limits.set(limits);
+        }
+        if let Some(mode) = self.overflow_mode {
+            context.overflow_mode.set(mode);
+        }
+        if let Some(steps) = self.fuel {
+            context.fuel.set(Some(steps));
+        }
+        if let Some(bytes) = self.allocation_limit {
+            context.allocation_limit.set(Some(bytes));
+        }
+        if let Some(duration) = self.timeout {
+            context.interrupts.set_timeout(Some(duration));
+        }
+        if let Some(depth) = self.depth_limit {
+            context.depth_limit.set(Some(depth));
+        }
+    }
+}
+
+/// Makes an interpreter with the builtins and settings of its own a host wants, as
+/// `Interpreter::new` makes one with every builtin and the settings of the current
+/// context. The settings are those of the evaluations of the interpreter and its
+/// clones.
+///
+/// ```
+/// use scheme_rs::{InterpreterBuilder, OverflowMode, Sandbox, SchemeError, Strictness};
+///
+/// let interpreter = InterpreterBuilder::new()
+///     .sandbox(Sandbox::Pure)
+///     .exclude("string-append")
+///     .overflow_mode(OverflowMode::Error)
+///     .strictness(Strictness::Strict)
+///     .fuel(10_000)
+///     .depth_limit(100)
+///     .stdout(std::io::sink())
+///     .build().unwrap();
+/// assert!(interpreter.eval_str("(display \"hi\")").is_err());
+/// assert!(interpreter.eval_str("(string-append \"h\" \"i\")").is_err());
+/// assert!(interpreter.eval_str("(* 4611686018427387904 2)").is_err());
+/// assert!(interpreter.eval_str("(if #t 1 typo)").is_err());
+/// let result = interpreter.eval_str("(define (forever) (forever)) (forever)");
+/// assert!(matches!(result, Err(SchemeError::BudgetExceeded { .. })));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InterpreterBuilder {
+    sandbox: Sandbox,
+    excluded: Vec<String>,
+    persistent: bool,
+    settings: Settings,
+    ports: Ports
+}
+
+impl InterpreterBuilder {
+    pub fn new() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+
+    /// Leaves out the builtins `sandbox` does not allow.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> InterpreterBuilder {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Leaves out the builtin `name`, whichever the sandbox is.
+    pub fn exclude(mut self, name: &str) -> InterpreterBuilder {
+        self.excluded.push(name.to_string());
+        self
+    }
+
+    /// Keeps the global bindings in a `PersistentMap`, as `Env::persistent` does, so
+    /// that `Env::persistent_bindings` forks them without copying any. The forks are
+    /// for the thread of the interpreter, like its values.
+    pub fn persistent_environment(mut self) -> InterpreterBuilder {
+        self.persistent = true;
+        self
+    }
+
+    /// The evaluator of every top-level evaluation, as `set_engine` sets it.
+    pub fn engine(mut self, engine: Engine) -> InterpreterBuilder {
+        self.settings.engine = Some(engine);
+        self
+    }
+
+    /// How much programs are rewritten before they are evaluated, as `set_opt_level`
+    /// sets it.
+    pub fn opt_level(mut self, level: OptLevel) -> InterpreterBuilder {
+        self.settings.opt_level = Some(level);
+        self
+    }
+
+    /// The limits of the programs and data the interpreter reads, as
+    /// `set_reader_limits` sets them.
+    pub fn reader_limits(mut self, limits: ReaderLimits) -> InterpreterBuilder {
+        self.settings.reader_limits = Some(limits);
+        self
+    }
+
+    /// What arithmetic does when a fixnum overflows, as `set_overflow_mode` sets it.
+    pub fn overflow_mode(mut self, mode: OverflowMode) -> InterpreterBuilder {
+        self.settings.overflow_mode = Some(mode);
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> InterpreterBuilder {
+        self.settings.strictness = strictness;
+        self
+    }
+
+    /// Limits every top-level evaluation to `steps` steps, as `set_fuel` does.
+    pub fn fuel(mut self, steps: usize) -> InterpreterBuilder {
+        self.settings.fuel = Some(steps);
+        self
+    }
+
+    /// Limits the bytes every top-level evaluation allocates to `bytes`, as
+    /// `set_allocation_limit` does.
+    pub fn allocation_limit(mut self, bytes: usize) -> InterpreterBuilder {
+        self.settings.allocation_limit = Some(bytes);
+        self
+    }
+
+    /// Stops every top-level evaluation running longer than `duration`, as
+    /// `set_timeout` does.
+    pub fn timeout(mut self, duration: Duration) -> InterpreterBuilder {
+        self.settings.timeout = Some(duration);
+        self
+    }
+
+    /// Limits the nesting of evaluations to `depth`, as `set_depth_limit` does.
+    pub fn depth_limit(mut self, depth: usize) -> InterpreterBuilder {
+        self.settings.depth_limit = Some(depth);
+        self
+    }
+
+    /// Where the current input port reads from.
+    pub fn stdin<R: Read + 'static>(mut self, reader: R) -> InterpreterBuilder {
+        self.ports.input = Some(Rc::new(Port::input(Box::new(reader))));
+        self
+    }
+
+    /// Where the current output port writes to.
+    pub fn stdout<W: Write + 'static>(mut self, writer: W) -> InterpreterBuilder {
+        self.ports.output = Some(Rc::new(Port::output(Box::new(writer))));
+        self
+    }
+
+    /// Where the current error port writes to.
+    pub fn stderr<W: Write + 'static>(mut self, writer: W) -> InterpreterBuilder {
+        self.ports.error = Some(Rc::new(Port::output(Box::new(writer))));
+        self
+    }
+
+    /// The interpreter, once the prelude has been evaluated with the builtins left in.
+    pub fn build(self) -> Result<Interpreter, SchemeError> {
+        let context = Rc::new(Context::inherit());
+        self.settings.apply(&context);
+        context.ports.borrow_mut().replace_with(self.ports.clone());
+        let _context = context::install(&context);
+        let excluded = |name: SymbolId| self.excluded.iter().any(|excluded| **excluded == *name.name());
+        let mut builtins = setup();
+        builtins.retain(|&name, _| {
+            let allowed = match BuiltinGroup::of(&name.name()) {
+                Some(group) => self.sandbox.allows(group),
+                None => self.sandbox == Sandbox::Full
+            };
+            allowed && !excluded(name)
+        });
+        // the procedures of the prelude see the builtins left alone, and are left out
+        // by name like them
+        define_prelude(&mut builtins)?;
+        builtins.retain(|&name, _| !excluded(name));
+        let env = match self.persistent {
+            true => Env::persistent(builtins.into_iter().collect(), None),
+            false => Env::new(builtins, None)
+        };
+        Ok(Interpreter { env: Rc::new(RefCell::new(env)), context: context.clone(), strictness: self.settings.strictness })
+    }
+}
+
+pub fn setup() -> HashMap<SymbolId, Value> {
+    let mut map = HashMap::new();
+    map.insert(SymbolId::intern("pi"), Value::Number(std::f64::consts::PI));
+
+    map.insert(SymbolId::intern("+"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "+", vec);
+        if let Some(sum) = fold_fixnums(&vec, i64::checked_add) {
+            return Ok(Value::Integer(sum));
+        }
+        check_overflow(&vec)?;
+        if let Some(integers) = exact_integers(&vec) {
+            return Ok(Value::integer(integers.into_iter().sum()));
+        }
+        if let Some(rationals) = exact_rationals(&vec) {
+            return Ok(Value::rational(rationals.into_iter().sum()));
+        }
+        let numbers = match inexact_numbers(&vec) {
+            Some(numbers) => numbers,
+            None => return Err(SchemeError::wrong_type("wrong argument datatype"))
+        };
+
+        debug!("Description: {}", numbers.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(" + "));
+        Ok(Value::Number(numbers.into_iter().sum()))
+    }))));
+
+    map.insert(SymbolId::intern("-"), Value::Proc(Function::new(Arity::at_least(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "-", vec);
+        if vec.is_empty() {
+            return Err(SchemeError::arity("- function requires at least one argument"));
+        }
+        let difference = match vec.as_slice() {
+            [Value::Integer(i)] => i.checked_neg(),
+            _ => fold_fixnums(&vec, i64::checked_sub)
+        };
+        if let Some(difference) = difference {
+            return Ok(Value::Integer(difference));
+        }
+        check_overflow(&vec)?;
+        if let Some(integers) = exact_integers(&vec) {
+            let (first, rest) = integers.split_first().unwrap();
+            if rest.is_empty() {
+                return Ok(Value::integer(-first));
+            }
+            return Ok(Value::integer(rest.iter().fold(first.clone(), |acc, x| acc - x)));
+        }
+        if let Some(rationals) = exact_rationals(&vec) {
+            let (first, rest) = rationals.split_first().unwrap();
+            if rest.is_empty() {
+                return Ok(Value::rational(-first));
+            }
+            return Ok(Value::rational(rest.iter().fold(first.clone(), |acc, x| acc - x)));
+        }
+        let numbers = match inexact_numbers(&vec) {
+            Some(numbers) => numbers,
+            None => return Err(SchemeError::wrong_type("wrong argument datatype"))
+        };
+
+        debug!("Description: {}", numbers.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(" - "));
+
+        let value = match numbers.split_first() {
+            Some((first, [])) => -first,
+            Some((first, rest)) => rest.iter().fold(*first, |acc, x| acc - x),
+            None => 0.0
+        };
+        Ok(Value::Number(value))
+    }))));
+
+    map.insert(SymbolId::intern("*"), Value::Proc(
+        Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+            debug!("Function - name: {:?} - Args: {:?}", "*", vec);
+            if let Some(product) = fold_fixnums(&vec, i64::checked_mul) {
+                return Ok(Value::Integer(product));
+            }
+            check_overflow(&vec)?;
+            if let Some(integers) = exact_integers(&vec) {
+                allocate(size_of_bits(integers.iter().fold(0u64, |bits, n| bits.saturating_add(n.bits()))))?;
+                return Ok(Value::integer(integers.into_iter().product()));
+            }
+            if let Some(rationals) = exact_rationals(&vec) {
+                allocate(size_of_bits(product_bits(&rationals)))?;
+                return Ok(Value::rational(rationals.into_iter().product()));
+            }
+            let numbers = match inexact_numbers(&vec) {
+                Some(numbers) => numbers,
+                None => return Err(SchemeError::wrong_type("wrong argument datatype"))
+            };
+
+            debug!("Description: {}", numbers.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(" x "));
+            Ok(Value::Number(numbers.into_iter().product()))
+        }))));
+
+    map.insert(SymbolId::intern("/"), Value::Proc(Function::new(Arity::at_least(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "/", vec);
+        if vec.is_empty() {
+            return Err(SchemeError::arity("/ function requires at least one argument"));
+        }
+        if let Some(rationals) = exact_rationals(&vec) {
+            let (first, rest) = rationals.split_first().unwrap();
+            let divisors = if rest.is_empty() { std::slice::from_ref(first) } else { rest };
+            if divisors.iter().any(|x| x.is_zero()) {
+                return Err(SchemeError::other("division by zero"));
+            }
+            if rest.is_empty() {
+                return Ok(Value::rational(first.recip()));
+            }
+            return Ok(Value::rational(rest.iter().fold(first.clone(), |acc, x| acc / x)));
+        }
+        let numbers = match inexact_numbers(&vec) {
+            Some(numbers) => numbers,
+            None => return Err(SchemeError::wrong_type("wrong argument datatype"))
+        };
+
+        debug!("Description: {}", numbers.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(" / "));
+
+        let value = match numbers.split_first() {
+            Some((first, [])) => 1.0 / first,
+            Some((first, rest)) => rest.iter().fold(*first, |acc, x| acc / x),
+            None => 0.0
+        };
+        Ok(Value::Number(value))
+    }))));
+
+    define_comparison!(gt, ">", |o| o == Ordering::Greater);
+    map.insert(SymbolId::intern(">"), gt);
+
+    define_comparison!(lt, "<", |o| o == Ordering::Less);
+    map.insert(SymbolId::intern("<"), lt);
+
+    define_comparison!(eq, "=", |o| o == Ordering::Equal);
+    map.insert(SymbolId::intern("="), eq);
+
+    define_comparison!(ge, ">=", |o| o != Ordering::Less);
+    map.insert(SymbolId::intern(">="), ge);
+
+    define_comparison!(le, "<=", |o| o != Ordering::Greater);
+    map.insert(SymbolId::intern("<="), le);
+
+    map.insert(SymbolId::intern("abs"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "abs", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("abs function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("abs function unknown argument type"));
+        }
+        match value_option.unwrap() {
+            &Value::Number(f) => Ok(Value::Number(f.abs())),
+            &Value::Integer(i) => match i.checked_abs() {
+                Some(abs) => Ok(Value::Integer(abs)),
+                None => check_overflow(&vec).map(|_| Value::integer(BigInt::from(i).abs()))
+            },
+            Value::BigInt(b) => Ok(Value::integer(b.abs())),
+            Value::Rational(r) => Ok(Value::Rational(Rc::new(r.abs()))),
+            _ => Err(SchemeError::wrong_type("abs function requires an argument of type 'number'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("acos"), transcendental("acos", f64::acos, (1, 0)));
+
+    map.insert(SymbolId::intern("append"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "append", vec);
+
+        // every argument but the last has to be a list, the last one becomes the tail
+        let (last, init) = match vec.split_last() {
+            Some(split) => split,
+            None => return Ok(Value::Nil)
+        };
+
+        let mut elements = vec![];
+        for item in init {
+            match list_elements(item) {
+                Some(mut l) => elements.append(&mut l),
+                None => return Err(SchemeError::wrong_type("append function requires an argument of type 'list'"))
+            }
+        }
+
+        match last {
+            Value::List(l) => {
+                elements.extend(l.iter().cloned());
+                Ok(Value::list(elements))
+            }
+            Value::Nil => {
+                Ok(Value::list(elements))
+            }
+            _ => Ok(elements.into_iter().rev().fold(last.clone(), |tail, x| Value::cons(x, tail)))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("append!"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "append!", vec);
+        let mut rest = vec.into_iter().rev();
+        match rest.next() {
+            Some(last) => rest.try_fold(last, |tail, head| append_in_place(head, tail)),
+            None => Ok(Value::Nil)
+        }
+    }))));
+
+    map.insert(SymbolId::intern("apply"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "apply", vec);
+
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("apply function requires two arguments"));
+        }
+
+        tuplet!((s0,s1) = vec);
+        if let Some(args) = s1.and_then(list_elements) {
+            match s0 {
+                Some(procedure @ Value::Proc(_)) | Some(procedure @ Value::Lambda(_)) => apply_procedure(procedure, args, env),
+                Some(_) | None => Err(SchemeError::wrong_type("apply function unknown first argument type"))
+            }
+        } else {
+            Err(SchemeError::wrong_type("apply function requires a last argument of type 'list'"))
+        }
+    }))));
+
+    // pre-defined commands
+
+    map.insert(SymbolId::intern("asin"), transcendental("asin", f64::asin, (0, 0)));
+
+    map.insert(SymbolId::intern("assoc"), Value::Proc(Function::new(Arity::between(2, 3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "assoc", vec);
+        match vec.as_slice() {
+            [key, list] => find_association(key, list, |a, b| Ok(is_equal(a, b))).ok_or(SchemeError::wrong_type("assoc function requires an argument of type 'list'"))?,
+            [key, list, compare] if is_procedure(compare) => {
+                let same = |a: &Value, b: &Value| apply_procedure(compare, vec![a.clone(), b.clone()], env.clone()).map(|r| r != Value::Bool(false));
+                find_association(key, list, same).ok_or(SchemeError::wrong_type("assoc function requires an argument of type 'list'"))?
+            }
+            _ => Err(SchemeError::wrong_type("assoc function requires two arguments and an optional procedure"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("assq"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "assq", vec);
+        match vec.as_slice() {
+            [key, list] => find_association(key, list, |a, b| Ok(is_eq(a, b))).ok_or(SchemeError::wrong_type("assq function requires an argument of type 'list'"))?,
+            _ => Err(SchemeError::arity("assq function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("assv"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "assv", vec);
+        match vec.as_slice() {
+            [key, list] => find_association(key, list, |a, b| Ok(is_eqv(a, b))).ok_or(SchemeError::wrong_type("assv function requires an argument of type 'list'"))?,
+            _ => Err(SchemeError::arity("assv function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("atan"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "atan", vec);
+        if vec == [Value::Integer(0)] {
+            return Ok(Value::Integer(0));
+        }
+        match (inexact_numbers(&vec).as_deref(), vec.len()) {
+            (Some(&[y, x]), 2) => Ok(Value::Number(y.atan2(x))),
+            (Some(&[x]), 1) => Ok(Value::Number(x.atan())),
+            _ => Err(SchemeError::wrong_type("math function requires an argument of type 'number'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("begin"), Value::Proc(
+        Function::new(Arity::at_least(0), Rc::new(|mut vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+            debug!("Function - name: {:?} - Args: {:?}", "begin", vec);
+            Ok(vec.pop().unwrap_or(Value::Unspecified))
+        }))
+    ));
+
+    map.insert(SymbolId::intern("binomial"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "binomial", vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("binomial function requires two arguments"));
+        }
+        match (integer_argument(&vec[0]).and_then(|n| n.to_u64()), integer_argument(&vec[1]).and_then(|k| k.to_u64())) {
+            (Some(n), Some(k)) => {
+                let factors = if k > n { 0 } else { k.min(n - k) };
+                allocate_bits(number_theory::product_bits(n.saturating_sub(factors).saturating_add(1), n))?;
+                match number_theory::binomial(n, k, &interrupt::poll)? {
+                    Some(binomial) => Ok(Value::integer(binomial)),
+                    None => Err(SchemeError::other("binomial result is too large"))
+                }
+            }
+            _ => Err(SchemeError::wrong_type("binomial function requires arguments of type 'non-negative integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("boolean=?"), Value::Proc(Function::new(Arity::at_least(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "boolean=?", vec);
+        if vec.len() < 2 {
+            return Err(SchemeError::arity("boolean=? function requires at least two arguments"));
+        }
+        if !vec.iter().all(|v| matches!(v, Value::Bool(_))) {
+            return Err(SchemeError::wrong_type("boolean=? function requires arguments of type 'boolean'"));
+        }
+        Ok(Value::Bool(vec.windows(2).all(|w| w[0] == w[1])))
+    }))));
+
+    map.insert(SymbolId::intern("boolean?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "boolean?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("boolean? function requires one argument only"));
+        }
+        Ok(Value::Bool(matches!(vec[0], Value::Bool(_))))
+    }))));
+
+    map.insert(SymbolId::intern("bound?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bound?", vec);
+        match vec.as_slice() {
+            [Value::Symbol(id)] => Ok(Value::Bool(env.borrow().is_bound(*id))),
+            _ => Err(SchemeError::wrong_type("bound? function requires an argument of type 'symbol'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("bytevector"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector", vec);
+        match vec.iter().map(byte_argument).collect::<Option<Vec<u8>>>() {
+            Some(bytes) => {
+                count_allocation(bytes.len());
+                Ok(Value::Bytevector(Rc::new(RefCell::new(bytes))))
+            }
+            None => Err(SchemeError::wrong_type("bytevector function requires arguments of type 'byte'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("bytevector-append"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector-append", vec);
+        let length = vec.iter().try_fold(0usize, |length, value| match value {
+            Value::Bytevector(b) => Ok(length.saturating_add(b.borrow().len())),
+            _ => Err(SchemeError::wrong_type("bytevector-append function requires arguments of type 'bytevector'"))
+        })?;
+        allocate(length)?;
+        let mut bytes = Vec::with_capacity(length);
+        for value in &vec {
+            if let Value::Bytevector(b) = value {
+                bytes.extend(b.borrow().iter());
+            }
+        }
+        Ok(Value::Bytevector(Rc::new(RefCell::new(bytes))))
+    }))));
+
+    map.insert(SymbolId::intern("bytevector-length"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector-length", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("bytevector-length function requires one argument only"));
+        }
+        match vec[0] {
+            Value::Bytevector(ref b) => Ok(Value::Integer(b.borrow().len() as i64)),
+            _ => Err(SchemeError::wrong_type("bytevector-length function requires an argument of type 'bytevector'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("bytevector-u8-ref"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector-u8-ref", vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("bytevector-u8-ref function requires two arguments"));
+        }
+        match (&vec[0], integer_argument(&vec[1]).and_then(|k| k.to_usize())) {
+            (Value::Bytevector(b), Some(k)) => match b.borrow().get(k) {
+                Some(&byte) => Ok(Value::Integer(byte as i64)),
+                None => Err(SchemeError::out_of_range("bytevector-u8-ref index out of range"))
+            },
+            _ => Err(SchemeError::wrong_type("bytevector-u8-ref function requires arguments of type 'bytevector' and 'non-negative integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("bytevector-u8-set!"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector-u8-set!", vec);
+        if vec.len() != 3 {
+            return Err(SchemeError::arity("bytevector-u8-set! function requires three arguments"));
+        }
+        let byte = match byte_argument(&vec[2]) {
+            Some(byte) => byte,
+            None => return Err(SchemeError::wrong_type("bytevector-u8-set! function requires a value of type 'byte'"))
+        };
+        match (&vec[0], integer_argument(&vec[1]).and_then(|k| k.to_usize())) {
+            (Value::Bytevector(b), Some(k)) => match b.borrow_mut().get_mut(k) {
+                Some(slot) => {
+                    *slot = byte;
+                    Ok(Value::Unspecified)
+                }
+                None => Err(SchemeError::out_of_range("bytevector-u8-set! index out of range"))
+            },
+            _ => Err(SchemeError::wrong_type("bytevector-u8-set! function requires arguments of type 'bytevector' and 'non-negative integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("bytevector?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "bytevector?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("bytevector? function requires one argument only"));
+        }
+        Ok(Value::Bool(matches!(vec[0], Value::Bytevector(_))))
+    }))));
+
+    map.insert(SymbolId::intern("car"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "car", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("car function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("car function unknown argument type"));
+        }
+        match value_option.unwrap() {
+            Value::List(vec) => {
+                match vec.first() {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(SchemeError::wrong_type("car function requires a non-empty list"))
+                }
+            }
+            Value::Nil => Err(SchemeError::wrong_type("car function requires a non-empty list")),
+            Value::Pair(p) => Ok(p.car()),
+            _ => Err(SchemeError::wrong_type("car function requires an argument of type 'list' / 'pair'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("cdr"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "cdr", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("cdr function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("cdr function unknown argument type"));
+        }
+        match value_option.unwrap() {
+            // the rest of the list shares its elements
+            Value::List(list) => Ok(list.tail(1).map_or(Value::Nil, Value::List)),
+            Value::Nil => Err(SchemeError::wrong_type("cdr function requires a non-empty list")),
+            Value::Pair(p) => Ok(p.cdr()),
+            _ => Err(SchemeError::wrong_type("cdr function requires an argument of type 'list'/ 'pair'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("ceiling"), rounding("ceiling", |r| r.ceil(), f64::ceil));
+
+    map.insert(SymbolId::intern("char->integer"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char->integer", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("char->integer function requires one argument only"));
+        }
+        match vec[0] {
+            Value::Char(c) => Ok(Value::Integer(c as i64)),
+            _ => Err(SchemeError::wrong_type("char->integer function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char-alphabetic?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char-alphabetic?", vec);
+        match vec.as_slice() {
+            [Value::Char(c)] => Ok(Value::Bool(c.is_alphabetic())),
+            _ => Err(SchemeError::wrong_type("char-alphabetic? function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char-downcase"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char-downcase", vec);
+        // a character whose case mapping takes several characters is left alone
+        match vec.as_slice() {
+            [Value::Char(c)] => {
+                let mut mapped = c.to_lowercase();
+                Ok(Value::Char(match (mapped.next(), mapped.next()) {
+                    (Some(m), None) => m,
+                    _ => *c
+                }))
+            }
+            _ => Err(SchemeError::wrong_type("char-downcase function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char-numeric?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char-numeric?", vec);
+        match vec.as_slice() {
+            [Value::Char(c)] => Ok(Value::Bool(c.is_numeric())),
+            _ => Err(SchemeError::wrong_type("char-numeric? function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char-upcase"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char-upcase", vec);
+        // a character whose case mapping takes several characters is left alone
+        match vec.as_slice() {
+            [Value::Char(c)] => {
+                let mut mapped = c.to_uppercase();
+                Ok(Value::Char(match (mapped.next(), mapped.next()) {
+                    (Some(m), None) => m,
+                    _ => *c
+                }))
+            }
+            _ => Err(SchemeError::wrong_type("char-upcase function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char-whitespace?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char-whitespace?", vec);
+        match vec.as_slice() {
+            [Value::Char(c)] => Ok(Value::Bool(c.is_whitespace())),
+            _ => Err(SchemeError::wrong_type("char-whitespace? function requires an argument of type 'char'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("char<=?"), strings::char_comparison(|o| o != Ordering::Greater));
+    map.insert(SymbolId::intern("char<?"), strings::char_comparison(|o| o == Ordering::Less));
+    map.insert(SymbolId::intern("char=?"), strings::char_comparison(|o| o == Ordering::Equal));
+    map.insert(SymbolId::intern("char>=?"), strings::char_comparison(|o| o != Ordering::Less));
+    map.insert(SymbolId::intern("char>?"), strings::char_comparison(|o| o == Ordering::Greater));
+
+    map.insert(SymbolId::intern("char?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "char?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("char? function requires one argument only"));
+        }
+        Ok(Value::Bool(matches!(vec[0], Value::Char(_))))
+    }))));
+
+    map.insert(SymbolId::intern("close-port"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "close-port", vec);
+        match vec.as_slice() {
+            [Value::Port(p)] => {
+                p.close();
+                Ok(Value::Unspecified)
+            }
+            [_] => Err(SchemeError::wrong_type("close-port function requires an argument of type 'port'")),
+            _ => Err(SchemeError::arity("close-port function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("cons"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "cons", vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("cons function requires two argument only"));
+        }
+
+        if let (Some(x), Some(y)) = (vec.first(), vec.get(1)) {
+            Ok(Value::cons(x.clone(), y.clone()))
+        } else {
+            Err(SchemeError::other("cons function unknown error"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("cos"), transcendental("cos", f64::cos, (0, 1)));
+
+    map.insert(SymbolId::intern("current-error-port"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "current-error-port", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("current-error-port function requires no argument"));
+        }
+        Ok(Value::Port(current_error_port()))
+    }))));
+
+    map.insert(SymbolId::intern("current-evaluation-depth"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "current-evaluation-depth", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("current-evaluation-depth function requires no argument"));
+        }
+        Ok(Value::Integer(evaluation_depth() as i64))
+    }))));
+
+    map.insert(SymbolId::intern("current-input-port"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "current-input-port", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("current-input-port function requires no argument"));
+        }
+        Ok(Value::Port(current_input_port()))
+    }))));
+
+    map.insert(SymbolId::intern("current-output-port"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "current-output-port", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("current-output-port function requires no argument"));
+        }
+        Ok(Value::Port(current_output_port()))
+    }))));
+
+    map.insert(SymbolId::intern("display"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "display", vec);
+        match vec.as_slice() {
+            [value] | [value, _] => output_port(vec.get(1))?.write_str(&format::display_string(value)).map(|_| Value::Unspecified),
+            _ => Err(SchemeError::arity("display function requires one or two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("environment-bindings"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "environment-bindings", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("environment-bindings function requires no argument"));
+        }
+        let bindings = env.borrow().visible_bindings().into_iter()
+            .map(|binding| Value::list(vec![Value::Symbol(binding.name), binding.value, Value::Integer(binding.frame as i64)]))
+            .collect::<Vec<Value>>();
+        Ok(Value::list(bindings))
+    }))));
+
+    map.insert(SymbolId::intern("eof-object"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "eof-object", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("eof-object function requires no argument"));
+        }
+        Ok(Value::Eof)
+    }))));
+
+    map.insert(SymbolId::intern("eof-object?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "eof-object?", vec);
+        match vec.as_slice() {
+            [value] => Ok(Value::Bool(*value == Value::Eof)),
+            _ => Err(SchemeError::arity("eof-object? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("eq?"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "eq?", vec);
+        match vec.as_slice() {
+            [a, b] => Ok(Value::Bool(is_eq(a, b))),
+            _ => Err(SchemeError::arity("eq? function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("equal?"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "equal?", vec);
+        match vec.as_slice() {
+            [a, b] => Ok(Value::Bool(is_equal(a, b))),
+            _ => Err(SchemeError::arity("equal? function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("eqv?"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "eqv?", vec);
+        match vec.as_slice() {
+            [a, b] => Ok(Value::Bool(is_eqv(a, b))),
+            _ => Err(SchemeError::arity("eqv? function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("error-object-message"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "error-object-message", vec);
+        match vec.as_slice() {
+            [value] => record::error_object_message(value).ok_or(SchemeError::wrong_type("error-object-message function requires an argument of type 'error-object'")),
+            _ => Err(SchemeError::arity("error-object-message function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("error-object?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "error-object?", vec);
+        match vec.as_slice() {
+            [value] => Ok(Value::Bool(record::error_object_message(value).is_some())),
+            _ => Err(SchemeError::arity("error-object? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("exact->inexact"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "exact->inexact", vec);
+        match vec.as_slice() {
+            [value] => number_to_f64(value).map(Value::Number).ok_or(SchemeError::wrong_type("exact->inexact function requires an argument of type 'number'")),
+            _ => Err(SchemeError::arity("exact->inexact function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("exact?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "exact?", vec);
+        match vec.as_slice() {
+            [Value::Number(_)] => Ok(Value::Bool(false)),
+            [value] => exact_rational(value).map(|_| Value::Bool(true)).ok_or(SchemeError::wrong_type("exact? function requires an argument of type 'number'")),
+            _ => Err(SchemeError::arity("exact? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("exp"), transcendental("exp", f64::exp, (0, 1)));
+
+    map.insert(SymbolId::intern("expt"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "expt", vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("expt function requires two arguments"));
+        }
+        // an exact base raised to an exact integer stays exact
+        if let (Some(base), Some(power)) = (exact_rational(&vec[0]), exact_integer(&vec[1])) {
+            if base.is_zero() && power.is_negative() {
+                return Err(SchemeError::other("division by zero"));
+            }
+            let base = if power.is_negative() { base.recip() } else { base };
+            let power = match power.abs().to_usize() {
+                Some(power) => power,
+                None if base.abs().is_one() => if power.is_even() { 2 } else { 1 },
+                None if base.is_zero() => 1,
+                None => return Err(SchemeError::other("expt exponent is too large"))
+            };
+            let bits = base.numer().bits().max(base.denom().bits());
+            if bits > 1 && (power as u64).saturating_mul(bits) > number_theory::MAX_RESULT_BITS {
+                return Err(SchemeError::other("expt exponent is too large"));
+            }
+            allocate(size_of_bits((power as u64).saturating_mul(bits)))?;
+            return exact_power(&base, power).map(Value::rational);
+        }
+        match (number_to_f64(&vec[0]), number_to_f64(&vec[1])) {
+            (Some(base), Some(power)) => Ok(Value::Number(base.powf(power))),
+            _ => Err(SchemeError::wrong_type("expt function requires arguments of type 'number'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("factorial"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "factorial", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("factorial function requires one argument only"));
+        }
+        match integer_argument(&vec[0]).and_then(|n| n.to_u64()) {
+            Some(n) => {
+                allocate_bits(number_theory::product_bits(2, n))?;
+                match number_theory::factorial(n, &interrupt::poll)? {
+                    Some(factorial) => Ok(Value::integer(factorial)),
+                    None => Err(SchemeError::other("factorial result is too large"))
+                }
+            }
+            None => Err(SchemeError::wrong_type("factorial function requires an argument of type 'non-negative integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("filter"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "filter", vec);
+        let (predicate, list) = match vec.as_slice() {
+            [predicate, list] if is_procedure(predicate) => (predicate, list_elements(list).ok_or(SchemeError::wrong_type("filter function requires an argument of type 'list'"))?),
+            _ => return Err(SchemeError::wrong_type("filter function requires a procedure and a list"))
+        };
+        let mut kept = vec![];
+        for element in list {
+            if apply_procedure(predicate, vec![element.clone()], env.clone())? != Value::Bool(false) {
+                kept.push(element);
+            }
+        }
+        Ok(Value::list(kept))
+    }))));
+
+    map.insert(SymbolId::intern("floor"), rounding("floor", |r| r.floor(), f64::floor));
+    map.insert(SymbolId::intern("floor-quotient"), integer_division("floor-quotient", |a, b| a.div_floor(b)));
+    map.insert(SymbolId::intern("floor-remainder"), integer_division("floor-remainder", |a, b| a.mod_floor(b)));
+    map.insert(SymbolId::intern("floor/"), integer_division_pair("floor/", |a, b| a.div_mod_floor(b)));
+
+    map.insert(SymbolId::intern("fold-left"), Value::Proc(Function::new(Arity::at_least(3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "fold-left", vec);
+        let (procedure, initial, lists) = match vec.as_slice() {
+            [procedure, initial, lists @ ..] if is_procedure(procedure) && !lists.is_empty() => (procedure, initial, lists),
+            _ => return Err(SchemeError::wrong_type("fold-left function requires a procedure, an initial value and at least one list"))
+        };
+        let lists = lists.iter().map(list_elements).collect::<Option<Vec<Vec<Value>>>>()
+            .ok_or(SchemeError::wrong_type("fold-left function requires arguments of type 'list'"))?;
+        let length = lists.iter().map(Vec::len).min().unwrap_or(0);
+        (0..length).try_fold(initial.clone(), |accumulated, i| {
+            let mut arguments = vec![accumulated];
+            arguments.extend(lists.iter().map(|list| list[i].clone()));
+            apply_procedure(procedure, arguments, env.clone())
+        })
+    }))));
+
+    map.insert(SymbolId::intern("fold-right"), Value::Proc(Function::new(Arity::at_least(3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "fold-right", vec);
+        let (procedure, initial, lists) = match vec.as_slice() {
+            [procedure, initial, lists @ ..] if is_procedure(procedure) && !lists.is_empty() => (procedure, initial, lists),
+            _ => return Err(SchemeError::wrong_type("fold-right function requires a procedure, an initial value and at least one list"))
+        };
+        let lists = lists.iter().map(list_elements).collect::<Option<Vec<Vec<Value>>>>()
+            .ok_or(SchemeError::wrong_type("fold-right function requires arguments of type 'list'"))?;
+        let length = lists.iter().map(Vec::len).min().unwrap_or(0);
+        (0..length).rev().try_fold(initial.clone(), |accumulated, i| {
+            let mut arguments = lists.iter().map(|list| list[i].clone()).collect::<Vec<Value>>();
+            arguments.push(accumulated);
+            apply_procedure(procedure, arguments, env.clone())
+        })
+    }))));
+
+    map.insert(SymbolId::intern("for-each"), Value::Proc(Function::new(Arity::at_least(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "for-each", vec);
+        let (procedure, lists) = match vec.split_first() {
+            Some((procedure, lists)) if is_procedure(procedure) && !lists.is_empty() => (procedure, lists),
+            _ => return Err(SchemeError::wrong_type("for-each function requires a procedure and at least one list"))
+        };
+        let lists = lists.iter().map(list_elements).collect::<Option<Vec<Vec<Value>>>>()
+            .ok_or(SchemeError::wrong_type("for-each function requires arguments of type 'list'"))?;
+        apply_elementwise(procedure, &lists, env, |_| {})?;
+        Ok(Value::Unspecified)
+    }))));
+
+    map.insert(SymbolId::intern("format"), Value::Proc(Function::new(Arity::at_least(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "format", vec);
+        match vec.split_first() {
+            Some((Value::String(template), arguments)) => format::format_template(template, arguments).map(|s| Value::String(s.into())),
+            _ => Err(SchemeError::wrong_type("format function requires a format string"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("gc"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "gc", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("gc function requires no argument"));
+        }
+        Ok(Value::Integer(collect_garbage() as i64))
+    }))));
+
+    map.insert(SymbolId::intern("gcd"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "gcd", vec);
+        match integer_arguments(&vec) {
+            Some((n, exact)) => Ok(integer_result(n.iter().fold(BigInt::zero(), |acc, x| acc.gcd(x)), exact)),
+            None => Err(SchemeError::wrong_type("gcd function requires arguments of type 'integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table->alist"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table->alist", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t)] => {
+                let alist = t.borrow().values().map(|(k, v)| Value::cons(k.clone(), v.clone())).collect::<Vec<Value>>();
+                Ok(Value::list(alist))
+            }
+            _ => Err(SchemeError::wrong_type("hash-table->alist function requires one argument of type 'hash-table'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-contains?"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-contains?", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t), key] => Ok(Value::Bool(t.borrow().contains_key(&HashKey::of(key)))),
+            _ => Err(SchemeError::wrong_type("hash-table-contains? function requires a hash table and a key"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-count"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-count", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t)] => Ok(Value::Integer(t.borrow().len() as i64)),
+            _ => Err(SchemeError::wrong_type("hash-table-count function requires one argument of type 'hash-table'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-delete!"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-delete!", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t), key] => {
+                t.borrow_mut().remove(&HashKey::of(key));
+                Ok(Value::Unspecified)
+            }
+            _ => Err(SchemeError::wrong_type("hash-table-delete! function requires a hash table and a key"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-keys"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-keys", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t)] => {
+                Ok(Value::list(t.borrow().values().map(|(k, _)| k.clone()).collect()))
+            }
+            _ => Err(SchemeError::wrong_type("hash-table-keys function requires one argument of type 'hash-table'"))
+        }
+    }))));
 
-    define_comparison!(le, "<=", |a,b| { a <= b });
-    map.insert("<=".to_string(), le);
+    map.insert(SymbolId::intern("hash-table-ref"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-ref", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t), key] => match t.borrow().get(&HashKey::of(key)) {
+                Some((_, value)) => Ok(value.clone()),
+                None => Err(SchemeError::out_of_range("hash-table-ref key not found"))
+            },
+            _ => Err(SchemeError::wrong_type("hash-table-ref function requires a hash table and a key"))
+        }
+    }))));
 
-    map.insert("abs".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "abs", vec);
+    map.insert(SymbolId::intern("hash-table-ref/default"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-ref/default", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t), key, default] => match t.borrow().get(&HashKey::of(key)) {
+                Some((_, value)) => Ok(value.clone()),
+                None => Ok(default.clone())
+            },
+            _ => Err(SchemeError::wrong_type("hash-table-ref/default function requires a hash table, a key and a default"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-set!"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-set!", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t), key, value] => {
+                t.borrow_mut().insert(HashKey::of(key), (key.clone(), value.clone()));
+                gc::track(&vec[0], key);
+                gc::track(&vec[0], value);
+                Ok(Value::Unspecified)
+            }
+            _ => Err(SchemeError::wrong_type("hash-table-set! function requires a hash table, a key and a value"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table-values"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table-values", vec);
+        match vec.as_slice() {
+            [Value::HashTable(t)] => {
+                Ok(Value::list(t.borrow().values().map(|(_, v)| v.clone()).collect()))
+            }
+            _ => Err(SchemeError::wrong_type("hash-table-values function requires one argument of type 'hash-table'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("hash-table?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "hash-table?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("hash-table? function requires one argument only"));
+        }
+        Ok(Value::Bool(matches!(vec[0], Value::HashTable(_))))
+    }))));
+
+    map.insert(SymbolId::intern("heap-statistics"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "heap-statistics", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("heap-statistics function requires no argument"));
+        }
+        let stats = heap_statistics();
+        Ok(Value::list(vec![
+            Value::cons(Value::Symbol("collections".into()), Value::Integer(stats.collections as i64)),
+            Value::cons(Value::Symbol("freed".into()), Value::Integer(stats.freed as i64)),
+            Value::cons(Value::Symbol("traced".into()), Value::Integer(stats.traced as i64)),
+            Value::cons(Value::Symbol("tracked".into()), Value::Integer(stats.tracked as i64))
+        ]))
+    }))));
+
+    map.insert(SymbolId::intern("inexact->exact"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "inexact->exact", vec);
+        match vec.as_slice() {
+            // the exact binary value of the float, so (inexact->exact 0.5) is 1/2
+            [Value::Number(f)] => BigRational::from_float(*f).map(Value::rational)
+                .ok_or(SchemeError::out_of_range("inexact->exact function requires a finite number")),
+            [value] => exact_rational(value).map(|_| value.clone()).ok_or(SchemeError::wrong_type("inexact->exact function requires an argument of type 'number'")),
+            _ => Err(SchemeError::arity("inexact->exact function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("inexact?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "inexact?", vec);
+        match vec.as_slice() {
+            [Value::Number(_)] => Ok(Value::Bool(true)),
+            [value] => exact_rational(value).map(|_| Value::Bool(false)).ok_or(SchemeError::wrong_type("inexact? function requires an argument of type 'number'")),
+            _ => Err(SchemeError::arity("inexact? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("input-port?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "input-port?", vec);
+        match vec.as_slice() {
+            [Value::Port(p)] => Ok(Value::Bool(p.is_input())),
+            [_] => Ok(Value::Bool(false)),
+            _ => Err(SchemeError::arity("input-port? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("integer->char"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "integer->char", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("integer->char function requires one argument only"));
+        }
+        match integer_argument(&vec[0]).and_then(|n| n.to_u32()) {
+            Some(n) => match std::char::from_u32(n) {
+                Some(c) => Ok(Value::Char(c)),
+                None => Err(SchemeError::out_of_range("integer->char function requires a valid unicode scalar value"))
+            },
+            None => Err(SchemeError::wrong_type("integer->char function requires an argument of type 'non-negative integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("integer?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "integer?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("integer? function requires one argument only"));
+        }
+        match vec[0] {
+            Value::Integer(_) | Value::BigInt(_) => Ok(Value::Bool(true)),
+            Value::Number(f) => Ok(Value::Bool(f.is_finite() && f.fract() == 0.0)),
+            _ => Ok(Value::Bool(false))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("last-eval-statistics"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "last-eval-statistics", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("last-eval-statistics function requires no argument"));
+        }
+        let stats = last_eval_statistics();
+        Ok(Value::list(vec![
+            Value::cons(Value::Symbol("steps".into()), Value::Integer(stats.steps as i64)),
+            Value::cons(Value::Symbol("allocations".into()), Value::Integer(stats.allocations as i64))
+        ]))
+    }))));
+
+    map.insert(SymbolId::intern("lcm"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "lcm", vec);
+        match integer_arguments(&vec) {
+            Some((n, exact)) => Ok(integer_result(n.iter().fold(BigInt::one(), |acc, x| acc.lcm(x)), exact)),
+            None => Err(SchemeError::wrong_type("lcm function requires arguments of type 'integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("length"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "length", vec);
         if vec.len() != 1 {
-            return Err("abs function requires one argument only");
+            return Err(SchemeError::arity("length function requires one argument only"));
         }
         let value_option = vec.first();
         if value_option.is_none() {
-            return Err("abs function unknown argument type");
+            return Err(SchemeError::wrong_type("length function unknown argument type"));
         }
-        match value_option.unwrap() {
-            &DataType::Number(f) => Ok(Some(DataType::Number(f.abs()))),
-            _ => Err("abs function requires an argument of type 'number'")
+        match list_elements(value_option.unwrap()) {
+            Some(l) => Ok(Value::Integer(l.len() as i64)),
+            None => Err(SchemeError::wrong_type("length function requires an argument of type 'list'"))
         }
     }))));
 
-    map.insert("append".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "append", vec);
+    map.insert(SymbolId::intern("list"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list", vec);
+        Ok(Value::list(vec))
+    }))));
 
-        if vec.is_empty() {
-            return Ok(Some(DataType::List(vec![])));
-        }
-
-        if vec.len() == 1 {
-            let value_option = vec.first();
-            return match value_option {
-                Some(&DataType::List(ref l)) => Ok(Some(DataType::List(l.clone()))),
-                Some(&DataType::Number(n)) => Ok(Some(DataType::Number(n))),
-                Some(&DataType::Bool(b)) => Ok(Some(DataType::Bool(b))),
-                Some(&DataType::Symbol(ref s)) => Ok(Some(DataType::Symbol(s.clone()))),
-                Some(&DataType::String(ref s)) => Ok(Some(DataType::String(s.clone()))),
-                Some(&DataType::Proc(ref p)) => Ok(Some(DataType::Proc(p.clone()))),
-                Some(&DataType::Lambda(ref l)) => Ok(Some(DataType::Lambda(l.clone()))),
-                Some(&DataType::Pair(ref p)) => Ok(Some(DataType::Pair((p.0.clone(), p.1.clone())))),
-                None => { return Err("append function unknown argument type"); }
-            };
+    map.insert(SymbolId::intern("list->string"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list->string", vec);
+        match vec.as_slice() {
+            [list] => {
+                let elements = list_elements(list).ok_or(SchemeError::wrong_type("list->string function requires an argument of type 'list'"))?;
+                allocate(elements.len())?;
+                let string = elements.iter().map(|c| match c {
+                    Value::Char(c) => Some(*c),
+                    _ => None
+                }).collect::<Option<String>>().ok_or(SchemeError::wrong_type("list->string function requires a list of characters"))?;
+                Ok(Value::String(string.into()))
+            }
+            _ => Err(SchemeError::arity("list->string function requires one argument only"))
         }
+    }))));
 
-        //        let first_option = vec.first();
-        tuplet!((first_option,*rest_option) = vec);
-
-        match first_option {
-            Some(&DataType::List(ref l1)) => {
-                let mut list = l1.clone();
-
-                match rest_option {
-                    Some(rest) => {
-                        for item in rest.iter() {
-                            match item {
-                                &DataType::List(ref l2) => list.append(&mut l2.clone()),
-                                &DataType::Number(n) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::Number(n)))
-                                        )
-                                    ))
-                                },
-                                &DataType::Bool(b) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::Bool(b)))
-                                        )
-                                    ))
-                                },
-                                &DataType::Pair(ref p) => {
-                                    list.push((*p.0).clone());
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             p.1.clone())
-                                        )
-                                    ))
-                                },
-                                &DataType::Symbol(ref s) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::Symbol(s.clone())))
-                                        )
-                                    ))
-                                },
-                                &DataType::String(ref s) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::String(s.clone())))
-                                        )
-                                    ))
-                                },
-                                &DataType::Proc(ref p) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::Proc(p.clone())))
-                                        )
-                                    ))
-                                },
-                                &DataType::Lambda(ref l) => {
-                                    return Ok(Some(
-                                        DataType::Pair(
-                                            (Box::new(DataType::List(list.clone())),
-                                             Box::new(DataType::Lambda(l.clone())))
-                                        )
-                                    ))
-                                }
-                            }
-                        }
-                    }
-                    None => {
-                        return Err("append function requires an argument of type 'list'");
-                    }
-                }
+    map.insert(SymbolId::intern("list->vector"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list->vector", vec);
+        match vec.as_slice() {
+            [list] => {
+                let elements = list_elements(list).ok_or(SchemeError::wrong_type("list->vector function requires an argument of type 'list'"))?;
+                count_allocation(size_of_values(elements.len()));
+                Ok(Value::Vector(Rc::new(RefCell::new(elements))))
+            }
+            _ => Err(SchemeError::arity("list->vector function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("list-ref"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list-ref", vec);
+        let (list, k) = match vec.as_slice() {
+            [list, k] => (list, integer_argument(k).and_then(|k| k.to_usize()).ok_or(SchemeError::wrong_type("list-ref function requires an index of type 'non-negative integer'"))?),
+            _ => return Err(SchemeError::arity("list-ref function requires two arguments"))
+        };
+        match list_tail(list.clone(), k) {
+            Some(Value::Pair(p)) => Ok(p.car()),
+            Some(Value::List(l)) => Ok(l[0].clone()),
+            _ => Err(SchemeError::out_of_range("list-ref index out of range"))
+        }
+    }))));
 
-                Ok(Some(DataType::List(list.clone())))
+    map.insert(SymbolId::intern("list-sort"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list-sort", vec);
+        match vec.as_slice() {
+            [less, list] if is_procedure(less) => {
+                let elements = list_elements(list).ok_or(SchemeError::wrong_type("list-sort function requires an argument of type 'list'"))?;
+                Ok(Value::list(sort::merge_sort(elements, |a, b| is_less(less, a, b, env.clone()))?))
             }
-            Some(_) => { return Err("append function wrong type of the first argument"); }
-            None => { return Err("append function unknown argument type"); }
+            _ => Err(SchemeError::wrong_type("list-sort function requires a procedure and a list"))
         }
     }))));
 
-    map.insert("apply".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, env: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "apply", vec);
+    map.insert(SymbolId::intern("list-tail"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list-tail", vec);
+        let (list, k) = match vec.as_slice() {
+            [list, k] => (list, integer_argument(k).and_then(|k| k.to_usize()).ok_or(SchemeError::wrong_type("list-tail function requires an index of type 'non-negative integer'"))?),
+            _ => return Err(SchemeError::arity("list-tail function requires two arguments"))
+        };
+        list_tail(list.clone(), k).ok_or(SchemeError::out_of_range("list-tail index out of range"))
+    }))));
 
-        if vec.len() != 2 {
-            return Err("apply function requires two arguments");
+    map.insert(SymbolId::intern("list?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "list?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("list? function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("list? function unknown argument type"));
         }
+        Ok(Value::Bool(list_elements(value_option.unwrap()).is_some()))
+    }))));
 
-        tuplet!((s0,s1) = vec);
-        if let Some(&DataType::List(ref args)) = s1 {
-            match s0 {
-                Some(&DataType::Proc(ref f)) => {
-                    f.call(args.clone(), env.clone()).and_then(|r| {
-                        match r {
-                            Some(data) => Ok(Some(data)),
-                            None => Ok(None)
-                        }
-                    })
-                }
-                Some(&DataType::Lambda(ref p)) => {
-                    debug!("first elm symbol - lambda: {:?}", p);
-                    debug!("first elm symbol - procedure params: {:?}", p.params);
-                    let procedure_local = p.env.borrow_mut().local.clone();
-
-                    for (name_ref, value_ref) in p.params.iter().zip(args.into_iter()) {
-                        debug!("first elm symbol - procedure params - name: {:?} value: {:?}", name_ref, value_ref);
-                        if let (Some(&DataType::Symbol(ref name)), Some(value)) = (Some(name_ref), Some(value_ref)) {
-                            procedure_local.borrow_mut().insert(name.to_string(), value.clone());
-                        } else {
-                            unreachable!()
-                        }
-                    }
+    map.insert(SymbolId::intern("log"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "log", vec);
+        if vec == [Value::Integer(1)] {
+            return Ok(Value::Integer(0));
+        }
+        // the optional second argument is the base
+        match inexact_numbers(&vec).as_deref() {
+            Some(&[z, base]) => Ok(Value::Number(z.ln() / base.ln())),
+            Some(&[z]) => Ok(Value::Number(z.ln())),
+            _ => Err(SchemeError::wrong_type("math function requires an argument of type 'number'"))
+        }
+    }))));
 
-                    let proc_env = Env {
-                        local: procedure_local,
-                        parent: p.env.borrow_mut().parent.clone()
-                    };
+    map.insert(SymbolId::intern("make-bytevector"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "make-bytevector", vec);
+        if vec.is_empty() || vec.len() > 2 {
+            return Err(SchemeError::arity("make-bytevector function requires one or two arguments"));
+        }
+        let length = match integer_argument(&vec[0]).and_then(|n| n.to_usize()) {
+            Some(length) => length,
+            None => return Err(SchemeError::wrong_type("make-bytevector function requires a length of type 'non-negative integer'"))
+        };
+        let fill = match vec.get(1).map(byte_argument) {
+            Some(Some(byte)) => byte,
+            Some(None) => return Err(SchemeError::wrong_type("make-bytevector function requires a fill of type 'byte'")),
+            None => 0
+        };
+        reserve(length)?;
+        let bytes = filled(fill, length).ok_or(SchemeError::out_of_range("make-bytevector length too large"))?;
+        count_allocation(bytes.len());
+        Ok(Value::Bytevector(Rc::new(RefCell::new(bytes))))
+    }))));
 
-                    debug!("proc_env: {:?}", proc_env);
-                    return eval(Some(p.body.clone()), Rc::new(RefCell::new(proc_env)));
-                }
-                Some(_) | None => Err("apply function unknown first argument type")
+    map.insert(SymbolId::intern("make-hash-table"), Value::Proc(Function::new(Arity::exactly(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "make-hash-table", vec);
+        if !vec.is_empty() {
+            return Err(SchemeError::arity("make-hash-table function requires no argument"));
+        }
+        count_allocation(mem::size_of::<HashMap<HashKey, (Value, Value)>>());
+        Ok(Value::HashTable(Rc::new(RefCell::new(HashMap::new()))))
+    }))));
+
+    map.insert(SymbolId::intern("make-vector"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "make-vector", vec);
+        if vec.is_empty() || vec.len() > 2 {
+            return Err(SchemeError::arity("make-vector function requires one or two arguments"));
+        }
+        let length = match integer_argument(&vec[0]).and_then(|n| n.to_usize()) {
+            Some(length) => length,
+            None => return Err(SchemeError::wrong_type("make-vector function requires a length of type 'non-negative integer'"))
+        };
+        let fill = vec.get(1).cloned().unwrap_or(Value::Integer(0));
+        reserve(size_of_values(length))?;
+        let items = filled(fill, length).ok_or(SchemeError::out_of_range("make-vector length too large"))?;
+        count_allocation(size_of_values(items.len()));
+        Ok(Value::Vector(Rc::new(RefCell::new(items))))
+    }))));
+
+    map.insert(SymbolId::intern("map"), Value::Proc(Function::new(Arity::at_least(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "map", vec);
+        let (procedure, lists) = match vec.split_first() {
+            Some((procedure, lists)) if is_procedure(procedure) && !lists.is_empty() => (procedure, lists),
+            _ => return Err(SchemeError::wrong_type("map function requires a procedure and at least one list"))
+        };
+        let lists = lists.iter().map(list_elements).collect::<Option<Vec<Vec<Value>>>>()
+            .ok_or(SchemeError::wrong_type("map function requires arguments of type 'list'"))?;
+        let mut results = vec![];
+        apply_elementwise(procedure, &lists, env, |result| results.push(result))?;
+        Ok(Value::list(results))
+    }))));
+
+    map.insert(SymbolId::intern("max"), Value::Proc(Function::new(Arity::at_least(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "max", vec);
+        if let Some(rationals) = exact_rationals(&vec) {
+            return rationals.into_iter().max().map(Value::rational).ok_or(SchemeError::arity("max function requires at least one argument"));
+        }
+        match inexact_numbers(&vec) {
+            Some(numbers) => Ok(Value::Number(numbers.into_iter().float_max())),
+            None => Err(SchemeError::wrong_type("wrong argument datatype"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("member"), Value::Proc(Function::new(Arity::between(2, 3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "member", vec);
+        match vec.as_slice() {
+            [key, list] => find_member(key, list, |a, b| Ok(is_equal(a, b))).ok_or(SchemeError::wrong_type("member function requires an argument of type 'list'"))?,
+            [key, list, compare] if is_procedure(compare) => {
+                let same = |a: &Value, b: &Value| apply_procedure(compare, vec![a.clone(), b.clone()], env.clone()).map(|r| r != Value::Bool(false));
+                find_member(key, list, same).ok_or(SchemeError::wrong_type("member function requires an argument of type 'list'"))?
             }
-        } else {
-            return Err("apply function requires two arguments");
+            _ => Err(SchemeError::wrong_type("member function requires two arguments and an optional procedure"))
         }
     }))));
 
-    // pre-defined commands
-    map.insert("begin".to_string(), DataType::Proc(
-        Function(
-            Rc::new(|mut vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-                debug!("Function - name: {:?} - Args: {:?}", "begin", vec);
-                Ok(vec.pop().clone())
-            })
-        )
-    ));
+    map.insert(SymbolId::intern("memq"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "memq", vec);
+        match vec.as_slice() {
+            [key, list] => find_member(key, list, |a, b| Ok(is_eq(a, b))).ok_or(SchemeError::wrong_type("memq function requires an argument of type 'list'"))?,
+            _ => Err(SchemeError::arity("memq function requires two arguments"))
+        }
+    }))));
 
-    map.insert("car".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "car", vec);
+    map.insert(SymbolId::intern("memv"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "memv", vec);
+        match vec.as_slice() {
+            [key, list] => find_member(key, list, |a, b| Ok(is_eqv(a, b))).ok_or(SchemeError::wrong_type("memv function requires an argument of type 'list'"))?,
+            _ => Err(SchemeError::arity("memv function requires two arguments"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("min"), Value::Proc(Function::new(Arity::at_least(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "min", vec);
+        if let Some(rationals) = exact_rationals(&vec) {
+            return rationals.into_iter().min().map(Value::rational).ok_or(SchemeError::arity("min function requires at least one argument"));
+        }
+        match inexact_numbers(&vec) {
+            Some(numbers) => Ok(Value::Number(numbers.into_iter().float_min())),
+            None => Err(SchemeError::wrong_type("wrong argument datatype"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("modular-expt"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "modular-expt", vec);
+        if vec.len() != 3 {
+            return Err(SchemeError::arity("modular-expt function requires three arguments"));
+        }
+        let arguments = vec.iter().map(integer_argument).collect::<Option<Vec<BigInt>>>();
+        match arguments {
+            Some(a) => match number_theory::modular_expt(&a[0], &a[1], &a[2]) {
+                Some(result) => Ok(Value::integer(result)),
+                None => Err(SchemeError::out_of_range("modular-expt function requires a non-negative exponent and a positive modulus"))
+            },
+            None => Err(SchemeError::wrong_type("modular-expt function requires arguments of type 'integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("modulo"), integer_division("modulo", |a, b| a.mod_floor(b)));
+
+    map.insert(SymbolId::intern("newline"), Value::Proc(Function::new(Arity::between(0, 1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "newline", vec);
+        if vec.len() > 1 {
+            return Err(SchemeError::arity("newline function requires at most one argument"));
+        }
+        output_port(vec.first())?.write_str("\n").map(|_| Value::Unspecified)
+    }))));
+
+    map.insert(SymbolId::intern("next-prime"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "next-prime", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("next-prime function requires one argument only"));
+        }
+        match integer_argument(&vec[0]) {
+            Some(n) => Ok(Value::integer(number_theory::next_prime(&n))),
+            None => Err(SchemeError::wrong_type("next-prime function requires an argument of type 'integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("null?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "null?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("null? function requires one argument only"));
+        }
+        Ok(Value::Bool(vec[0] == Value::Nil))
+    }))));
+
+    map.insert(SymbolId::intern("number->string"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "number->string", vec);
+        let value = vec.first().ok_or(SchemeError::wrong_type("number->string function requires an argument of type 'number'"))?;
+        if number_to_f64(value).is_none() {
+            return Err(SchemeError::wrong_type("number->string function requires an argument of type 'number'"));
+        }
+        let radix = radix_argument(vec.get(1)).ok_or(SchemeError::other("number->string radix must be 2, 8, 10 or 16"))?;
+        let s = number_to_string(value, radix).ok_or(SchemeError::other("number->string can only write inexact numbers in radix 10"))?;
+        Ok(Value::String(s.into()))
+    }))));
+
+    map.insert(SymbolId::intern("number?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "number?", vec);
         if vec.len() != 1 {
-            return Err("car function requires one argument only");
+            return Err(SchemeError::arity("number? function requires one argument only"));
         }
         let value_option = vec.first();
         if value_option.is_none() {
-            return Err("car function unknown argument type");
+            return Err(SchemeError::wrong_type("number? function unknown argument type"));
         }
         match value_option.unwrap() {
-            &DataType::List(ref vec) => {
-                let value = vec.first();
-                if value.is_some() {
-                    Ok(Some(DataType::from(value.unwrap().clone())))
-                } else {
-                    Err("car function requires a non-empty list")
-                }
+            &Value::Number(_) | &Value::Integer(_) | &Value::BigInt(_) | &Value::Rational(_) => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("output-port?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "output-port?", vec);
+        match vec.as_slice() {
+            [Value::Port(p)] => Ok(Value::Bool(p.is_output())),
+            [_] => Ok(Value::Bool(false)),
+            _ => Err(SchemeError::arity("output-port? function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("pair?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "pair?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("pair? function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("pair? function unknown argument type"));
+        }
+        // a `List` is never empty, so it is always made of at least one pair
+        match value_option.unwrap() {
+            &Value::Pair(_) | &Value::List(_) => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("prime?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "prime?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("prime? function requires one argument only"));
+        }
+        match integer_argument(&vec[0]) {
+            Some(n) => Ok(Value::Bool(number_theory::is_prime(&n))),
+            None => Err(SchemeError::wrong_type("prime? function requires an argument of type 'integer'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("print"), Value::Proc(
+        Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+            debug!("Function - name: {:?} - Args: {:?}", "print", vec);
+            match vec.as_slice() {
+                [value] | [value, _] => output_port(vec.get(1))?.write_str(&format!("{}\n", datatype2str(value))).map(|_| Value::Unspecified),
+                _ => Err(SchemeError::arity("print function requires one or two arguments"))
+            }
+        }))));
+
+    map.insert(SymbolId::intern("procedure-arity"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "procedure-arity", vec);
+        let arity = match vec.as_slice() {
+            [Value::Proc(f)] => f.arity(),
+            [Value::Lambda(p)] => p.arity(),
+            _ => return Err(SchemeError::wrong_type("procedure-arity function requires an argument of type 'procedure'"))
+        };
+        let max = arity.max.map_or(Value::Bool(false), |max| Value::Integer(max as i64));
+        Ok(Value::cons(Value::Integer(arity.min as i64), max))
+    }))));
+
+    map.insert(SymbolId::intern("procedure-name"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "procedure-name", vec);
+        let name = match vec.as_slice() {
+            [Value::Proc(f)] => f.name(),
+            [Value::Lambda(p)] => p.name(),
+            _ => return Err(SchemeError::wrong_type("procedure-name function requires an argument of type 'procedure'"))
+        };
+        Ok(name.map_or(Value::Bool(false), Value::Symbol))
+    }))));
+
+    map.insert(SymbolId::intern("procedure?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "procedure?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("procedure? function requires one argument only"));
+        }
+        let value_option = vec.first();
+        if value_option.is_none() {
+            return Err(SchemeError::wrong_type("procedure? function unknown argument type"));
+        }
+        match *value_option.unwrap() {
+            Value::Proc(_) => Ok(Value::Bool(true)),
+            Value::Lambda(_) => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("quotient"), integer_division("quotient", |a, b| a / b));
+
+    map.insert(SymbolId::intern("read"), Value::Proc(Function::new(Arity::between(0, 1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "read", vec);
+        if vec.len() > 1 {
+            return Err(SchemeError::arity("read function requires at most one argument"));
+        }
+        match input_port(vec.first())?.read_datum()? {
+            Some(datum) => Ok(to_data(&datum)),
+            None => Ok(Value::Eof)
+        }
+    }))));
+
+    map.insert(SymbolId::intern("real?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "real?", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("real? function requires one argument only"));
+        }
+        Ok(Value::Bool(matches!(vec[0], Value::Number(_) | Value::Integer(_) | Value::BigInt(_) | Value::Rational(_))))
+    }))));
+
+    map.insert(SymbolId::intern("reduce"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "reduce", vec);
+        let (procedure, identity, list) = match vec.as_slice() {
+            [procedure, identity, list] if is_procedure(procedure) => (procedure, identity, list_elements(list).ok_or(SchemeError::wrong_type("reduce function requires an argument of type 'list'"))?),
+            _ => return Err(SchemeError::wrong_type("reduce function requires a procedure, an identity value and a list"))
+        };
+        // the identity is returned for an empty list but never passed to the procedure
+        let mut elements = list.into_iter();
+        match elements.next() {
+            Some(first) => elements.try_fold(first, |accumulated, element| apply_procedure(procedure, vec![element, accumulated], env.clone())),
+            None => Ok(identity.clone())
+        }
+    }))));
+
+    map.insert(SymbolId::intern("ref-in"), Value::Proc(Function::new(Arity::between(2, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "ref-in", vec);
+        let (data, path, default) = match vec.as_slice() {
+            [data, path] => (data, path, None),
+            [data, path, default] => (data, path, Some(default)),
+            _ => return Err(SchemeError::arity("ref-in function requires two or three arguments"))
+        };
+        let path = list_elements(path).ok_or(SchemeError::wrong_type("ref-in function requires a path of type 'list'"))?;
+        match path::ref_in(data, &path) {
+            Some(value) => Ok(value),
+            None => default.cloned().ok_or(SchemeError::out_of_range("ref-in path not found"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("remainder"), integer_division("remainder", |a, b| a % b));
+
+    map.insert(SymbolId::intern("reverse"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "reverse", vec);
+        match vec.as_slice() {
+            [list] => {
+                let mut elements = list_elements(list).ok_or(SchemeError::wrong_type("reverse function requires an argument of type 'list'"))?;
+                elements.reverse();
+                Ok(Value::list(elements))
+            }
+            _ => Err(SchemeError::arity("reverse function requires one argument only"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("reverse!"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "reverse!", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("reverse! function requires one argument only"));
+        }
+        reverse_in_place(vec.into_iter().next().unwrap())
+    }))));
+
+    map.insert(SymbolId::intern("round"), rounding("round", round_half_even, f64::round_ties_even));
+
+    map.insert(SymbolId::intern("set-car!"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "set-car!", vec);
+        match vec.as_slice() {
+            [Value::Pair(p), value] => {
+                p.set_car(value.clone());
+                gc::track(&vec[0], value);
+                Ok(Value::Unspecified)
+            }
+            _ => Err(SchemeError::wrong_type("set-car! function requires arguments of type 'pair' and a value"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("set-cdr!"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "set-cdr!", vec);
+        match vec.as_slice() {
+            [Value::Pair(p), value] => {
+                p.set_cdr(value.clone());
+                gc::track(&vec[0], value);
+                Ok(Value::Unspecified)
+            }
+            _ => Err(SchemeError::wrong_type("set-cdr! function requires arguments of type 'pair' and a value"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("sin"), transcendental("sin", f64::sin, (0, 0)));
+
+    map.insert(SymbolId::intern("sort"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "sort", vec);
+        match vec.as_slice() {
+            [Value::Vector(v), less] if is_procedure(less) => {
+                let elements = v.borrow().clone();
+                count_allocation(size_of_values(elements.len()));
+                Ok(Value::Vector(Rc::new(RefCell::new(sort::merge_sort(elements, |a, b| is_less(less, a, b, env.clone()))?))))
+            }
+            [list, less] if is_procedure(less) => {
+                let elements = list_elements(list).ok_or(SchemeError::wrong_type("sort function requires an argument of type 'list' / 'vector'"))?;
+                Ok(Value::list(sort::merge_sort(elements, |a, b| is_less(less, a, b, env.clone()))?))
+            }
+            _ => Err(SchemeError::wrong_type("sort function requires a list or vector and a procedure"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("sqrt"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "sqrt", vec);
+        if vec.len() != 1 {
+            return Err(SchemeError::arity("math function requires one argument only"));
+        }
+        if let Some(root) = exact_rational(&vec[0]).as_ref().and_then(exact_sqrt) {
+            return Ok(Value::rational(root));
+        }
+        match number_to_f64(&vec[0]) {
+            Some(x) => Ok(Value::Number(x.sqrt())),
+            None => Err(SchemeError::wrong_type("math function requires an argument of type 'number'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string->list"), Value::Proc(Function::new(Arity::between(1, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string->list", vec);
+        match vec.split_first() {
+            Some((Value::String(s), range)) => {
+                let chars = s.chars().map(Value::Char).collect::<Vec<Value>>();
+                let (start, end) = range_arguments(chars.len(), range).ok_or(SchemeError::out_of_range("string->list range out of bounds"))?;
+                Ok(Value::list(chars[start..end].to_vec()))
+            }
+            _ => Err(SchemeError::wrong_type("string->list function requires an argument of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string->number"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string->number", vec);
+        match vec.first() {
+            Some(Value::String(s)) => {
+                let radix = radix_argument(vec.get(1)).ok_or(SchemeError::other("string->number radix must be 2, 8, 10 or 16"))?;
+                Ok(parse_number(s, radix).unwrap_or(Value::Bool(false)))
+            }
+            _ => Err(SchemeError::wrong_type("string->number function requires an argument of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string->vector"), Value::Proc(Function::new(Arity::between(1, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string->vector", vec);
+        match vec.split_first() {
+            Some((Value::String(s), range)) => {
+                let chars = s.chars().map(Value::Char).collect::<Vec<Value>>();
+                let (start, end) = range_arguments(chars.len(), range).ok_or(SchemeError::out_of_range("string->vector range out of bounds"))?;
+                count_allocation(size_of_values(end - start));
+                Ok(Value::Vector(Rc::new(RefCell::new(chars[start..end].to_vec()))))
+            }
+            _ => Err(SchemeError::wrong_type("string->vector function requires an argument of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string-append"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-append", vec);
+        let strings = vec.iter().map(|value| match value {
+            Value::String(s) => Some(&**s),
+            _ => None
+        }).collect::<Option<Vec<&str>>>().ok_or(SchemeError::wrong_type("string-append function requires arguments of type 'string'"))?;
+        allocate(strings.iter().fold(0usize, |length, s| length.saturating_add(s.len())))?;
+        Ok(Value::String(strings.concat().into()))
+    }))));
+
+    map.insert(SymbolId::intern("string-contains"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-contains", vec);
+        match vec.as_slice() {
+            [Value::String(s), Value::String(needle)] => Ok(match s.find(&**needle) {
+                Some(offset) => Value::Integer(strings::char_position(s, offset) as i64),
+                None => Value::Bool(false)
+            }),
+            _ => Err(SchemeError::wrong_type("string-contains function requires two arguments of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string-downcase"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-downcase", vec);
+        match vec.as_slice() {
+            [Value::String(s)] => allocate(s.len()).map(|_| Value::String(s.to_lowercase().into())),
+            _ => Err(SchemeError::wrong_type("string-downcase function requires an argument of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string-index"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-index", vec);
+        let (s, target) = match vec.as_slice() {
+            [Value::String(s), target @ Value::Char(_)] => (s, target),
+            [Value::String(s), target] if is_procedure(target) => (s, target),
+            _ => return Err(SchemeError::wrong_type("string-index function requires a string and a character or procedure"))
+        };
+        for (i, c) in s.chars().enumerate() {
+            let found = match target {
+                Value::Char(t) => c == *t,
+                predicate => apply_procedure(predicate, vec![Value::Char(c)], env.clone())? != Value::Bool(false)
+            };
+            if found {
+                return Ok(Value::Integer(i as i64));
+            }
+        }
+        Ok(Value::Bool(false))
+    }))));
+
+    map.insert(SymbolId::intern("string-join"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-join", vec);
+        let (list, delimiter) = match vec.as_slice() {
+            [list] => (list, " "),
+            [list, Value::String(delimiter)] => (list, &**delimiter),
+            _ => return Err(SchemeError::wrong_type("string-join function requires a list and an optional delimiter of type 'string'"))
+        };
+        let pieces = list_elements(list).ok_or(SchemeError::wrong_type("string-join function requires an argument of type 'list'"))?;
+        let pieces = pieces.iter().map(|piece| match piece {
+            Value::String(s) => Some(&**s),
+            _ => None
+        }).collect::<Option<Vec<&str>>>().ok_or(SchemeError::wrong_type("string-join function requires a list of strings"))?;
+        let delimiters = delimiter.len().saturating_mul(pieces.len().saturating_sub(1));
+        let length = pieces.iter().fold(delimiters, |length, s| length.saturating_add(s.len()));
+        allocate(length)?;
+        let mut joined = String::with_capacity(length);
+        for (i, piece) in pieces.iter().enumerate() {
+            if i > 0 {
+                joined.push_str(delimiter);
+            }
+            if i % POLL_INTERVAL == POLL_INTERVAL - 1 {
+                interrupt::poll()?;
             }
-            &DataType::Pair(ref p) => Ok(Some(*(p.0).clone())),
-            _ => Err("car function requires an argument of type 'list' / 'pair'")
+            joined.push_str(piece);
         }
+        Ok(Value::String(joined.into()))
     }))));
 
-    map.insert("cdr".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "cdr", vec);
+    map.insert(SymbolId::intern("string-length"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-length", vec);
         if vec.len() != 1 {
-            return Err("cdr function requires one argument only");
-        }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("cdr function unknown argument type");
+            return Err(SchemeError::arity("string-length function requires one argument only"));
         }
-        match value_option.unwrap() {
-            &DataType::List(ref vec) => {
-                if vec.len() > 0 {
-                    Ok(Some(DataType::List((&vec[1..]).to_vec())))
-                } else {
-                    Err("cdr function requires a non-empty list")
-                }
-            },
-            &DataType::Pair(ref p) => Ok(Some(*(p.1).clone())),
-            _ => Err("cdr function requires an argument of type 'list'/ 'pair'")
+        match vec[0] {
+            Value::String(ref s) => Ok(Value::Integer(s.chars().count() as i64)),
+            _ => Err(SchemeError::wrong_type("string-length function requires an argument of type 'string'"))
         }
     }))));
 
-    map.insert("cons".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "cons", vec);
-        if vec.len() != 2 {
-            return Err("cons function requires two argument only");
-        }
-
-        if let (Some(x), Some(y)) = (vec.get(0), vec.get(1)) {
-            match y {
-                &DataType::List(ref l) => {
-                    let mut result :Vec<DataType> = vec![(*x).clone()];
-                    result.extend(l.iter().map(|item| item.clone()));
-                    Ok(Some(DataType::List(result)))
-                },
-                _ => {
-                    Ok(Some(DataType::Pair(
-                        (Box::new(x.clone()), Box::new(y.clone()))
-                    )))
-                }
+    map.insert(SymbolId::intern("string-ref"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-ref", vec);
+        match vec.as_slice() {
+            [Value::String(s), k] => {
+                let k = integer_argument(k).and_then(|n| n.to_usize()).ok_or(SchemeError::wrong_type("string-ref function requires an index of type 'non-negative integer'"))?;
+                s.chars().nth(k).map(Value::Char).ok_or(SchemeError::out_of_range("string-ref index out of range"))
             }
-        } else {
-            return Err("cons function unknown error")
+            _ => Err(SchemeError::wrong_type("string-ref function requires a string and an index"))
         }
     }))));
 
-    map.insert("length".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "length", vec);
+    map.insert(SymbolId::intern("string-split"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-split", vec);
+        match vec.as_slice() {
+            [Value::String(s)] => Ok(Value::list(strings::split(s, None))),
+            [Value::String(s), Value::String(separator)] => Ok(Value::list(strings::split(s, Some(separator)))),
+            [Value::String(s), Value::Char(separator)] => Ok(Value::list(strings::split(s, Some(&separator.to_string())))),
+            _ => Err(SchemeError::wrong_type("string-split function requires a string and an optional separator"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string-upcase"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string-upcase", vec);
+        match vec.as_slice() {
+            [Value::String(s)] => allocate(s.len()).map(|_| Value::String(s.to_uppercase().into())),
+            _ => Err(SchemeError::wrong_type("string-upcase function requires an argument of type 'string'"))
+        }
+    }))));
+
+    map.insert(SymbolId::intern("string<=?"), strings::comparison(|o| o != Ordering::Greater));
+    map.insert(SymbolId::intern("string<?"), strings::comparison(|o| o == Ordering::Less));
+    map.insert(SymbolId::intern("string=?"), strings::comparison(|o| o == Ordering::Equal));
+    map.insert(SymbolId::intern("string>=?"), strings::comparison(|o| o != Ordering::Less));
+    map.insert(SymbolId::intern("string>?"), strings::comparison(|o| o == Ordering::Greater));
+
+    map.insert(SymbolId::intern("string?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "string?", vec);
         if vec.len() != 1 {
-            return Err("length function requires one argument only");
+            return Err(SchemeError::arity("string? function requires one argument only"));
         }
         let value_option = vec.first();
         if value_option.is_none() {
-            return Err("length function unknown argument type");
+            return Err(SchemeError::wrong_type("string? function unknown argument type"));
         }
         match value_option.unwrap() {
-            &DataType::List(ref vec) => Ok(Some(DataType::Number(vec.len() as f64))),
-            _ => Err("length function requires an argument of type 'list'")
+            &Value::String(_) => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false))
         }
     }))));
 
-    map.insert("list".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "list", vec);
-        Ok(Some(DataType::List(vec)))
+    map.insert(SymbolId::intern("substring"), Value::Proc(Function::new(Arity::between(2, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "substring", vec);
+        match vec.split_first() {
+            Some((Value::String(s), range @ [_, ..])) => {
+                let (start, end) = range_arguments(s.chars().count(), range).ok_or(SchemeError::out_of_range("substring range out of bounds"))?;
+                Ok(Value::String(strings::substring(s, start, end).unwrap().into()))
+            }
+            _ => Err(SchemeError::wrong_type("substring function requires a string, a start and an optional end"))
+        }
     }))));
 
-    map.insert("list?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "list?", vec);
+    map.insert(SymbolId::intern("symbol?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "symbol?", vec);
         if vec.len() != 1 {
-            return Err("list? function requires one argument only");
+            return Err(SchemeError::arity("symbol? function requires one argument only"));
         }
         let value_option = vec.first();
         if value_option.is_none() {
-            return Err("list? function unknown argument type");
+            return Err(SchemeError::wrong_type("symbol? function unknown argument type"));
         }
         match value_option.unwrap() {
-            &DataType::List(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+            &Value::Symbol(_) => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false))
         }
     }))));
 
-    map.insert("map".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, env: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "map", vec);
-        if vec.len() != 2 {
-            return Err("map function requires two argument only");
-        }
+    //    debug!("map start");
+    //    for (i, key) in map.keys().enumerate() {
+    //        debug!("{} => {}", i + 1, key);
+    //        match map.get(key) {
+    //            Some(&Value::Proc(ref f)) => {
+    //                match f.call(vec![Value::Integer(1), Value::Integer(2), Value::Float(5.1)]) {
+    //                    Ok(result) => { debug!("Execution is good. Result: {:?}", result); }
+    //                    Err(_) => { debug!("Execution is failed"); }
+    //                }
+    //            }
+    //            Some(&ref o) => {
+    //                debug!("{:?}", o);
+    //            },
+    //            None => {}
+    //        }
+    //    }
+    //    debug!("map end");
 
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("map function unknown argument type");
-        }
-
-        if let (Some(d), Some(&DataType::List(ref l))) = (vec.first(), vec.get(1)) {
-            match d {
-                &DataType::Proc(ref f) => {
-                    let list = l.iter()
-                        .map(|item| f.call(vec![item.clone()], env.clone()))
-                        .flat_map(|x| x.ok())
-                        .filter(|x| x.is_some())
-                        .flat_map(|x| x)
-                        .collect::<Vec<DataType>>();
-
-                    Ok(Some(DataType::List(list)))
-                },
-                &DataType::Lambda(ref p) => {
-                    let list = l.iter().map(|item| {
-                        let procedure_local = p.env.borrow_mut().local.clone();
-                        let args = vec![item.clone()];
-                        for (name_ref, value_ref) in p.params.iter().zip(args.into_iter()) {
-                            if let (Some(&DataType::Symbol(ref name)), Some(ref value)) = (Some(name_ref), Some(value_ref)) {
-                                procedure_local.borrow_mut().insert(name.to_string(), value.clone());
-                            } else {
-                                unreachable!()
-                            }
-                        }
+    map.insert(SymbolId::intern("tan"), transcendental("tan", f64::tan, (0, 0)));
 
-                        let proc_env = Env {
-                            local: procedure_local,
-                            parent: p.env.borrow_mut().parent.clone()
-                        };
+    map.insert(SymbolId::intern("truncate"), rounding("truncate", |r| r.trunc(), f64::trunc));
+    map.insert(SymbolId::intern("truncate-quotient"), integer_division("truncate-quotient", |a, b| a / b));
+    map.insert(SymbolId::intern("truncate-remainder"), integer_division("truncate-remainder", |a, b| a % b));
+    map.insert(SymbolId::intern("truncate/"), integer_division_pair("truncate/", |a, b| a.div_rem(b)));
 
-                        debug!("proc_env: {:?}", proc_env);
-                        eval(Some(p.body.clone()), Rc::new(RefCell::new(proc_env)))
-                    }).flat_map(|x| x.ok())
-                        .filter(|x| x.is_some())
-                        .flat_map(|x| x)
-                        .collect::<Vec<DataType>>();
+    // a symbol, or the start and end of a span of the program as byte offsets
+    map.insert(SymbolId::intern("unwatch"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "unwatch", vec);
+        match vec.as_slice() {
+            [Value::Symbol(s)] => unwatch(&s.name()),
+            [start, end] => match (start.as_i64().and_then(|i| i.to_usize()), end.as_i64().and_then(|i| i.to_usize())) {
+                (Some(start), Some(end)) if start <= end => unwatch_span(Span { start, end }),
+                _ => return Err(SchemeError::wrong_type("unwatch function requires a symbol or the start and end of a span"))
+            },
+            _ => return Err(SchemeError::wrong_type("unwatch function requires a symbol or the start and end of a span"))
+        }
+        Ok(Value::Unspecified)
+    }))));
 
-                    Ok(Some(DataType::List(list)))
-                },
-                _ => unreachable!()
+    map.insert(SymbolId::intern("update-in"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "update-in", vec);
+        match vec.as_slice() {
+            [data, path, f @ Value::Proc(_)] | [data, path, f @ Value::Lambda(_)] => {
+                let path = list_elements(path).ok_or(SchemeError::wrong_type("update-in function requires a path of type 'list'"))?;
+                path::update_in(data.clone(), &path, &|value| {
+                    apply_procedure(f, vec![value], env.clone())
+                })
             }
-        } else {
-            Err("syntax error")
+            _ => Err(SchemeError::wrong_type("update-in function requires data, a path and a procedure"))
         }
     }))));
 
-    map.insert("max".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "max", vec);
-        let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
-        if !is_numbers {
-            return Err("wrong argument datatype");
-        }
-        let numbers = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } });
-        let data = numbers.map(|x| {
-            let y: f64 = x.clone().into();
-            y
-        }).float_max();
-        Ok(Some(DataType::Number(data.into())))
+    map.insert(SymbolId::intern("vector"), Value::Proc(Function::new(Arity::at_least(0), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector", vec);
+        count_allocation(size_of_values(vec.len()));
+        Ok(Value::Vector(Rc::new(RefCell::new(vec))))
     }))));
 
-    map.insert("min".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "min", vec);
-        let is_numbers = vec.iter().all(|&ref x| if let &DataType::Number(_) = x { true } else { false });
-        if !is_numbers {
-            return Err("wrong argument datatype");
+    map.insert(SymbolId::intern("vector->list"), Value::Proc(Function::new(Arity::between(1, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector->list", vec);
+        match vec.split_first() {
+            Some((Value::Vector(v), range)) => {
+                let v = v.borrow();
+                let (start, end) = range_arguments(v.len(), range).ok_or(SchemeError::out_of_range("vector->list range out of bounds"))?;
+                Ok(Value::list(v[start..end].to_vec()))
+            }
+            _ => Err(SchemeError::wrong_type("vector->list function requires an argument of type 'vector'"))
         }
-        let numbers = vec.iter().filter_map(|&ref x| { if let &DataType::Number(ref y) = x { Some(y.clone()) } else { None } });
-
-        let data = numbers.map(|x| {
-            let y: f64 = x.clone().into();
-            y
-        }).float_min();
-        Ok(Some(DataType::Number(data.into())))
     }))));
 
-    map.insert("not".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "not", vec);
-        if vec.len() != 1 {
-            return Err("not function requires one argument only");
-        }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("not function unknown argument type");
-        }
-        match value_option.unwrap() {
-            &DataType::Bool(b) => Ok(Some(DataType::Bool(!b))),
-            _ => Err("not function requires an argument of type 'boolean'")
+    map.insert(SymbolId::intern("vector->string"), Value::Proc(Function::new(Arity::between(1, 3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector->string", vec);
+        match vec.split_first() {
+            Some((Value::Vector(v), range)) => {
+                let v = v.borrow();
+                let (start, end) = range_arguments(v.len(), range).ok_or(SchemeError::out_of_range("vector->string range out of bounds"))?;
+                allocate(end - start)?;
+                let string = v[start..end].iter().map(|c| match c {
+                    Value::Char(c) => Some(*c),
+                    _ => None
+                }).collect::<Option<String>>().ok_or(SchemeError::wrong_type("vector->string function requires a vector of characters"))?;
+                Ok(Value::String(string.into()))
+            }
+            _ => Err(SchemeError::wrong_type("vector->string function requires an argument of type 'vector'"))
         }
     }))));
 
-    map.insert("number?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "number?", vec);
+    map.insert(SymbolId::intern("vector-length"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector-length", vec);
         if vec.len() != 1 {
-            return Err("number? function requires one argument only");
-        }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("number? function unknown argument type");
+            return Err(SchemeError::arity("vector-length function requires one argument only"));
         }
-        match value_option.unwrap() {
-            &DataType::Number(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+        match vec[0] {
+            Value::Vector(ref v) => Ok(Value::Integer(v.borrow().len() as i64)),
+            _ => Err(SchemeError::wrong_type("vector-length function requires an argument of type 'vector'"))
         }
     }))));
-    map.insert("pair?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "pair?", vec);
-        if vec.len() != 1 {
-            return Err("pair? function requires one argument only");
-        }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("pair? function unknown argument type");
+
+    map.insert(SymbolId::intern("vector-ref"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector-ref", vec);
+        if vec.len() != 2 {
+            return Err(SchemeError::arity("vector-ref function requires two arguments"));
         }
-        match value_option.unwrap() {
-            &DataType::Pair(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+        match (&vec[0], integer_argument(&vec[1]).and_then(|k| k.to_usize())) {
+            (Value::Vector(v), Some(k)) => match v.borrow().get(k) {
+                Some(value) => Ok(value.clone()),
+                None => Err(SchemeError::out_of_range("vector-ref index out of range"))
+            },
+            _ => Err(SchemeError::wrong_type("vector-ref function requires arguments of type 'vector' and 'non-negative integer'"))
         }
     }))));
 
-    map.insert("print".to_string(), DataType::Proc(
-        Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-            debug!("Function - name: {:?} - Args: {:?}", "print", vec);
-            if vec.len() != 1 {
-                return Err("print function requires one argument only");
+    map.insert(SymbolId::intern("vector-set!"), Value::Proc(Function::new(Arity::exactly(3), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector-set!", vec);
+        if vec.len() != 3 {
+            return Err(SchemeError::arity("vector-set! function requires three arguments"));
+        }
+        match (&vec[0], integer_argument(&vec[1]).and_then(|k| k.to_usize())) {
+            (Value::Vector(v), Some(k)) => {
+                match v.borrow_mut().get_mut(k) {
+                    Some(slot) => *slot = vec[2].clone(),
+                    None => return Err(SchemeError::out_of_range("vector-set! index out of range"))
+                }
+                gc::track(&vec[0], &vec[2]);
+                Ok(Value::Unspecified)
             }
+            _ => Err(SchemeError::wrong_type("vector-set! function requires arguments of type 'vector' and 'non-negative integer'"))
+        }
+    }))));
 
-            let value_option = vec.first();
-            if value_option.is_none() {
-                return Err("unknown argument type");
+    map.insert(SymbolId::intern("vector-sort!"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector-sort!", vec);
+        match vec.as_slice() {
+            [Value::Vector(v), less] if is_procedure(less) => {
+                // sorts a copy, so that the comparison may look at the vector
+                let items = v.borrow().clone();
+                let sorted = sort::merge_sort(items, |a, b| is_less(less, a, b, env.clone()))?;
+                *v.borrow_mut() = sorted;
+                Ok(Value::Unspecified)
             }
-            println!("{}", datatype2str(value_option.unwrap()));
-            //        print_fn(value_option.unwrap());
-            Ok(None)
-        }))));
+            _ => Err(SchemeError::wrong_type("vector-sort! function requires a vector and a procedure"))
+        }
+    }))));
 
-    map.insert("procedure?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "procedure?", vec);
+    map.insert(SymbolId::intern("vector?"), Value::Proc(Function::new(Arity::exactly(1), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "vector?", vec);
         if vec.len() != 1 {
-            return Err("procedure? function requires one argument only");
-        }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("procedure? function unknown argument type");
+            return Err(SchemeError::arity("vector? function requires one argument only"));
         }
-        match value_option.unwrap() {
-            &DataType::Proc(_) => Ok(Some(DataType::Bool(true))),
-            &DataType::Lambda(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+        Ok(Value::Bool(matches!(vec[0], Value::Vector(_))))
+    }))));
+
+    // a symbol, or the start and end of a span of the program as byte offsets
+    map.insert(SymbolId::intern("watch"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "watch", vec);
+        match vec.as_slice() {
+            [Value::Symbol(s)] => watch(&s.name()),
+            [start, end] => match (start.as_i64().and_then(|i| i.to_usize()), end.as_i64().and_then(|i| i.to_usize())) {
+                (Some(start), Some(end)) if start <= end => watch_span(Span { start, end }),
+                _ => return Err(SchemeError::wrong_type("watch function requires a symbol or the start and end of a span"))
+            },
+            _ => return Err(SchemeError::wrong_type("watch function requires a symbol or the start and end of a span"))
         }
+        Ok(Value::Unspecified)
     }))));
 
-    map.insert("string?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "string?", vec);
-        if vec.len() != 1 {
-            return Err("string? function requires one argument only");
+    // Without continuations the handler cannot resume the failed computation, so its
+    // result becomes the result of the whole form, as with `guard`.
+    map.insert(SymbolId::intern("with-exception-handler"), Value::Proc(Function::new(Arity::exactly(2), Rc::new(|vec: Vec<Value>, env: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "with-exception-handler", vec);
+        match vec.as_slice() {
+            [handler, thunk] if is_procedure(handler) && is_procedure(thunk) => match apply_procedure(thunk, vec![], env.clone()) {
+                // a script cannot go on once it has used up its budget
+                Err(error @ SchemeError::BudgetExceeded { .. }) => Err(error),
+                Err(message) => {
+                    forget_error();
+                    apply_procedure(handler, vec![record::error_object(message.message())], env)
+                }
+                result => result
+            },
+            _ => Err(SchemeError::wrong_type("with-exception-handler function requires two procedures"))
         }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("string? function unknown argument type");
+    }))));
+
+    map.insert(SymbolId::intern("write"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "write", vec);
+        match vec.as_slice() {
+            [value] | [value, _] => output_port(vec.get(1))?.write_str(&unparse(value)).map(|_| Value::Unspecified),
+            _ => Err(SchemeError::arity("write function requires one or two arguments"))
         }
-        match value_option.unwrap() {
-            &DataType::String(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+    }))));
+
+    map.insert(SymbolId::intern("write-char"), Value::Proc(Function::new(Arity::between(1, 2), Rc::new(|vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        debug!("Function - name: {:?} - Args: {:?}", "write-char", vec);
+        match vec.as_slice() {
+            [Value::Char(c)] | [Value::Char(c), _] => output_port(vec.get(1))?.write_str(c.encode_utf8(&mut [0; 4])).map(|_| Value::Unspecified),
+            [_] | [_, _] => Err(SchemeError::wrong_type("write-char function requires an argument of type 'char'")),
+            _ => Err(SchemeError::arity("write-char function requires one or two arguments"))
         }
     }))));
 
-    map.insert("symbol?".to_string(), DataType::Proc(Function(Rc::new(|vec: Vec<DataType>, _: Rc<RefCell<Env>>| {
-        debug!("Function - name: {:?} - Args: {:?}", "symbol?", vec);
-        if vec.len() != 1 {
-            return Err("symbol? function requires one argument only");
+    for (name, value) in map.iter_mut() {
+        if let Value::Proc(f) = value {
+            f.name = Some(*name);
         }
-        let value_option = vec.first();
-        if value_option.is_none() {
-            return Err("symbol? function unknown argument type");
+    }
+    map
+}
+
+// A symbol as it is written, between bars when it would not read back as the same
+// symbol otherwise.
+fn symbol_text(name: &str) -> Cow<'_, str> {
+    let delimiter = |c: char| c.is_whitespace() || "()\";|'`,".contains(c);
+    let plain = !name.is_empty() && name != "." && !name.starts_with('#') && !name.contains(delimiter)
+        && matches!(atom(name), Ok(Value::Symbol(_)));
+    if plain {
+        return Cow::Borrowed(name);
+    }
+    let escaped = name.chars().map(|c| match c {
+        '|' => "\\|".to_string(),
+        '\\' => "\\\\".to_string(),
+        _ => c.to_string()
+    }).collect::<String>();
+    Cow::Owned(format!("|{}|", escaped))
+}
+
+fn escape_string(s: &str) -> String {
+    s.chars().map(|c| match c {
+        '"' => "\\\"".to_string(),
+        '\\' => "\\\\".to_string(),
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        _ => c.to_string()
+    }).collect()
+}
+
+fn datatype2str(value: &Value) -> String {
+    write_datum(value, &mut Labels::new(value))
+}
+
+// Values on a cycle are printed with datum labels, like `'#0=(1 . #0#)`.
+fn write_datum(value: &Value, labels: &mut Labels) -> String {
+    let label = match labels.label(value) {
+        Label::Reference(n) => return format!("#{}#", n),
+        Label::Define(n) => format!("#{}=", n),
+        Label::None => String::new()
+    };
+    match value {
+        Value::Bool(b) => format!("{}", b),
+        Value::Pair(p) => {
+            let mut elements = vec![write_datum(&p.car(), labels)];
+            let mut tail = p.cdr();
+            loop {
+                match tail {
+                    // a pair with a label is written as the tail of a dotted list
+                    Value::Pair(ref next) if !labels.is_cyclic(&tail) => {
+                        elements.push(write_datum(&next.car(), labels));
+                        tail = next.cdr();
+                    }
+                    Value::List(ref l) => {
+                        elements.extend(l.iter().map(|x| write_datum(x, labels)));
+                        return format!("'{}({})", label, elements.join(" "));
+                    }
+                    Value::Nil => return format!("'{}({})", label, elements.join(" ")),
+                    _ => return format!("'{}({} . {})", label, elements.join(" "), write_datum(&tail, labels))
+                }
+            }
         }
-        match value_option.unwrap() {
-            &DataType::Symbol(_) => Ok(Some(DataType::Bool(true))),
-            _ => Ok(Some(DataType::Bool(false)))
+        Value::Number(f) => float_to_string(*f),
+        Value::Integer(i) => format!("{}", i),
+        Value::BigInt(b) => format!("{}", b),
+        Value::Rational(r) => format!("{}", r),
+        Value::Char(' ') => "#\\space".to_string(),
+        Value::Char('\n') => "#\\newline".to_string(),
+        Value::Char('\t') => "#\\tab".to_string(),
+        Value::Char(c) => format!("#\\{}", c),
+        Value::Symbol(s) => format!("'{}", symbol_text(&s.name())),
+        Value::String(s) => format!("\"{}\"", escape_string(s)),
+        Value::Proc(f) => match f.name {
+            Some(name) => format!("#<procedure {}>", name),
+            None => "#<procedure>".to_string()
+        },
+        Value::Lambda(p) => {
+            let params = p.closure.params.iter().map(|param| match param {
+                Value::Symbol(s) => s.to_string(),
+                other => write_datum(other, labels)
+            }).collect::<Vec<String>>().join(" ");
+            match p.name {
+                Some(name) => format!("#<procedure {} ({})>", name, params),
+                None => format!("#<procedure ({})>", params)
+            }
         }
-    }))));
+        Value::List(v) => format!("'({})", v.iter()
+            .map(|x| write_datum(x, labels)).collect::<Vec<_>>().join(" ")),
+        Value::Vector(v) => format!("{}#({})", label, v.borrow().iter()
+            .map(|x| write_datum(x, labels)).collect::<Vec<_>>().join(" ")),
+        Value::Bytevector(b) => format!("#u8({})", b.borrow().iter()
+            .map(|x| x.to_string()).collect::<Vec<_>>().join(" ")),
+        Value::HashTable(t) => format!("#<hash-table {}>", t.borrow().len()),
+        Value::Record(r) => format!("{}#<record {}{}>", label, r.record_type.name, r.record_type.fields.iter()
+            .zip(r.fields.borrow().iter())
+            .map(|(name, value)| format!(" {}={}", name, write_datum(value, labels))).collect::<String>()),
+        Value::Port(p) if p.is_input() => "#<input-port>".to_string(),
+        Value::Port(_) => "#<output-port>".to_string(),
+        Value::Foreign(_) => "#<foreign>".to_string(),
+        Value::Eof => "#<eof>".to_string(),
+        Value::Nil => "'()".to_string(),
+        Value::Unspecified => "#<unspecified>".to_string()
+    }
+}
 
-    //    debug!("map start");
-    //    for (i, key) in map.keys().enumerate() {
-    //        debug!("{} => {}", i + 1, key);
-    //        match map.get(key) {
-    //            Some(&DataType::Proc(ref f)) => {
-    //                match f.call(vec![DataType::Integer(1), DataType::Integer(2), DataType::Float(5.1)]) {
-    //                    Ok(result) => { debug!("Execution is good. Result: {:?}", result); }
-    //                    Err(_) => { debug!("Execution is failed"); }
-    //                }
-    //            }
-    //            Some(&ref o) => {
-    //                debug!("{:?}", o);
-    //            },
-    //            None => {}
-    //        }
-    //    }
-    //    debug!("map end");
+// A list as the reader makes them for programs, which is never changed, or `Nil`.
+// `Value::list` makes lists of pairs instead, for data.
+fn read_list(elements: Vec<Value>) -> Value {
+    if elements.is_empty() {
+        Value::Nil
+    } else {
+        Value::List(elements.into())
+    }
+}
+
+// A copy of `datum` whose lists are made of pairs, down to the lists in its vectors,
+// so that programs can change the data they quote or read. Data the datum labels of
+// the reader share are shared by the copy as well, cycles included.
+fn to_data(datum: &Value) -> Value {
+    let copy = copy_data(datum, &mut HashMap::new());
+    if Labels::new(&copy).has_cycles() {
+        gc::track(&copy, &copy);
+    }
+    copy
+}
+
+// Copies `datum`, unless `copies`, keyed by address, has it already. Pairs and vectors
+// are put in `copies` before their elements are copied, which may lead back to them.
+fn copy_data(datum: &Value, copies: &mut HashMap<usize, Value>) -> Value {
+    let key = match datum {
+        Value::List(list) => list.as_ptr() as usize,
+        Value::Pair(p) => Rc::as_ptr(p) as usize,
+        Value::Vector(v) => Rc::as_ptr(v) as *const u8 as usize,
+        _ => return datum.clone()
+    };
+    if let Some(copy) = copies.get(&key) {
+        return copy.clone();
+    }
+    let copy = match datum {
+        Value::List(list) => Value::list(list.iter().map(|element| copy_data(element, copies)).collect()),
+        Value::Vector(vector) => {
+            let copy = Rc::new(RefCell::new(vec![]));
+            copies.insert(key, Value::Vector(copy.clone()));
+            let elements = vector.borrow().iter().map(|element| copy_data(element, copies)).collect();
+            *copy.borrow_mut() = elements;
+            Value::Vector(copy)
+        }
+        _ => {
+            // walks along the cdrs, so that long dotted lists do not grow the stack
+            let first = empty_pair();
+            copies.insert(key, Value::Pair(first.clone()));
+            let (mut last, mut pair) = (first.clone(), datum.clone());
+            loop {
+                let (car, cdr) = match pair {
+                    Value::Pair(ref p) => (p.car(), p.cdr()),
+                    _ => unreachable!()
+                };
+                last.set_car(copy_data(&car, copies));
+                match cdr {
+                    Value::Pair(ref p) if !copies.contains_key(&(Rc::as_ptr(p) as usize)) => {
+                        let next = empty_pair();
+                        copies.insert(Rc::as_ptr(p) as usize, Value::Pair(next.clone()));
+                        last.set_cdr(Value::Pair(next.clone()));
+                        last = next;
+                        pair = cdr;
+                    }
+                    _ => {
+                        last.set_cdr(copy_data(&cdr, copies));
+                        break;
+                    }
+                }
+            }
+            Value::Pair(first)
+        }
+    };
+    copies.insert(key, copy.clone());
+    copy
+}
 
-    return map;
+fn empty_pair() -> Rc<Pair> {
+    count_allocation(mem::size_of::<Pair>());
+    Rc::new(Pair { car: RefCell::new(Value::Nil), cdr: RefCell::new(Value::Nil) })
 }
 
-fn datatype2str(value: &DataType) -> String {
+// The elements of a list that is not made of pairs, as the reader makes lists in
+// programs.
+fn list_items(value: &Value) -> Option<&[Value]> {
     match value {
-        &DataType::Bool(b) => format!("{}", b),
-        &DataType::Pair(ref p) => format!("({:?} . {:?})", p.0, p.1),
-        &DataType::Number(f) => format!("{}", f),
-        &DataType::Symbol(ref s) => format!("'{}", s),
-        &DataType::String(ref s) => format!("\"{}\"", s),
-        &DataType::Proc(ref p) => format!("{:?}", p),
-        &DataType::Lambda(ref p) => format!("{:?}", p),
-        &DataType::List(ref v) => format!("'({})", v.iter()
-            .map(|d| datatype2str(d)).collect::<Vec<_>>().join(" "))
+        Value::List(l) => Some(l),
+        Value::Nil => Some(&[]),
+        _ => None
+    }
+}
+
+// Follows a walk down the pairs of a list at half its speed, so that the walk can
+// tell it is going round a cycle: the two meet on a pair.
+struct CycleCheck {
+    slow: Value,
+    steps: usize
+}
+
+impl CycleCheck {
+    fn new(list: &Value) -> CycleCheck {
+        CycleCheck { slow: list.clone(), steps: 0 }
+    }
+
+    // Whether the walk, having stepped to `current`, is on a cycle.
+    fn is_cycle(&mut self, current: &Value) -> bool {
+        self.steps += 1;
+        if self.steps.is_multiple_of(2) {
+            let next = match self.slow {
+                Value::Pair(ref p) => p.cdr(),
+                _ => return false
+            };
+            self.slow = next;
+        }
+        matches!((current, &self.slow), (Value::Pair(a), Value::Pair(b)) if Rc::ptr_eq(a, b))
+    }
+}
+
+// Collects the elements of a proper list, whether it is a `List` or a chain of pairs
+// ending in one. Circular lists are not proper lists.
+fn list_elements(value: &Value) -> Option<Vec<Value>> {
+    let mut elements = vec![];
+    let mut current = value.clone();
+    let mut cycle = CycleCheck::new(value);
+    loop {
+        match current {
+            Value::List(l) => {
+                elements.extend(l);
+                return Some(elements);
+            }
+            Value::Nil => return Some(elements),
+            Value::Pair(p) => {
+                elements.push(p.car());
+                current = p.cdr();
+                if cycle.is_cycle(&current) {
+                    return None;
+                }
+            }
+            _ => return None
+        }
+    }
+}
+
+// `eq?`: whether two values are the same object. Fixnums, characters, booleans and
+// symbols are immediate and compare by value; other numbers are never `eq?`. Lists
+// built by `list` or `quote` have no identity and compare by their elements.
+fn is_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), _) | (Value::BigInt(_), _) | (Value::Rational(_), _) => false,
+        _ => is_eqv(a, b)
+    }
+}
+
+// `eqv?`: `eq?`, except that numbers of the same exactness compare by value.
+fn is_eqv(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        // 0.0 and -0.0 differ
+        (Value::Number(x), Value::Number(y)) => x.to_bits() == y.to_bits(),
+        (Value::Pair(x), Value::Pair(y)) => Rc::ptr_eq(x, y),
+        (Value::String(x), Value::String(y)) => Rc::ptr_eq(x, y),
+        (Value::Vector(x), Value::Vector(y)) => Rc::ptr_eq(x, y),
+        (Value::Bytevector(x), Value::Bytevector(y)) => Rc::ptr_eq(x, y),
+        (Value::HashTable(x), Value::HashTable(y)) => Rc::ptr_eq(x, y),
+        (Value::Record(x), Value::Record(y)) => Rc::ptr_eq(x, y),
+        (Value::Port(x), Value::Port(y)) => Rc::ptr_eq(x, y),
+        (Value::Proc(x), Value::Proc(y)) => x == y,
+        (Value::Lambda(x), Value::Lambda(y)) => x.identity() == y.identity(),
+        (Value::List(x), Value::List(y)) => x.ptr_eq(y),
+        (Value::List(_) | Value::Pair(_), _) => false,
+        _ => a == b
     }
 }
 
-fn ast2datatype(value: &AST) -> Result<DataType, &'static str> {
+// First element and rest of a non-empty list, whether a `List` or a pair.
+fn split_list(value: &Value) -> Option<(Value, Value)> {
     match value {
-        &AST::Children(ref v) => {
-            let children_result: Result<Vec<_>, _> = v.iter().map(|ast| ast2datatype(&ast)).collect();
-            if let Result::Err(ref e) = children_result { return Err(e); }
+        Value::List(l) => Some((l[0].clone(), l.tail(1).map_or(Value::Nil, Value::List))),
+        Value::Pair(p) => Some((p.car(), p.cdr())),
+        _ => None
+    }
+}
+
+// `equal?`: lists, pairs, vectors, strings and bytevectors compare by their contents,
+// everything else with `eqv?`.
+fn is_equal(a: &Value, b: &Value) -> bool {
+    equal_contents(a, b, &mut HashSet::new())
+}
 
-            let children = children_result.unwrap().into_iter().collect::<Vec<DataType>>();
-            Ok(DataType::List(children))
+// `equal?` for the pairs and vectors of `a` and `b`, where the pairs and vectors
+// compared in `seen` are taken to be equal, so that comparing circular data ends.
+fn equal_contents(a: &Value, b: &Value, seen: &mut HashSet<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => x == y,
+        (Value::Vector(x), Value::Vector(y)) => {
+            if !seen.insert((Rc::as_ptr(x) as *const u8 as usize, Rc::as_ptr(y) as *const u8 as usize)) {
+                return true;
+            }
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| equal_contents(x, y, seen))
         }
-        &AST::Symbol(ref s) => {
-            if s.starts_with("#") {
-                if s.len() != 2 {
-                    return Err("syntax error");
+        (Value::Bytevector(x), Value::Bytevector(y)) => x == y,
+        (Value::List(_), _) | (Value::Pair(_), _) => {
+            // walks along the cdrs so that long lists do not grow the stack
+            let (mut x, mut y) = (a.clone(), b.clone());
+            while let (Some((x_car, x_cdr)), Some((y_car, y_cdr))) = (split_list(&x), split_list(&y)) {
+                if let (Value::Pair(p), Value::Pair(q)) = (&x, &y) {
+                    if !seen.insert((Rc::as_ptr(p) as usize, Rc::as_ptr(q) as usize)) {
+                        return true;
+                    }
                 }
-                let c_option = s.chars().nth(1);
-                if let Some('t') = c_option {
-                    Ok(DataType::Bool(true))
-                } else if let Some('f') = c_option {
-                    Ok(DataType::Bool(false))
-                } else {
-                    Err("syntax error")
+                if !equal_contents(&x_car, &y_car, seen) {
+                    return false;
                 }
-            } else if s.starts_with("\"") && s.ends_with("\"") {
-                Ok(DataType::Symbol((&s[1..s.len() - 1]).to_string()))
-            } else {
-                Ok(DataType::Symbol(s.clone()))
+                x = x_cdr;
+                y = y_cdr;
+            }
+            match (split_list(&x), split_list(&y)) {
+                (None, None) => equal_contents(&x, &y, seen),
+                _ => false
+            }
+        }
+        _ => is_eqv(a, b)
+    }
+}
+
+// `length` copies of `fill`, or None when there is no memory for them.
+fn filled<T: Clone>(fill: T, length: usize) -> Option<Vec<T>> {
+    let mut items = vec![];
+    items.try_reserve_exact(length).ok()?;
+    items.resize(length, fill);
+    Some(items)
+}
+
+// The optional start and end arguments selecting part of a sequence of `length`
+// elements; the whole sequence when they are left out.
+fn range_arguments(length: usize, arguments: &[Value]) -> Option<(usize, usize)> {
+    let index = |value: &Value| integer_argument(value).and_then(|n| n.to_usize());
+    let (start, end) = match arguments {
+        [] => (0, length),
+        [start] => (index(start)?, length),
+        [start, end] => (index(start)?, index(end)?),
+        _ => return None
+    };
+    if start <= end && end <= length {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+// What is left of a list after dropping its first `k` elements, sharing the pairs
+// of the original list. The tail of an improper list may be any value.
+fn list_tail(value: Value, k: usize) -> Option<Value> {
+    let mut current = value;
+    let mut k = k;
+    while k > 0 {
+        current = match current {
+            Value::List(l) if k <= l.len() => return Some(l.tail(k).map_or(Value::Nil, Value::List)),
+            Value::Pair(p) => p.cdr(),
+            _ => return None
+        };
+        k -= 1;
+    }
+    Some(current)
+}
+
+// The first tail of `list` whose car is the same as `key`, or #f. None if `list` is
+// not a list.
+fn find_member<F>(key: &Value, list: &Value, mut same: F) -> Option<Result<Value, SchemeError>>
+    where F: FnMut(&Value, &Value) -> Result<bool, SchemeError> {
+    let mut current = list.clone();
+    let mut cycle = CycleCheck::new(list);
+    loop {
+        match current {
+            Value::Pair(p) => match same(key, &p.car()) {
+                Ok(true) => return Some(Ok(Value::Pair(p))),
+                Ok(false) => {
+                    current = p.cdr();
+                    if cycle.is_cycle(&current) {
+                        return None;
+                    }
+                }
+                Err(e) => return Some(Err(e))
+            },
+            Value::List(l) => {
+                for (i, element) in l.iter().enumerate() {
+                    match same(key, element) {
+                        Ok(true) => return Some(Ok(Value::List(l.tail(i).unwrap()))),
+                        Ok(false) => {}
+                        Err(e) => return Some(Err(e))
+                    }
+                }
+                return Some(Ok(Value::Bool(false)));
+            }
+            Value::Nil => return Some(Ok(Value::Bool(false))),
+            _ => return None
+        }
+    }
+}
+
+// The first entry of the association list `alist` whose car is the same as `key`,
+// or #f. None if `alist` is not a list of pairs.
+fn find_association<F>(key: &Value, alist: &Value, mut same: F) -> Option<Result<Value, SchemeError>>
+    where F: FnMut(&Value, &Value) -> Result<bool, SchemeError> {
+    for entry in list_elements(alist)? {
+        let entry_key = match entry {
+            Value::Pair(ref p) => p.car(),
+            Value::List(ref l) => l[0].clone(),
+            _ => return None
+        };
+        match same(key, &entry_key) {
+            Ok(true) => return Some(Ok(entry)),
+            Ok(false) => {}
+            Err(e) => return Some(Err(e))
+        }
+    }
+    Some(Ok(Value::Bool(false)))
+}
+
+// Links `tail` after the last pair of `head` instead of copying `head`.
+fn append_in_place(head: Value, tail: Value) -> Result<Value, SchemeError> {
+    match head {
+        Value::Nil => Ok(tail),
+        Value::List(mut l) => match tail {
+            _ if l.is_empty() => Ok(tail),
+            Value::List(t) => {
+                l.make_mut().extend(t);
+                Ok(Value::List(l))
+            }
+            _ => Ok(l.into_iter().rev().fold(tail, |acc, x| Value::cons(x, acc)))
+        },
+        Value::Pair(ref first) => {
+            let mut last = first.clone();
+            let mut cycle = CycleCheck::new(&head);
+            while let Value::Pair(next) = last.cdr() {
+                if cycle.is_cycle(&Value::Pair(next.clone())) {
+                    return Err(SchemeError::wrong_type("append! function requires arguments of type 'list'"));
+                }
+                last = next;
+            }
+            match last.cdr() {
+                Value::List(l) => last.set_cdr(append_in_place(Value::List(l), tail)?),
+                Value::Nil => {
+                    gc::track(&Value::Pair(last.clone()), &tail);
+                    last.set_cdr(tail);
+                }
+                _ => return Err(SchemeError::wrong_type("append! function requires arguments of type 'list'"))
+            }
+            Ok(head)
+        }
+        _ => Err(SchemeError::wrong_type("append! function requires arguments of type 'list'"))
+    }
+}
+
+// Reverses a list by relinking its pairs, so that no new pair is allocated.
+fn reverse_in_place(value: Value) -> Result<Value, SchemeError> {
+    if list_elements(&value).is_none() {
+        return Err(SchemeError::wrong_type("reverse! function requires an argument of type 'list'"));
+    }
+    let mut reversed = Value::Nil;
+    let mut current = value;
+    loop {
+        match current {
+            Value::Pair(p) => {
+                current = p.cdr();
+                p.set_cdr(reversed);
+                reversed = Value::Pair(p);
+            }
+            Value::List(mut l) => {
+                if let Value::Pair(_) = reversed {
+                    return Ok(l.into_iter().fold(reversed, |acc, x| Value::cons(x, acc)));
+                }
+                l.reverse();
+                return Ok(Value::List(l));
+            }
+            Value::Nil => return Ok(reversed),
+            _ => unreachable!()
+        }
+    }
+}
+
+// The operand of `(name operand)`, such as the expression in `,x`.
+fn prefixed<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::List(v) if v.len() == 2 && v[0] == Value::Symbol(SymbolId::intern(name)) => Some(&v[1]),
+        _ => None
+    }
+}
+
+// Elements of a quasiquote template list or vector, with ,@ splicing in place.
+fn quasiquote_elements(elements: &[Value], depth: usize, env: Rc<RefCell<Env>>) -> Result<Vec<Value>, SchemeError> {
+    let mut result = vec![];
+    for element in elements {
+        match prefixed(element, "unquote-splicing") {
+            Some(value) if depth == 1 => {
+                let spliced = eval_ref(Some(value), env.clone())?;
+                result.extend(list_elements(&spliced).ok_or(SchemeError::wrong_type("unquote-splicing requires a value of type 'list'"))?);
+            }
+            _ => result.push(quasiquote(element, depth, env.clone())?)
+        }
+    }
+    Ok(result)
+}
+
+// Builds the datum of a quasiquote template. `depth` counts the enclosing
+// quasiquotes; only unquotes at depth 1 are evaluated.
+fn quasiquote(template: &Value, depth: usize, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    // the operand may splice into the form, as in ,,@x
+    let nested = |name: &str, value: &Value, depth: usize| -> Result<Value, SchemeError> {
+        let mut form = vec![Value::Symbol(SymbolId::intern(name))];
+        form.extend(quasiquote_elements(std::slice::from_ref(value), depth, env.clone())?);
+        Ok(Value::list(form))
+    };
+    if let Some(value) = prefixed(template, "unquote") {
+        return match depth {
+            1 => eval_ref(Some(value), env.clone()),
+            _ => nested("unquote", value, depth - 1)
+        };
+    }
+    if let Some(value) = prefixed(template, "unquote-splicing") {
+        return match depth {
+            1 => Err(SchemeError::syntax("unquote-splicing is only allowed inside a list or vector")),
+            _ => nested("unquote-splicing", value, depth - 1)
+        };
+    }
+    if let Some(value) = prefixed(template, "quasiquote") {
+        return nested("quasiquote", value, depth + 1);
+    }
+    match template {
+        // a dotted template, whose tail may be unquoted as in `(a . ,b)`
+        Value::Pair(_) => {
+            let mut elements = vec![];
+            let mut tail = template.clone();
+            while let Value::Pair(p) = tail {
+                elements.push(p.car());
+                tail = p.cdr();
             }
+            let tail = quasiquote(&tail, depth, env.clone())?;
+            quasiquote_elements(&elements, depth, env)?.into_iter().rev().try_fold(tail, |tail, element| {
+                Ok(Value::cons(element, tail))
+            })
+        }
+        Value::List(v) => {
+            let elements = quasiquote_elements(v, depth, env)?;
+            Ok(Value::list(elements))
+        }
+        Value::Vector(v) => {
+            let template = v.borrow().clone();
+            let elements = quasiquote_elements(&template, depth, env)?;
+            count_allocation(size_of_values(elements.len()));
+            Ok(Value::Vector(Rc::new(RefCell::new(elements))))
         }
-        &AST::Integer(i) => Ok(DataType::Number(i as f64)),
-        &AST::Float(f) => Ok(DataType::Number(f))
+        _ => Ok(template.clone())
     }
 }
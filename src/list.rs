@@ -0,0 +1,129 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::slice;
+use std::vec;
+
+use super::Value;
+
+/// The elements of a proper list as the reader makes them for programs. Copies
+/// share the elements, and `cdr` and `list-tail` give the rest of the list without
+/// copying it, so that walking down a list takes time in proportion to its length.
+/// A list is never changed once made, and it is never empty; the empty list is
+/// `Value::Nil`.
+#[derive(Clone)]
+pub struct List {
+    items: Rc<Vec<Value>>,
+    // the index in `items` of the first element
+    start: usize
+}
+
+impl List {
+    /// The list without its first `k` elements, sharing them with this one; None if
+    /// that leaves nothing.
+    pub fn tail(&self, k: usize) -> Option<List> {
+        if k < self.len() {
+            Some(List { items: self.items.clone(), start: self.start + k })
+        } else {
+            None
+        }
+    }
+
+    /// The elements, copied only if another list shares them.
+    pub fn into_vec(self) -> Vec<Value> {
+        match Rc::try_unwrap(self.items) {
+            Ok(mut items) => {
+                items.drain(..self.start);
+                items
+            }
+            Err(items) => items[self.start..].to_vec()
+        }
+    }
+
+    /// The elements to change in place, which are first copied if another list
+    /// shares them, as `Rc::make_mut` does. The list must not be left empty.
+    pub fn make_mut(&mut self) -> &mut Vec<Value> {
+        if Rc::get_mut(&mut self.items).is_none() {
+            self.items = Rc::new(self.items[self.start..].to_vec());
+            self.start = 0;
+        }
+        let items = Rc::get_mut(&mut self.items).unwrap();
+        items.drain(..self.start);
+        self.start = 0;
+        items
+    }
+
+    // The elements of every list sharing them, which the cycle collector counts
+    // references to.
+    pub(crate) fn storage(&self) -> &Rc<Vec<Value>> {
+        &self.items
+    }
+
+    /// Whether both lists are the same elements of the same storage.
+    pub fn ptr_eq(&self, other: &List) -> bool {
+        Rc::ptr_eq(&self.items, &other.items) && self.start == other.start
+    }
+
+    /// The number of elements the storage of the list has room for.
+    pub fn capacity(&self) -> usize {
+        self.items.capacity() - self.start
+    }
+}
+
+impl Deref for List {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        &self.items[self.start..]
+    }
+}
+
+impl DerefMut for List {
+    fn deref_mut(&mut self) -> &mut [Value] {
+        self.make_mut()
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(items: Vec<Value>) -> List {
+        debug_assert!(!items.is_empty());
+        List { items: Rc::new(items), start: 0 }
+    }
+}
+
+impl FromIterator<Value> for List {
+    fn from_iter<I: IntoIterator<Item = Value>>(items: I) -> List {
+        List::from(items.into_iter().collect::<Vec<Value>>())
+    }
+}
+
+impl IntoIterator for List {
+    type Item = Value;
+    type IntoIter = vec::IntoIter<Value>;
+
+    fn into_iter(self) -> vec::IntoIter<Value> {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Value;
+    type IntoIter = slice::Iter<'a, Value>;
+
+    fn into_iter(self) -> slice::Iter<'a, Value> {
+        self.iter()
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &List) -> bool {
+        self.ptr_eq(other) || **self == **other
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,473 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use super::{bind_arguments, context, count_allocation, frames, gc, list_items, observe, quasiquote, record, record_failure, safe_point, source_map, vm, watch};
+use super::{count_step, DepthGuard, Env, Procedure, SchemeError, Span, SymbolId, Value, WatchAccess};
+use watch::CallGuard;
+
+// Builtins that call procedures, like `map`, evaluate them with a stack of frames of
+// their own on top of the Rust stack. Each of these costs a few kilobytes of it, so
+// their nesting in a context is bounded well below what a thread's stack holds.
+const MAX_NESTED_RUNS: usize = 128;
+
+pub(crate) struct RunGuard;
+
+impl RunGuard {
+    pub(crate) fn enter() -> Result<RunGuard, SchemeError> {
+        context::with(|c| {
+            let runs = c.runs.get();
+            if runs == MAX_NESTED_RUNS {
+                return Err(SchemeError::other("procedures called by builtins are nested too deeply"));
+            }
+            c.runs.set(runs + 1);
+            Ok(RunGuard)
+        })
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        context::with(|c| c.runs.set(c.runs.get() - 1));
+    }
+}
+
+// The tree a form belongs to: the program being evaluated, or the body of a
+// procedure, which the procedure shares with the frames evaluating it.
+#[derive(Clone)]
+enum Root<'a> {
+    Program(&'a Value),
+    Body(Rc<Value>)
+}
+
+/// Where a form is: the indices of the lists leading to it from its root. Frames
+/// hold this instead of a reference, so that the body of a procedure called later can
+/// be pushed on the same stack as the program that calls it.
+#[derive(Clone)]
+pub(crate) struct Code<'a> {
+    root: Root<'a>,
+    path: Vec<usize>
+}
+
+impl<'a> Code<'a> {
+    pub(crate) fn program(form: &'a Value) -> Code<'a> {
+        Code { root: Root::Program(form), path: vec![] }
+    }
+
+    pub(crate) fn body(body: Rc<Value>) -> Code<'a> {
+        Code { root: Root::Body(body), path: vec![] }
+    }
+
+    fn node(&self) -> &Value {
+        let mut node = match self.root {
+            Root::Program(form) => form,
+            Root::Body(ref body) => body
+        };
+        for &i in &self.path {
+            node = match node {
+                Value::List(list) => &list[i],
+                _ => unreachable!()
+            };
+        }
+        node
+    }
+
+    fn child(&self, i: usize) -> Code<'a> {
+        let mut path = Vec::with_capacity(self.path.len() + 1);
+        path.extend_from_slice(&self.path);
+        path.push(i);
+        Code { root: self.root.clone(), path }
+    }
+}
+
+// What a frame is waiting for.
+enum State {
+    Start,
+    // the value of the subform is the value of the form, as for a branch of `if`
+    Tail,
+    If,
+    Define(SymbolId),
+    // `next` counts the bindings evaluated, then the body forms
+    Letrec { env: Rc<RefCell<Env>>, next: usize },
+    // the procedure of `((...) arguments)`
+    Operator,
+    Arguments { procedure: Value, values: Vec<Value> }
+}
+
+// The evaluation of one form. The frame a procedure body is first evaluated in holds
+// a level of `evaluation_depth`, which passes on to the frames that take its place,
+// as the call frames of the bytecode machine do.
+// `owns_env` is set when `env` is the frame of a procedure call, given back to the
+// free frames once the frame is done with it, and `origin` is where the form this frame
+// took the place of was read, if it was.
+struct Frame<'a> {
+    code: Code<'a>,
+    env: Rc<RefCell<Env>>,
+    owns_env: bool,
+    origin: Option<Span>,
+    state: State,
+    call: Option<CallGuard>,
+    _depth: Option<DepthGuard>
+}
+
+enum Step<'a> {
+    Eval(Code<'a>, Rc<RefCell<Env>>),
+    // a subform in tail position, whose value is that of the frame, evaluated in
+    // place of the frame
+    Tail(Code<'a>, Rc<RefCell<Env>>),
+    // the body of a lambda called by the form, evaluated in place of the frame
+    Call(Code<'a>, Rc<RefCell<Env>>),
+    Return(Value)
+}
+
+impl<'a> Frame<'a> {
+    fn new(code: Code<'a>, env: Rc<RefCell<Env>>) -> Frame<'a> {
+        count_step();
+        Frame::at(code, env, None)
+    }
+
+    // The frame a procedure body is first evaluated in, one level deeper than the
+    // frame that called it.
+    fn deeper(code: Code<'a>, env: Rc<RefCell<Env>>) -> Frame<'a> {
+        Frame::at(code, env, Some(DepthGuard::enter()))
+    }
+
+    fn at(code: Code<'a>, env: Rc<RefCell<Env>>, depth: Option<DepthGuard>) -> Frame<'a> {
+        Frame { code, env, owns_env: false, origin: None, state: State::Start, call: None, _depth: depth }
+    }
+
+    // A frame evaluating `code` in place of this one. The procedure whose body this
+    // frame was evaluating stays on the call stack until the new frame calls another.
+    fn replace(mut self, code: Code<'a>, env: Rc<RefCell<Env>>, call: bool) -> Frame<'a> {
+        let mut frame = match self._depth.take() {
+            Some(depth) => Frame { _depth: Some(depth), ..Frame::new(code, env) },
+            None if call => Frame::deeper(code, env),
+            None => Frame::new(code, env)
+        };
+        frame.call = self.call.take();
+        frame.owns_env = call || (self.owns_env && Rc::ptr_eq(&self.env, &frame.env));
+        frame.origin = source_map::span_of(self.code.node()).or(self.origin);
+        self.finish();
+        frame
+    }
+
+    // Drops the frame, giving its procedure frame back to the free ones unless something
+    // else still refers to it.
+    fn finish(self) {
+        if self.owns_env {
+            frames::release(self.env);
+        }
+    }
+
+
+    fn start(&mut self) -> Result<Step<'a>, SchemeError> {
+        safe_point()?;
+        observe::enter_form(self.code.node());
+        let list = match self.code.node() {
+            Value::List(list) => list,
+            atom => return evaluate_atom(atom, &self.env).map(Step::Return)
+        };
+        let id = match list.first() {
+            Some(Value::Symbol(id)) => *id,
+            Some(Value::List(_)) => {
+                enter_call(&mut self.call, SymbolId::intern("lambda"));
+                self.state = State::Operator;
+                return Ok(Step::Eval(self.code.child(0), self.env.clone()));
+            }
+            _ => return Err(SchemeError::syntax("syntax error"))
+        };
+        match &*id.name() {
+            "quote" => match list.get(1) {
+                Some(datum) => Ok(Step::Return(datum.clone())),
+                None => Err(SchemeError::syntax("wrong number of parts"))
+            },
+            "quasiquote" => match &list[..] {
+                [_, template] => quasiquote(template, 1, self.env.clone()).map(Step::Return),
+                _ => Err(SchemeError::syntax("wrong number of parts"))
+            },
+            "if" if list.len() >= 4 => {
+                self.state = State::If;
+                Ok(Step::Eval(self.code.child(1), self.env.clone()))
+            }
+            "if" => Err(SchemeError::syntax("wrong syntax for if expression")),
+            "define" => match (list.get(1), list.get(2)) {
+                (Some(Value::Symbol(name)), Some(Value::List(_))) => {
+                    self.state = State::Define(*name);
+                    Ok(Step::Eval(self.code.child(2), self.env.clone()))
+                }
+                // other values are worked out in this frame
+                (Some(Value::Symbol(name)), Some(value)) => {
+                    let data = evaluate_atom(value, &self.env)?;
+                    define(&self.env, *name, data, || source_map::span_of(self.code.node()));
+                    Ok(Step::Return(Value::Unspecified))
+                }
+                _ => Err(SchemeError::syntax("wrong syntax for define expression"))
+            },
+            "lambda" => lambda(list, &self.env).map(Step::Return),
+            "define-record-type" => {
+                for (name, data) in record::define_record_type(list)? {
+                    let id = SymbolId::intern(&name);
+                    watch::notify(id, WatchAccess::Write, &data, || source_map::span_of(self.code.node()));
+                    observe::define(id, &data);
+                    self.env.borrow_mut().bind(id, data);
+                }
+                Ok(Step::Return(Value::Unspecified))
+            }
+            "letrec" | "letrec*" => match (list.get(1), list.get(2)) {
+                (Some(Value::List(_)), Some(_)) | (Some(Value::Nil), Some(_)) => {
+                    // every init is evaluated inside the new frame, so the lambdas
+                    // it creates can refer to each other regardless of their order
+                    let env = Rc::new(RefCell::new(Env::new(HashMap::new(), Some(self.env.clone()))));
+                    count_allocation(mem::size_of::<Env>());
+                    self.state = State::Letrec { env, next: 0 };
+                    self.next_letrec(Value::Unspecified)
+                }
+                _ => Err(SchemeError::syntax("wrong syntax for letrec expression"))
+            },
+            name => {
+                let data = self.env.borrow().get(id);
+                if let Some(ref data) = data {
+                    watch::notify(id, WatchAccess::Read, data, || source_map::span_of(&list[0]).or_else(|| source_map::span_of(self.code.node())));
+                }
+                enter_call(&mut self.call, id);
+                match data {
+                    Some(procedure @ Value::Proc(_)) | Some(procedure @ Value::Lambda(_)) => {
+                        self.state = State::Arguments { procedure, values: Vec::with_capacity(list.len() - 1) };
+                        self.next_argument()
+                    }
+                    Some(_) => Err(SchemeError::wrong_type("wrong type to apply")),
+                    None => Err(SchemeError::unbound(name))
+                }
+            }
+        }
+    }
+
+    // Goes on with `value`, the value of the subform the frame was waiting for.
+    fn resume(&mut self, value: Value) -> Result<Step<'a>, SchemeError> {
+        match mem::replace(&mut self.state, State::Tail) {
+            State::Start | State::Tail => Ok(Step::Return(value)),
+            // every value but #f counts as true, 0 and the empty list included
+            State::If => Ok(Step::Tail(self.code.child(if value == Value::Bool(false) { 3 } else { 2 }), self.env.clone())),
+            State::Define(id) => {
+                define(&self.env, id, value, || source_map::span_of(self.code.node()));
+                Ok(Step::Return(Value::Unspecified))
+            }
+            State::Letrec { env, next } => {
+                if let (Value::List(list), Some(i)) = (self.code.node(), next.checked_sub(1)) {
+                    if let Some(form @ Value::List(binding)) = letrec_bindings(list).get(i) {
+                        if let Value::Symbol(id) = binding[0] {
+                            watch::notify(id, WatchAccess::Write, &value, || source_map::span_of(form));
+                            gc::track_env(&env, &value);
+                            env.borrow_mut().bind(id, value.clone());
+                        }
+                    }
+                }
+                self.state = State::Letrec { env, next };
+                self.next_letrec(value)
+            }
+            State::Operator => match value {
+                procedure @ Value::Proc(_) | procedure @ Value::Lambda(_) => {
+                    self.state = State::Arguments { procedure, values: vec![] };
+                    self.next_argument()
+                }
+                _ => Err(SchemeError::syntax("unsupported data type on first element"))
+            },
+            State::Arguments { procedure, mut values } => {
+                values.push(value);
+                self.state = State::Arguments { procedure, values };
+                self.next_argument()
+            }
+        }
+    }
+
+    // Evaluates the next binding of a `letrec`, then its body forms one after the
+    // other. `value` is the value of the last one evaluated.
+    fn next_letrec(&mut self, value: Value) -> Result<Step<'a>, SchemeError> {
+        let (env, next) = match self.state {
+            State::Letrec { ref env, ref mut next } => (env.clone(), next),
+            _ => unreachable!()
+        };
+        let list = match self.code.node() {
+            Value::List(list) => list,
+            _ => unreachable!()
+        };
+        let bindings = letrec_bindings(list);
+        let i = *next;
+        *next += 1;
+        if let Some(binding) = bindings.get(i) {
+            return match binding {
+                Value::List(parts) if parts.len() == 2 => match parts[0] {
+                    Value::Symbol(_) => Ok(Step::Eval(self.code.child(1).child(i).child(1), env)),
+                    _ => Err(SchemeError::syntax("letrec binding name must be a symbol"))
+                },
+                _ => Err(SchemeError::syntax("wrong syntax for letrec binding"))
+            };
+        }
+        let body = 2 + i - bindings.len();
+        if body + 1 < list.len() {
+            Ok(Step::Eval(self.code.child(body), env))
+        } else if body < list.len() {
+            Ok(Step::Tail(self.code.child(body), env))
+        } else {
+            Ok(Step::Return(value))
+        }
+    }
+
+    // Evaluates the next argument of a call, or calls the procedure once all of them
+    // are evaluated.
+    fn next_argument(&mut self) -> Result<Step<'a>, SchemeError> {
+        let count = match self.code.node() {
+            Value::List(list) => list.len() - 1,
+            _ => unreachable!()
+        };
+        let evaluated = match self.state {
+            State::Arguments { ref values, .. } => values.len(),
+            _ => unreachable!()
+        };
+        if evaluated < count {
+            return Ok(Step::Eval(self.code.child(evaluated + 1), self.env.clone()));
+        }
+        if let State::Arguments { ref procedure, ref values } = self.state {
+            observe::apply(procedure, values);
+        }
+        match mem::replace(&mut self.state, State::Tail) {
+            State::Arguments { procedure: Value::Proc(f), values } => f.call(values, self.env.clone()).map(Step::Return),
+            // the variables of compiled procedures are found by position
+            State::Arguments { procedure: Value::Lambda(p), values } if p.closure.code.is_some() => vm::call(&p, values).map(Step::Return),
+            State::Arguments { procedure: Value::Lambda(p), values } => {
+                let env = bind_arguments(&p, values);
+                Ok(Step::Call(Code::body(p.closure.body.clone()), env))
+            }
+            _ => unreachable!()
+        }
+    }
+
+    // Records `error` as the failure of this frame: the innermost failing frame
+    // keeps its environment for inspection, and the error is placed at the innermost
+    // form read by `parse` that failed with it.
+    fn fail(mut self, error: SchemeError) -> SchemeError {
+        let error = error.traced();
+        // the procedure called by the form has returned by now
+        self.call = None;
+        record_failure(&self.env, &error);
+        // procedure bodies are copies of the source, so an error inside one is placed
+        // at the form that called it, even when the body took the place of its frame
+        let error = match (error.span(), source_map::span_of(self.code.node()).or(self.origin)) {
+            (None, Some(span)) => error.with_span(span),
+            _ => error
+        };
+        self.finish();
+        error
+    }
+}
+
+// Puts `id` on the call stack in place of the procedure the frame holding `call` was
+// evaluating the body of, which a call in tail position does not return to.
+fn enter_call(call: &mut Option<CallGuard>, id: SymbolId) {
+    *call = None;
+    *call = Some(CallGuard::enter(id));
+}
+
+fn letrec_bindings(list: &[Value]) -> &[Value] {
+    list.get(1).and_then(list_items).unwrap_or(&[])
+}
+
+/// Evaluates `code` with an explicit stack of frames instead of recursion, so that
+/// deeply nested forms and deep recursion in Scheme do not use up the Rust stack.
+/// Builtins that call procedures, like `map`, still run them in a stack of their own.
+pub(crate) fn run(code: Code, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    let _run = RunGuard::enter()?;
+    // the run is a level of its own, which the frames that take the place of its first
+    // one do not carry into the procedures they call; entering it counts its first step
+    let _depth = DepthGuard::enter();
+    let mut stack = vec![Frame::at(code, env, None)];
+    let mut value = None;
+    loop {
+        let frame = stack.last_mut().unwrap();
+        let step = match value.take() {
+            None => frame.start(),
+            Some(value) => frame.resume(value)
+        };
+        match step {
+            Ok(Step::Eval(code, env)) => stack.push(Frame::new(code, env)),
+            // the frame waiting for a form in tail position is replaced by it, so
+            // that loops of tail calls run in a bounded number of frames
+            Ok(Step::Tail(code, env)) => {
+                let frame = stack.pop().unwrap().replace(code, env, false);
+                stack.push(frame);
+            }
+            Ok(Step::Call(code, env)) => {
+                let frame = stack.pop().unwrap().replace(code, env, true);
+                stack.push(frame);
+            }
+            Ok(Step::Return(result)) => {
+                stack.pop().unwrap().finish();
+                if stack.is_empty() {
+                    return Ok(result);
+                }
+                value = Some(result);
+            }
+            Err(mut error) => {
+                // the frames waiting for the failed form fail with it, innermost first
+                while let Some(frame) = stack.pop() {
+                    error = frame.fail(error);
+                }
+                return Err(error);
+            }
+        }
+    }
+}
+
+// The value of a form that is not a list: symbols are variables, and other data
+// evaluate to themselves.
+fn evaluate_atom(value: &Value, env: &Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    match value {
+        Value::Symbol(id) => match env.borrow().get(*id) {
+            Some(data) => {
+                watch::notify(*id, WatchAccess::Read, &data, || source_map::span_of(value));
+                Ok(data)
+            }
+            None => Err(SchemeError::unbound(&id.name()))
+        },
+        Value::Nil => Err(SchemeError::syntax("syntax error")),
+        Value::Pair(_) => Err(SchemeError::syntax("datum labels and dotted lists can only be used in quoted data")),
+        Value::List(_) => unreachable!(),
+        _ => Ok(value.clone())
+    }
+}
+
+// `data`, named after `id` if it is a procedure without a name.
+pub(crate) fn named(data: Value, id: SymbolId) -> Value {
+    match data {
+        Value::Lambda(mut p) if p.name.is_none() => {
+            p.name = Some(id);
+            Value::Lambda(p)
+        }
+        Value::Proc(f) if f.name.is_none() => Value::Proc(f.named(id)),
+        data => data
+    }
+}
+
+// Binds `data` to `id` in the innermost frame of `env`, naming procedures that do not
+// have a name yet after it. `at` is where the definition was read, for watches.
+pub(crate) fn define<F: FnOnce() -> Option<Span>>(env: &Rc<RefCell<Env>>, id: SymbolId, data: Value, at: F) {
+    let data = named(data, id);
+    watch::notify(id, WatchAccess::Write, &data, at);
+    observe::define(id, &data);
+    gc::track_env(env, &data);
+    env.borrow_mut().bind(id, data);
+}
+
+fn lambda(list: &[Value], env: &Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    match (list.get(1).and_then(list_items), list.get(2)) {
+        (Some(args), Some(body @ Value::List(_))) => {
+            let params = args.iter().map(|arg| match arg {
+                Value::Symbol(_) => Ok(arg.clone()),
+                _ => Err(SchemeError::syntax("lambda argument must be a symbol"))
+            }).collect::<Result<Vec<Value>, SchemeError>>()?;
+            Ok(Value::Lambda(Procedure::new(params.into(), Rc::new(body.clone()), env.clone(), None)))
+        }
+        _ => Err(SchemeError::syntax("syntax error"))
+    }
+}
@@ -6,34 +6,208 @@ extern crate env_logger;
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::env;
+use std::fs;
 use std::io;
 use std::io::Write;
+use std::process;
 use scheme_rs::*;
 
 fn main() {
     env_logger::init().unwrap();
-    let local = RefCell::new(setup());
-    let env = Env {
-        local,
-        parent: None
-    };
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.first().map(String::as_str) == Some("check") {
+        process::exit(check_files(&args[1..]));
+    }
+    let mut builtins = setup();
+    if let Err(e) = define_prelude(&mut builtins) {
+        println!("error: {}", e);
+        process::exit(1);
+    }
+    // the bindings are shared with the snapshots `:undo-definitions` keeps, not copied
+    let env = Env::persistent(builtins.into_iter().collect(), None);
     debug!("Env: {:?}", env);
 
     println!("Welcome to scheme-rs");
     repl(Rc::new(RefCell::new(env)));
 }
 
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// `main check <file>...` reads and lints the files without running them, printing
+// one JSON object per problem. The exit status is 1 if any error was found.
+fn check_files(files: &[String]) -> i32 {
+    let mut status = 0;
+    for file in files {
+        let diagnostics = match fs::read_to_string(file) {
+            Ok(source) => check(&source).into_iter().map(|d| {
+                let (line, column) = line_column(&source, d.span.start);
+                (line, column, d.severity, d.message)
+            }).collect::<Vec<_>>(),
+            Err(e) => vec![(1, 1, Severity::Error, e.to_string())]
+        };
+        for (line, column, severity, message) in diagnostics {
+            if severity == Severity::Error {
+                status = 1;
+            }
+            let severity = match severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning"
+            };
+            println!("{{\"file\":{},\"line\":{},\"column\":{},\"severity\":\"{}\",\"message\":{}}}",
+                json_string(file), line, column, severity, json_string(&message));
+        }
+    }
+    status
+}
+
+// Returns None at the end of the input.
+fn read_input(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().expect("cannot flush screen");
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input).expect("cannot read input") {
+        0 => None,
+        _ => Some(input)
+    }
+}
+
+// Inputs whose definitions `:undo-definitions` can take back.
+const UNDO_LIMIT: usize = 100;
+
 fn repl(env: Rc<RefCell<Env>>) {
-    loop {
-        print!("scheme=> ");
-        io::stdout().flush().expect("cannot flush screen");
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("cannot read input");
+    // the global bindings before each of the last inputs
+    let mut undo = vec![];
+    // where the last input failed, for `:debug`
+    let mut failure = None;
+    while let Some(mut input) = read_input("scheme=> ") {
+        if let Some(file) = input.trim().strip_prefix(":inspect-dump") {
+            inspect_dump(file.trim());
+            continue;
+        }
+        if input.trim() == ":undo-definitions" {
+            match undo.pop() {
+                Some(snapshot) => env.borrow_mut().restore(&snapshot),
+                None => println!("no definitions to undo")
+            }
+            continue;
+        }
+        if input.trim() == ":debug" {
+            match failure.take() {
+                Some((error_env, dump)) => if let Some(d) = debug_repl(error_env, dump) {
+                    println!("{}", d);
+                },
+                None => println!("no error to debug")
+            }
+            continue;
+        }
+        let snapshot = env.borrow().snapshot();
+        // an expression goes on over the next lines until its lists are closed
+        while let PartialParse::Incomplete { .. } = parse_partial(&input) {
+            match read_input("    ... ") {
+                Some(line) => input.push_str(&line),
+                None => break
+            }
+        }
+        failure = None;
         match parse(input.as_str()).and_then(|ast| eval(Some(ast.result), env.clone())) {
-            Ok(Some(d)) => println!("{:?}", d),
-            Ok(None) => {}
-            Err(e) => println!("error: {}", e)
+            Ok(Value::Unspecified) => {}
+            Ok(d) => println!("{}", d),
+            Err(e) => {
+                match e.span() {
+                    Some(span) => println!("{}", error_at(&input, span, &e.to_string())),
+                    None => println!("error: {}", e)
+                }
+                if let Some(error_env) = error_environment() {
+                    println!("(:debug evaluates in the scope of the error)");
+                    failure = Some((error_env, error_dump()));
+                }
+            }
+        }
+        if env.borrow().changed_since(&snapshot) {
+            if undo.len() == UNDO_LIMIT {
+                undo.remove(0);
+            }
+            undo.push(snapshot);
         }
         debug!("ENV: {:?}", &env);
     }
+    println!();
+}
+
+fn inspect_dump(file: &str) {
+    let dump = match fs::read_to_string(file) {
+        Ok(text) => Dump::parse(&text),
+        Err(e) => {
+            println!("{}: {}", file, e);
+            return;
+        }
+    };
+    match dump {
+        Ok(dump) => {
+            println!("error: {}", dump.error);
+            println!("stack: {}", dump.stack.join(" > "));
+            for (depth, frame) in dump.frames.iter().enumerate() {
+                println!("frame {}:", depth);
+                for (name, value) in frame {
+                    println!("  {} = {}", name, value);
+                }
+            }
+            println!("trace: {}", dump.trace.join(" "));
+        }
+        Err(e) => println!("{}: {}", file, e)
+    }
+}
+
+// Evaluates expressions in the scope where an error happened, until `:abort`
+// gives up on the failed input or `:return <expr>` supplies its value.
+fn debug_repl(env: Rc<RefCell<Env>>, dump: Option<Dump>) -> Option<Value> {
+    println!("entering debug mode: :locals lists the bindings in scope, :dump <file> saves them for a bug report, :abort or :return <expr> leaves");
+    while let Some(input) = read_input("debug=> ") {
+        let command = input.trim();
+        if command == ":abort" {
+            return None;
+        }
+        if command == ":locals" {
+            let mut locals = env.borrow().bindings().iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect::<Vec<String>>();
+            locals.sort();
+            println!("{}", locals.join("\n"));
+            continue;
+        }
+        if let Some(file) = command.strip_prefix(":dump") {
+            match dump {
+                Some(ref dump) => if let Err(e) = fs::write(file.trim(), format!("{}\n", dump)) {
+                    println!("{}: {}", file.trim(), e);
+                },
+                None => println!("no error to dump")
+            }
+            continue;
+        }
+        let (returning, source) = match command.strip_prefix(":return") {
+            Some(rest) => (true, rest),
+            None => (false, command)
+        };
+        match parse(source).and_then(|ast| eval(Some(ast.result), env.clone())) {
+            Ok(d) if returning => return Some(d),
+            Ok(Value::Unspecified) => {}
+            Ok(d) => println!("{}", d),
+            Err(e) => println!("error: {}", e)
+        }
+    }
+    None
 }
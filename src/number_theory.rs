@@ -0,0 +1,125 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+const SMALL_PRIMES: [u32; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Exact results larger than this many bits are not computed: they would take more
+/// memory than there is to spare.
+pub const MAX_RESULT_BITS: u64 = 1 << 26;
+
+// Ranges at least this long are multiplied from halves large enough to take a while.
+const POLL_RANGE: u64 = 1024;
+
+// Product of all integers in [low, high], split in halves so that the
+// multiplications work on operands of similar size. `poll` is called before those
+// of large halves, and stops the product with its error.
+fn product_range<E>(low: u64, high: u64, poll: &dyn Fn() -> Result<(), E>) -> Result<BigInt, E> {
+    if low > high {
+        return Ok(BigInt::one());
+    }
+    if high - low < 8 {
+        return Ok((low..=high).fold(BigInt::one(), |acc, x| acc * x));
+    }
+    let middle = low + (high - low) / 2;
+    let (left, right) = (product_range(low, middle, poll)?, product_range(middle + 1, high, poll)?);
+    if high - low >= POLL_RANGE {
+        poll()?;
+    }
+    Ok(left * right)
+}
+
+/// An upper bound on the number of bits of the product of all integers in [low, high].
+pub fn product_bits(low: u64, high: u64) -> u64 {
+    match low > high {
+        true => 0,
+        false => (high - low + 1).saturating_mul(u64::from(64 - high.leading_zeros()))
+    }
+}
+
+/// `n!`, or None when it has more than `MAX_RESULT_BITS` bits. `poll` is called
+/// every so often while it is computed, and stops it with its error.
+pub fn factorial<E>(n: u64, poll: &dyn Fn() -> Result<(), E>) -> Result<Option<BigInt>, E> {
+    if product_bits(2, n) > MAX_RESULT_BITS {
+        return Ok(None);
+    }
+    product_range(2, n, poll).map(Some)
+}
+
+/// The number of ways to choose `k` of `n` things, or None when it has more than
+/// `MAX_RESULT_BITS` bits. `poll` is called as `factorial` calls it.
+pub fn binomial<E>(n: u64, k: u64, poll: &dyn Fn() -> Result<(), E>) -> Result<Option<BigInt>, E> {
+    if k > n {
+        return Ok(Some(BigInt::zero()));
+    }
+    let k = k.min(n - k);
+    if product_bits(n - k + 1, n) > MAX_RESULT_BITS {
+        return Ok(None);
+    }
+    let product = product_range(n - k + 1, n, poll)?;
+    Ok(factorial(k, poll)?.map(|factorial| product / factorial))
+}
+
+// Miller-Rabin with the first twelve primes as witnesses, which is deterministic
+// below 3.3 * 10^24 and a strong probable-prime test beyond that.
+pub fn is_prime(n: &BigInt) -> bool {
+    let two = BigInt::from(2);
+    if *n < two {
+        return false;
+    }
+    for &p in SMALL_PRIMES.iter() {
+        let p = BigInt::from(p);
+        if *n == p {
+            return true;
+        }
+        if (n % &p).is_zero() {
+            return false;
+        }
+    }
+
+    let n_minus_one: BigInt = n - 1;
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while d.is_even() {
+        d >>= 1;
+        s += 1;
+    }
+
+    'witness: for &a in SMALL_PRIMES.iter() {
+        let mut x = BigInt::from(a).modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+pub fn next_prime(n: &BigInt) -> BigInt {
+    let two = BigInt::from(2);
+    if *n < two {
+        return two;
+    }
+    let mut candidate: BigInt = n + 1;
+    if candidate.is_even() {
+        candidate += 1;
+    }
+    while !is_prime(&candidate) {
+        candidate += 2;
+    }
+    candidate
+}
+
+// Returns None when the exponent is negative or the modulus is not positive.
+pub fn modular_expt(base: &BigInt, exponent: &BigInt, modulus: &BigInt) -> Option<BigInt> {
+    if exponent.is_negative() || !modulus.is_positive() {
+        return None;
+    }
+    Some(base.mod_floor(modulus).modpow(exponent, modulus))
+}
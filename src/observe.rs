@@ -0,0 +1,82 @@
+use std::rc::Rc;
+
+use super::{context, SchemeError, SymbolId, Value};
+
+/// Hooks into the evaluation of programs by an interpreter or on the current thread,
+/// for debuggers, profilers or audit logs. Every method does nothing unless implemented, and runs in
+/// the middle of the evaluation: it may evaluate Scheme code itself, which is
+/// observed too.
+///
+/// ```
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use scheme_rs::{EvalObserver, Interpreter, Value};
+///
+/// #[derive(Default)]
+/// struct Definitions(RefCell<Vec<String>>);
+///
+/// impl EvalObserver for Definitions {
+///     fn on_define(&self, name: &str, _: &Value) {
+///         self.0.borrow_mut().push(name.to_string());
+///     }
+/// }
+///
+/// let definitions = Rc::new(Definitions::default());
+/// let interpreter = Interpreter::new().unwrap();
+/// interpreter.set_eval_observer(Some(definitions.clone()));
+/// interpreter.eval_str("(define x 1) (define (f) x)").unwrap();
+/// assert_eq!(vec!["x", "f"], *definitions.0.borrow());
+/// ```
+pub trait EvalObserver {
+    /// A form is about to be evaluated. The tree-walking evaluator reports every
+    /// form, subforms included; compiled code only the forms it is given to run.
+    fn on_enter_form(&self, _form: &Value) {}
+
+    /// A lambda or a builtin is called with `args`, evaluated already.
+    fn on_apply(&self, _procedure: &Value, _args: &[Value]) {}
+
+    /// `name` is bound by `define` or `define-record-type`, globally or in the body
+    /// of a procedure.
+    fn on_define(&self, _name: &str, _value: &Value) {}
+
+    /// An evaluation fails with `error`, reported once where it is raised rather
+    /// than by every form it unwinds. Errors caught by an exception handler are
+    /// reported too.
+    fn on_error(&self, _error: &SchemeError) {}
+}
+
+/// Starts reporting the evaluations of the current context to `observer`, replacing
+/// the one before, or stops with None.
+pub fn set_eval_observer(observer: Option<Rc<dyn EvalObserver>>) {
+    context::with(|c| *c.observer.borrow_mut() = observer);
+}
+
+// The observer is cloned out of the cell before it is called, so that it can
+// evaluate code and replace itself while the cell is not borrowed.
+pub(crate) fn observer() -> Option<Rc<dyn EvalObserver>> {
+    context::with(|c| c.observer.borrow().clone())
+}
+
+pub(crate) fn enter_form(form: &Value) {
+    if let Some(observer) = observer() {
+        observer.on_enter_form(form);
+    }
+}
+
+pub(crate) fn apply(procedure: &Value, args: &[Value]) {
+    if let Some(observer) = observer() {
+        observer.on_apply(procedure, args);
+    }
+}
+
+pub(crate) fn define(name: SymbolId, value: &Value) {
+    if let Some(observer) = observer() {
+        observer.on_define(&name.name(), value);
+    }
+}
+
+pub(crate) fn error(error: &SchemeError) {
+    if let Some(observer) = observer() {
+        observer.on_error(error);
+    }
+}
@@ -0,0 +1,260 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+
+use super::{list_items, source_map, Env, Span, SymbolId, Value};
+use bytecode::definitions;
+
+// Builtins without side effects, whose calls on constants are worked out before
+// evaluation.
+const FOLDED: &[&str] = &["+", "-", "*", "/", "=", "<", ">", "<=", ">=", "quotient", "remainder", "modulo", "abs", "min", "max", "expt"];
+
+struct Optimizer<'a> {
+    env: &'a Rc<RefCell<Env>>,
+    // names the program binds somewhere, which may not refer to the builtins there
+    bound: HashSet<SymbolId>
+}
+
+/// Rewrites `form`, a program about to be evaluated in `env`, into a simpler one with
+/// the same effects: calls of arithmetic builtins on constants are replaced by their
+/// values, `if` with a constant test by the branch it takes, constants whose value
+/// `begin` drops are left out, and lambdas without parameters that are called where
+/// they are created are replaced by their bodies. Forms that fail are left for the
+/// evaluation to report, and the forms kept keep their spans.
+pub fn optimize(form: &mut Value, env: &Rc<RefCell<Env>>) {
+    let mut bound = HashSet::new();
+    bindings(form, &mut bound);
+    Optimizer { env, bound }.form(form, false);
+}
+
+// Adds the names `form` binds with `define`, `lambda`, `letrec` and
+// `define-record-type` to `bound`. Quoted data are not left out, which only adds
+// names that need not be there.
+fn bindings(form: &Value, bound: &mut HashSet<SymbolId>) {
+    let list = match form {
+        Value::List(list) => list,
+        _ => return
+    };
+    let mut bind = |name: &Value| if let Value::Symbol(id) = *name {
+        bound.insert(id);
+    };
+    let keyword = match list.first() {
+        Some(Value::Symbol(id)) => Some(id.name()),
+        _ => None
+    };
+    match (keyword.as_deref(), list.get(1)) {
+        (Some("define"), Some(name)) => bind(name),
+        (Some("lambda"), Some(params)) => list_items(params).unwrap_or(&[]).iter().for_each(bind),
+        (Some("letrec"), Some(letrec)) | (Some("letrec*"), Some(letrec)) => for binding in list_items(letrec).unwrap_or(&[]) {
+            if let Value::List(parts) = binding {
+                parts.iter().take(1).for_each(&mut bind);
+            }
+        },
+        (Some("define-record-type"), _) => for part in list {
+            match part {
+                Value::List(parts) => parts.iter().for_each(&mut bind),
+                part => bind(part)
+            }
+        },
+        _ => {}
+    }
+    for child in list {
+        bindings(child, bound);
+    }
+}
+
+impl<'a> Optimizer<'a> {
+    // Whether `name` refers to the builtin of that name wherever it is used.
+    fn is_builtin(&self, id: SymbolId) -> bool {
+        if self.bound.contains(&id) {
+            return false;
+        }
+        matches!(self.env.borrow().get(id), Some(Value::Proc(ref f)) if f.name() == Some(id))
+    }
+
+    // Rewrites `form`, into a list again when `keep_list` is set, as lambda bodies must be.
+    fn form(&self, form: &mut Value, keep_list: bool) {
+        let list = match form {
+            Value::List(list) => list,
+            _ => return
+        };
+        let id = match list.first() {
+            Some(&Value::Symbol(id)) => id,
+            Some(Value::List(_)) => {
+                for part in list.iter_mut() {
+                    self.form(part, false);
+                }
+                return inline(form);
+            }
+            _ => return
+        };
+        match &*id.name() {
+            "quote" | "quasiquote" | "define-record-type" => {}
+            "define" => if let Some(part @ Value::List(_)) = list.get_mut(2) {
+                self.form(part, false);
+            },
+            "lambda" => if let Some(part @ Value::List(_)) = list.get_mut(2) {
+                self.form(part, true);
+            },
+            "letrec" | "letrec*" => {
+                if let Some(Value::List(bindings)) = list.get_mut(1) {
+                    for binding in bindings.iter_mut() {
+                        if let Value::List(parts) = binding {
+                            if parts.len() == 2 {
+                                self.form(&mut parts[1], false);
+                            }
+                        }
+                    }
+                }
+                for part in list.iter_mut().skip(2) {
+                    self.form(part, false);
+                }
+            }
+            "if" => {
+                for (i, part) in list.iter_mut().enumerate().skip(1) {
+                    self.form(part, keep_list && i > 1);
+                }
+                if list.len() >= 4 {
+                    if let Some(truth) = truth(&list[1]) {
+                        let taken = if truth { 2 } else { 3 };
+                        if !keep_list || matches!(list[taken], Value::List(_)) {
+                            replace_by_part(form, taken);
+                        }
+                    }
+                }
+            }
+            name => {
+                for part in list.iter_mut().skip(1) {
+                    self.form(part, false);
+                }
+                if FOLDED.contains(&name) && self.is_builtin(id) && !keep_list {
+                    self.fold(form, id);
+                } else if name == "begin" && self.is_builtin(id) {
+                    prune(form, keep_list);
+                }
+            }
+        }
+    }
+
+    // Replaces a call of a builtin on constants by its value.
+    fn fold(&self, form: &mut Value, name: SymbolId) {
+        let list = match form {
+            Value::List(list) => list,
+            _ => return
+        };
+        let arguments = match list[1..].iter().map(constant).collect::<Option<Vec<Value>>>() {
+            Some(arguments) => arguments,
+            None => return
+        };
+        let value = match self.env.borrow().get(name) {
+            Some(Value::Proc(f)) => f.call(arguments, self.env.clone()),
+            _ => return
+        };
+        if let Some(value) = value.ok().filter(is_literal) {
+            let span = source_map::take_span(form);
+            *form = value;
+            source_map::put_span(form, span);
+        }
+    }
+}
+
+// The value of a number, boolean or character, which evaluate to themselves.
+fn constant(form: &Value) -> Option<Value> {
+    match form {
+        Value::Integer(_) | Value::BigInt(_) | Value::Rational(_) | Value::Number(_) | Value::Bool(_) | Value::Char(_) => Some(form.clone()),
+        _ => None
+    }
+}
+
+// Whether `value` can take the place of the call folding gave it: numbers and booleans.
+fn is_literal(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::BigInt(_) | Value::Rational(_) | Value::Number(_) | Value::Bool(_))
+}
+
+// Whether the value of `form` counts as true, when that is known without evaluating it.
+fn truth(form: &Value) -> Option<bool> {
+    match constant(form) {
+        Some(value) => Some(value != Value::Bool(false)),
+        None if is_constant(form) => Some(true),
+        None => None
+    }
+}
+
+// Whether evaluating `form` can neither fail nor have effects.
+fn is_constant(form: &Value) -> bool {
+    match form {
+        Value::String(_) => true,
+        Value::List(list) => match (list.first(), list.get(1), list.get(2)) {
+            (Some(Value::Symbol(keyword)), Some(_), None) if &*keyword.name() == "quote" => true,
+            (Some(Value::Symbol(keyword)), Some(params), Some(Value::List(_))) if &*keyword.name() == "lambda" && list.len() == 3 =>
+                list_items(params).is_some_and(|params| params.iter().all(|param| matches!(param, Value::Symbol(_)))),
+            _ => false
+        },
+        _ => constant(form).is_some()
+    }
+}
+
+// Takes `form` out of its place, with its span.
+fn take(form: &mut Value) -> (Value, Option<Span>) {
+    let span = source_map::take_span(form);
+    (mem::replace(form, Value::Nil), span)
+}
+
+fn put(form: &mut Value, (part, span): (Value, Option<Span>)) {
+    source_map::take_span(form);
+    *form = part;
+    source_map::put_span(form, span);
+}
+
+// Replaces the list `form` by its part `i`.
+fn replace_by_part(form: &mut Value, i: usize) {
+    let part = match form {
+        Value::List(list) => take(&mut list[i]),
+        _ => return
+    };
+    put(form, part);
+}
+
+// Leaves the constants out of `(begin ...)` but the last, and replaces `(begin x)` by `x`.
+fn prune(form: &mut Value, keep_list: bool) {
+    let list = match form {
+        Value::List(list) => list,
+        _ => return
+    };
+    if list.len() > 2 && list[1..list.len() - 1].iter().any(is_constant) {
+        let last = list.len() - 1;
+        let spans = list.iter().map(source_map::take_span).collect::<Vec<Option<Span>>>();
+        let kept = mem::take(list.make_mut()).into_iter().zip(spans).enumerate()
+            .filter(|(i, (part, _))| *i == 0 || *i == last || !is_constant(part))
+            .map(|(_, kept)| kept)
+            .collect::<Vec<(Value, Option<Span>)>>();
+        let spans = kept.iter().map(|(_, span)| *span).collect::<Vec<Option<Span>>>();
+        *list = kept.into_iter().map(|(part, _)| part).collect();
+        for (part, span) in list.iter().zip(spans) {
+            source_map::put_span(part, span);
+        }
+    }
+    if list.len() == 2 && (!keep_list || matches!(list[1], Value::List(_))) {
+        replace_by_part(form, 1);
+    }
+}
+
+// Replaces `((lambda () body))` by `body`, unless `body` defines variables, which
+// have to go in the frame of the call.
+fn inline(form: &mut Value) {
+    let body = match form {
+        Value::List(list) if list.len() == 1 => match list[0] {
+            Value::List(ref mut lambda) if lambda.len() == 3 && lambda[0] == Value::Symbol(SymbolId::intern("lambda")) && list_items(&lambda[1]) == Some(&[]) => &mut lambda[2],
+            _ => return
+        },
+        _ => return
+    };
+    let (mut names, mut open) = (vec![], false);
+    definitions(body, &mut names, &mut open);
+    if !matches!(body, Value::List(_)) || !names.is_empty() || open {
+        return;
+    }
+    let body = take(body);
+    put(form, body);
+}
@@ -0,0 +1,126 @@
+use num_traits::ToPrimitive;
+
+use super::{gc, integer_argument, is_eqv, list_tail, split_list, CycleCheck, HashKey, SchemeError, Value};
+
+// Position `key` names in a list or vector.
+fn index(key: &Value) -> Option<usize> {
+    integer_argument(key).and_then(|n| n.to_usize())
+}
+
+// Position of the field named by the symbol `key` in a record.
+fn field_index(record: &super::Record, key: &Value) -> Option<usize> {
+    match key {
+        Value::Symbol(s) => record.record_type.fields.iter().position(|f| **f == *s.name()),
+        _ => None
+    }
+}
+
+// The `i`th element of a list, walking no further than it.
+fn element(list: &Value, i: usize) -> Option<Value> {
+    split_list(&list_tail(list.clone(), i)?).map(|(car, _)| car)
+}
+
+// The position and the entry of the first element of the association list `list`
+// whose car is `eqv?` to `key`, as `assv` finds it.
+fn entry(list: &Value, key: &Value) -> Option<(usize, Value)> {
+    let mut current = list.clone();
+    let mut cycle = CycleCheck::new(list);
+    let mut i = 0;
+    while let Some((element, rest)) = split_list(&current) {
+        if let Some((car, _)) = split_list(&element) {
+            if is_eqv(&car, key) {
+                return Some((i, element));
+            }
+        }
+        current = rest;
+        if cycle.is_cycle(&current) {
+            return None;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The element `key` selects in `data`: an index into a list or vector, a key of a
+/// hash table, or a field name of a record. Any other key of a list looks it up as an
+/// association list, selecting the cdr of its entry.
+pub fn step(data: &Value, key: &Value) -> Option<Value> {
+    match data {
+        Value::List(_) | Value::Pair(_) | Value::Nil => match index(key) {
+            Some(i) => element(data, i),
+            None => split_list(&entry(data, key)?.1).map(|(_, cdr)| cdr)
+        },
+        Value::Vector(v) => v.borrow().get(index(key)?).cloned(),
+        Value::HashTable(t) => t.borrow().get(&HashKey::of(key)).map(|(_, value)| value.clone()),
+        Value::Record(r) => Some(r.fields.borrow()[field_index(r, key)?].clone()),
+        _ => None
+    }
+}
+
+pub fn ref_in(data: &Value, path: &[Value]) -> Option<Value> {
+    path.iter().try_fold(data.clone(), |current, key| step(&current, key))
+}
+
+// Replaces the `i`th element of a list, changing pairs in place.
+fn set_list_element(list: Value, i: usize, value: Value) -> Option<Value> {
+    match list {
+        Value::List(mut l) => {
+            *l.get_mut(i)? = value;
+            Some(Value::List(l))
+        }
+        Value::Pair(p) => {
+            if i == 0 {
+                gc::track(&Value::Pair(p.clone()), &value);
+                p.set_car(value);
+            } else {
+                p.set_cdr(set_list_element(p.cdr(), i - 1, value)?);
+            }
+            Some(Value::Pair(p))
+        }
+        _ => None
+    }
+}
+
+/// Replaces the value at the end of `path` with the result of `f`. Vectors, hash
+/// tables, records and pairs are changed in place; the updated data is returned.
+pub fn update_in<F>(data: Value, path: &[Value], f: &F) -> Result<Value, SchemeError>
+    where F: Fn(Value) -> Result<Value, SchemeError> {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return f(data)
+    };
+    let child = step(&data, key).ok_or(SchemeError::out_of_range("update-in path not found"))?;
+    let value = update_in(child, rest, f)?;
+    match data {
+        Value::List(_) | Value::Pair(_) => match index(key) {
+            Some(i) => set_list_element(data, i, value),
+            None => match entry(&data, key) {
+                Some((_, Value::Pair(p))) => {
+                    gc::track(&Value::Pair(p.clone()), &value);
+                    p.set_cdr(value);
+                    Some(data)
+                }
+                // entries read as code are not pairs, and are replaced by one
+                Some((i, entry)) => split_list(&entry).and_then(|(car, _)| set_list_element(data, i, Value::cons(car, value))),
+                None => None
+            }
+        }.ok_or(SchemeError::out_of_range("update-in path not found")),
+        Value::Vector(ref v) => {
+            gc::track(&data, &value);
+            v.borrow_mut()[index(key).unwrap()] = value;
+            Ok(data)
+        }
+        Value::HashTable(ref t) => {
+            gc::track(&data, &value);
+            t.borrow_mut().insert(HashKey::of(key), (key.clone(), value));
+            Ok(data)
+        }
+        Value::Record(ref r) => {
+            let i = field_index(r, key).unwrap();
+            gc::track(&data, &value);
+            r.fields.borrow_mut()[i] = value;
+            Ok(data)
+        }
+        _ => unreachable!()
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::slice;
+use std::sync::Arc;
+
+// Bits of the hash that choose the child at each level of the trie.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// A hash map that is never changed in place: `insert` and `remove` give a new map,
+/// which shares all of the old one but the path to the entry changed. Cloning is
+/// constant time, which is what makes forking the bindings of an environment cheap.
+/// Those forks stay on the thread of their interpreter: values are reference counted
+/// without atomics, so a map of bindings can not go to another thread.
+pub struct PersistentMap<K, V> {
+    root: Option<Arc<Node<K, V>>>,
+    len: usize
+}
+
+// A hash array mapped trie.
+enum Node<K, V> {
+    // the children for the values of the next bits of the hash set in the bitmap,
+    // in their order
+    Branch(u32, Vec<Child<K, V>>),
+    // entries whose keys have the same hash in all its bits
+    Collision(u64, Vec<(K, V)>)
+}
+
+enum Child<K, V> {
+    Leaf(u64, K, V),
+    Node(Arc<Node<K, V>>)
+}
+
+// Clones share the nodes below, which is all that is needed of K and V.
+impl<K: Clone, V: Clone> Clone for Child<K, V> {
+    fn clone(&self) -> Child<K, V> {
+        match self {
+            Child::Leaf(hash, key, value) => Child::Leaf(*hash, key.clone(), value.clone()),
+            Child::Node(node) => Child::Node(node.clone())
+        }
+    }
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> PersistentMap<K, V> {
+        PersistentMap { root: self.root.clone(), len: self.len }
+    }
+}
+
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The bit of `hash` in a bitmap at the level of `shift`, and the position of its
+// child among those of `bitmap`.
+fn position(bitmap: u32, hash: u64, shift: u32) -> (u32, usize) {
+    let bit = 1 << ((hash >> shift) & MASK);
+    (bit, (bitmap & (bit - 1)).count_ones() as usize)
+}
+
+// A node holding two leaves with different keys, from the level of `shift` down.
+fn pair<K, V>(shift: u32, a: (u64, K, V), b: (u64, K, V)) -> Node<K, V> {
+    if a.0 == b.0 {
+        return Node::Collision(a.0, vec![(a.1, a.2), (b.1, b.2)]);
+    }
+    let (i, j) = ((a.0 >> shift) & MASK, (b.0 >> shift) & MASK);
+    if i == j {
+        return Node::Branch(1 << i, vec![Child::Node(Arc::new(pair(shift + BITS, a, b)))]);
+    }
+    let (first, second) = if i < j { (a, b) } else { (b, a) };
+    Node::Branch((1 << i) | (1 << j), vec![Child::Leaf(first.0, first.1, first.2), Child::Leaf(second.0, second.1, second.2)])
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Node<K, V> {
+    fn get(&self, hash: u64, shift: u32, key: &K) -> Option<&V> {
+        match self {
+            Node::Branch(bitmap, children) => {
+                let (bit, i) = position(*bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                match children[i] {
+                    Child::Leaf(_, ref k, ref v) if k == key => Some(v),
+                    Child::Leaf(..) => None,
+                    Child::Node(ref node) => node.get(hash, shift + BITS, key)
+                }
+            }
+            Node::Collision(_, entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        }
+    }
+
+    // The node with `key` bound to `value`, and whether the key is new.
+    fn insert(&self, hash: u64, shift: u32, key: K, value: V) -> (Node<K, V>, bool) {
+        match self {
+            Node::Branch(bitmap, children) => {
+                let (bit, i) = position(*bitmap, hash, shift);
+                let mut children = children.clone();
+                if bitmap & bit == 0 {
+                    children.insert(i, Child::Leaf(hash, key, value));
+                    return (Node::Branch(bitmap | bit, children), true);
+                }
+                let (child, added) = match children[i] {
+                    Child::Leaf(_, ref k, _) if *k == key => (Child::Leaf(hash, key, value), false),
+                    Child::Leaf(h, ref k, ref v) => (Child::Node(Arc::new(pair(shift + BITS, (h, k.clone(), v.clone()), (hash, key, value)))), true),
+                    Child::Node(ref node) => {
+                        let (node, added) = node.insert(hash, shift + BITS, key, value);
+                        (Child::Node(Arc::new(node)), added)
+                    }
+                };
+                children[i] = child;
+                (Node::Branch(*bitmap, children), added)
+            }
+            Node::Collision(h, entries) => {
+                let mut entries = entries.clone();
+                match entries.iter().position(|(k, _)| *k == key) {
+                    Some(i) => {
+                        entries[i].1 = value;
+                        (Node::Collision(*h, entries), false)
+                    }
+                    None => {
+                        entries.push((key, value));
+                        (Node::Collision(*h, entries), true)
+                    }
+                }
+            }
+        }
+    }
+
+    // The node without `key`, None if it is left empty; Err if `key` is not there.
+    fn remove(&self, hash: u64, shift: u32, key: &K) -> Result<Option<Node<K, V>>, ()> {
+        match self {
+            Node::Branch(bitmap, children) => {
+                let (bit, i) = position(*bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    return Err(());
+                }
+                let child = match children[i] {
+                    Child::Leaf(_, ref k, _) if k == key => None,
+                    Child::Leaf(..) => return Err(()),
+                    Child::Node(ref node) => node.remove(hash, shift + BITS, key)?.map(|node| Child::Node(Arc::new(node)))
+                };
+                let mut children = children.clone();
+                let bitmap = match child {
+                    Some(child) => {
+                        children[i] = child;
+                        *bitmap
+                    }
+                    None => {
+                        children.remove(i);
+                        bitmap & !bit
+                    }
+                };
+                Ok(if children.is_empty() { None } else { Some(Node::Branch(bitmap, children)) })
+            }
+            Node::Collision(h, entries) => {
+                let i = entries.iter().position(|(k, _)| k == key).ok_or(())?;
+                let mut entries = entries.clone();
+                entries.remove(i);
+                Ok(if entries.is_empty() { None } else { Some(Node::Collision(*h, entries)) })
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V> {
+    pub fn new() -> PersistentMap<K, V> {
+        PersistentMap { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.as_ref().and_then(|root| root.get(hash_of(key), 0, key))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// This map with `key` bound to `value`.
+    pub fn insert(&self, key: K, value: V) -> PersistentMap<K, V> {
+        let hash = hash_of(&key);
+        match self.root {
+            Some(ref root) => {
+                let (root, added) = root.insert(hash, 0, key, value);
+                PersistentMap { root: Some(Arc::new(root)), len: self.len + added as usize }
+            }
+            None => PersistentMap { root: Some(Arc::new(Node::Branch(0, vec![]).insert(hash, 0, key, value).0)), len: 1 }
+        }
+    }
+
+    /// This map without `key`.
+    pub fn remove(&self, key: &K) -> PersistentMap<K, V> {
+        match self.root.as_ref().map(|root| root.remove(hash_of(key), 0, key)) {
+            Some(Ok(root)) => PersistentMap { root: root.map(Arc::new), len: self.len - 1 },
+            _ => self.clone()
+        }
+    }
+
+    /// Whether the two maps are the same one, from which neither was changed.
+    pub fn ptr_eq(&self, other: &PersistentMap<K, V>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false
+        }
+    }
+
+    /// The entries, in no particular order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { nodes: self.root.iter().map(|root| &**root).collect(), children: [].iter(), collisions: [].iter() }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for PersistentMap<K, V> {
+    fn default() -> PersistentMap<K, V> {
+        PersistentMap::new()
+    }
+}
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    nodes: Vec<&'a Node<K, V>>,
+    children: slice::Iter<'a, Child<K, V>>,
+    collisions: slice::Iter<'a, (K, V)>
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            if let Some((k, v)) = self.collisions.next() {
+                return Some((k, v));
+            }
+            match self.children.next() {
+                Some(Child::Leaf(_, k, v)) => return Some((k, v)),
+                Some(Child::Node(node)) => self.nodes.push(node),
+                None => match self.nodes.pop() {
+                    Some(Node::Branch(_, children)) => self.children = children.iter(),
+                    Some(Node::Collision(_, entries)) => self.collisions = entries.iter(),
+                    None => return None
+                }
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> PersistentMap<K, V> {
+        entries.into_iter().fold(PersistentMap::new(), |map, (key, value)| map.insert(key, value))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + PartialEq> PartialEq for PersistentMap<K, V> {
+    fn eq(&self, other: &PersistentMap<K, V>) -> bool {
+        self.ptr_eq(other) || (self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v)))
+    }
+}
+
+impl<K: Hash + Eq + Clone + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+
+use super::{context, Reader, SchemeError, Value};
+
+type InputReader = Reader<Box<dyn BufRead>>;
+
+/// Source or sink of characters for the I/O builtins. A closed port keeps its
+/// direction but drops the underlying reader or writer.
+pub struct Port {
+    reader: Option<RefCell<Option<InputReader>>>,
+    writer: Option<RefCell<Option<Box<dyn Write>>>>
+}
+
+impl Port {
+    pub fn input(reader: Box<dyn Read>) -> Port {
+        Port { reader: Some(RefCell::new(Some(Reader::new(Box::new(io::BufReader::new(reader)))))), writer: None }
+    }
+
+    pub fn output(writer: Box<dyn Write>) -> Port {
+        Port { reader: None, writer: Some(RefCell::new(Some(writer))) }
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    pub fn is_output(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn close(&self) {
+        if let Some(ref reader) = self.reader {
+            reader.borrow_mut().take();
+        }
+        if let Some(ref writer) = self.writer {
+            if let Some(mut w) = writer.borrow_mut().take() {
+                let _ = w.flush();
+            }
+        }
+    }
+
+    /// The next datum of the input, reading as many lines as it spans, or None at
+    /// the end of the input.
+    pub fn read_datum(&self) -> Result<Option<Value>, SchemeError> {
+        let reader = self.reader.as_ref().ok_or(SchemeError::wrong_type("port is not an input port"))?;
+        match *reader.borrow_mut() {
+            Some(ref mut r) => r.read_datum(),
+            None => Err(SchemeError::other("port is closed"))
+        }
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), SchemeError> {
+        let writer = self.writer.as_ref().ok_or(SchemeError::wrong_type("port is not an output port"))?;
+        match *writer.borrow_mut() {
+            Some(ref mut w) => w.write_all(s.as_bytes()).and_then(|_| w.flush()).map_err(|_| SchemeError::other("cannot write to port")),
+            None => Err(SchemeError::other("port is closed"))
+        }
+    }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Port").field("input", &self.is_input()).field("output", &self.is_output()).finish()
+    }
+}
+
+// Ports are equal only to themselves.
+impl PartialEq for Port {
+    fn eq(&self, other: &Port) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// The port input builtins read from when none is given; standard input unless replaced.
+pub fn current_input_port() -> Rc<Port> {
+    context::with(|c| c.ports.borrow_mut().input.get_or_insert_with(|| Rc::new(Port::input(Box::new(io::stdin())))).clone())
+}
+
+/// The port `display`, `newline` and `print` write to when none is given; standard
+/// output unless replaced.
+pub fn current_output_port() -> Rc<Port> {
+    context::with(|c| c.ports.borrow_mut().output.get_or_insert_with(|| Rc::new(Port::output(Box::new(io::stdout())))).clone())
+}
+
+/// The port for reporting errors, which no builtin writes to unless given it;
+/// standard error unless replaced.
+pub fn current_error_port() -> Rc<Port> {
+    context::with(|c| c.ports.borrow_mut().error.get_or_insert_with(|| Rc::new(Port::output(Box::new(io::stderr())))).clone())
+}
+
+/// Redirects the input of the current context, or restores standard input with None.
+pub fn set_current_input_port(port: Option<Rc<Port>>) {
+    context::with(|c| c.ports.borrow_mut().input = port);
+}
+
+/// Redirects the output of the current context, or restores standard output with
+/// None.
+pub fn set_current_output_port(port: Option<Rc<Port>>) {
+    context::with(|c| c.ports.borrow_mut().output = port);
+}
+
+/// Redirects the error output of the current context, or restores standard error
+/// with None.
+pub fn set_current_error_port(port: Option<Rc<Port>>) {
+    context::with(|c| c.ports.borrow_mut().error = port);
+}
+
+// The current ports of a context, each standard input or output until one is set.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ports {
+    pub input: Option<Rc<Port>>,
+    pub output: Option<Rc<Port>>,
+    pub error: Option<Rc<Port>>
+}
+
+impl Ports {
+    // Replaces the ports `ports` has with those.
+    pub fn replace_with(&mut self, ports: Ports) {
+        self.input = ports.input.or(self.input.take());
+        self.output = ports.output.or(self.output.take());
+        self.error = ports.error.or(self.error.take());
+    }
+}
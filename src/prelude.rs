@@ -0,0 +1,77 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{depth_limit, eval, fuel, allocation_limit, read_from_tokens, reader_limits, set_depth_limit, set_fuel};
+use super::{set_allocation_limit, set_reader_limits, tokenize_with_spans};
+use super::{Env, ReaderLimits, SchemeError, SymbolId, Value};
+use interrupt::{set_timeout, timeout};
+use observe::{observer, set_eval_observer, EvalObserver};
+
+// Procedures derived from the builtins, kept as Scheme source to show the evaluator
+// can carry its own library.
+const PRELUDE: &str = include_str!("prelude.scm");
+
+// The limits of the current context and its observer, lifted while the prelude is
+// evaluated and put back when dropped.
+struct Lifted {
+    fuel: Option<usize>,
+    allocation_limit: Option<usize>,
+    depth_limit: Option<usize>,
+    timeout: Option<Duration>,
+    observer: Option<Rc<dyn EvalObserver>>
+}
+
+impl Lifted {
+    fn lift() -> Lifted {
+        let lifted = Lifted { fuel: fuel(), allocation_limit: allocation_limit(), depth_limit: depth_limit(), timeout: timeout(), observer: observer() };
+        set_fuel(None);
+        set_allocation_limit(None);
+        set_depth_limit(None);
+        set_timeout(None);
+        set_eval_observer(None);
+        lifted
+    }
+}
+
+impl Drop for Lifted {
+    fn drop(&mut self) {
+        set_fuel(self.fuel);
+        set_allocation_limit(self.allocation_limit);
+        set_depth_limit(self.depth_limit);
+        set_timeout(self.timeout);
+        set_eval_observer(self.observer.take());
+    }
+}
+
+/// Evaluates the prelude in an environment of `builtins`, and adds the procedures it
+/// defines to them. The limits of the current context, like `set_fuel`, do not
+/// apply to it, and its definitions are not reported to its observer.
+pub fn define_prelude(builtins: &mut HashMap<SymbolId, Value>) -> Result<(), SchemeError> {
+    let env = Rc::new(RefCell::new(Env::new(builtins.clone(), None)));
+    let _lifted = Lifted::lift();
+    for form in read()? {
+        eval(Some(form), env.clone())?;
+    }
+    let defined = env.borrow().bindings().into_iter().filter(|(name, _)| !builtins.contains_key(name)).collect::<Vec<(SymbolId, Value)>>();
+    builtins.extend(defined);
+    Ok(())
+}
+
+// The forms of the prelude, read with the default limits, and without recording
+// spans, which belong to the program being evaluated.
+fn read() -> Result<Vec<Value>, SchemeError> {
+    let limits = reader_limits();
+    set_reader_limits(ReaderLimits::default());
+    let source = format!("({}\n)", PRELUDE);
+    let forms = tokenize_with_spans(&source).map_err(SchemeError::from).and_then(|tokens| {
+        let tokens = tokens.iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>();
+        read_from_tokens(&tokens, &mut 0).map_err(SchemeError::read)
+    });
+    set_reader_limits(limits);
+    match forms? {
+        Value::List(forms) => Ok(forms.to_vec()),
+        _ => Err(SchemeError::read("the prelude is not a list of forms"))
+    }
+}
@@ -0,0 +1,60 @@
+;;; Procedures derived from the builtins, written in Scheme and evaluated for every
+;;; interpreter. Each one sees the builtins and the procedures of the prelude alone,
+;;; not what the programs using it define, so that a program cannot change how they
+;;; work.
+
+(define (not x)
+  (if x #f #t))
+
+(define (zero? n) (= n 0))
+(define (positive? n) (> n 0))
+(define (negative? n) (< n 0))
+(define (even? n) (= (remainder n 2) 0))
+(define (odd? n) (if (= (remainder n 2) 0) #f #t))
+
+(define (caar pair) (car (car pair)))
+(define (cadr pair) (car (cdr pair)))
+(define (cdar pair) (cdr (car pair)))
+(define (cddr pair) (cdr (cdr pair)))
+(define (caddr pair) (car (cdr (cdr pair))))
+(define (cdddr pair) (cdr (cdr (cdr pair))))
+
+(define (last-pair list)
+  (let loop ((pair list))
+    (if (pair? (cdr pair)) (loop (cdr pair)) pair)))
+
+(define (last list)
+  (let loop ((pair list))
+    (if (pair? (cdr pair)) (loop (cdr pair)) (car pair))))
+
+;; The integers from 0 up to count, count excluded.
+(define (iota count)
+  (let loop ((i (- count 1)) (result '()))
+    (if (< i 0) result (loop (- i 1) (cons i result)))))
+
+(define (list-index pred list)
+  (let loop ((rest list) (i 0))
+    (cond ((null? rest) #f)
+          ((pred (car rest)) i)
+          (else (loop (cdr rest) (+ i 1))))))
+
+(define (any pred list)
+  (let loop ((rest list))
+    (and (pair? rest) (or (pred (car rest)) (loop (cdr rest))))))
+
+(define (every pred list)
+  (let loop ((rest list))
+    (or (null? rest) (and (pred (car rest)) (loop (cdr rest))))))
+
+(define (count pred list)
+  (fold-left (lambda (n x) (if (pred x) (+ n 1) n)) 0 list))
+
+(define (delete x list)
+  (filter (lambda (y) (if (equal? x y) #f #t)) list))
+
+;; The results of f that are not #f.
+(define (filter-map f list)
+  (fold-right (lambda (x rest) (let ((y (f x))) (if y (cons y rest) rest))) '() list))
+
+(define (append-map f list)
+  (apply append (map f list)))
@@ -0,0 +1,54 @@
+use super::{list_elements, unparse, Labels, Value};
+
+/// `value` as `unparse` writes it, with the lists and vectors too long to fit in
+/// `width` columns broken over lines. A form named by a symbol keeps its first
+/// argument on its line and has the others under it, indented by two columns; the
+/// elements of other lists and vectors line up under the first. Values on a cycle are
+/// written on one line.
+pub(crate) fn pretty(value: &Value, width: usize) -> String {
+    let mut out = String::new();
+    if Labels::new(value).has_cycles() {
+        out.push_str(&unparse(value));
+    } else {
+        write(value, 0, width, &mut out);
+    }
+    out
+}
+
+// Writes `value`, which starts at `column`, to `out`.
+fn write(value: &Value, column: usize, width: usize, out: &mut String) {
+    let flat = unparse(value);
+    let (open, elements) = match *value {
+        _ if column + flat.chars().count() <= width => return out.push_str(&flat),
+        Value::Vector(ref vector) => ("#(", vector.borrow().clone()),
+        Value::Pair(_) | Value::List(_) => match list_elements(value) {
+            Some(elements) => ("(", elements),
+            // dotted lists stay on one line
+            None => return out.push_str(&flat)
+        },
+        _ => return out.push_str(&flat)
+    };
+    out.push_str(open);
+    let mut indent = column + open.len();
+    let mut elements = elements.iter().peekable();
+    let mut first = true;
+    if let (true, Some(Value::Symbol(_))) = (open == "(", elements.peek()) {
+        let name = unparse(elements.next().unwrap());
+        out.push_str(&name);
+        if let Some(argument) = elements.next() {
+            out.push(' ');
+            write(argument, indent + name.chars().count() + 1, width, out);
+        }
+        indent = column + 2;
+        first = false;
+    }
+    for element in elements {
+        if !first {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+        }
+        write(element, indent, width, out);
+        first = false;
+    }
+    out.push(')');
+}
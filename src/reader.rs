@@ -0,0 +1,207 @@
+use std::io::{BufRead, BufReader, Read};
+
+use super::{parse_datum, parse_partial, read_form, PartialParse, SchemeError, Span, SyntaxError, Value};
+
+/// Reads data one at a time from a stream of source text, pulling in only as many
+/// lines as the next datum spans. A malformed datum is skipped like `parse_program`
+/// does, so reading goes on with the next form after a syntax error.
+///
+/// ```
+/// use scheme_rs::{unparse, Reader};
+///
+/// let mut reader = Reader::new("(+ 1\n   2) x".as_bytes());
+/// assert_eq!(Ok(Some("(+ 1 2)".to_string())), reader.read_datum().map(|d| d.map(|datum| unparse(&datum))));
+/// assert_eq!(Ok(Some("x".to_string())), reader.read_datum().map(|d| d.map(|datum| unparse(&datum))));
+/// assert_eq!(Ok(None), reader.read_datum());
+/// ```
+pub struct Reader<R> {
+    input: R,
+    // input read from `input` that is not part of a datum returned yet
+    pending: String,
+    // length of the input read before `pending`
+    consumed: usize,
+    // how far `pending` has been followed, and what it leaves open
+    scan: Scan,
+    // syntax errors are kept here instead of being returned, when recovering
+    errors: Option<Vec<SyntaxError>>
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R) -> Reader<R> {
+        Reader { input, pending: String::new(), consumed: 0, scan: Scan::default(), errors: None }
+    }
+
+    /// A reader that does not stop at syntax errors: it skips the malformed data and
+    /// keeps the errors for `errors`, so that all of them can be reported at once.
+    pub fn recovering(input: R) -> Reader<R> {
+        Reader { errors: Some(vec![]), ..Reader::new(input) }
+    }
+
+    /// The syntax errors skipped so far by a recovering reader, with spans counted
+    /// from the start of the input.
+    pub fn errors(&self) -> &[SyntaxError] {
+        self.errors.as_deref().unwrap_or(&[])
+    }
+
+    fn consume(&mut self, length: usize) {
+        self.pending.drain(..length);
+        self.consumed += length;
+        self.scan = Scan::default();
+    }
+
+    // Appends the next line of input to `pending`; false at the end of the input.
+    fn read_line(&mut self) -> Result<bool, SchemeError> {
+        self.input.read_line(&mut self.pending).map(|n| n > 0).map_err(|_| SchemeError::other("cannot read from port"))
+    }
+
+    /// The next datum of the input, reading as many lines as it spans, or None at
+    /// the end of the input. The lines are only parsed once they may end a datum, so
+    /// that a long datum is not parsed again after each of its lines.
+    pub fn read_datum(&mut self) -> Result<Option<Value>, SchemeError> {
+        let mut more = true;
+        loop {
+            self.scan.follow(&self.pending);
+            if !self.pending.trim().is_empty() && (self.scan.may_end_datum() || !more) {
+                match parse_datum(&self.pending) {
+                    Ok((datum, span)) => {
+                        self.consume(span.end);
+                        return Ok(Some(datum));
+                    }
+                    // the datum goes on in the lines not read yet, like after a quote
+                    // ending a line
+                    Err(_) if more && matches!(parse_partial(&self.pending), PartialParse::Incomplete { .. }) => self.scan.wait(),
+                    Err(_) => {
+                        // the error may be in a later datum, or there may be no datum at all
+                        let (form, next) = read_form(&self.pending);
+                        self.consume(next);
+                        let offset = self.consumed - next;
+                        let errors = match form {
+                            Ok(datum) => return Ok(Some(datum)),
+                            Err(errors) => errors.into_iter().map(|e| SyntaxError {
+                                span: Span { start: e.span.start + offset, end: e.span.end + offset },
+                                message: e.message
+                            })
+                        };
+                        match self.errors.as_mut() {
+                            Some(kept) => kept.extend(errors),
+                            None => if let Some(error) = errors.into_iter().next() {
+                                return Err(error.into());
+                            }
+                        }
+                        continue;
+                    }
+                }
+            } else if !more {
+                let length = self.pending.len();
+                self.consume(length);
+                return Ok(None);
+            }
+            more = self.read_line()?;
+        }
+    }
+}
+
+// What the text of a reader followed so far leaves open, kept from one line to the
+// next so that each line is looked at once.
+#[derive(Default)]
+struct Scan {
+    // bytes of the pending text followed
+    followed: usize,
+    lists: usize,
+    within: Within,
+    // whether a list or string ended outside of any list, or text outside of them
+    // was met, since the reader last waited for more
+    ended: bool,
+    text: bool
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Within {
+    #[default]
+    Code,
+    String,
+    Bar,
+    // block comments nest
+    Comment(usize)
+}
+
+impl Scan {
+    // Follows the text of `pending` after what was followed already.
+    fn follow(&mut self, pending: &str) {
+        let mut chars = pending[self.followed..].chars().peekable();
+        self.followed = pending.len();
+        while let Some(c) = chars.next() {
+            match (self.within, c) {
+                (Within::String, '\\') | (Within::Bar, '\\') => {
+                    chars.next();
+                }
+                (Within::String, '"') | (Within::Bar, '|') => self.end(),
+                (Within::String, _) | (Within::Bar, _) => {}
+                (Within::Comment(depth), '|') if chars.peek() == Some(&'#') => {
+                    chars.next();
+                    self.within = if depth == 1 { Within::Code } else { Within::Comment(depth - 1) };
+                }
+                (Within::Comment(depth), '#') if chars.peek() == Some(&'|') => {
+                    chars.next();
+                    self.within = Within::Comment(depth + 1);
+                }
+                (Within::Comment(_), _) => {}
+                (Within::Code, '#') if chars.peek() == Some(&'|') => {
+                    chars.next();
+                    self.within = Within::Comment(1);
+                }
+                (Within::Code, ';') => {
+                    chars.by_ref().find(|&x| x == '\n');
+                }
+                (Within::Code, '"') => self.within = Within::String,
+                (Within::Code, '|') => self.within = Within::Bar,
+                (Within::Code, '(') => self.lists += 1,
+                (Within::Code, ')') => {
+                    self.lists = self.lists.saturating_sub(1);
+                    self.end();
+                }
+                (Within::Code, _) if c.is_whitespace() => {}
+                (Within::Code, _) => {
+                    // the character after `#\` belongs to it, even a parenthesis
+                    if c == '#' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        chars.next();
+                    }
+                    self.text |= self.lists == 0;
+                }
+            }
+        }
+    }
+
+    fn end(&mut self) {
+        self.within = Within::Code;
+        self.ended |= self.lists == 0;
+    }
+
+    // Whether the text followed may hold a whole datum: one ended outside of any list,
+    // or the text leaves nothing open.
+    fn may_end_datum(&self) -> bool {
+        self.ended || (self.text && self.lists == 0 && self.within == Within::Code)
+    }
+
+    // Forgets the data that seemed to end, which turned out to want more input.
+    fn wait(&mut self) {
+        self.ended = false;
+        self.text = false;
+    }
+}
+
+impl<R: Read> Reader<BufReader<R>> {
+    /// A reader of unbuffered input, such as a file.
+    pub fn from_read(input: R) -> Reader<BufReader<R>> {
+        Reader::new(BufReader::new(input))
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Value, SchemeError>;
+
+    fn next(&mut self) -> Option<Result<Value, SchemeError>> {
+        self.read_datum().transpose()
+    }
+}
@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{gc, Arity, Env, Function, SchemeError, SymbolId, Value};
+
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>
+}
+
+/// Instance of a type created by `define-record-type`. Two records have the same
+/// type only if they come from the same definition.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub fields: RefCell<Vec<Value>>
+}
+
+fn symbol(value: &Value) -> Result<String, SchemeError> {
+    match value {
+        Value::Symbol(s) => Ok(s.name().to_string()),
+        _ => Err(SchemeError::syntax("wrong syntax for define-record-type expression"))
+    }
+}
+
+fn procedure<F>(name: &str, arity: Arity, f: F) -> Value where F: Fn(Vec<Value>) -> Result<Value, SchemeError> + 'static {
+    Value::Proc(Function::new(arity, Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| f(vec))).named(SymbolId::intern(name)))
+}
+
+// The record of the expected type an accessor or modifier is applied to.
+fn record_of(value: &Value, record_type: &Rc<RecordType>) -> Result<Rc<Record>, SchemeError> {
+    match value {
+        Value::Record(r) if Rc::ptr_eq(&r.record_type, record_type) => Ok(r.clone()),
+        _ => Err(SchemeError::wrong_type("record procedure applied to a value of another type"))
+    }
+}
+
+/// Bindings introduced by `(define-record-type <name> (constructor field ...) predicate
+/// (field accessor [modifier]) ...)`.
+pub fn define_record_type(list: &[Value]) -> Result<Vec<(String, Value)>, SchemeError> {
+    if list.len() < 4 {
+        return Err(SchemeError::syntax("wrong syntax for define-record-type expression"));
+    }
+    let name = symbol(&list[1])?;
+    let field_specs = list[4..].iter().map(|spec| match spec {
+        Value::List(parts) if !parts.is_empty() && parts.len() <= 3 => parts.iter().map(symbol).collect(),
+        _ => Err(SchemeError::syntax("wrong syntax for define-record-type expression"))
+    }).collect::<Result<Vec<Vec<String>>, SchemeError>>()?;
+
+    let record_type = Rc::new(RecordType {
+        name: name.trim_start_matches('<').trim_end_matches('>').to_string(),
+        fields: field_specs.iter().map(|spec| spec[0].clone()).collect()
+    });
+    let mut bindings = vec![];
+
+    let (constructor, arguments) = match list[2] {
+        Value::List(ref parts) if !parts.is_empty() => (symbol(&parts[0])?, parts[1..].iter().map(symbol).collect::<Result<Vec<String>, _>>()?),
+        _ => return Err(SchemeError::syntax("wrong syntax for define-record-type expression"))
+    };
+    let indices = arguments.iter().map(|argument| record_type.fields.iter().position(|f| f == argument))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or(SchemeError::syntax("record constructor argument is not a field of the record type"))?;
+    let constructed_type = record_type.clone();
+    bindings.push((constructor.clone(), procedure(&constructor, Arity::exactly(indices.len()), move |vec| {
+        if vec.len() != indices.len() {
+            return Err(SchemeError::arity("wrong number of arguments to record constructor"));
+        }
+        let mut fields = vec![Value::Bool(false); constructed_type.fields.len()];
+        for (&i, value) in indices.iter().zip(vec) {
+            fields[i] = value;
+        }
+        Ok(Value::Record(Rc::new(Record {
+            record_type: constructed_type.clone(),
+            fields: RefCell::new(fields)
+        })))
+    })));
+
+    let predicate_type = record_type.clone();
+    let predicate = symbol(&list[3])?;
+    bindings.push((predicate.clone(), procedure(&predicate, Arity::exactly(1), move |vec| match vec.as_slice() {
+        [value] => Ok(Value::Bool(record_of(value, &predicate_type).is_ok())),
+        _ => Err(SchemeError::arity("record predicate requires one argument only"))
+    })));
+
+    for (i, spec) in field_specs.iter().enumerate() {
+        if let Some(accessor) = spec.get(1) {
+            let accessor_type = record_type.clone();
+            bindings.push((accessor.clone(), procedure(accessor, Arity::exactly(1), move |vec| match vec.as_slice() {
+                [value] => Ok(record_of(value, &accessor_type)?.fields.borrow()[i].clone()),
+                _ => Err(SchemeError::arity("record accessor requires one argument only"))
+            })));
+        }
+        if let Some(modifier) = spec.get(2) {
+            let modifier_type = record_type.clone();
+            bindings.push((modifier.clone(), procedure(modifier, Arity::exactly(2), move |vec| match vec.as_slice() {
+                [value, field] => {
+                    record_of(value, &modifier_type)?.fields.borrow_mut()[i] = field.clone();
+                    gc::track(value, field);
+                    Ok(Value::Unspecified)
+                }
+                _ => Err(SchemeError::arity("record modifier requires two arguments"))
+            })));
+        }
+    }
+    Ok(bindings)
+}
+
+thread_local! {
+    static ERROR_OBJECT_TYPE: Rc<RecordType> = Rc::new(RecordType {
+        name: "error-object".to_string(),
+        fields: vec!["message".to_string()]
+    });
+}
+
+/// The error object a failed evaluation is turned into when a handler catches it.
+pub(crate) fn error_object(message: &str) -> Value {
+    Value::Record(Rc::new(Record {
+        record_type: ERROR_OBJECT_TYPE.with(|t| t.clone()),
+        fields: RefCell::new(vec![Value::String(message.into())])
+    }))
+}
+
+pub(crate) fn error_object_message(value: &Value) -> Option<Value> {
+    let record = ERROR_OBJECT_TYPE.with(|t| record_of(value, t)).ok()?;
+    let message = record.fields.borrow()[0].clone();
+    Some(message)
+}
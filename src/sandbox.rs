@@ -0,0 +1,99 @@
+/// The kinds of builtins, by what they reach beyond the values of the script
+/// calling them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuiltinGroup {
+    /// Computing with values, which every sandbox keeps.
+    Core,
+    /// Reading and writing ports, like `display` and `read`.
+    Io,
+    /// Files and directories. No builtin opens them yet, and the group keeps those
+    /// that will out of the sandboxes that leave it out.
+    Filesystem,
+    /// Sockets and other connections, which no builtin opens yet either.
+    Network,
+    /// The state of the interpreter and its thread: collections, watches and
+    /// statistics.
+    Host
+}
+
+// The groups of the builtins `setup` binds, which every builtin has to be in.
+const CORE: &[&str] = &[
+    "*", "+", "-", "/", "<", "<=", "=", ">", ">=", "abs", "acos", "append", "append!", "apply",
+    "asin", "assoc", "assq", "assv", "atan", "begin", "binomial", "boolean=?", "boolean?", "bound?",
+    "bytevector", "bytevector-append", "bytevector-length", "bytevector-u8-ref",
+    "bytevector-u8-set!", "bytevector?", "car", "cdr", "ceiling", "char->integer",
+    "char-alphabetic?", "char-downcase", "char-numeric?", "char-upcase", "char-whitespace?",
+    "char<=?", "char<?", "char=?", "char>=?", "char>?", "char?", "cons", "cos", "eof-object",
+    "eof-object?", "eq?", "equal?", "eqv?", "error-object-message", "error-object?",
+    "exact->inexact", "exact?", "exp", "expt", "factorial", "filter", "floor", "floor-quotient",
+    "floor-remainder", "floor/", "fold-left", "fold-right", "for-each", "format", "gcd",
+    "hash-table->alist", "hash-table-contains?", "hash-table-count", "hash-table-delete!",
+    "hash-table-keys", "hash-table-ref", "hash-table-ref/default", "hash-table-set!",
+    "hash-table-values", "hash-table?", "inexact->exact", "inexact?", "input-port?",
+    "integer->char", "integer?", "lcm", "length", "list", "list->string", "list->vector",
+    "list-ref", "list-sort", "list-tail", "list?", "log", "make-bytevector", "make-hash-table",
+    "make-vector", "map", "max", "member", "memq", "memv", "min", "modular-expt", "modulo",
+    "next-prime", "null?", "number->string", "number?", "output-port?", "pair?", "pi", "prime?",
+    "procedure-arity", "procedure-name", "procedure?", "quotient", "real?", "reduce", "ref-in",
+    "remainder", "reverse", "reverse!", "round", "set-car!", "set-cdr!", "sin", "sort", "sqrt",
+    "string->list", "string->number", "string->vector", "string-append", "string-contains",
+    "string-downcase", "string-index", "string-join", "string-length", "string-ref", "string-split",
+    "string-upcase", "string<=?", "string<?", "string=?", "string>=?", "string>?", "string?",
+    "substring", "symbol?", "tan", "truncate", "truncate-quotient", "truncate-remainder",
+    "truncate/", "update-in", "vector", "vector->list", "vector->string", "vector-length",
+    "vector-ref", "vector-set!", "vector-sort!", "vector?", "with-exception-handler"
+];
+
+const IO: &[&str] = &[
+    "close-port", "current-error-port", "current-input-port", "current-output-port", "display", "newline", "print", "read",
+    "write", "write-char"
+];
+
+const HOST: &[&str] = &[
+    "current-evaluation-depth", "environment-bindings", "gc", "heap-statistics", "last-eval-statistics", "unwatch", "watch"
+];
+
+impl BuiltinGroup {
+    /// The group of the builtin `name` binds in `setup`, or None for a name that is
+    /// not one, which only `Sandbox::Full` allows.
+    pub fn of(name: &str) -> Option<BuiltinGroup> {
+        if CORE.contains(&name) {
+            Some(BuiltinGroup::Core)
+        } else if IO.contains(&name) {
+            Some(BuiltinGroup::Io)
+        } else if HOST.contains(&name) {
+            Some(BuiltinGroup::Host)
+        } else {
+            None
+        }
+    }
+}
+
+/// Which builtins an interpreter built by `InterpreterBuilder` has, for running
+/// scripts that are not trusted. Special forms are always there, and so are the
+/// functions the host defines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Sandbox {
+    /// Every builtin.
+    #[default]
+    Full,
+    /// No ports, files or connections.
+    NoIo,
+    NoFilesystem,
+    NoNetwork,
+    /// Only computing with values: no I/O, and no builtins reaching the state of
+    /// the interpreter either.
+    Pure
+}
+
+impl Sandbox {
+    pub fn allows(self, group: BuiltinGroup) -> bool {
+        match self {
+            Sandbox::Full => true,
+            Sandbox::NoIo => group == BuiltinGroup::Core || group == BuiltinGroup::Host,
+            Sandbox::NoFilesystem => group != BuiltinGroup::Filesystem,
+            Sandbox::NoNetwork => group != BuiltinGroup::Network,
+            Sandbox::Pure => group == BuiltinGroup::Core
+        }
+    }
+}
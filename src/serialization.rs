@@ -0,0 +1,638 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use super::{list_elements, SchemeError, SymbolId, Value};
+
+// Values map to the serde data model as JSON does: proper lists are sequences, and
+// association lists whose keys are symbols or strings are maps, and so structs. The
+// empty list is the unit and `None`.
+
+impl ser::Error for SchemeError {
+    fn custom<T: fmt::Display>(message: T) -> SchemeError {
+        SchemeError::conversion(&message.to_string())
+    }
+}
+
+impl de::Error for SchemeError {
+    fn custom<T: fmt::Display>(message: T) -> SchemeError {
+        SchemeError::conversion(&message.to_string())
+    }
+}
+
+// The entries of `elements` if it is an association list: each one a pair whose car
+// is a symbol or a string.
+fn entries(elements: &[Value]) -> Option<Vec<(Value, Value)>> {
+    elements.iter().map(|element| match element {
+        Value::Pair(p) => match p.car() {
+            key @ Value::Symbol(_) | key @ Value::String(_) => Some((key, p.cdr())),
+            _ => None
+        },
+        _ => None
+    }).collect()
+}
+
+// What `value` is, for the errors about values that have no serde counterpart.
+fn unsupported(value: &Value) -> String {
+    let kind = match value {
+        Value::Proc(_) | Value::Lambda(_) => "a procedure",
+        Value::Record(_) => "a record",
+        Value::Port(_) => "a port",
+        Value::Foreign(_) => "foreign data",
+        Value::Eof => "the end of file object",
+        Value::Unspecified => "an unspecified value",
+        Value::BigInt(_) => "an integer of more than 128 bits",
+        _ => "an improper or circular list"
+    };
+    format!("{} has no serde counterpart", kind)
+}
+
+impl Serialize for Value {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Integer(i) => serializer.serialize_i64(*i),
+            Value::BigInt(b) => match (b.to_i128(), b.to_u128()) {
+                (Some(i), _) => serializer.serialize_i128(i),
+                (_, Some(u)) => serializer.serialize_u128(u),
+                _ => Err(ser::Error::custom(unsupported(self)))
+            },
+            Value::Number(_) | Value::Rational(_) => serializer.serialize_f64(self.as_f64().unwrap()),
+            Value::Char(c) => serializer.serialize_char(*c),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Symbol(id) => serializer.serialize_str(&id.name()),
+            Value::Bytevector(bytes) => serializer.serialize_bytes(&bytes.borrow()),
+            Value::Vector(items) => serializer.collect_seq(items.borrow().iter()),
+            Value::Nil => serializer.serialize_unit(),
+            Value::List(_) | Value::Pair(_) => match list_elements(self) {
+                Some(elements) => match entries(&elements) {
+                    Some(entries) => serializer.collect_map(entries),
+                    None => serializer.collect_seq(elements)
+                },
+                None => Err(ser::Error::custom(unsupported(self)))
+            },
+            Value::HashTable(table) => serializer.collect_map(table.borrow().values().map(|(key, value)| (key, value))),
+            _ => Err(ser::Error::custom(unsupported(self)))
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "data a Scheme value can hold")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Integer(i))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Value, E> {
+        Ok(Value::from(u))
+    }
+
+    fn visit_i128<E>(self, i: i128) -> Result<Value, E> {
+        Ok(Value::integer(BigInt::from(i)))
+    }
+
+    fn visit_u128<E>(self, u: u128) -> Result<Value, E> {
+        Ok(Value::integer(BigInt::from(u)))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Number(f))
+    }
+
+    fn visit_char<E>(self, c: char) -> Result<Value, E> {
+        Ok(Value::Char(c))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::string(s))
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Value, E> {
+        Ok(Value::bytevector(bytes.to_vec()))
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::list(elements))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = vec![];
+        while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+            entries.push(Value::cons(symbol_key(key), value));
+        }
+        Ok(Value::list(entries))
+    }
+}
+
+// Keys that are strings become symbols, as the names of fields are.
+fn symbol_key(key: Value) -> Value {
+    match key {
+        Value::String(s) => Value::Symbol(SymbolId::intern(&s)),
+        key => key
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Value {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+/// Converts Rust data into a value: structs and maps become association lists with
+/// symbols as keys, sequences and tuples lists, and enum variants symbols, or
+/// association lists of one entry when they hold data.
+///
+/// ```
+/// #[macro_use]
+/// extern crate serde;
+/// extern crate scheme_rs;
+///
+/// use scheme_rs::{from_value, to_value, Interpreter};
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Order { item: String, quantity: u32 }
+///
+/// fn main() {
+///     let interpreter = Interpreter::new().unwrap();
+///     let double = interpreter.eval_str("(lambda (order) (list (assq 'item order) (cons 'quantity (* 2 (cdr (assq 'quantity order))))))").unwrap();
+///     let order = to_value(&Order { item: "tea".to_string(), quantity: 3 }).unwrap();
+///     let doubled = interpreter.call(&double, &[order]).unwrap();
+///     assert_eq!(Ok(Order { item: "tea".to_string(), quantity: 6 }), from_value(&doubled));
+/// }
+/// ```
+pub fn to_value<T: Serialize + ?Sized>(data: &T) -> Result<Value, SchemeError> {
+    data.serialize(ValueSerializer)
+}
+
+/// Converts a value into Rust data, the reverse of `to_value`. Strings and symbols
+/// are read alike, and so are lists and vectors.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, SchemeError> {
+    T::deserialize(ValueDeserializer(value.clone()))
+}
+
+struct ValueSerializer;
+
+// An association list of one entry, the name of an enum variant and its data.
+fn variant(name: &str, data: Value) -> Value {
+    Value::list(vec![Value::cons(Value::symbol(name), data)])
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = SchemeError;
+    type SerializeSeq = Elements;
+    type SerializeTuple = Elements;
+    type SerializeTupleStruct = Elements;
+    type SerializeTupleVariant = Elements;
+    type SerializeMap = Entries;
+    type SerializeStruct = Entries;
+    type SerializeStructVariant = Entries;
+
+    fn serialize_bool(self, b: bool) -> Result<Value, SchemeError> {
+        Ok(Value::Bool(b))
+    }
+
+    fn serialize_i8(self, i: i8) -> Result<Value, SchemeError> {
+        Ok(Value::from(i))
+    }
+
+    fn serialize_i16(self, i: i16) -> Result<Value, SchemeError> {
+        Ok(Value::from(i))
+    }
+
+    fn serialize_i32(self, i: i32) -> Result<Value, SchemeError> {
+        Ok(Value::from(i))
+    }
+
+    fn serialize_i64(self, i: i64) -> Result<Value, SchemeError> {
+        Ok(Value::Integer(i))
+    }
+
+    fn serialize_i128(self, i: i128) -> Result<Value, SchemeError> {
+        Ok(Value::integer(BigInt::from(i)))
+    }
+
+    fn serialize_u8(self, u: u8) -> Result<Value, SchemeError> {
+        Ok(Value::from(u))
+    }
+
+    fn serialize_u16(self, u: u16) -> Result<Value, SchemeError> {
+        Ok(Value::from(u))
+    }
+
+    fn serialize_u32(self, u: u32) -> Result<Value, SchemeError> {
+        Ok(Value::from(u))
+    }
+
+    fn serialize_u64(self, u: u64) -> Result<Value, SchemeError> {
+        Ok(Value::from(u))
+    }
+
+    fn serialize_u128(self, u: u128) -> Result<Value, SchemeError> {
+        Ok(Value::integer(BigInt::from(u)))
+    }
+
+    fn serialize_f32(self, f: f32) -> Result<Value, SchemeError> {
+        Ok(Value::Number(f.into()))
+    }
+
+    fn serialize_f64(self, f: f64) -> Result<Value, SchemeError> {
+        Ok(Value::Number(f))
+    }
+
+    fn serialize_char(self, c: char) -> Result<Value, SchemeError> {
+        Ok(Value::Char(c))
+    }
+
+    fn serialize_str(self, s: &str) -> Result<Value, SchemeError> {
+        Ok(Value::string(s))
+    }
+
+    fn serialize_bytes(self, bytes: &[u8]) -> Result<Value, SchemeError> {
+        Ok(Value::bytevector(bytes.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value, SchemeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, data: &T) -> Result<Value, SchemeError> {
+        data.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, SchemeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Value, SchemeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, name: &'static str) -> Result<Value, SchemeError> {
+        Ok(Value::symbol(name))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, data: &T) -> Result<Value, SchemeError> {
+        data.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, name: &'static str, data: &T) -> Result<Value, SchemeError> {
+        Ok(variant(name, data.serialize(self)?))
+    }
+
+    fn serialize_seq(self, length: Option<usize>) -> Result<Elements, SchemeError> {
+        Ok(Elements { elements: Vec::with_capacity(length.unwrap_or(0)), variant: None })
+    }
+
+    fn serialize_tuple(self, length: usize) -> Result<Elements, SchemeError> {
+        self.serialize_seq(Some(length))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, length: usize) -> Result<Elements, SchemeError> {
+        self.serialize_seq(Some(length))
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, name: &'static str, length: usize) -> Result<Elements, SchemeError> {
+        Ok(Elements { elements: Vec::with_capacity(length), variant: Some(name) })
+    }
+
+    fn serialize_map(self, length: Option<usize>) -> Result<Entries, SchemeError> {
+        Ok(Entries { entries: Vec::with_capacity(length.unwrap_or(0)), key: None, variant: None })
+    }
+
+    fn serialize_struct(self, _: &'static str, length: usize) -> Result<Entries, SchemeError> {
+        self.serialize_map(Some(length))
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, name: &'static str, length: usize) -> Result<Entries, SchemeError> {
+        Ok(Entries { entries: Vec::with_capacity(length), key: None, variant: Some(name) })
+    }
+}
+
+// The elements of a sequence, a tuple or a tuple variant being serialized.
+struct Elements {
+    elements: Vec<Value>,
+    variant: Option<&'static str>
+}
+
+impl Elements {
+    fn push<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        self.elements.push(data.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        let list = Value::list(self.elements);
+        match self.variant {
+            Some(name) => variant(name, list),
+            None => list
+        }
+    }
+}
+
+impl ser::SerializeSeq for Elements {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        self.push(data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for Elements {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        self.push(data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for Elements {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        self.push(data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for Elements {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        self.push(data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+// The entries of a map, a struct or a struct variant being serialized.
+struct Entries {
+    entries: Vec<Value>,
+    // the key of a map entry whose value comes next
+    key: Option<Value>,
+    variant: Option<&'static str>
+}
+
+impl Entries {
+    fn push<T: Serialize + ?Sized>(&mut self, key: Value, data: &T) -> Result<(), SchemeError> {
+        self.entries.push(Value::cons(symbol_key(key), data.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        let alist = Value::list(self.entries);
+        match self.variant {
+            Some(name) => variant(name, alist),
+            None => alist
+        }
+    }
+}
+
+impl ser::SerializeMap for Entries {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SchemeError> {
+        self.key = Some(key.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, data: &T) -> Result<(), SchemeError> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.push(key, data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for Entries {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, name: &'static str, data: &T) -> Result<(), SchemeError> {
+        self.push(Value::symbol(name), data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for Entries {
+    type Ok = Value;
+    type Error = SchemeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, name: &'static str, data: &T) -> Result<(), SchemeError> {
+        self.push(Value::symbol(name), data)
+    }
+
+    fn end(self) -> Result<Value, SchemeError> {
+        Ok(self.finish())
+    }
+}
+
+// Reads Rust data out of a value, which it owns so that the elements of lists can be
+// handed on without borrowing.
+struct ValueDeserializer(Value);
+
+impl ValueDeserializer {
+    // The elements of a proper list or a vector.
+    fn elements(&self) -> Result<Vec<Value>, SchemeError> {
+        match self.0 {
+            Value::Vector(ref items) => Ok(items.borrow().clone()),
+            ref value => list_elements(value).ok_or(SchemeError::conversion("expected a list"))
+        }
+    }
+
+    // The entries of an association list or a hash table.
+    fn entries(&self) -> Result<Vec<(Value, Value)>, SchemeError> {
+        match self.0 {
+            Value::HashTable(ref table) => Ok(table.borrow().values().cloned().collect()),
+            ref value => list_elements(value).and_then(|elements| entries(&elements))
+                .ok_or(SchemeError::conversion("expected an association list"))
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = SchemeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SchemeError> {
+        match self.0 {
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::BigInt(ref b) => match (b.to_i128(), b.to_u128()) {
+                (Some(i), _) => visitor.visit_i128(i),
+                (_, Some(u)) => visitor.visit_u128(u),
+                _ => Err(SchemeError::conversion(&unsupported(&self.0)))
+            },
+            Value::Number(f) => visitor.visit_f64(f),
+            Value::Rational(_) => visitor.visit_f64(self.0.as_f64().unwrap()),
+            Value::Char(c) => visitor.visit_char(c),
+            Value::String(ref s) => visitor.visit_str(s),
+            Value::Symbol(id) => visitor.visit_str(&id.name()),
+            Value::Bytevector(ref bytes) => visitor.visit_bytes(&bytes.borrow()),
+            Value::Nil => visitor.visit_unit(),
+            Value::Vector(_) => self.deserialize_seq(visitor),
+            Value::HashTable(_) => self.deserialize_map(visitor),
+            Value::List(_) | Value::Pair(_) => match self.entries() {
+                Ok(_) => self.deserialize_map(visitor),
+                Err(_) => self.deserialize_seq(visitor)
+            },
+            _ => Err(SchemeError::conversion(&unsupported(&self.0)))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SchemeError> {
+        match self.0 {
+            Value::Nil | Value::Unspecified => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, SchemeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SchemeError> {
+        let mut elements = de::value::SeqDeserializer::new(self.elements()?.into_iter().map(ValueDeserializer));
+        let data = visitor.visit_seq(&mut elements)?;
+        elements.end()?;
+        Ok(data)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, SchemeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _: &'static str, _: usize, visitor: V) -> Result<V::Value, SchemeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SchemeError> {
+        let entries = self.entries()?.into_iter().map(|(key, value)| (ValueDeserializer(key), ValueDeserializer(value)));
+        let mut entries = de::value::MapDeserializer::new(entries);
+        let data = visitor.visit_map(&mut entries)?;
+        entries.end()?;
+        Ok(data)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _: &'static str, _: &'static [&'static str], visitor: V) -> Result<V::Value, SchemeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _: &'static str, _: &'static [&'static str], visitor: V) -> Result<V::Value, SchemeError> {
+        match self.0 {
+            Value::Symbol(_) | Value::String(_) => visitor.visit_enum(Variant { name: self.0, data: None }),
+            _ => match self.entries()?.as_slice() {
+                [(name, data)] => visitor.visit_enum(Variant { name: name.clone(), data: Some(data.clone()) }),
+                _ => Err(SchemeError::conversion("expected a symbol or an association list of one entry"))
+            }
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, SchemeError> for ValueDeserializer {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> ValueDeserializer {
+        self
+    }
+}
+
+// An enum variant: its name, and its data unless it is a unit variant.
+struct Variant {
+    name: Value,
+    data: Option<Value>
+}
+
+impl<'de> de::EnumAccess<'de> for Variant {
+    type Error = SchemeError;
+    type Variant = Variant;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Variant), SchemeError> {
+        let name = seed.deserialize(ValueDeserializer(self.name.clone()))?;
+        Ok((name, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = SchemeError;
+
+    fn unit_variant(self) -> Result<(), SchemeError> {
+        match self.data {
+            None | Some(Value::Nil) => Ok(()),
+            Some(_) => Err(SchemeError::conversion("expected a unit variant"))
+        }
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, SchemeError> {
+        seed.deserialize(ValueDeserializer(self.data.unwrap_or(Value::Nil)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, SchemeError> {
+        de::Deserializer::deserialize_seq(ValueDeserializer(self.data.unwrap_or(Value::Nil)), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _: &'static [&'static str], visitor: V) -> Result<V::Value, SchemeError> {
+        de::Deserializer::deserialize_map(ValueDeserializer(self.data.unwrap_or(Value::Nil)), visitor)
+    }
+}
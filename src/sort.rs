@@ -0,0 +1,33 @@
+use super::{SchemeError, Value};
+
+/// Sorts `items` with a bottom-up merge sort, so that elements `less` considers equal
+/// keep their order. `less` is a Scheme procedure that may fail, which stops the sort.
+pub(crate) fn merge_sort<F>(items: Vec<Value>, mut less: F) -> Result<Vec<Value>, SchemeError>
+    where F: FnMut(&Value, &Value) -> Result<bool, SchemeError> {
+    let mut items = items;
+    let mut buffer = Vec::with_capacity(items.len());
+    let mut width = 1;
+    while width < items.len() {
+        for start in (0..items.len()).step_by(2 * width) {
+            let middle = (start + width).min(items.len());
+            let end = (start + 2 * width).min(items.len());
+            let (mut i, mut j) = (start, middle);
+            while i < middle && j < end {
+                // the right element goes first only if it is strictly less
+                if less(&items[j], &items[i])? {
+                    buffer.push(items[j].clone());
+                    j += 1;
+                } else {
+                    buffer.push(items[i].clone());
+                    i += 1;
+                }
+            }
+            buffer.extend_from_slice(&items[i..middle]);
+            buffer.extend_from_slice(&items[j..end]);
+        }
+        std::mem::swap(&mut items, &mut buffer);
+        buffer.clear();
+        width *= 2;
+    }
+    Ok(items)
+}
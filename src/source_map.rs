@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+
+use super::{abbreviation, context, datum_label, is_prefix, opens_list, Span, Token, Value};
+
+// Spans of the nodes of the program read last, keyed by node address. The kind of
+// node is kept as well, so that a node allocated later at the same address is not
+// mistaken for the one that was read.
+pub(crate) type Spans = HashMap<usize, (Span, Discriminant<Value>)>;
+
+pub(crate) fn key(value: &Value) -> usize {
+    value as *const Value as usize
+}
+
+/// Replaces the recorded spans by those of `value`, which was read from `tokens`.
+/// Spans are moved back by `offset` bytes, the length of any text put in front of
+/// the source before reading it.
+pub(crate) fn record(value: &Value, tokens: &[Token], offset: usize) {
+    context::with(|c| {
+        let mut spans = c.spans.borrow_mut();
+        spans.clear();
+        let mut position = 0;
+        walk(value, tokens, &mut position, offset, &mut spans);
+    });
+}
+
+/// The spans of `value` and its elements, which was read from `tokens`, keyed by node
+/// address, leaving the recorded spans alone.
+pub(crate) fn spans(value: &Value, tokens: &[Token]) -> HashMap<usize, Span> {
+    let mut spans = HashMap::new();
+    walk(value, tokens, &mut 0, 0, &mut spans);
+    spans.into_iter().map(|(key, (span, _))| (key, span)).collect()
+}
+
+// Records the span of `value` and its elements, whose tokens start at `position`,
+// and moves `position` past them. Returns the end of the span. Dotted lists and
+// labeled data, which are made of pairs, are only data, and their elements are
+// skipped.
+fn walk(value: &Value, tokens: &[Token], position: &mut usize, offset: usize, spans: &mut Spans) -> usize {
+    let (token, first) = match tokens.get(*position) {
+        Some((token, span)) => (token.as_ref(), *span),
+        None => return 0
+    };
+    *position += 1;
+    let end = match value {
+        _ if datum_label(token, '=').is_some() => skip(tokens, position),
+        // `'datum` is read as `(quote datum)`
+        Value::List(list) if abbreviation(token).is_some() && list.len() == 2 => walk(&list[1], tokens, position, offset, spans),
+        Value::List(list) if token.ends_with('(') => {
+            for element in list {
+                walk(element, tokens, position, offset, spans);
+            }
+            close(tokens, position)
+        }
+        Value::Vector(vector) if token.ends_with('(') => {
+            for element in vector.borrow().iter() {
+                walk(element, tokens, position, offset, spans);
+            }
+            close(tokens, position)
+        }
+        _ if opens_list(token) => {
+            *position -= 1;
+            skip(tokens, position)
+        }
+        _ => first.end
+    };
+    let span = Span { start: first.start.saturating_sub(offset), end: end.saturating_sub(offset) };
+    spans.insert(key(value), (span, mem::discriminant(value)));
+    end
+}
+
+// Moves `position` past the tokens of the datum there. Returns the end of the last one.
+fn skip(tokens: &[Token], position: &mut usize) -> usize {
+    let mut depth = 0;
+    while let Some((token, span)) = tokens.get(*position) {
+        *position += 1;
+        if opens_list(token) {
+            depth += 1;
+        } else if token == ")" {
+            depth -= 1;
+        } else if is_prefix(token) {
+            continue;
+        }
+        if depth == 0 {
+            return span.end;
+        }
+    }
+    0
+}
+
+// End of the `)` at `position`, which closes a list.
+fn close(tokens: &[Token], position: &mut usize) -> usize {
+    let end = tokens.get(*position).map_or(0, |(_, span)| span.end);
+    *position += 1;
+    end
+}
+
+/// Where `value` was read from, if it is a node of the program read last.
+pub(crate) fn span_of(value: &Value) -> Option<Span> {
+    context::with(|c| match c.spans.borrow().get(&key(value)) {
+        Some(&(span, kind)) if kind == mem::discriminant(value) => Some(span),
+        _ => None
+    })
+}
+
+/// Forgets the span of `value`, which is about to be moved or replaced, and returns it.
+pub(crate) fn take_span(value: &Value) -> Option<Span> {
+    context::with(|c| {
+        let mut spans = c.spans.borrow_mut();
+        match spans.get(&key(value)) {
+            Some(&(_, kind)) if kind == mem::discriminant(value) => spans.remove(&key(value)).map(|(span, _)| span),
+            _ => None
+        }
+    })
+}
+
+/// Records `span` for `value` in its new place.
+pub(crate) fn put_span(value: &Value, span: Option<Span>) {
+    if let Some(span) = span {
+        context::with(|c| c.spans.borrow_mut().insert(key(value), (span, mem::discriminant(value))));
+    }
+}
@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use super::{Arity, Env, Function, SchemeError, Value};
+
+/// The characters `start..end` of `s`, counted in characters rather than bytes.
+pub(crate) fn substring(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end {
+        return None;
+    }
+    let byte_offset = |n: usize| s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len())).nth(n);
+    Some(&s[byte_offset(start)?..byte_offset(end)?])
+}
+
+/// Position in characters of the byte offset `offset` of `s`.
+pub(crate) fn char_position(s: &str, offset: usize) -> usize {
+    s[..offset].chars().count()
+}
+
+/// Pieces of `s` between occurrences of `separator`, or between runs of whitespace
+/// when there is no separator.
+pub(crate) fn split(s: &str, separator: Option<&str>) -> Vec<Value> {
+    let string = |piece: &str| Value::String(piece.into());
+    match separator {
+        // an empty separator splits into characters
+        Some("") => s.chars().map(|c| string(&c.to_string())).collect(),
+        Some(separator) => s.split(separator).map(string).collect(),
+        None => s.split_whitespace().map(string).collect()
+    }
+}
+
+/// `char=?`, `char<?` and the other comparisons of characters by code point.
+pub(crate) fn char_comparison(test: fn(Ordering) -> bool) -> Value {
+    Value::Proc(Function::new(Arity::at_least(2), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        let chars = vec.iter().map(|value| match value {
+            Value::Char(c) => Some(*c),
+            _ => None
+        }).collect::<Option<Vec<char>>>().ok_or(SchemeError::wrong_type("character comparison requires arguments of type 'char'"))?;
+        if chars.len() < 2 {
+            return Err(SchemeError::arity("character comparison requires at least two arguments"));
+        }
+        Ok(Value::Bool(chars.windows(2).all(|pair| test(pair[0].cmp(&pair[1])))))
+    })))
+}
+
+/// `string=?`, `string<?` and the other comparisons, which hold when every pair of
+/// adjacent arguments is ordered as `test` expects.
+pub(crate) fn comparison(test: fn(Ordering) -> bool) -> Value {
+    Value::Proc(Function::new(Arity::at_least(2), Rc::new(move |vec: Vec<Value>, _: Rc<RefCell<Env>>| {
+        let strings = vec.iter().map(|value| match value {
+            Value::String(s) => Some(s.clone()),
+            _ => None
+        }).collect::<Option<Vec<Rc<str>>>>().ok_or(SchemeError::wrong_type("string comparison requires arguments of type 'string'"))?;
+        if strings.len() < 2 {
+            return Err(SchemeError::arity("string comparison requires at least two arguments"));
+        }
+        Ok(Value::Bool(strings.windows(2).all(|pair| test(pair[0].cmp(&pair[1])))))
+    })))
+}
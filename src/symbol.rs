@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use super::context;
+
+/// Interned symbol: equal names share one id, so comparing and hashing symbols
+/// works on an integer. Ids are only meaningful on the thread that created them, where
+/// every interpreter gives a name the same id. Names are never freed, so that an id a
+/// host keeps, in a value or as a key, names the same symbol for the life of the
+/// thread even once the interpreter that made it is dropped; every new name grows the
+/// table of the thread.
+#[derive(Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+// The names of the symbols interned on this thread, by id.
+#[derive(Default)]
+struct Table {
+    ids: HashMap<Rc<str>, SymbolId>,
+    names: Vec<Rc<str>>
+}
+
+impl Table {
+    fn intern(&mut self, name: Rc<str>) -> SymbolId {
+        let id = SymbolId(self.names.len() as u32);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+thread_local! {
+    static TABLE: RefCell<Table> = RefCell::new(Table::default());
+}
+
+// What a context interned: the symbols it used from the table of its thread, and
+// the string literals it read. Repeated parses of the same text get the same ids and
+// share the storage of names and literals, and a literal spelled like a symbol shares
+// the symbol's name. Literals no value holds any more are dropped whenever the table
+// of them has doubled, and the rest with the context.
+#[derive(Default)]
+pub(crate) struct Interner {
+    symbols: RefCell<HashSet<SymbolId>>,
+    strings: RefCell<Strings>
+}
+
+#[derive(Default)]
+struct Strings {
+    strings: HashSet<Rc<str>>,
+    // the number of literals kept by the last purge
+    kept: usize
+}
+
+impl Interner {
+    fn symbol(&self, name: &str) -> SymbolId {
+        TABLE.with(|t| {
+            let mut table = t.borrow_mut();
+            let id = match table.ids.get(name) {
+                Some(&id) => id,
+                // a literal read before with this text gives the name its storage
+                None => match self.strings.borrow().strings.get(name) {
+                    Some(string) => table.intern(string.clone()),
+                    None => table.intern(Rc::from(name))
+                }
+            };
+            self.symbols.borrow_mut().insert(id);
+            id
+        })
+    }
+
+    fn string(&self, s: &str) -> Rc<str> {
+        let mut strings = self.strings.borrow_mut();
+        if let Some(string) = strings.strings.get(s) {
+            return string.clone();
+        }
+        let name = TABLE.with(|t| t.borrow().ids.get_key_value(s).map(|(name, _)| name.clone()));
+        if let Some(name) = name {
+            return name;
+        }
+        if strings.strings.len() >= 2 * strings.kept.max(MIN_PURGE) {
+            // the table's own reference is the only one left to those nobody uses
+            strings.strings.retain(|string| Rc::strong_count(string) > 1);
+            strings.kept = strings.strings.len();
+        }
+        let string: Rc<str> = Rc::from(s);
+        strings.strings.insert(string.clone());
+        string
+    }
+}
+
+// Tables of literals smaller than this are never purged.
+const MIN_PURGE: usize = 512;
+
+/// The size of the interner of the current context, as returned by
+/// `interner_statistics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InternerStatistics {
+    /// Symbols the context interned, whose names stay for the life of the thread.
+    pub symbols: usize,
+    /// String literals held, some of which may be waiting to be dropped.
+    pub strings: usize
+}
+
+/// The size of the interner of the current context: the symbols and literals the
+/// interpreter evaluating, or the thread outside of any, has interned.
+pub fn interner_statistics() -> InternerStatistics {
+    context::with(|c| InternerStatistics {
+        symbols: c.interner.symbols.borrow().len(),
+        strings: c.interner.strings.borrow().strings.len()
+    })
+}
+
+/// The shared storage of the string `s`: equal strings interned in the current
+/// context are the same allocation, so that reading the same literal again allocates
+/// nothing. Unlike symbols, the strings no value holds any more are dropped from time
+/// to time.
+pub fn intern_string(s: &str) -> Rc<str> {
+    context::with(|c| c.interner.string(s))
+}
+
+impl SymbolId {
+    pub fn intern(name: &str) -> SymbolId {
+        context::with(|c| c.interner.symbol(name))
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        TABLE.with(|t| t.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl<'a> From<&'a str> for SymbolId {
+    fn from(name: &'a str) -> SymbolId {
+        SymbolId::intern(name)
+    }
+}
+
+impl From<String> for SymbolId {
+    fn from(name: String) -> SymbolId {
+        SymbolId::intern(&name)
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.name())
+    }
+}
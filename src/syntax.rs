@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use super::{datum_label, form_end, read_from_tokens, read_list, scan, source_map};
+use super::{SchemeError, Span, SyntaxError, Token, Value};
+
+/// A datum of source text as it was written, with the span of every part of it, for
+/// tools that analyze or rewrite programs without evaluating them. Unlike the values
+/// `parse` returns, syntax owns its contents: changing it affects no running program.
+///
+/// ```
+/// use scheme_rs::{parse_all, unparse, Span, SyntaxKind};
+///
+/// let forms = parse_all("(define x 1)\n'y").unwrap();
+/// assert_eq!(Span { start: 0, end: 12 }, forms[0].span);
+/// match &forms[0].kind {
+///     SyntaxKind::List(elements) => {
+///         assert_eq!(SyntaxKind::Symbol("x".to_string()), elements[1].kind);
+///         assert_eq!(Span { start: 8, end: 9 }, elements[1].span);
+///     }
+///     _ => unreachable!()
+/// }
+/// assert_eq!("(quote y)", unparse(&forms[1].to_value()));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Syntax {
+    pub kind: SyntaxKind,
+    /// Where the datum was read from. Parts the reader makes up, like the `quote` of
+    /// `'y`, have the span of the datum they are in.
+    pub span: Span
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxKind {
+    Boolean(bool),
+    /// An exact or inexact number, as the reader makes it.
+    Number(Value),
+    Character(char),
+    String(String),
+    Symbol(String),
+    Bytevector(Vec<u8>),
+    /// A proper list, empty for `()`. Abbreviations are read as lists: `'y` is
+    /// `(quote y)`.
+    List(Vec<Syntax>),
+    Vector(Vec<Syntax>),
+    /// A datum whose parts are not kept apart, such as dotted lists and labeled data,
+    /// which may be cyclic.
+    Datum(Value)
+}
+
+impl Syntax {
+    /// The datum as the value `parse` would read, for instance to evaluate it once
+    /// rewritten.
+    pub fn to_value(&self) -> Value {
+        match self.kind {
+            SyntaxKind::Boolean(b) => Value::Bool(b),
+            SyntaxKind::Number(ref n) | SyntaxKind::Datum(ref n) => n.clone(),
+            SyntaxKind::Character(c) => Value::Char(c),
+            SyntaxKind::String(ref s) => Value::string(s),
+            SyntaxKind::Symbol(ref name) => Value::symbol(name),
+            SyntaxKind::Bytevector(ref bytes) => Value::bytevector(bytes.clone()),
+            SyntaxKind::List(ref elements) => read_list(elements.iter().map(Syntax::to_value).collect()),
+            SyntaxKind::Vector(ref elements) => Value::vector(elements.iter().map(Syntax::to_value).collect())
+        }
+    }
+}
+
+/// Reads every datum of `source` without evaluating it, failing with the first syntax
+/// error. Spans are recorded for the syntax alone, so that reading code leaves the
+/// error locations of the program being run as they were.
+pub fn parse_all(source: &str) -> Result<Vec<Syntax>, SchemeError> {
+    let (tokens, mut errors) = scan(source);
+    let mut forms = vec![];
+    let mut start = 0;
+    while start < tokens.len() {
+        let end = form_end(source, &tokens, start);
+        let group = &tokens[start..end];
+        let span = Span { start: group[0].1.start, end: group[group.len() - 1].1.end };
+        match read_from_tokens(&group.iter().map(|(token, _)| token.as_ref()).collect::<Vec<&str>>(), &mut 0) {
+            Ok(form) => forms.push(syntax(&form, group, span)),
+            Err(message) => errors.push(SyntaxError { span, message })
+        }
+        start = end;
+    }
+    match errors.into_iter().min_by_key(|e| e.span.start) {
+        Some(error) => Err(SchemeError::read(error.message).with_span(error.span)),
+        None => Ok(forms)
+    }
+}
+
+// The syntax of `form`, read from `tokens` which span `span`.
+fn syntax(form: &Value, tokens: &[Token], span: Span) -> Syntax {
+    let spans = source_map::spans(form, tokens);
+    let labels = tokens.iter().filter(|(token, _)| datum_label(token, '=').is_some()).map(|(_, span)| span.start).collect::<Vec<usize>>();
+    build(form, &spans, &labels, span)
+}
+
+// Elements without a span of their own get `outer`, the span of the datum they are in.
+fn build(value: &Value, spans: &HashMap<usize, Span>, labels: &[usize], outer: Span) -> Syntax {
+    let span = spans.get(&source_map::key(value)).cloned().unwrap_or(outer);
+    let kind = match *value {
+        _ if labels.contains(&span.start) => SyntaxKind::Datum(value.clone()),
+        Value::Bool(b) => SyntaxKind::Boolean(b),
+        Value::Integer(_) | Value::BigInt(_) | Value::Rational(_) | Value::Number(_) => SyntaxKind::Number(value.clone()),
+        Value::Char(c) => SyntaxKind::Character(c),
+        Value::String(ref s) => SyntaxKind::String(s.to_string()),
+        Value::Symbol(id) => SyntaxKind::Symbol(id.name().to_string()),
+        Value::Bytevector(ref bytes) => SyntaxKind::Bytevector(bytes.borrow().clone()),
+        Value::Nil => SyntaxKind::List(vec![]),
+        Value::List(ref list) => SyntaxKind::List(list.iter().map(|e| build(e, spans, labels, span)).collect()),
+        Value::Vector(ref vector) => SyntaxKind::Vector(vector.borrow().iter().map(|e| build(e, spans, labels, span)).collect()),
+        _ => SyntaxKind::Datum(value.clone())
+    };
+    Syntax { kind, span }
+}
@@ -0,0 +1,106 @@
+//! Helpers for crates that embed the interpreter and test their integration with it.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate scheme_rs;
+//!
+//! use scheme_rs::testing::fixture_env;
+//! use scheme_rs::{SchemeError, Value};
+//!
+//! fn main() {
+//!     eval_ok!("(+ 1 2)", 3);
+//!     eval_err!("(car 1)", "car function requires an argument of type 'list' / 'pair'");
+//!     eval_err!("(car 1)", SchemeError::WrongType { .. });
+//!
+//!     let env = fixture_env(&[("limit", Value::Integer(10))]);
+//!     eval_ok!(env, "(* limit 2)", 20);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{define_prelude, eval, parse, setup, Env, SchemeError, SymbolId, Value};
+
+/// A global environment holding only the builtins and the prelude.
+pub fn default_env() -> Rc<RefCell<Env>> {
+    fixture_env(&[])
+}
+
+/// A global environment holding the builtins, the prelude and `bindings`, which
+/// replace builtins of the same name. Panics if the prelude fails to evaluate.
+pub fn fixture_env(bindings: &[(&str, Value)]) -> Rc<RefCell<Env>> {
+    let mut local = setup();
+    if let Err(e) = define_prelude(&mut local) {
+        panic!("prelude failed to evaluate: {}", e);
+    }
+    local.extend(bindings.iter().map(|(name, value)| (SymbolId::intern(name), value.clone())));
+    Rc::new(RefCell::new(Env::new(local, None)))
+}
+
+/// A global environment holding the builtins and everything `source` defines.
+/// Panics if `source` fails to evaluate, since the fixture itself is broken then.
+pub fn fixture_env_from_source(source: &str) -> Rc<RefCell<Env>> {
+    let env = default_env();
+    if let Err(e) = eval_source(source, env.clone()) {
+        panic!("fixture failed to evaluate: {}", e);
+    }
+    env
+}
+
+/// A new scope inside `parent`, so that a test can bind names without changing
+/// a shared fixture.
+pub fn child_env(parent: Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
+    Rc::new(RefCell::new(Env::new(HashMap::new(), Some(parent))))
+}
+
+/// Reads and evaluates every expression of `source`, returning the value of the last.
+pub fn eval_source(source: &str, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    parse(source).and_then(|ast| eval(Some(ast.result), env))
+}
+
+/// Asserts that evaluating the source succeeds with the expected value, which is
+/// anything that converts into a `Value`. The environment is optional and
+/// defaults to a new `default_env()`.
+#[macro_export]
+macro_rules! eval_ok {
+    ($source:expr, $expected:expr) => {
+        $crate::eval_ok!($crate::testing::default_env(), $source, $expected)
+    };
+    ($env:expr, $source:expr, $expected:expr) => {
+        assert_eq!(
+            Ok($crate::Value::from($expected)),
+            $crate::testing::eval_source($source, $env.clone()),
+            "evaluating {}", $source
+        )
+    };
+}
+
+/// Asserts that evaluating the source fails, with the expected error message or an
+/// error matching the expected `SchemeError` pattern, if one is given.
+#[macro_export]
+macro_rules! eval_err {
+    ($source:expr) => {
+        match $crate::testing::eval_source($source, $crate::testing::default_env()) {
+            Ok(value) => panic!("evaluating {} returned {} instead of failing", $source, value),
+            Err(_) => {}
+        }
+    };
+    // the patterns come first, since a struct pattern does not parse as an expression
+    ($source:expr, $variant:ident $(:: $path:ident)* { $($fields:tt)* }) => {
+        $crate::eval_err!($crate::testing::default_env(), $source, $variant $(:: $path)* { $($fields)* })
+    };
+    ($env:expr, $source:expr, $variant:ident $(:: $path:ident)* { $($fields:tt)* }) => {
+        match $crate::testing::eval_source($source, $env.clone()) {
+            Err($variant $(:: $path)* { $($fields)* }) => {}
+            other => panic!("evaluating {} returned {:?} instead of failing with {}", $source, other, stringify!($variant $(:: $path)* { $($fields)* }))
+        }
+    };
+    ($source:expr, $expected:expr) => {
+        $crate::eval_err!($crate::testing::default_env(), $source, $expected)
+    };
+    ($env:expr, $source:expr, $expected:expr) => {
+        assert_eq!(Err($expected), $crate::testing::eval_source($source, $env.clone()).map_err(|e| e.message()), "evaluating {}", $source)
+    };
+}
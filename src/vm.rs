@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use super::{bind_arguments, count_allocation, frames, gc, observe, quasiquote, record, record_failure, safe_point, watch};
+use super::{DepthGuard, Env, Procedure, SchemeError, SymbolId, Value, WatchAccess};
+use bytecode::{compile, Address, Bytecode, Op};
+use machine::{define, named, RunGuard};
+use watch::CallGuard;
+
+// The activation of compiled code: the program, or the body of a procedure.
+struct Frame {
+    code: Rc<Bytecode>,
+    pc: usize,
+    env: Rc<RefCell<Env>>,
+    // whether `env` was created for the call, and goes back to the free frames after it
+    owned: bool,
+    _call: Option<CallGuard>,
+    _depth: Option<DepthGuard>
+}
+
+impl Frame {
+    fn call(p: &Procedure, args: Vec<Value>, call: Option<CallGuard>) -> Frame {
+        let (code, env) = match p.closure.code {
+            Some(ref code) => {
+                let env = Env::with_slots(code.locals.clone(), p.closure.env.clone());
+                for (slot, (name, value)) in p.closure.params.iter().zip(args).enumerate() {
+                    if let Value::Symbol(name) = *name {
+                        watch::notify(name, WatchAccess::Write, &value, || None);
+                    }
+                    env.set_slot(slot, value);
+                }
+                count_allocation(mem::size_of::<Env>());
+                (code.clone(), Rc::new(RefCell::new(env)))
+            }
+            // procedures created by the tree-walking evaluator are compiled when called
+            None => (compile(&p.closure.body), bind_arguments(p, args))
+        };
+        Frame { code, pc: 0, env, owned: true, _call: call, _depth: Some(DepthGuard::enter()) }
+    }
+
+    fn finish(self) {
+        if self.owned {
+            frames::release(self.env);
+        }
+    }
+}
+
+struct Machine {
+    frames: Vec<Frame>,
+    values: Vec<Value>,
+    // the procedures of the calls whose arguments are being evaluated
+    callees: Vec<CallGuard>
+}
+
+impl Machine {
+    fn new(frame: Frame) -> Machine {
+        Machine { frames: vec![frame], values: vec![], callees: vec![] }
+    }
+}
+
+enum Next {
+    Continue,
+    Return(Value)
+}
+
+impl Machine {
+    fn execute(mut self) -> Result<Value, SchemeError> {
+        loop {
+            match self.step() {
+                Ok(Next::Continue) => {}
+                Ok(Next::Return(value)) => {
+                    let frame = self.frames.pop().unwrap();
+                    frame.finish();
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    self.values.push(value);
+                }
+                Err(error) => return Err(self.fail(error))
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<Next, SchemeError> {
+        let frame = self.frames.last_mut().unwrap();
+        let code = frame.code.clone();
+        let op = &code.ops[frame.pc];
+        frame.pc += 1;
+        match op {
+            Op::Constant(data) => self.values.push(data.clone()),
+            Op::Quasiquote(template) => {
+                let env = frame.env.clone();
+                self.values.push(quasiquote(template, 1, env)?);
+            }
+            Op::Variable(address) => {
+                let data = lookup(&frame.env, address);
+                match data {
+                    Some(data) => {
+                        watch::notify(address.name(), WatchAccess::Read, &data, || code.spans[frame.pc - 1]);
+                        self.values.push(data);
+                    }
+                    None => return Err(SchemeError::unbound(&address.name().name()))
+                }
+            }
+            Op::Callee(address) => {
+                let id = address.name();
+                let data = lookup(&frame.env, address);
+                if let Some(ref data) = data {
+                    watch::notify(id, WatchAccess::Read, data, || code.spans[frame.pc - 1]);
+                }
+                let call = CallGuard::enter(id);
+                match data {
+                    Some(procedure @ Value::Proc(_)) | Some(procedure @ Value::Lambda(_)) => {
+                        self.values.push(procedure);
+                        self.callees.push(call);
+                    }
+                    Some(_) => return Err(SchemeError::wrong_type("wrong type to apply")),
+                    None => return Err(SchemeError::unbound(&id.name()))
+                }
+            }
+            Op::EnterCall(name) => self.callees.push(CallGuard::enter(*name)),
+            Op::Operator => match self.values.last() {
+                Some(Value::Proc(_)) | Some(Value::Lambda(_)) => {}
+                _ => {
+                    self.callees.pop();
+                    return Err(SchemeError::syntax("unsupported data type on first element"));
+                }
+            },
+            Op::Define(id) => {
+                let value = self.values.pop().unwrap();
+                define(&frame.env, *id, value, || code.spans[frame.pc - 1]);
+                self.values.push(Value::Unspecified);
+            }
+            Op::DefineLocal(slot, id) => {
+                let value = self.values.pop().unwrap();
+                let value = named(value, *id);
+                watch::notify(*id, WatchAccess::Write, &value, || code.spans[frame.pc - 1]);
+                observe::define(*id, &value);
+                gc::track_env(&frame.env, &value);
+                frame.env.borrow().set_slot(*slot, value);
+                self.values.push(Value::Unspecified);
+            }
+            Op::Bind(slot, id) => {
+                let value = self.values.pop().unwrap();
+                watch::notify(*id, WatchAccess::Write, &value, || code.spans[frame.pc - 1]);
+                gc::track_env(&frame.env, &value);
+                frame.env.borrow().set_slot(*slot, value);
+            }
+            Op::DefineRecordType(form) => {
+                let list = match **form {
+                    Value::List(ref list) => list,
+                    _ => unreachable!()
+                };
+                for (name, data) in record::define_record_type(list)? {
+                    let id = SymbolId::intern(&name);
+                    watch::notify(id, WatchAccess::Write, &data, || code.spans[frame.pc - 1]);
+                    observe::define(id, &data);
+                    frame.env.borrow_mut().bind(id, data);
+                }
+                self.values.push(Value::Unspecified);
+            }
+            Op::Lambda(template) => {
+                let mut env = frame.env.clone();
+                for _ in 0..template.outer {
+                    let parent = env.borrow().parent.as_deref().cloned().unwrap();
+                    env = parent;
+                }
+                let procedure = Procedure::new(template.params.clone(), template.body.clone(), env, Some(template.code.clone()));
+                self.values.push(Value::Lambda(procedure));
+            }
+            Op::EnterScope(names) => {
+                frame.env = Rc::new(RefCell::new(Env::with_slots(names.clone(), frame.env.clone())));
+                count_allocation(mem::size_of::<Env>());
+            }
+            Op::LeaveScope => {
+                let parent = frame.env.borrow().parent.clone().unwrap();
+                frame.env = *parent;
+            }
+            Op::Pop => {
+                self.values.pop();
+            }
+            Op::Jump(to) => frame.pc = *to,
+            Op::JumpIfFalse(to) => {
+                // every value but #f counts as true, 0 and the empty list included
+                if self.values.pop() == Some(Value::Bool(false)) {
+                    frame.pc = *to;
+                }
+            }
+            Op::Call { arguments, .. } => {
+                let args = self.values.split_off(self.values.len() - arguments);
+                let call = self.callees.pop();
+                observe::apply(self.values.last().unwrap(), &args);
+                match self.values.pop().unwrap() {
+                    Value::Proc(f) => {
+                        let env = frame.env.clone();
+                        let result = f.call(args, env);
+                        drop(call);
+                        self.values.push(result?);
+                    }
+                    Value::Lambda(p) => {
+                        safe_point()?;
+                        let frame = Frame::call(&p, args, call);
+                        self.frames.push(frame);
+                    }
+                    _ => unreachable!()
+                }
+            }
+            Op::TailCall { arguments, name } => {
+                let args = self.values.split_off(self.values.len() - arguments);
+                let call = self.callees.pop();
+                observe::apply(self.values.last().unwrap(), &args);
+                match self.values.pop().unwrap() {
+                    Value::Proc(f) => {
+                        let env = frame.env.clone();
+                        let result = f.call(args, env);
+                        drop(call);
+                        return result.map(Next::Return);
+                    }
+                    Value::Lambda(p) => {
+                        // the frame making the call is done with: the procedure it calls
+                        // takes its place on the call stack
+                        safe_point()?;
+                        drop(call);
+                        self.frames.pop().unwrap().finish();
+                        let frame = Frame::call(&p, args, Some(CallGuard::resume(*name)));
+                        self.frames.push(frame);
+                    }
+                    _ => unreachable!()
+                }
+            }
+            Op::Return => return Ok(Next::Return(self.values.pop().unwrap())),
+            Op::Fail(error) => return Err(error.clone())
+        }
+        Ok(Next::Continue)
+    }
+
+    // Unwinds every frame after `error`: the innermost one keeps its environment for
+    // inspection, and the error is placed at the innermost form read by `parse` that
+    // failed with it.
+    fn fail(mut self, error: SchemeError) -> SchemeError {
+        let mut error = error.traced();
+        if let Some(frame) = self.frames.last() {
+            record_failure(&frame.env, &error);
+        }
+        self.callees.clear();
+        while let Some(frame) = self.frames.pop() {
+            if let (None, Some(span)) = (error.span(), frame.code.spans[frame.pc - 1]) {
+                error = error.with_span(span);
+            }
+            frame.finish();
+        }
+        error
+    }
+}
+
+// The value of the variable at `address`, seen from `env`.
+fn lookup(env: &Rc<RefCell<Env>>, address: &Address) -> Option<Value> {
+    match *address {
+        Address::Local { depth: 0, slot, .. } => env.borrow().slot(slot),
+        Address::Local { depth, slot, name } => match env.borrow().parent {
+            Some(ref parent) => lookup(parent, &Address::Local { depth: depth - 1, slot, name }),
+            None => None
+        },
+        Address::Free(name) => env.borrow().get(name)
+    }
+}
+
+/// Runs `code`, compiled with `compile`, in `env`. Calls of procedures are frames
+/// on a stack of the machine's own, and calls in tail position replace the frame of
+/// the procedure making them, so that loops written as recursion run in constant
+/// space.
+pub fn execute(code: &Rc<Bytecode>, env: Rc<RefCell<Env>>) -> Result<Value, SchemeError> {
+    let _run = RunGuard::enter()?;
+    let _depth = DepthGuard::enter();
+    let frame = Frame { code: code.clone(), pc: 0, env, owned: false, _call: None, _depth: None };
+    Machine::new(frame).execute()
+}
+
+// Calls the lambda `p` for a builtin, like `map`, on a machine of its own.
+pub(crate) fn call(p: &Procedure, args: Vec<Value>) -> Result<Value, SchemeError> {
+    let _run = RunGuard::enter()?;
+    safe_point()?;
+    Machine::new(Frame::call(p, args, None)).execute()
+}
@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
+use super::{context, Span, SymbolId, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchAccess {
+    Read,
+    Write
+}
+
+/// One access to a watched binding, or made from a watched span, with the names of
+/// the procedures being applied at that moment, outermost first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchEvent {
+    pub symbol: String,
+    pub access: WatchAccess,
+    pub value: Value,
+    pub stack: Vec<String>,
+    /// Where the form making the access was read, if it is known. Parameters are
+    /// bound by the call, which has none.
+    pub span: Option<Span>
+}
+
+pub type WatchHandler = dyn Fn(&WatchEvent);
+
+// Number of procedure applications kept for post-mortem dumps.
+const TRACE_LENGTH: usize = 32;
+
+// The watches of a context, and the procedures its evaluations are applying.
+#[derive(Default)]
+pub(crate) struct Watches {
+    watched: RefCell<HashSet<SymbolId>>,
+    spans: RefCell<Vec<Span>>,
+    handler: RefCell<Option<Rc<WatchHandler>>>,
+    call_stack: RefCell<Vec<SymbolId>>,
+    trace: RefCell<VecDeque<SymbolId>>
+}
+
+impl Watches {
+    pub fn inherit(&self, watches: &Watches) {
+        *self.handler.borrow_mut() = watches.handler.borrow().clone();
+    }
+}
+
+/// Starts reporting every read and write of bindings named `symbol` in the current
+/// context.
+pub fn watch(symbol: &str) {
+    let symbol = SymbolId::intern(symbol);
+    context::with(|c| c.watches.watched.borrow_mut().insert(symbol));
+}
+
+pub fn unwatch(symbol: &str) {
+    let symbol = SymbolId::intern(symbol);
+    context::with(|c| c.watches.watched.borrow_mut().remove(&symbol));
+}
+
+/// Starts reporting every read and write of a binding made by the forms read from
+/// within `span`, whatever their name. Spans are those of the program read last, as
+/// in errors, or those a procedure was compiled with by the bytecode engine.
+pub fn watch_span(span: Span) {
+    context::with(|c| c.watches.spans.borrow_mut().push(span));
+}
+
+pub fn unwatch_span(span: Span) {
+    context::with(|c| c.watches.spans.borrow_mut().retain(|&watched| watched != span));
+}
+
+/// Replaces the default handler, which logs the events at info level.
+pub fn set_watch_handler(handler: Option<Rc<WatchHandler>>) {
+    context::with(|c| *c.watches.handler.borrow_mut() = handler);
+}
+
+// Reports an access to the handler if the binding or the place of the access is
+// watched. `at` gives that place, and is only called when spans are watched.
+pub(crate) fn notify<F: FnOnce() -> Option<Span>>(symbol: SymbolId, access: WatchAccess, value: &Value, at: F) {
+    let (by_name, by_span) = context::with(|c| {
+        let watched = c.watches.watched.borrow();
+        (!watched.is_empty() && watched.contains(&symbol), !c.watches.spans.borrow().is_empty())
+    });
+    if !by_name && !by_span {
+        return;
+    }
+    let span = at();
+    let within = |span: Span| context::with(|c| c.watches.spans.borrow().iter().any(|w| w.start <= span.start && span.end <= w.end));
+    if !by_name && !span.is_some_and(within) {
+        return;
+    }
+    let event = WatchEvent {
+        symbol: symbol.to_string(),
+        access,
+        value: value.clone(),
+        stack: call_stack(),
+        span
+    };
+    match context::with(|c| c.watches.handler.borrow().clone()) {
+        Some(handler) => handler(&event),
+        None => info!("watch: {:?} {} = {} in [{}]", event.access, event.symbol, event.value, event.stack.join(" > "))
+    }
+}
+
+/// Names of the procedures being applied, outermost first.
+pub(crate) fn call_stack() -> Vec<String> {
+    context::with(|c| c.watches.call_stack.borrow().iter().map(|name| name.to_string()).collect())
+}
+
+/// Names of the procedures applied most recently, oldest first.
+pub(crate) fn trace() -> Vec<String> {
+    context::with(|c| c.watches.trace.borrow().iter().map(|name| name.to_string()).collect())
+}
+
+// Keeps the name of a procedure on the call stack while it is being applied.
+pub(crate) struct CallGuard;
+
+impl CallGuard {
+    pub(crate) fn enter(name: SymbolId) -> CallGuard {
+        context::with(|c| {
+            c.watches.call_stack.borrow_mut().push(name);
+            let mut trace = c.watches.trace.borrow_mut();
+            if trace.len() == TRACE_LENGTH {
+                trace.pop_front();
+            }
+            trace.push_back(name);
+        });
+        CallGuard
+    }
+
+    // Puts `name` back on the call stack without tracing it again, for a procedure
+    // that moved to another place of the stack.
+    pub(crate) fn resume(name: SymbolId) -> CallGuard {
+        context::with(|c| c.watches.call_stack.borrow_mut().push(name));
+        CallGuard
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        context::with(|c| c.watches.call_stack.borrow_mut().pop());
+    }
+}
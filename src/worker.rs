@@ -0,0 +1,93 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use super::{FromScheme, InterruptHandle, Interpreter, SchemeError};
+
+type Job = Box<dyn FnOnce(&Interpreter) + Send>;
+
+/// An interpreter on a thread of its own, which the handles to it pass work to. The
+/// environment and values of an interpreter hold `Rc`s, and symbols are numbered by
+/// the thread that interns them, so they never leave that thread; what comes back
+/// from it is Rust data, such as the types `FromScheme` reads. Handles can be cloned
+/// and sent to any thread, like the workers of a pool or a web server, and the
+/// thread ends once every handle is dropped.
+///
+/// ```
+/// use std::thread;
+/// use scheme_rs::InterpreterThread;
+///
+/// let interpreter = InterpreterThread::spawn().unwrap();
+/// interpreter.eval::<()>("(define (square x) (* x x))").unwrap();
+/// let workers = (1..=3).map(|n| {
+///     let interpreter = interpreter.clone();
+///     thread::spawn(move || interpreter.eval::<i64>(&format!("(square {})", n)).unwrap())
+/// }).collect::<Vec<_>>();
+/// let squares = workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Vec<i64>>();
+/// assert_eq!(vec![1, 4, 9], squares);
+/// ```
+#[derive(Clone)]
+pub struct InterpreterThread {
+    jobs: Sender<Job>,
+    interrupts: InterruptHandle
+}
+
+impl InterpreterThread {
+    /// Starts a thread with a new interpreter, or fails as `Interpreter::new` does.
+    pub fn spawn() -> Result<InterpreterThread, SchemeError> {
+        InterpreterThread::spawn_with(Interpreter::new)
+    }
+
+    /// Starts a thread with the interpreter `init` makes there, which may define
+    /// native functions, or evaluate a program to set up the environment. The thread
+    /// ends at once if `init` fails, with the error returned here.
+    pub fn spawn_with<F>(init: F) -> Result<InterpreterThread, SchemeError>
+        where F: FnOnce() -> Result<Interpreter, SchemeError> + Send + 'static {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (handle, interrupts) = mpsc::channel();
+        thread::spawn(move || {
+            let interpreter = match init() {
+                Ok(interpreter) => interpreter,
+                Err(error) => {
+                    handle.send(Err(error)).ok();
+                    return;
+                }
+            };
+            handle.send(Ok(interpreter.interrupt_handle())).ok();
+            for job in queue {
+                job(&interpreter);
+            }
+        });
+        let interrupts = interrupts.recv().expect("the interpreter thread has stopped")?;
+        Ok(InterpreterThread { jobs, interrupts })
+    }
+
+    /// The handle interrupting the evaluations of the interpreter.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupts.clone()
+    }
+
+    /// Runs `f` with the interpreter on its thread, after the work passed to it
+    /// before, and waits for its result. A panic in `f` is raised again here, and the
+    /// interpreter goes on with the work passed to it next.
+    pub fn run<F, R>(&self, f: F) -> R
+        where F: FnOnce(&Interpreter) -> R + Send + 'static, R: Send + 'static {
+        let (reply, result) = mpsc::channel::<Result<R, Box<dyn Any + Send>>>();
+        let job = move |interpreter: &Interpreter| {
+            reply.send(panic::catch_unwind(AssertUnwindSafe(|| f(interpreter)))).ok();
+        };
+        self.jobs.send(Box::new(job)).expect("the interpreter thread has stopped");
+        match result.recv().expect("the interpreter thread has stopped") {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload)
+        }
+    }
+
+    /// Evaluates `source` as `Interpreter::eval_str` does and reads its value as a
+    /// `T`. `()` takes any value.
+    pub fn eval<T: FromScheme + Send + 'static>(&self, source: &str) -> Result<T, SchemeError> {
+        let source = source.to_string();
+        self.run(move |interpreter| interpreter.eval_str(&source).and_then(|value| T::from_scheme(&value)))
+    }
+}
@@ -0,0 +1,66 @@
+// The allocations the reader and the evaluator make, counted by a global allocator
+// of this test binary alone, so that the other tests and the benchmarks run without
+// it.
+extern crate scheme_rs;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use scheme_rs::*;
+
+// Counts the allocations made on each thread, growing ones included.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<R, F: FnOnce() -> R>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    drop(f());
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+// The reader reads the elements of every open list onto one stack kept for the next
+// read, and allocates a list, its storage and its `Rc`, once it is closed: about two
+// allocations a list. With a new stack growing for every read it took 13,024
+// allocations here instead of 11,524.
+#[test]
+fn reader_allocations() {
+    let definition = "(define (f x y) (if (< x y) (list x y (quote (a b c)) #(1 2 3)) (f (- x 1) (+ y 1))))\n";
+    let source = definition.repeat(500);
+    parse_program(&source);
+    let lists = 11 * 500;
+    let made = allocations(|| parse_program(&source));
+    assert!(made < 2 * lists + 1000, "{} allocations reading {} lists", made, lists);
+}
+
+// Procedure calls take the binding table of their frame from those of the frames
+// released earlier. Here 2,000 calls made 23,026 allocations; with a new table for
+// every call they made 25,027.
+#[test]
+fn frame_allocations() {
+    let env = testing::fixture_env_from_source("(define (add a b) (+ a b)) (define (loop n acc) (if (= n 0) acc (loop (- n 1) (add acc n))))");
+    testing::eval_source("(loop 10 0)", env.clone()).unwrap();
+    let calls = 2 * 1000;
+    let made = allocations(|| testing::eval_source("(loop 1000 0)", env.clone()));
+    assert!(made < 12 * calls, "{} allocations making {} calls", made, calls);
+}
@@ -1,50 +1,61 @@
 extern crate scheme_rs;
+extern crate num_bigint;
+extern crate num_rational;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
 extern crate log;
 extern crate env_logger;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::rc::Rc;
 use scheme_rs::*;
 
 #[test]
 fn if_expression_test() {
     let test_result = run("(if (> (* 11 11) 120) #t #f)");
-    assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+    assert_eq!(Ok(Value::Bool(true)), test_result.value);
 }
 
 #[test]
 fn quote_expression_test() {
     {
         let test_result = run("(quote apple)");
-        assert_eq!(Ok(Some(DataType::Symbol("apple".to_string()))), test_result.value);
+        assert_eq!(Ok(Value::Symbol("apple".into())), test_result.value);
     }
     {
         let test_result = run("(quote \"orange\")");
-        assert_eq!(Ok(Some(DataType::Symbol("orange".to_string()))), test_result.value);
+        assert_eq!(Ok(Value::String("orange".into())), test_result.value);
     }
     {
         let test_result = run("(quote 42)");
-        assert_eq!(Ok(Some(DataType::Number(42.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(42)), test_result.value);
     }
     {
         let test_result = run("(quote #t)");
-        assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+        assert_eq!(Ok(Value::Bool(true)), test_result.value);
     }
     {
         let test_result = run("(quote (define x 1))");
-        assert_eq!(Ok(Some(DataType::List(vec![
-            DataType::Symbol("define".to_string()),
-            DataType::Symbol("x".to_string()),
-            DataType::Number(1.0),
-        ]))), test_result.value);
+        assert_eq!(Ok(Value::list(vec![
+            Value::Symbol("define".into()),
+            Value::Symbol("x".into()),
+            Value::Integer(1),
+        ])), test_result.value);
+    }
+    {
+        // the datum is the one read, each time the quote is evaluated
+        let test_result = run("(define (f) '(1 2)) (eq? (f) (f))");
+        assert_eq!(Ok(Value::Bool(true)), test_result.value);
     }
 }
 
 #[test]
 fn variable_retrieving_test() {
     let test_result = run("(define r 10)(* pi (* r r))");
-    assert_eq!(Ok(Some(DataType::Number(314.1592653589793))), test_result.value);
+    assert_eq!(Ok(Value::Number(314.1592653589793)), test_result.value);
 }
 
 #[test]
@@ -53,7 +64,7 @@ fn lambda_retrieving_test() {
     (define circle-area (lambda (r) (* pi (* r r))))
     (circle-area 3)
     "#);
-    assert_eq!(Ok(Some(DataType::Number(28.274333882308138))), test_result.value);
+    assert_eq!(Ok(Value::Number(28.274333882308138)), test_result.value);
 }
 
 #[test]
@@ -62,7 +73,7 @@ fn recursive_lambda_test() {
     (define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))
     (fact 10)
     "#);
-    assert_eq!(Ok(Some(DataType::Number(3628800.0))), test_result.value);
+    assert_eq!(Ok(Value::Integer(3628800)), test_result.value);
 }
 
 #[test]
@@ -71,7 +82,7 @@ fn lambda_call_test() {
     (define twice (lambda (x) (* 2 x)))
     (twice 5)
     "#);
-    assert_eq!(Ok(Some(DataType::Number(10.0))), test_result.value);
+    assert_eq!(Ok(Value::Integer(10)), test_result.value);
 }
 
 #[test]
@@ -81,11 +92,7 @@ fn nested_lambda_test() {
     (define repeat (lambda (f) (lambda (x) (f (f x)))))
     repeat
     "#);
-        if let Ok(Some(DataType::Lambda(_))) = test_result.value {
-            assert!(true);
-        } else {
-            assert!(false);
-        }
+        assert!(matches!(test_result.value, Ok(Value::Lambda(_))));
     }
     {
         let test_result = run(r#"
@@ -95,7 +102,7 @@ fn nested_lambda_test() {
                      (+ x y)) x y) z)))
         (add3 2 3 4)
         "#);
-        assert_eq!(Ok(Some(DataType::Number(9.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(9)), test_result.value);
     }
 }
 
@@ -106,7 +113,7 @@ fn complex_lambda_test() {
     (define repeat (lambda (f) (lambda (x) (f (f x)))))
     ((repeat (repeat twice)) 10)
     "#);
-    assert_eq!(Ok(Some(DataType::Number(160.0))), test_result.value);
+    assert_eq!(Ok(Value::Integer(160)), test_result.value);
 }
 
 #[test]
@@ -117,13 +124,91 @@ fn tricky_test1 () {
 
     // function
     let test_result1 = run("((begin +))");
-    assert_eq!(Ok(Some(DataType::Number(0.0))), test_result1.value);
+    assert_eq!(Ok(Value::Integer(0)), test_result1.value);
 
     // lambda
     let env_ref = default_env();
     run_with_env("(define add (lambda () (+)))", env_ref.clone());
     let test_result0 = run_with_env("((begin add))", env_ref.clone());
-    assert_eq!(Ok(Some(DataType::Number(0.0))), test_result0.value);
+    assert_eq!(Ok(Value::Integer(0)), test_result0.value);
+}
+
+#[test]
+fn mutual_recursion_test() {
+    let env_ref = default_env();
+    run_with_env(r#"
+    (define even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+    (define odd? (lambda (n) (if (= n 0) #f (even? (- n 1)))))
+    "#, env_ref.clone());
+    assert_eq!(Ok(Value::Bool(true)), run_with_env("(even? 10)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Bool(true)), run_with_env("(odd? 7)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::list(vec![
+        Value::Bool(false),
+        Value::Bool(true),
+    ])), run_with_env("(map even? (list 3 4))", env_ref.clone()).value);
+
+    let test_result = run(r#"
+    (define total (+ 1 2))
+    (define bump (lambda (n) (+ n total)))
+    (bump 10)
+    "#);
+    assert_eq!(Ok(Value::Integer(13)), test_result.value);
+}
+
+#[test]
+fn letrec_test() {
+    {
+        let test_result = run(r#"
+        (letrec* ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                  (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+            (list (even? 10) (odd? 10)))
+        "#);
+        assert_eq!(Ok(Value::list(vec![
+            Value::Bool(true),
+            Value::Bool(false),
+        ])), test_result.value);
+    }
+    {
+        let test_result = run(r#"
+        (letrec ((fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1)))))))
+            (fact 5))
+        "#);
+        assert_eq!(Ok(Value::Integer(120)), test_result.value);
+    }
+    {
+        let test_result = run("(letrec* ((a 1) (b (+ a 1))) (+ a b))");
+        assert_eq!(Ok(Value::Integer(3)), test_result.value);
+    }
+    {
+        let env_ref = default_env();
+        run_with_env("(letrec* ((hidden 1)) hidden)", env_ref.clone());
+        assert_eq!(Err("symbol is not defined."), run_with_env("hidden", env_ref.clone()).value);
+    }
+    assert_eq!(Err("wrong syntax for letrec binding"), run("(letrec* ((a)) a)").value);
+    assert_eq!(Err("letrec binding name must be a symbol"), run("(letrec* ((1 2)) 1)").value);
+    assert_eq!(Err("wrong syntax for letrec expression"), run("(letrec* ((a 1)))").value);
+}
+
+#[test]
+fn dotted_pair_test() {
+    assert_eq!(Ok(Value::cons(Value::Integer(1), Value::Integer(2))), run("(quote (1 . 2))").value);
+    assert_eq!(Ok(Value::cons(
+        Value::Symbol("a".into()),
+        Value::cons(Value::Symbol("b".into()), Value::Symbol("c".into()))
+    )), run("(quote (a b . c))").value);
+    assert_eq!(Ok(Value::cons(
+        Value::Integer(1),
+        Value::list(vec![Value::Integer(2)])
+    )), run("(quote (1 . (2)))").value);
+    assert_eq!(Err("unexpected ."), run("(quote (1 . 2 3))").value);
+    assert_eq!(Err("unexpected ."), run("(quote (. 2))").value);
+    assert_eq!(Err("unexpected ."), run("(quote (1 . 2 . 3))").value);
+
+    let printed = |s: &str| run(s).value.unwrap().to_string();
+    assert_eq!("'(1 . 2)", printed("(cons 1 2)"));
+    assert_eq!("'(1 2 . 3)", printed("(cons 1 (cons 2 3))"));
+    assert_eq!("'(1 2 3)", printed("(cons 1 (cons 2 (list 3)))"));
+    assert_eq!("'(1 2 3 . 4)", printed("(append (list 1 2) (cons 3 4))"));
 }
 
 #[test]
@@ -133,35 +218,75 @@ fn state_test() {
     assert_eq!(Err("symbol is not defined."), test_result0.value);
 
     let test_result1 = run_with_env("(define s \"hello world\")", env_ref.clone());
-    assert_eq!(Ok(None), test_result1.value);
+    assert_eq!(Ok(Value::Unspecified), test_result1.value);
 
     let test_result2 = run_with_env("s", env_ref.clone());
-    assert_eq!(Ok(Some(DataType::String("hello world".to_string()))), test_result2.value);
+    assert_eq!(Ok(Value::String("hello world".into())), test_result2.value);
+}
+
+#[test]
+fn string_test() {
+    assert_eq!(Ok(Value::String("hello   (world)".into())), run("\"hello   (world)\"").value);
+    assert_eq!(Ok(Value::String("say \"hi\"\n\\".into())), run(r#""say \"hi\"\n\\""#).value);
+    assert_eq!(Ok(Value::String("".into())), run("\"\"").value);
+    assert_eq!(Err("unknown escape sequence in string"), run(r#""\q""#).value);
+    assert_eq!(r#""a \"b\"""#, run(r#""a \"b\"""#).value.unwrap().to_string());
+
+    assert_eq!(Ok(Value::Integer(5)), run("(string-length \"hello\")").value);
+    assert_eq!(Ok(Value::Integer(3)), run("(string-length \"a b\")").value);
+    assert_eq!(Err("string-length function requires an argument of type 'string'"), run("(string-length 1)").value);
+    assert_eq!(Err("string-length function requires one argument only"), run("(string-length)").value);
+
+    assert_eq!(Ok(Value::String("hello world".into())), run("(string-append \"hello\" \" \" \"world\")").value);
+    assert_eq!(Ok(Value::String("".into())), run("(string-append)").value);
+    assert_eq!(Err("string-append function requires arguments of type 'string'"), run("(string-append \"a\" 1)").value);
+    assert_eq!(Ok(Value::Bool(true)), run("(string? (string-append \"a\" \"b\"))").value);
+}
+
+#[test]
+fn char_test() {
+    assert_eq!(Ok(Value::Char('a')), run("#\\a").value);
+    assert_eq!(Ok(Value::Char(' ')), run("#\\space").value);
+    assert_eq!(Ok(Value::Char('\n')), run("#\\newline").value);
+    assert_eq!(Ok(Value::Char('(')), run("#\\(").value);
+    assert_eq!(Ok(Value::Char(')')), run("(car (list #\\)))").value);
+    assert_eq!(Err("unknown character name"), run("#\\foo").value);
+    assert_eq!("#\\space", run("#\\space").value.unwrap().to_string());
+    assert_eq!("#\\x", run("#\\x").value.unwrap().to_string());
+
+    assert_eq!(Ok(Value::Bool(true)), run("(char? #\\a)").value);
+    assert_eq!(Ok(Value::Bool(false)), run("(char? \"a\")").value);
+    assert_eq!(Ok(Value::Integer(97)), run("(char->integer #\\a)").value);
+    assert_eq!(Ok(Value::Integer(10)), run("(char->integer #\\newline)").value);
+    assert_eq!(Err("char->integer function requires an argument of type 'char'"), run("(char->integer 97)").value);
+    assert_eq!(Ok(Value::Char('A')), run("(integer->char 65)").value);
+    assert_eq!(Ok(Value::Char('λ')), run("(integer->char (char->integer #\\λ))").value);
+    assert_eq!(Err("integer->char function requires a valid unicode scalar value"), run("(integer->char 55296)").value);
+    assert_eq!(Err("integer->char function requires an argument of type 'non-negative integer'"), run("(integer->char -1)").value);
+
+    let env_ref = default_env();
+    run_with_env("(define c #\\z)", env_ref.clone());
+    assert_eq!(Ok(Value::Char('z')), run_with_env("c", env_ref.clone()).value);
 }
 
 #[test]
 fn type_test() {
-    assert_eq!(Ok(Some(DataType::String("hello world".into()))), run("\"hello world\"").value);
+    assert_eq!(Ok(Value::String("hello world".into())), run("\"hello world\"").value);
     assert_eq!(Err("can not find an end quote"), run("\"hello world").value);
-    assert_eq!(Ok(Some(DataType::Number(1.0))), run("1").value);
-    assert_eq!(Ok(Some(DataType::Number(3.9))), run("3.9").value);
-    assert_eq!(Ok(Some(DataType::Symbol("foo".into()))), run("'foo").value);
-    assert_eq!(Ok(Some(DataType::Bool(true))), run("#t").value);
+    assert_eq!(Ok(Value::Integer(1)), run("1").value);
+    assert_eq!(Ok(Value::Number(3.9)), run("3.9").value);
+    assert_eq!(Ok(Value::Symbol("foo".into())), run("'foo").value);
+    assert_eq!(Ok(Value::Bool(true)), run("#t").value);
     assert_eq!(Err("syntax error"), run("#tt").value);
-    assert_eq!(Ok(Some(DataType::Pair(
-        (
-            Box::new(DataType::Number(1.0)),
-            Box::new(DataType::Number(2.0))
-        )
-    ))), run("(cons 1 2)").value);
-    assert_eq!(Ok(Some(DataType::List(vec![
-        DataType::Symbol("aa".into()),
-        DataType::Symbol("bbb".into()),
-        DataType::Symbol("cccc".into()),
+    assert_eq!(Ok(Value::cons(Value::Integer(1), Value::Integer(2))), run("(cons 1 2)").value);
+    assert_eq!(Ok(Value::list(vec![
+        Value::Symbol("aa".into()),
+        Value::Symbol("bbb".into()),
+        Value::Symbol("cccc".into()),
     ]
-    ))), run("(list 'aa 'bbb 'cccc)").value);
-    if let Ok(Some(DataType::Proc(_))) = run("+").value { assert!(true) } else { assert!(false) }
-    if let Ok(Some(DataType::Lambda(_))) = run("(lambda ()(print \"something\"))").value { assert!(true) } else { assert!(false) }
+    )), run("(list 'aa 'bbb 'cccc)").value);
+    assert!(matches!(run("+").value, Ok(Value::Proc(_))));
+    assert!(matches!(run("(lambda ()(print \"something\"))").value, Ok(Value::Lambda(_))));
 }
 
 mod op {
@@ -170,13 +295,13 @@ mod op {
     #[test]
     fn stmt1() {
         let test_result = run("(+ 1 2 3 (+ 4 5) 6)");
-        assert_eq!(Ok(Some(DataType::Number(21.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(21)), test_result.value);
     }
 
     #[test]
     fn stmt2() {
         let test_result = run("(- (/ (* 1 2 3 4 5) 6) 7)");
-        assert_eq!(Ok(Some(DataType::Number(13.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(13)), test_result.value);
     }
 }
 
@@ -186,183 +311,367 @@ mod std_function {
     #[test]
     fn list() {
         let test_result = run("(list 0 1 2 3 0 0)");
-        assert_eq!(Ok(Some(DataType::List(vec![
-            DataType::Number(0.0),
-            DataType::Number(1.0),
-            DataType::Number(2.0),
-            DataType::Number(3.0),
-            DataType::Number(0.0),
-            DataType::Number(0.0)
-        ]))), test_result.value);
+        assert_eq!(Ok(Value::list(vec![
+            Value::Integer(0),
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::Integer(3),
+            Value::Integer(0),
+            Value::Integer(0)
+        ])), test_result.value);
     }
 
     #[test]
     fn car() {
         let test_result = run("(car (list 0 1 2 3 0 0))");
-        assert_eq!(Ok(Some(DataType::Number(0.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(0)), test_result.value);
     }
 
     #[test]
     fn cdr() {
         let test_result = run("(cdr (cdr (list 0 1 2 3 0 0)))");
-        assert_eq!(Ok(Some(DataType::List(vec![
-            DataType::Number(2.0),
-            DataType::Number(3.0),
-            DataType::Number(0.0),
-            DataType::Number(0.0)
-        ]))), test_result.value);
+        assert_eq!(Ok(Value::list(vec![
+            Value::Integer(2),
+            Value::Integer(3),
+            Value::Integer(0),
+            Value::Integer(0)
+        ])), test_result.value);
+    }
+
+    #[test]
+    fn shared_list_tails() {
+        let env_ref = default_env();
+        run_with_env("(define l (list 1 2 3 4))", env_ref.clone());
+        let tails = ["(cdr l)", "(list-tail l 1)", "(memv 2 l)"].iter()
+            .map(|source| match run_with_env(source, env_ref.clone()).value {
+                Ok(Value::Pair(pair)) => pair,
+                value => panic!("{}: {:?}", source, value)
+            })
+            .collect::<Vec<Rc<Pair>>>();
+        assert!(Rc::ptr_eq(&tails[0], &tails[1]) && Rc::ptr_eq(&tails[1], &tails[2]));
+        assert_eq!(Ok(Value::Bool(true)), run_with_env("(eq? (cdr l) (cdr l))", env_ref.clone()).value);
+        assert_eq!("'(3 4)", run_with_env("(cdr (cdr l))", env_ref.clone()).value.unwrap().to_string());
+        assert_eq!(Ok(Value::Nil), run_with_env("(list-tail l 4)", env_ref.clone()).value);
+
+        // the lists of programs share their tails too, and changing a copy leaves the
+        // list it was copied from as it was
+        let tails = match parse_datum("(1 2 3 4)").unwrap().0 {
+            Value::List(list) => [list.tail(1).unwrap(), list.tail(1).unwrap()],
+            value => panic!("{:?}", value)
+        };
+        assert!(tails[0].ptr_eq(&tails[1]));
+        let mut copy = tails[0].clone();
+        copy[0] = Value::Integer(20);
+        assert_eq!((Value::Integer(20), Value::Integer(2)), (copy[0].clone(), tails[0][0].clone()));
+        assert_eq!(vec![Value::Integer(2), Value::Integer(3), Value::Integer(4)], tails[1].clone().into_vec());
     }
 
     #[test]
     fn cons() {
-        assert_eq!(Ok(Some(DataType::Pair(
-            (
-                Box::new(DataType::Number(1.0)),
-                Box::new(DataType::Number(2.0))
-            )
-        ))), run("(cons 1 2)").value);
+        assert_eq!(Ok(Value::cons(Value::Integer(1), Value::Integer(2))), run("(cons 1 2)").value);
         assert_eq!(Err("cons function requires two argument only"), run("(cons 'a)").value);
-
+        assert_eq!(Ok(Value::Integer(1)), run("(car (cons 1 2))").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(cdr (cons 1 2))").value);
+        assert_eq!(Ok(Value::cons(
+            Value::Integer(0),
+            Value::list(vec![Value::Integer(1), Value::Integer(2)])
+        )), run("(cons 0 (list 1 2))").value);
+        assert_eq!(Ok(Value::Integer(3)), run("(length (cons 0 (list 1 2)))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(list? (cons 0 (cons 1 (list))))").value);
+        assert_eq!(Ok(Value::Integer(3)), run("(apply + (cons 1 (cons 2 (list))))").value);
     }
 
     #[test]
     fn abs() {
         let test_result = run("(abs -42)");
-        assert_eq!(Ok(Some(DataType::Number(42.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(42)), test_result.value);
     }
 
     #[test]
     fn append() {
-        assert_eq!(Ok(Some(DataType::List(vec![
-            DataType::Number(1.0),
-            DataType::Number(2.0),
-            DataType::Number(3.0),
-            DataType::Number(4.0),
-            DataType::Number(5.0)
-        ]))), run("(append (list 1 2 3) (list 4 5))").value);
-
-        assert_eq!(Ok(Some(
-            DataType::Pair(
-                (
-                    Box::new(
-                        DataType::List(vec![
-                            DataType::Number(1.0),
-                            DataType::Number(2.0),
-                            DataType::Number(3.0),
-                        ])
-                    ),
-                    Box::new(DataType::Number(4.0))
-                )
-            )
-        )), run("(append (list 1 2 3) 4)").value);
-
-        assert_eq!(Ok(Some(
-            DataType::Pair(
-                (
-                    Box::new(
-                        DataType::List(vec![
-                            DataType::Number(1.0),
-                            DataType::Number(2.0),
-                            DataType::Number(3.0),
-                            DataType::Number(4.0),
-
-                        ])
-                    ),
-                    Box::new(DataType::Bool(false))
-                )
-            )
-        )), run("(append (list 1 2 3 4) #f)").value);
-
-        assert_eq!(Ok(Some(
-            DataType::Pair(
-                (
-                    Box::new(
-                        DataType::List(vec![
-                            DataType::Number(1.0),
-                            DataType::Number(2.0)
-                        ])
-                    ),
-                    Box::new(DataType::String("hello".into()))
-                )
-            )
-        )), run("(append (list 1 2) \"hello\")").value);
-
-        assert_eq!(Ok(Some(
-            DataType::Pair(
-                (
-                    Box::new(
-                        DataType::List(vec![
-                            DataType::Number(1.0),
-                            DataType::Number(2.0),
-                            DataType::Number(3.0),
-                        ])
-                    ),
-                    Box::new(DataType::Symbol("world".into()))
-                )
-            )
-        )), run("(append (list 1 2 3) 'world)").value);
+        assert_eq!(Ok(Value::list(vec![
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::Integer(3),
+            Value::Integer(4),
+            Value::Integer(5)
+        ])), run("(append (list 1 2 3) (list 4 5))").value);
+
+        assert_eq!(Ok(
+            Value::cons(Value::Integer(1),
+                Value::cons(Value::Integer(2),
+                    Value::cons(Value::Integer(3), Value::Integer(4))))
+        ), run("(append (list 1 2 3) 4)").value);
+
+        assert_eq!(Ok(
+            Value::cons(Value::Integer(1),
+                Value::cons(Value::Integer(2),
+                    Value::cons(Value::Integer(3),
+                        Value::cons(Value::Integer(4), Value::Bool(false)))))
+        ), run("(append (list 1 2 3 4) #f)").value);
+
+        assert_eq!(Ok(
+            Value::cons(Value::Integer(1),
+                Value::cons(Value::Integer(2), Value::String("hello".into())))
+        ), run("(append (list 1 2) \"hello\")").value);
+
+        assert_eq!(Ok(
+            Value::cons(Value::Integer(1),
+                Value::cons(Value::Integer(2),
+                    Value::cons(Value::Integer(3), Value::Symbol("world".into()))))
+        ), run("(append (list 1 2 3) 'world)").value);
+
+        assert_eq!(Ok(
+            Value::cons(Value::Integer(1),
+                Value::cons(Value::Integer(2), Value::Integer(3)))
+        ), run("(append (list 1) (cons 2 3))").value);
+
+        assert_eq!(Ok(Value::Integer(1)), run("(append 1)").value);
+        assert_eq!(Ok(Value::Nil), run("(append)").value);
+        assert_eq!(Err("append function requires an argument of type 'list'"), run("(append 1 (list 2))").value);
 
         // TODO: test append with procedure and lambda
     }
 
+    #[test]
+    fn associations() {
+        let env_ref = default_env();
+        run_with_env(r#"
+        (define e (list (list (quote a) 1) (cons (quote b) 2) (cons 3 (quote c)) (cons (list 1) (quote d))))
+        (define n (cons 1 (cons 2.5 (cons (list 1) (list)))))
+        "#, env_ref.clone());
+        let run_a = |s: &str| run_with_env(s, env_ref.clone()).value;
+
+        assert_eq!(Ok(Value::cons(Value::Symbol("b".into()), Value::Integer(2))), run_a("(assq (quote b) e)"));
+        assert_eq!(Ok(Value::cons(Value::Integer(3), Value::Symbol("c".into()))), run_a("(assv 3 e)"));
+        assert_eq!(Ok(Value::Bool(false)), run_a("(assq (quote z) e)"));
+        assert_eq!(Ok(Value::cons(Value::list(vec![Value::Integer(1)]), Value::Symbol("d".into()))), run_a("(assoc (list 1) e)"));
+        assert_eq!(Ok(Value::list(vec![Value::Symbol("a".into()), Value::Integer(1)])), run_a("(assoc 2.0 e (lambda (a b) (symbol? b)))"));
+        assert_eq!(Err("assq function requires an argument of type 'list'"), run_a("(assq 1 (list 1 2))"));
+
+        assert_eq!(Ok(Value::Bool(false)), run_a("(memq 2.5 n)"));
+        assert_eq!(Ok(Value::Integer(3)), run_a("(length (memq 1 n))"));
+        assert_eq!(Ok(Value::Integer(2)), run_a("(length (memv 2.5 n))"));
+        assert_eq!(Ok(Value::Integer(1)), run_a("(length (member (list 1) n))"));
+        assert_eq!(Ok(Value::list(vec![Value::Integer(3), Value::Integer(4)])), run_a("(member 2 (list 1 3 4) <)"));
+        assert_eq!(Ok(Value::Bool(false)), run_a("(member 5 (list))"));
+        assert_eq!(Err("member function requires an argument of type 'list'"), run_a("(member 5 6)"));
+    }
+
+    #[test]
+    fn sorting() {
+        let run_s = |s: &str| run(s).value.unwrap().to_string();
+        assert_eq!("'(1 2 3 5 8)", run_s("(sort (list 5 3 8 1 2) <)"));
+        assert_eq!("'(8 5 3 2 1)", run_s("(list-sort > (cons 5 (cons 3 (list 8 1 2))))"));
+        assert_eq!("#(1 2 3)", run_s("(sort (vector 3 1 2) <)"));
+        assert_eq!(Ok(Value::Nil), run("(sort (list) <)").value);
+        // elements that compare equal keep their order
+        assert_eq!("'(20 40 10 30)", run_s("(map cdr (sort (list (cons 1 10) (cons 0 20) (cons 1 30) (cons 0 40)) (lambda (x y) (< (car x) (car y)))))"));
+
+        let env_ref = default_env();
+        run_with_env("(define v (vector 4 2 9 2))", env_ref.clone());
+        assert_eq!(Ok(Value::Unspecified), run_with_env("(vector-sort! v <)", env_ref.clone()).value);
+        assert_eq!("#(2 2 4 9)", run_with_env("v", env_ref.clone()).value.unwrap().to_string());
+
+        assert_eq!(Err("function requires at least 2 arguments"), run("(sort (list 2 1) (lambda (x y) (< x)))").value);
+        assert_eq!(Err("sort function requires an argument of type 'list' / 'vector'"), run("(sort 1 <)").value);
+        assert_eq!(Err("vector-sort! function requires a vector and a procedure"), run("(vector-sort! (list 1) <)").value);
+    }
+
+    #[test]
+    fn sequence_conversions() {
+        let chars = |s: &str| s.chars().map(Value::Char).collect::<Vec<Value>>();
+        assert_eq!(Ok(Value::list(chars("abc"))), run("(string->list \"abc\")").value);
+        assert_eq!(Ok(Value::list(chars("bc"))), run("(string->list \"abc\" 1)").value);
+        assert_eq!(Ok(Value::list(chars("é"))), run("(string->list \"héllo\" 1 2)").value);
+        assert_eq!(Ok(Value::Nil), run("(string->list \"\")").value);
+        assert_eq!(Ok(Value::String("hi".into())), run("(list->string (list #\\h #\\i))").value);
+        assert_eq!(Ok(Value::String("".into())), run("(list->string (list))").value);
+        assert_eq!(Err("list->string function requires a list of characters"), run("(list->string (list 1))").value);
+
+        assert_eq!("#(1 2 3)", run("(list->vector (cons 1 (list 2 3)))").value.unwrap().to_string());
+        assert_eq!("'(2 3)", run("(vector->list (vector 1 2 3) 1 3)").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Nil), run("(vector->list (vector))").value);
+        assert_eq!(Err("vector->list range out of bounds"), run("(vector->list (vector 1 2) 1 3)").value);
+        assert_eq!(Err("vector->list range out of bounds"), run("(vector->list (vector 1 2) 2 1)").value);
+
+        assert_eq!(Ok(Value::String("ab".into())), run("(vector->string (string->vector \"cab\" 1))").value);
+        assert_eq!(Err("vector->string function requires a vector of characters"), run("(vector->string (vector 1))").value);
+        assert_eq!(Err("string->vector function requires an argument of type 'string'"), run("(string->vector (quote a))").value);
+    }
+
+    #[test]
+    fn number_strings() {
+        let string = |s: &str| Ok(Value::String(s.into()));
+        assert_eq!(string("255"), run("(number->string 255)").value);
+        assert_eq!(string("ff"), run("(number->string 255 16)").value);
+        assert_eq!(string("-101"), run("(number->string -5 2)").value);
+        assert_eq!(string("17"), run("(number->string 15 8)").value);
+        assert_eq!(string("1/3"), run("(number->string (/ 1 3))").value);
+        assert_eq!(string("1/11"), run("(number->string (/ 1 3) 2)").value);
+        assert_eq!(string("2.5"), run("(number->string 2.5)").value);
+        assert_eq!(string("100000000000000000000"), run("(number->string (* 10000000000 10000000000) 10)").value);
+        assert_eq!(Err("number->string can only write inexact numbers in radix 10"), run("(number->string 2.5 2)").value);
+        assert_eq!(Err("number->string radix must be 2, 8, 10 or 16"), run("(number->string 10 3)").value);
+        assert_eq!(Err("number->string function requires an argument of type 'number'"), run("(number->string \"1\")").value);
+
+        assert_eq!(Ok(Value::Integer(255)), run("(string->number \"255\")").value);
+        assert_eq!(Ok(Value::Integer(255)), run("(string->number \"ff\" 16)").value);
+        assert_eq!(Ok(Value::Integer(-5)), run("(string->number \"-101\" 2)").value);
+        assert_eq!(Ok(Value::Number(2.5)), run("(string->number \"2.5\")").value);
+        assert_eq!(Ok(Value::Number(1500.0)), run("(string->number \"1.5e3\")").value);
+        assert_eq!(Ok(Value::Integer(3)), run("(string->number \"#e3.0\")").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(= (string->number \"2/6\") (/ 1 3))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(= (string->number (number->string 123456789 16) 16) 123456789)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"12\" 2)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"2.5\" 16)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"1/0\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"inf\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"1_000\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"abc\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"\")").value);
+        assert_eq!(Err("string->number radix must be 2, 8, 10 or 16"), run("(string->number \"1\" 7)").value);
+    }
+
+    #[test]
+    fn strings() {
+        let string = |s: &str| Ok(Value::String(s.into()));
+        let strings = |l: &[&str]| Ok(Value::list(l.iter().map(|s| Value::String((*s).into())).collect()));
+        assert_eq!(string("llo"), run("(substring \"héllo\" 2)").value);
+        assert_eq!(string("él"), run("(substring \"héllo\" 1 3)").value);
+        assert_eq!(Err("substring range out of bounds"), run("(substring \"abc\" 2 4)").value);
+        assert_eq!(Ok(Value::Char('é')), run("(string-ref \"héllo\" 1)").value);
+        assert_eq!(Err("string-ref index out of range"), run("(string-ref \"abc\" 3)").value);
+        assert_eq!(string("HÉLLO"), run("(string-upcase \"héllo\")").value);
+        assert_eq!(string("straße"), run("(string-downcase \"STRAßE\")").value);
+
+        assert_eq!(strings(&["a", "b", "c"]), run("(string-split \"  a b\tc \")").value);
+        assert_eq!(strings(&["a", "", "b"]), run("(string-split \"a,,b\" #\\,)").value);
+        assert_eq!(strings(&["a", "b"]), run("(string-split \"a::b\" \"::\")").value);
+        assert_eq!(string("a b c"), run("(string-join (list \"a\" \"b\" \"c\"))").value);
+        assert_eq!(string("a, b"), run("(string-join (string-split \"a b\") \", \")").value);
+        assert_eq!(Err("string-join function requires a list of strings"), run("(string-join (list 1))").value);
+
+        assert_eq!(Ok(Value::Integer(2)), run("(string-index \"héllo\" #\\l)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(string-index \"ab1\" (lambda (c) (eqv? c #\\1)))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string-index \"abc\" #\\z)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(string-contains \"héllo\" \"ll\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string-contains \"hello\" \"z\")").value);
+
+        assert_eq!(Ok(Value::Bool(true)), run("(string=? \"a\" \"a\" \"a\")").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(string<? \"a\" \"ab\" \"b\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string<? \"b\" \"a\")").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(string>=? \"b\" \"b\" \"a\")").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(string<=? \"a\" \"a\")").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string>? \"a\" \"a\")").value);
+        assert_eq!(Err("string comparison requires arguments of type 'string'"), run("(string=? \"a\" 1)").value);
+    }
+
+    #[test]
+    fn characters() {
+        let t = Ok(Value::Bool(true));
+        let f = Ok(Value::Bool(false));
+        assert_eq!(t, run("(char-alphabetic? #\\é)").value);
+        assert_eq!(f, run("(char-alphabetic? #\\1)").value);
+        assert_eq!(t, run("(char-numeric? #\\7)").value);
+        assert_eq!(f, run("(char-numeric? #\\x)").value);
+        assert_eq!(t, run("(char-whitespace? #\\space)").value);
+        assert_eq!(t, run("(char-whitespace? #\\newline)").value);
+        assert_eq!(f, run("(char-whitespace? #\\a)").value);
+        assert_eq!(Err("char-numeric? function requires an argument of type 'char'"), run("(char-numeric? 1)").value);
+
+        assert_eq!(Ok(Value::Char('A')), run("(char-upcase #\\a)").value);
+        assert_eq!(Ok(Value::Char('é')), run("(char-downcase #\\É)").value);
+        assert_eq!(Ok(Value::Char('1')), run("(char-upcase #\\1)").value);
+        // the upper case of ß is two characters
+        assert_eq!(Ok(Value::Char('ß')), run("(char-upcase #\\ß)").value);
+
+        assert_eq!(t, run("(char<? #\\a #\\b #\\c)").value);
+        assert_eq!(f, run("(char<? #\\a #\\c #\\b)").value);
+        assert_eq!(t, run("(char=? #\\a #\\a)").value);
+        assert_eq!(t, run("(char>=? #\\b #\\a #\\a)").value);
+        assert_eq!(t, run("(char<=? #\\a #\\a)").value);
+        assert_eq!(f, run("(char>? #\\a #\\b)").value);
+        assert_eq!(Err("character comparison requires arguments of type 'char'"), run("(char=? #\\a \"a\")").value);
+    }
+
+    #[test]
+    fn list_operations() {
+        let run_s = |s: &str| run(s).value.unwrap().to_string();
+        assert_eq!("'(3 2 1)", run_s("(reverse (list 1 2 3))"));
+        assert_eq!("'(3 2 1)", run_s("(reverse (cons 1 (cons 2 (list 3))))"));
+        assert_eq!(Ok(Value::Nil), run("(reverse (list))").value);
+        assert_eq!(Err("reverse function requires an argument of type 'list'"), run("(reverse (cons 1 2))").value);
+
+        assert_eq!(Ok(Value::Integer(3)), run("(list-ref (list 1 2 3) 2)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(list-ref (cons 1 (cons 2 (list 3))) 1)").value);
+        assert_eq!(Err("list-ref index out of range"), run("(list-ref (list 1 2 3) 3)").value);
+        assert_eq!(Err("list-ref function requires an index of type 'non-negative integer'"), run("(list-ref (list 1) -1)").value);
+
+        assert_eq!("'(2 3)", run_s("(list-tail (list 1 2 3) 1)"));
+        assert_eq!(Ok(Value::Nil), run("(list-tail (list 1 2 3) 3)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(list-tail (cons 1 2) 1)").value);
+        assert_eq!(Err("list-tail index out of range"), run("(list-tail (list 1 2) 3)").value);
+        // the tail shares its pairs with the list
+        let env_ref = default_env();
+        run_with_env("(define l (cons 1 (cons 2 (cons 3 (list)))))", env_ref.clone());
+        run_with_env("(set-car! (list-tail l 1) 20)", env_ref.clone());
+        assert_eq!("'(1 20 3)", run_with_env("l", env_ref.clone()).value.unwrap().to_string());
+    }
+
     #[test]
     fn apply() {
         {
             let test_result = run("(apply * (list 7 9))");
-            assert_eq!(Ok(Some(DataType::Number(63.0))), test_result.value);
+            assert_eq!(Ok(Value::Integer(63)), test_result.value);
         }
         {
             let test_result = run("(apply (lambda (x y)(* x y)) (list 7 9))");
-            assert_eq!(Ok(Some(DataType::Number(63.0))), test_result.value);
+            assert_eq!(Ok(Value::Integer(63)), test_result.value);
+        }
+        {
+            let test_result = run("(apply + 1)");
+            assert_eq!(Err("apply function requires a last argument of type 'list'"), test_result.value);
+            assert_eq!(Err("apply function requires two arguments"), run("(apply +)").value);
         }
     }
 
     #[test]
     fn length() {
         let test_result = run("(length (list 7 9 4 0 3))");
-        assert_eq!(Ok(Some(DataType::Number(5.0))), test_result.value);
+        assert_eq!(Ok(Value::Integer(5)), test_result.value);
     }
 
     #[test]
     fn map() {
-        assert_eq!(Ok(Some(
-            DataType::List(vec![
-                DataType::Bool(false),
-                DataType::Bool(false),
-                DataType::Bool(true),
-                DataType::Bool(false),
-                DataType::Bool(false),
-                DataType::Bool(true),
-                DataType::Bool(false),
+        assert_eq!(Ok(
+            Value::list(vec![
+                Value::Bool(false),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(false),
             ])
-        )), run("(map number? (list #t \"hello\" 3 's - 2.1 (lambda () (+ 1 2)) ))").value);
-
-        assert_eq!(Ok(Some(
-            DataType::List(vec![
-                DataType::Number(1.0),
-                DataType::Number(4.0),
-                DataType::Number(9.0),
-                DataType::Number(16.0),
-                DataType::Number(25.0),
+        ), run("(map number? (list #t \"hello\" 3 's - 2.1 (lambda () (+ 1 2)) ))").value);
+
+        assert_eq!(Ok(
+            Value::list(vec![
+                Value::Integer(1),
+                Value::Integer(4),
+                Value::Integer(9),
+                Value::Integer(16),
+                Value::Integer(25),
             ])
-        )), run("(map (lambda (x) (* x x)) (list 1 2 3 4 5))").value);
-
-        assert_eq!(Ok(Some(
-            DataType::List(vec![
-                DataType::Pair(
-                    (
-                        Box::new(DataType::Number(2.0)),
-                        Box::new(DataType::Number(1.0))
-                    )
-                ),
-                DataType::Pair(
-                    (
-                        Box::new(DataType::Number(4.0)),
-                        Box::new(DataType::Number(3.0))
-                    )
-                )
+        ), run("(map (lambda (x) (* x x)) (list 1 2 3 4 5))").value);
+
+        assert_eq!(Ok(
+            Value::list(vec![
+                Value::cons(Value::Integer(2), Value::Integer(1)),
+                Value::cons(Value::Integer(4), Value::Integer(3))
             ])
-        )), run(r#"(map (lambda (x)
+        ), run(r#"(map (lambda (x)
                                    (cons (car (cdr x))
                                    (car x) ))
                            (list (list 1 2) (list 3 4)))"#).value);
@@ -371,32 +680,106 @@ mod std_function {
             let env_ref = default_env();
             run_with_env("(define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))", env_ref.clone());
 
-            assert_eq!(Ok(Some(DataType::List(
+            assert_eq!(Ok(Value::list(
                 vec![
-                    DataType::Number(1.0),
-                    DataType::Number(1.0),
-                    DataType::Number(2.0),
-                    DataType::Number(3.0),
-                    DataType::Number(5.0),
-                    DataType::Number(8.0),
-                    DataType::Number(13.0),
-                    DataType::Number(21.0),
-                    DataType::Number(34.0),
-                    DataType::Number(55.0)
+                    Value::Integer(1),
+                    Value::Integer(1),
+                    Value::Integer(2),
+                    Value::Integer(3),
+                    Value::Integer(5),
+                    Value::Integer(8),
+                    Value::Integer(13),
+                    Value::Integer(21),
+                    Value::Integer(34),
+                    Value::Integer(55)
                 ]
-            ))), run_with_env("(map fib (list 0 1 2 3 4 5 6 7 8 9))", env_ref.clone()).value);
+            )), run_with_env("(map fib (list 0 1 2 3 4 5 6 7 8 9))", env_ref.clone()).value);
         }
+
+        // several lists are walked together until the shortest one ends
+        assert_eq!("'(11 22)", run("(map + (list 1 2 3) (list 10 20))").value.unwrap().to_string());
+        assert_eq!(Ok(Value::list(vec![
+            Value::cons(Value::Integer(1), Value::Symbol("a".into())),
+            Value::cons(Value::Integer(2), Value::Symbol("b".into()))
+        ])), run("(map cons (list 1 2) '(a b))").value);
+        assert_eq!(Ok(Value::Nil), run("(map car (list))").value);
+        assert_eq!(Err("car function requires an argument of type 'list' / 'pair'"), run("(map car (list 1))").value);
+        assert_eq!(Err("map function requires arguments of type 'list'"), run("(map + (list 1) 2)").value);
+        assert_eq!(Err("map function requires a procedure and at least one list"), run("(map 1 (list 1))").value);
+    }
+
+    #[test]
+    fn folds() {
+        let run_s = |s: &str| run(s).value.unwrap().to_string();
+        assert_eq!("'(3 4)", run_s("(filter (lambda (x) (> x 2)) (list 1 2 3 4))"));
+        assert_eq!("'(0 1 2)", run_s("(filter (lambda (x) (car (list x))) (list 0 1 2))"));
+        assert_eq!(Ok(Value::Nil), run("(filter number? (list (quote a)))").value);
+        assert_eq!(Err("filter function requires an argument of type 'list'"), run("(filter number? 1)").value);
+
+        assert_eq!("'(3 2 1)", run_s("(fold-left (lambda (acc x) (cons x acc)) (list) (list 1 2 3))"));
+        assert_eq!("'(1 2 3)", run_s("(fold-right cons (list) (list 1 2 3))"));
+        assert_eq!(Ok(Value::Integer(-6)), run("(fold-left - 0 (list 1 2 3))").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(fold-right - 0 (list 1 2 3))").value);
+        assert_eq!(Ok(Value::Integer(14)), run("(fold-left (lambda (acc x y) (+ acc (* x y))) 0 (list 1 2 3) (list 4 5))").value);
+        assert_eq!(Ok(Value::Integer(7)), run("(fold-left + 7 (list))").value);
+
+        assert_eq!(Ok(Value::Integer(10)), run("(reduce + 0 (list 1 2 3 4))").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(reduce - 0 (list 1 2 3))").value);
+        assert_eq!(Ok(Value::Integer(0)), run("(reduce + 0 (list))").value);
+        assert_eq!(Err("reduce function requires a procedure, an identity value and a list"), run("(reduce 1 0 (list))").value);
+    }
+
+    #[test]
+    fn prelude() {
+        let run_s = |s: &str| run(s).value.unwrap().to_string();
+        assert_eq!(Ok(Value::Integer(2)), run("(cadr (list 1 2 3))").value);
+        assert_eq!("'(3)", run_s("(cddr (list 1 2 3))"));
+        assert_eq!(Ok(Value::Integer(3)), run("(caddr (list 1 2 3))").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(caar (list (list 1) 2))").value);
+        assert_eq!(Ok(Value::Integer(3)), run("(last (list 1 2 3))").value);
+        assert_eq!("'(3 . 4)", run_s("(last-pair (cons 1 (cons 3 4)))"));
+        assert_eq!("'(0 1 2 3)", run_s("(iota 4)"));
+        assert_eq!(Ok(Value::Nil), run("(iota 0)").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(list-index even? (list 1 2 3))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(list-index even? (list 1 3))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(any even? (list 1 2))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(any even? (list))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(every odd? (list))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(every odd? (list 1 2))").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(count odd? (list 1 2 3))").value);
+        assert_eq!("'(1 3)", run_s("(delete 2 (list 1 2 3 2))"));
+        assert_eq!("'(20 40)", run_s("(filter-map (lambda (x) (and (even? x) (* 10 x))) (list 1 2 3 4))"));
+        assert_eq!("'(1 1 2 2)", run_s("(append-map (lambda (x) (list x x)) (list 1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run("(not #f)").value);
+
+        // they are procedures like any other, named and with an arity
+        assert_eq!(Ok(Value::Symbol("cadr".into())), run("(procedure-name cadr)").value);
+        assert_eq!(Err("car function requires a non-empty list"), run("(cadr (list 1))").value);
+        assert!(run("(cadr 1 2)").value.is_err());
+        // and the definitions of a program change neither them nor the builtins they use
+        let env_ref = default_env();
+        run_with_env("(define car cdr) (define (cadr x) x)", env_ref.clone());
+        assert_eq!(Ok(Value::Integer(3)), run_with_env("(caddr (list 1 2 3))", env_ref.clone()).value);
+    }
+
+    #[test]
+    fn for_each() {
+        let env_ref = default_env();
+        run_with_env("(define v (make-vector 3 0))", env_ref.clone());
+        assert_eq!(Ok(Value::Unspecified), run_with_env("(for-each (lambda (i x) (vector-set! v i x)) (list 0 1 2) (list 7 8 9 10))", env_ref.clone()).value);
+        assert_eq!("#(7 8 9)", run_with_env("v", env_ref.clone()).value.unwrap().to_string());
+        assert_eq!(Err("for-each function requires a procedure and at least one list"), run("(for-each car)").value);
     }
 
     #[test]
     fn max_min() {
         {
             let test_result = run("(max 7 9 4 0 3)");
-            assert_eq!(Ok(Some(DataType::Number(9.0))), test_result.value);
+            assert_eq!(Ok(Value::Integer(9)), test_result.value);
         }
         {
             let test_result = run("(min 7 9 4 0 3)");
-            assert_eq!(Ok(Some(DataType::Number(0.0))), test_result.value);
+            assert_eq!(Ok(Value::Integer(0)), test_result.value);
         }
     }
 
@@ -404,16 +787,82 @@ mod std_function {
     fn not() {
         {
             let test_result = run("(not #t)");
-            assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+            assert_eq!(Ok(Value::Bool(false)), test_result.value);
         }
         {
             let test_result = run("(not #f)");
-            assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+            assert_eq!(Ok(Value::Bool(true)), test_result.value);
         }
         {
             let test_result = run("(not 1)");
-            assert_eq!(Err("not function requires an argument of type 'boolean'"), test_result.value);
+            assert_eq!(Ok(Value::Bool(false)), test_result.value);
         }
+        {
+            let test_result = run("(not (list))");
+            assert_eq!(Ok(Value::Bool(false)), test_result.value);
+        }
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(Ok(Value::Bool(true)), run("(boolean? #f)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(boolean? 0)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(boolean=? #t #t #t)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(boolean=? #f #f #t)").value);
+        assert_eq!(Err("boolean=? function requires arguments of type 'boolean'"), run("(boolean=? #t 1)").value);
+        assert_eq!(Err("boolean=? function requires at least two arguments"), run("(boolean=? #t)").value);
+    }
+
+    #[test]
+    fn truthiness() {
+        assert_eq!(Ok(Value::Integer(1)), run("(if 0 1 2)").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(if (list) 1 2)").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(if \"\" 1 2)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(if #f 1 2)").value);
+    }
+
+    #[test]
+    fn equality() {
+        let env_ref = default_env();
+        run_with_env(r#"
+        (define p (cons 1 2))
+        (define v (vector 1 2))
+        (define l (list 1 2))
+        "#, env_ref.clone());
+        let run_e = |s: &str| run_with_env(s, env_ref.clone()).value;
+
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? p p)"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eq? p (cons 1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? v v)"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eq? v (vector 1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? l l)"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? (cdr l) (cdr l))"));
+        // fresh lists are equal without being the same list
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eq? (list 1) (list 1))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eqv? l (list 1 2))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eq? '(1 2) '(1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(equal? l (list 1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? (quote a) (quote a))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? car car)"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eq? 7 7)"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eq? 2.5 2.5)"));
+
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eqv? 2.5 2.5)"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eqv? (/ 1 3) (/ 2 6))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eqv? (factorial 30) (factorial 30))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eqv? 2 2.0)"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eqv? 0.0 -0.0)"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(eqv? #\\a #\\a)"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(eqv? (string-append \"a\") (string-append \"a\"))"));
+
+        assert_eq!(Ok(Value::Bool(true)), run_e("(equal? (string-append \"a\") (string-append \"a\"))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(equal? v (vector 1 2))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(equal? l (cons 1 (cons 2 (list))))"));
+        assert_eq!(Ok(Value::Bool(true)), run_e("(equal? (cons 1 (cons (vector l) 3)) (cons 1 (cons (vector (list 1 2)) 3)))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(equal? l (list 1 2 3))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(equal? (cons 1 2) (list 1 2))"));
+        assert_eq!(Ok(Value::Bool(false)), run_e("(equal? 2 2.0)"));
+        assert_eq!(Err("equal? function requires two arguments"), run_e("(equal? 1)"));
     }
 
     mod type_checking_function {
@@ -423,71 +872,90 @@ mod std_function {
         fn list_q() {
             {
                 let test_result = run("(list? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(list? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? +)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(list? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
         }
 
+        #[test]
+        fn integer_q() {
+            assert_eq!(Ok(Value::Bool(true)), run("(integer? 1)").value);
+            assert_eq!(Ok(Value::Bool(true)), run("(integer? (factorial 30))").value);
+            assert_eq!(Ok(Value::Bool(true)), run("(integer? 2.0)").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(integer? 2.5)").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(integer? (/ 1 3))").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(integer? \"1\")").value);
+        }
+
+        #[test]
+        fn null_q() {
+            assert_eq!(Ok(Value::Bool(true)), run("(null? (list))").value);
+            assert_eq!(Ok(Value::Bool(true)), run("(null? (cdr (list 1)))").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(null? (list 1))").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(null? #f)").value);
+            assert_eq!(Err("null? function requires one argument only"), run("(null?)").value);
+        }
+
         #[test]
         fn number_q() {
             {
                 let test_result = run("(number? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(number? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(number? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(number? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(number? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(number? +)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(number? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(number? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
         }
 
@@ -495,71 +963,83 @@ mod std_function {
         fn pair_q() {
             {
                 let test_result = run("(pair? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
+            }
+            {
+                let test_result = run("(pair? (list))");
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? +)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(pair? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
         }
 
+        #[test]
+        fn real_q() {
+            assert_eq!(Ok(Value::Bool(true)), run("(real? 1)").value);
+            assert_eq!(Ok(Value::Bool(true)), run("(real? 2.5)").value);
+            assert_eq!(Ok(Value::Bool(true)), run("(real? (/ 1 3))").value);
+            assert_eq!(Ok(Value::Bool(false)), run("(real? (quote a))").value);
+        }
+
         #[test]
         fn procedure_q() {
             {
                 let test_result = run("(procedure? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(procedure? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(procedure? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(procedure? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(procedure? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(procedure? +)");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(procedure? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(procedure? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
         }
 
@@ -567,35 +1047,35 @@ mod std_function {
         fn string_q() {
             {
                 let test_result = run("(string? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(string? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? +)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(string? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
         }
 
@@ -603,70 +1083,3115 @@ mod std_function {
         fn symbol_q() {
             {
                 let test_result = run("(symbol? (list 7 9 4 0 3))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? 1)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? 5.5)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? \"hello\")");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? 'hello)");
-                assert_eq!(Ok(Some(DataType::Bool(true))), test_result.value);
+                assert_eq!(Ok(Value::Bool(true)), test_result.value);
             }
             {
                 let test_result = run("(symbol? +)");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? (lambda (x y) (+ x y)))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
             {
                 let test_result = run("(symbol? (cons 1 2))");
-                assert_eq!(Ok(Some(DataType::Bool(false))), test_result.value);
+                assert_eq!(Ok(Value::Bool(false)), test_result.value);
             }
         }
     }
 }
 
+mod space_safety {
+    use super::*;
 
-#[derive(Debug)]
-struct TestResult {
-    value: Result<Option<DataType>, &'static str>,
-    env: Rc<RefCell<Env>>
+    // Peak evaluation depth reached while running `s` in a fresh environment.
+    fn peak_depth(s: &str) -> usize {
+        reset_max_evaluation_depth();
+        let test_result = run(s);
+        assert!(test_result.value.is_ok(), "{:?}", test_result.value);
+        max_evaluation_depth()
+    }
+
+    // Runs `template` with `{}` replaced by a small and a large list and asserts
+    // that the peak evaluation depth does not depend on the length of the list.
+    fn assert_bounded_depth(template: &str) {
+        let list_of = |n: usize| format!("(list {})", (0..n).map(|i| i.to_string()).collect::<Vec<_>>().join(" "));
+        let small = peak_depth(&template.replace("{}", &list_of(5)));
+        let large = peak_depth(&template.replace("{}", &list_of(300)));
+        assert_eq!(small, large, "evaluation depth of {:?} grows with its input", template);
+    }
+
+    // Objects the cycle collector still tracks after running `s` in a fresh
+    // environment and collecting the garbage it left.
+    fn retained_objects(s: &str) -> usize {
+        let env_ref = default_env();
+        collect_garbage();
+        let before = heap_statistics().tracked;
+        let test_result = run_with_env(s, env_ref.clone());
+        assert!(test_result.value.is_ok(), "{:?}", test_result.value);
+        collect_garbage();
+        heap_statistics().tracked - before
+    }
+
+    // Runs `template` with `{}` replaced by a small and a large list and asserts
+    // that what it leaves on the heap does not depend on the length of the list.
+    fn assert_bounded_heap(template: &str) {
+        let list_of = |n: usize| format!("(list {})", (0..n).map(|i| i.to_string()).collect::<Vec<_>>().join(" "));
+        let small = retained_objects(&template.replace("{}", &list_of(5)));
+        let large = retained_objects(&template.replace("{}", &list_of(300)));
+        assert_eq!(small, large, "heap left by {:?} grows with its input", template);
+    }
+
+    #[test]
+    fn current_evaluation_depth() {
+        // the top-level evaluation and every procedure call waiting for its value,
+        // whichever engine and optimization level run them
+        let cases = [
+            ("(current-evaluation-depth)", 1),
+            ("(+ 0 (+ 0 (current-evaluation-depth)))", 1),
+            ("(define (f) (+ 0 (current-evaluation-depth))) (+ 0 (f))", 2),
+            ("(define (f n) (if (= n 0) (current-evaluation-depth) (f (- n 1)))) (f 10)", 2),
+            ("(define (f n) (if (= n 0) (current-evaluation-depth) (+ 0 (f (- n 1))))) (f 3)", 5),
+            ("(let ((x 1)) (+ x (current-evaluation-depth)))", 3),
+            ("(car (map (lambda (x) (current-evaluation-depth)) (list 1)))", 2)
+        ];
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            for level in &[OptLevel::None, OptLevel::Simple] {
+                set_engine(*engine);
+                set_opt_level(*level);
+                for &(source, depth) in &cases {
+                    assert_eq!(Ok(Value::Integer(depth)), run(source).value, "{} with {:?} at {:?}", source, engine, level);
+                }
+            }
+        }
+        set_engine(Engine::TreeWalker);
+        set_opt_level(OptLevel::None);
+        assert_eq!(Err("current-evaluation-depth function requires no argument"), run("(current-evaluation-depth 1)").value);
+        assert_eq!(0, evaluation_depth());
+    }
+
+    #[test]
+    fn reused_frames() {
+        // frames captured by a closure must not be handed to later calls
+        let env_ref = default_env();
+        let run_e = |s: &str| run_with_env(s, env_ref.clone()).value;
+        assert_eq!(Ok(Value::Unspecified), run_e("(define make-adder (lambda (n) (lambda (x) (+ x n))))"));
+        assert_eq!(Ok(Value::Unspecified), run_e("(define add1 (make-adder 1))"));
+        assert_eq!(Ok(Value::Unspecified), run_e("(define square (lambda (x) (* x x)))"));
+        assert_eq!(Ok(Value::list(vec![Value::Integer(1), Value::Integer(4), Value::Integer(9)])), run_e("(map square (list 1 2 3))"));
+        assert_eq!(Ok(Value::Unspecified), run_e("(define add5 (make-adder 5))"));
+        assert_eq!(Ok(Value::Integer(11)), run_e("(add1 10)"));
+        assert_eq!(Ok(Value::Integer(15)), run_e("(add5 10)"));
+        assert_eq!(Ok(Value::Integer(16)), run_e("((make-adder 7) (square 3))"));
+    }
+
+    #[test]
+    fn iterative_builtins() {
+        assert_bounded_depth("(map (lambda (x) (* x x)) {})");
+        assert_bounded_depth("(map number? {})");
+        assert_bounded_depth("(apply + {})");
+        assert_bounded_depth("(length {})");
+        assert_bounded_depth("(append {} {})");
+        assert_bounded_depth("(filter (lambda (x) (< x 3)) {})");
+        assert_bounded_depth("(fold-left + 0 {})");
+        assert_bounded_depth("(fold-right cons (list) {})");
+        assert_bounded_depth("(reduce max 0 {})");
+        assert_bounded_depth("(sort {} >)");
+    }
+
+    #[test]
+    fn tail_calls() {
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            set_engine(*engine);
+            assert_bounded_depth("(define (loop l) (if (null? l) 0 (loop (cdr l)))) (loop {})");
+            assert_bounded_depth("(let loop ((l {}) (n 0)) (if (null? l) n (loop (cdr l) (+ n 1))))");
+            assert_bounded_depth("(define (even? l) (if (null? l) #t (odd? (cdr l)))) (define (odd? l) (if (null? l) #f (even? (cdr l)))) (even? {})");
+        }
+        set_engine(Engine::TreeWalker);
+    }
+
+    #[test]
+    fn garbage_cycles() {
+        assert_bounded_heap("(for-each (lambda (x) (let ((p (list x))) (set-cdr! p p))) {})");
+        assert_bounded_heap("(for-each (lambda (x) (let ((v (vector x))) (vector-set! v 0 v))) {})");
+        assert_bounded_heap("(define v (vector 0)) (for-each (lambda (x) (vector-set! v 0 (list x))) {})");
+        assert_bounded_heap("(define (loop l) (if (null? l) 0 (let ((p (list l))) (set-car! p p) (loop (cdr l))))) (loop {})");
+    }
+
+    #[test]
+    fn non_tail_recursion() {
+        let fact = "(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))";
+        let shallow = peak_depth(&format!("{} (fact 5)", fact));
+        let deep = peak_depth(&format!("{} (fact 20)", fact));
+        assert!(deep > shallow);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // recursion and nesting no longer use the Rust stack for each level
+        let count = "(define count (lambda (n) (if (= n 0) 0 (+ 1 (count (- n 1))))))";
+        assert_eq!(Ok(Value::Integer(20000)), run(&format!("{} (count 20000)", count)).value);
+        let nested = format!("{}0{}", "(+ 1 ".repeat(500), ")".repeat(500));
+        assert_eq!(Ok(Value::Integer(500)), run(&nested).value);
+        // procedures called by builtins still nest, up to a limit
+        let through_map = "(define deep (lambda (n) (if (= n 0) 0 (+ 1 (car (map deep (list (- n 1))))))))";
+        assert_eq!(Ok(Value::Integer(100)), run(&format!("{} (deep 100)", through_map)).value);
+        assert_eq!(Err("procedures called by builtins are nested too deeply"), run(&format!("{} (deep 1000)", through_map)).value);
+        assert_eq!(0, evaluation_depth());
+    }
 }
 
-fn default_env() -> Rc<RefCell<Env>> {
-    let local = RefCell::new(setup());
-    let env = Env {
-        local,
-        parent: None
-    };
+mod panic_safety {
+    use super::*;
+    use std::panic;
+
+    const TOKENS: &[&str] = &["(", "(", "(", ")", ")", ")", "#(", "#u8(", "'", "`", ",", ",@", ".", "#0=", "#0#", "lambda", "define", "let", "if", "quote",
+        "quasiquote", "unquote", "syntax-rules", "define-syntax", "...", "_", "cond", "else", "=>", "case", "do", "letrec", "define-record-type", "begin",
+        "and", "or", "x", "f", "m", "1", "0", "-1", "2.5", "9223372036854775807", "\"s\"", "#t", "()", "#\\a", "car", "cdr", "cons", "list", "vector",
+        "+", "/", "apply", "map", "set-car!", "set-cdr!", "vector-set!", "length", "append", "equal?", "eval", "make-vector", "expt", "error", "raise",
+        "with-exception-handler", "hash-table-set!", "make-hash-table"];
+
+    // Whether reading, checking or evaluating `source` with either engine, or
+    // formatting what it evaluates to for debugging, panics.
+    fn panics(source: &str) -> bool {
+        let source = source.to_string();
+        panic::catch_unwind(move || {
+            check(&source);
+            parse_partial(&source);
+            for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+                set_engine(*engine);
+                let _ = format!("{:?}", evaluate(&source));
+            }
+            set_engine(Engine::TreeWalker);
+        }).is_err()
+    }
+
+    #[test]
+    fn circular_data() {
+        assert_eq!(Ok(Value::Bool(true)), run("(equal? '#0=(1 2 . #0#) '#1=(1 2 1 2 . #1#))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(equal? '#0=(1 2 . #0#) '#1=(1 2 3 . #1#))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(define v (vector 1)) (vector-set! v 0 v) (equal? v (vector v))").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(define c '#0=(1 . #0#)) (define t (make-hash-table)) (hash-table-set! t c 1) (hash-table-ref t c)").value);
+        assert_eq!(Err("length function requires an argument of type 'list'"), run("(length '#0=(1 . #0#))").value);
+        assert_eq!(Err("append! function requires arguments of type 'list'"), run("(append! '#0=(1 . #0#) '(2))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(list? '#0=(1 2 . #0#))").value);
+    }
+
+    #[test]
+    fn long_lists() {
+        // dropping a list built from pairs does not recurse along it
+        let build = "(define build (lambda (n l) (if (= n 0) l (build (- n 1) (cons n l)))))";
+        assert_eq!(Ok(Value::Integer(200000)), run(&format!("{} (length (build 200000 '()))", build)).value);
+    }
+
+    #[test]
+    fn large_results() {
+        assert_eq!(Err("make-vector length too large"), run("(make-vector 9223372036854775807)").value);
+        assert_eq!(Err("make-bytevector length too large"), run("(make-bytevector 1e18)").value);
+        assert_eq!(Err("expt exponent is too large"), run("(expt 2 9223372036854775807)").value);
+        assert_eq!(Err("factorial result is too large"), run("(factorial 99999999999)").value);
+        assert_eq!(Err("binomial result is too large"), run("(binomial 99999999999 49999999999)").value);
+        assert_eq!(Ok(Value::Integer(-1)), run("(expt -1 9223372036854775807)").value);
+    }
 
-    let env_ref = Rc::new(RefCell::new(env));
-    env_ref
+    #[test]
+    fn callbacks_changing_their_data() {
+        assert_eq!(Ok(Value::Bool(true)), run("(define v (vector 3 1 2)) (vector-sort! v (lambda (a b) (vector-set! v 0 9) (< a b))) (vector? v)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(define v (vector 3 1 2)) (vector-sort! v (lambda (a b) (vector-sort! v <) (< a b))) (vector? v)").value);
+    }
+
+    #[test]
+    fn no_input_panics() {
+        for source in &["", ")", "(", "#0#", "#0=#0#", "(lambda)", "(define)", "(define-syntax m (syntax-rules () ((m . x) x))) (m . 1)", "((lambda x x) . 1)",
+            "(let ((x 1) . 2) x)", "(cond (else))", "(case)", "(do ((x 1 . 2)) (#t))", "`(1 ,@2)", "`#0=(1 . #0#)", "(apply + '#0=(1 . #0#))",
+            "(eval '#0=(1 . #0#))", "(car '#0=(#0#))", "'#0=(1 . #0#)", "'#0=#(#0#)", "(define v (vector 1)) (vector-set! v 0 v) (list v)",
+            "(lambda () '#0=(1 . #0#))", "(define-record-type)", "(vector-ref (vector) -1)", "(string->symbol \"\")", "(/ 1 0)", "(/ 1.0 0)"] {
+            assert!(!panics(source), "{:?} panics", source);
+        }
+        // programs made of random tokens, the same every run
+        let mut state: u64 = 338;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        for _ in 0..2000 {
+            let length = next() % 30 + 1;
+            let source = (0..length).map(|_| TOKENS[next() % TOKENS.len()]).collect::<Vec<&str>>().join(" ");
+            assert!(!panics(&source), "{:?} panics", source);
+        }
+    }
 }
 
-fn run(s: &str) -> TestResult {
-    env_logger::init();
-    run_with_env(s, default_env().clone())
+mod bytecode {
+    use super::*;
+
+    fn evaluate_with(engine: Engine, s: &str) -> Result<Value, SchemeError> {
+        set_engine(engine);
+        let result = evaluate(s);
+        set_engine(Engine::TreeWalker);
+        result
+    }
+
+    #[test]
+    fn same_results() {
+        for source in &[
+            "(if (> 3 2) (quote yes) (quote no))",
+            "(define x 5) (* x x)",
+            "(define make-adder (lambda (n) (lambda (x) (+ x n)))) ((make-adder 2) 40)",
+            "((lambda (x) (+ x 1)) 41)",
+            "(letrec* ((a 1) (b (+ a 1))) (+ a b))",
+            "(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1))))) (odd? (lambda (n) (if (= n 0) #f (even? (- n 1)))))) (even? 100))",
+            "(map (lambda (x) (* x x)) (list 1 2 3))",
+            "(quasiquote (1 (unquote (+ 1 1)) (unquote-splicing (list 3 4))))",
+            "(define v (vector 1 2)) (vector-set! v 0 9) v",
+            "(quote (a #(b) \"c\"))",
+            "(define-record-type point (make-point x y) point? (x point-x) (y point-y)) (point-x (make-point 3 4))",
+            "(define f (lambda (x) x)) (procedure-name f)",
+            "(with-exception-handler error-object-message (lambda () (car 1)))",
+            "(if 1)",
+            "(car 1)",
+            "(+ 1 nope)",
+            "(define x 1) (x 2)",
+            "((quote x) 1)",
+            "(lambda (1) x)",
+            "(letrec* ((1 2)) 1)",
+            "(list #\\a #zzz)",
+            "((lambda (x) (begin (define y (* x 2)) (+ x y))) 3)",
+            "(define n 1) ((lambda (n) ((lambda () (begin (define-record-type r (make-r) r?) n)))) 2)",
+            "((lambda (x x) x) 1 2)",
+            "((lambda (x y) y) 1)",
+            "(letrec ((a 1) (a 2)) a)",
+        ] {
+            assert_eq!(evaluate_with(Engine::TreeWalker, source), evaluate_with(Engine::Bytecode, source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn tail_calls() {
+        let count = "(define loop (lambda (n acc) (if (= n 0) acc (loop (- n 1) (+ acc 1)))))";
+        reset_max_evaluation_depth();
+        assert_eq!(Ok(Value::Integer(10)), evaluate_with(Engine::Bytecode, &format!("{} (loop 10 0)", count)));
+        let shallow = max_evaluation_depth();
+        reset_max_evaluation_depth();
+        assert_eq!(Ok(Value::Integer(100000)), evaluate_with(Engine::Bytecode, &format!("{} (loop 100000 0)", count)));
+        assert_eq!(shallow, max_evaluation_depth());
+        assert_eq!(0, evaluation_depth());
+
+        // a procedure called in tail position takes the place of its caller
+        let env_ref = default_env();
+        set_engine(Engine::Bytecode);
+        run_with_env("(define check (lambda (x) (+ x missing))) (define outer (lambda (y) (check (* y 2))))", env_ref.clone());
+        assert_eq!(Err("symbol is not defined."), run_with_env("(outer 20)", env_ref.clone()).value);
+        set_engine(Engine::TreeWalker);
+        assert_eq!(vec!["begin", "check", "+"], error_dump().unwrap().stack);
+    }
+
+    #[test]
+    fn procedures_across_engines() {
+        let env_ref = default_env();
+        set_engine(Engine::Bytecode);
+        run_with_env("(define square (lambda (x) (* x x)))", env_ref.clone());
+        set_engine(Engine::TreeWalker);
+        run_with_env("(define twice (lambda (f x) (f (f x))))", env_ref.clone());
+        assert_eq!(Ok(Value::Integer(81)), run_with_env("(twice square 3)", env_ref.clone()).value);
+        set_engine(Engine::Bytecode);
+        assert_eq!(Ok(Value::Integer(16)), run_with_env("(twice square 2)", env_ref.clone()).value);
+        set_engine(Engine::TreeWalker);
+    }
+
+    #[test]
+    fn closures() {
+        for source in &[
+            // procedures refer to the frame they were created in, and see what is defined there later
+            "((lambda () (begin (define g (lambda () (+ x 0))) (define x 2) (g))))",
+            "(define make-adder (lambda (n) (lambda (x) (+ x n)))) (define add1 (make-adder 1)) (define add5 (make-adder 5)) (list (add1 1) (add5 1) (eq? add1 add1) (eq? add1 add5))",
+            // the frame of g is left out of the procedure it returns, which uses a from the frame of f
+            "(define f (lambda (a) (begin (define g (lambda (b) (lambda (c) (+ a c)))) (g 1)))) ((f 10) 5)",
+            "(define f (lambda (a) (letrec ((b 1)) (lambda (c) (list a c))))) ((f 1) 2)",
+            "(define f (lambda (a) (lambda () (quasiquote (a (unquote a)))))) ((f 1))",
+            "(define f (lambda (a) (lambda () (begin (define-record-type r (make-r) r?) a)))) ((f 1))",
+            "(define f (lambda (a) (lambda (b) (lambda (c) (list a b c))))) (((f 1) 2) 3)",
+        ] {
+            assert_eq!(evaluate_with(Engine::TreeWalker, source), evaluate_with(Engine::Bytecode, source), "{}", source);
+        }
+        assert_eq!(Ok(Value::Integer(2)), evaluate_with(Engine::Bytecode, "((lambda () (begin (define g (lambda () (+ x 0))) (define x 2) (g))))"));
+        assert_eq!(Ok(Value::Integer(15)), evaluate_with(Engine::Bytecode, "(define f (lambda (a) (begin (define g (lambda (b) (lambda (c) (+ a c)))) (g 1)))) ((f 10) 5)"));
+
+        // procedures whose body uses no variable of the frames around them refer to the
+        // frame further out instead
+        let (ast, _) = parse_datum("(lambda (n) (lambda (b) (lambda (x) (* x n))))").unwrap();
+        let code = compile(&ast).to_string();
+        assert!(code.contains("lambda 1 outer 1\n"), "{}", code);
+        assert!(code.ends_with("lambda (* x n):
+   0 callee *
+   1 variable x 0:0
+   2 variable n 1:0
+   3 tail-call * 2
+   4 return
+"), "{}", code);
+        let (ast, _) = parse_datum("(lambda (n) (lambda (x) (* x x)))").unwrap();
+        assert!(compile(&ast).to_string().contains("lambda 1 outer 1\n"));
+        let (ast, _) = parse_datum("(lambda (n) (lambda (x) (quasiquote (unquote n))))").unwrap();
+        assert!(!compile(&ast).to_string().contains("outer"));
+    }
+
+    #[test]
+    fn error_positions() {
+        // the body of a compiled lambda keeps the positions of its forms
+        let source = "(define f (lambda (x)\n  (car x)))\n(+ 1 (f 5))";
+        let span = evaluate_with(Engine::Bytecode, source).unwrap_err().span().unwrap();
+        assert_eq!("(car x)", &source[span.start..span.end]);
+        // variables found by position are bound in the environment all the same
+        let environment = error_environment().unwrap();
+        assert_eq!(Ok(Value::Integer(5)), eval(Some(Value::Symbol("x".into())), environment.clone()));
+        assert_eq!(vec![(SymbolId::intern("x"), Value::Integer(5))], environment.borrow().bindings());
+    }
+
+    #[test]
+    fn disassembly() {
+        let (ast, _) = parse_datum("(if (f x) 1 (g 2))").unwrap();
+        assert_eq!("   0 callee f
+   1 variable x
+   2 call f 1
+   3 jump-if-false 6
+   4 constant 1
+   5 jump 9
+   6 callee g
+   7 constant 2
+   8 tail-call g 1
+   9 return
+", compile(&ast).to_string());
+        let (ast, _) = parse_datum("(lambda (n) (lambda (x) (+ x n y)))").unwrap();
+        assert!(compile(&ast).to_string().ends_with("lambda (+ x n y):
+   0 callee +
+   1 variable x 0:0
+   2 variable n 1:0
+   3 variable y
+   4 tail-call + 3
+   5 return
+"));
+        let env_ref = default_env();
+        assert_eq!(Ok(Value::Integer(2)), execute(&compile(&parse_datum("((lambda (n) (+ n 1)) 1)").unwrap().0), env_ref));
+    }
 }
 
-fn run_with_env(s: &str, env_ref: Rc<RefCell<Env>>) -> TestResult {
-    env_logger::init();
-    let result = parse(s)
-        .and_then(|ast| eval(Some(ast.result), env_ref.clone()));
+mod optimization {
+    use super::*;
 
-    TestResult {
-        value: result.clone(),
-        env: env_ref.clone()
+    fn optimized(s: &str) -> String {
+        let mut ast = parse_datum(s).unwrap().0;
+        optimize(&mut ast, &default_env());
+        unparse(&ast)
+    }
+
+    fn evaluate_at(level: OptLevel, s: &str) -> Result<Value, SchemeError> {
+        set_opt_level(level);
+        let result = evaluate(s);
+        set_opt_level(OptLevel::None);
+        result
+    }
+
+    #[test]
+    fn folding() {
+        assert_eq!("7", optimized("(+ 1 (* 2 3))"));
+        assert_eq!("1/2", optimized("(/ 1 2)"));
+        assert_eq!("#t", optimized("(< 1 2 3)"));
+        assert_eq!("(+ x 6)", optimized("(+ x (* 2 3))"));
+        assert_eq!("(foo 6)", optimized("(if (> 2 1) (foo (+ 1 5)) (bar))"));
+        assert_eq!("(bar)", optimized("(if #f (foo) (bar))"));
+        assert_eq!("(if x 1 2)", optimized("(if x 1 2)"));
+        assert_eq!("(begin (display 1) 2)", optimized("(begin 1 (display 1) \"a\" (quote b) 2)"));
+        assert_eq!("(display 1)", optimized("(begin 1 (display 1))"));
+        assert_eq!("(f 2)", optimized("((lambda () (f (+ 1 1))))"));
+        // the body of a lambda stays a list
+        assert_eq!("(lambda () (+ 1 2))", optimized("(lambda () (+ 1 2))"));
+        assert_eq!("(lambda () (if #t 1 (f)))", optimized("(lambda () (if #t 1 (f)))"));
+        // definitions belong in the frame of the call
+        assert_eq!("((lambda () (begin (define y 1) y)))", optimized("((lambda () (begin (define y 1) y)))"));
+        assert_eq!("(quote (+ 1 2))", optimized("(quote (+ 1 2))"));
+    }
+
+    #[test]
+    fn bound_names() {
+        assert_eq!("(begin (define + -) (+ 1 2))", optimized("(begin (define + -) (+ 1 2))"));
+        assert_eq!("((lambda (* x) (* 2 3)) - 1)", optimized("((lambda (* x) (* 2 3)) - 1)"));
+        assert_eq!("(letrec ((not (lambda (x) x))) (not 1))", optimized("(letrec ((not (lambda (x) x))) (not 1))"));
+        let env_ref = default_env();
+        run_with_env("(define + -)", env_ref.clone());
+        let mut ast = parse_datum("(+ 1 2)").unwrap().0;
+        optimize(&mut ast, &env_ref);
+        assert_eq!("(+ 1 2)", unparse(&ast));
+    }
+
+    #[test]
+    fn errors_are_kept() {
+        assert_eq!("(/ 1 0)", optimized("(/ 1 0)"));
+        let source = "(define f (lambda (x) (car x)))\n(+ 2 (/ (* 1 2) 0))";
+        let span = evaluate_at(OptLevel::Simple, source).unwrap_err().span().unwrap();
+        assert_eq!("(/ (* 1 2) 0)", &source[span.start..span.end]);
+    }
+
+    #[test]
+    fn same_results() {
+        for source in &[
+            "(+ 1 (* 2 3))",
+            "(define x 5) (if (> 3 2) (* x x) 0)",
+            "(define x (if #t 'a 0)) x",
+            "(define a 1) (define b (if #t 'a 0)) b",
+            "(begin 1 2 (quote c))",
+            "((lambda () (begin (define y 2) (* y 3))))",
+            "(define f (lambda () (if #f 1 (list (+ 1 1))))) (f)",
+            "(define + -) (+ 5 2)",
+            "(car (quote (1 2)))",
+            "(/ 1 0)",
+            "(expt 2 100)",
+            "(+ 0.5 1/2)",
+        ] {
+            for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+                set_engine(*engine);
+                assert_eq!(evaluate_at(OptLevel::None, source), evaluate_at(OptLevel::Simple, source), "{}", source);
+                set_engine(Engine::TreeWalker);
+            }
+        }
+    }
+}
+
+mod expansion {
+    use super::*;
+
+    fn expanded(s: &str) -> String {
+        let mut ast = parse_datum(s).unwrap().0;
+        expand(&mut ast, &default_env()).unwrap();
+        unparse(&ast)
+    }
+
+    #[test]
+    fn derived_forms() {
+        assert_eq!("((lambda (x y) (+ x y)) 1 2)", expanded("(let ((x 1) (y 2)) (+ x y))"));
+        assert_eq!("((lambda () (begin (define z 1) z)))", expanded("(let () (define z 1) z)"));
+        assert_eq!("(if a (if b c #f) #f)", expanded("(and a b c)"));
+        assert_eq!("(if a (begin b c) (begin))", expanded("(when a b c)"));
+        assert_eq!("(define f (lambda (x) (begin x)))", expanded("(define (f x) x)"));
+        assert_eq!("(quote (let ((x 1)) x))", expanded("(quote (let ((x 1)) x))"));
+        assert_eq!("(quasiquote (let (unquote ((lambda (x) (+ x 1)) 1))))", expanded("`(let ,(let ((x 1)) (+ x 1)))"));
+        assert_eq!("((lambda (let) (let 1)) f)", expanded("((lambda (let) (let 1)) f)"));
+        assert_eq!("(lambda (x) (begin x))", expanded("(lambda (x) x)"));
+        assert_eq!("(lambda (x) (begin (display x) x))", expanded("(lambda (x) (display x) x)"));
+
+        let results = [
+            ("(let ((x 2) (y 3)) (* x y))", Value::Integer(6)),
+            ("(let loop ((i 0) (acc 0)) (if (= i 5) acc (loop (+ i 1) (+ acc i))))", Value::Integer(10)),
+            ("(let* ((x 1) (y (+ x 1)) (z (* y 2))) (list x y z))", Value::list(vec![Value::Integer(1), Value::Integer(2), Value::Integer(4)])),
+            ("(let* () 5)", Value::Integer(5)),
+            ("(cond ((> 1 2) 'a) ((< 1 2) 'b) (else 'c))", Value::Symbol(SymbolId::intern("b"))),
+            ("(cond ((assv 2 '((1 . a) (2 . b))) => cdr) (else #f))", Value::Symbol(SymbolId::intern("b"))),
+            ("(cond (#f 1) (7))", Value::Integer(7)),
+            ("(cond (#f 1))", Value::Unspecified),
+            ("(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite))", Value::Symbol(SymbolId::intern("composite"))),
+            ("(case 'x ((a) 1) (else => (lambda (k) (list k))))", Value::list(vec![Value::Symbol(SymbolId::intern("x"))])),
+            ("(list (and) (and 1 2) (and #f (car 1)) (or) (or #f 3) (or 4 (car 1)))", Value::list(vec![
+                Value::Bool(true), Value::Integer(2), Value::Bool(false), Value::Bool(false), Value::Integer(3), Value::Integer(4)
+            ])),
+            ("(list (when #t 1 2) (unless #f 3))", Value::list(vec![Value::Integer(2), Value::Integer(3)])),
+            ("(do ((vec (make-vector 5)) (i 0 (+ i 1))) ((= i 5) (vector->list vec)) (vector-set! vec i i))", Value::list(vec![
+                Value::Integer(0), Value::Integer(1), Value::Integer(2), Value::Integer(3), Value::Integer(4)
+            ])),
+            ("(define (add x y) (+ x y)) (add 40 2)", Value::Integer(42)),
+            ("(define (f) (define x 4) (* x x)) (f)", Value::Integer(16)),
+            ("((lambda (x) x) 3)", Value::Integer(3)),
+            ("((lambda (x) (define y 2) (+ x y)) 3)", Value::Integer(5)),
+        ];
+        for (source, value) in results.iter() {
+            for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+                set_engine(*engine);
+                assert_eq!(Ok(value.clone()), evaluate(source), "{}", source);
+                set_engine(Engine::TreeWalker);
+            }
+        }
+
+        // the loops of named let and do are calls in tail position
+        set_engine(Engine::Bytecode);
+        assert_eq!(Ok(Value::Integer(100000)), evaluate("(let loop ((i 0)) (if (= i 100000) i (loop (+ i 1))))"));
+        assert_eq!(Ok(Value::Integer(100000)), evaluate("(do ((i 0 (+ i 1))) ((= i 100000) i))"));
+        set_engine(Engine::TreeWalker);
+    }
+
+    #[test]
+    fn shadowed_keywords() {
+        let env_ref = default_env();
+        run_with_env("(define when (lambda (x y) (list x y)))", env_ref.clone());
+        assert_eq!("'(1 2)", run_with_env("(when 1 2)", env_ref.clone()).value.unwrap().to_string());
+        assert_eq!(Ok(Value::Integer(3)), run("(define (or a b) (+ a b)) (or 1 2)").value);
+        assert_eq!(Ok(Value::Integer(2)), run("((lambda (and) (and 1 1)) +)").value);
+    }
+
+    #[test]
+    fn syntax_rules() {
+        let env_ref = default_env();
+        run_with_env("(define-syntax swap! (syntax-rules () ((_ a b) (let ((tmp a)) (define a b) (define b tmp)))))", env_ref.clone());
+        run_with_env("(define-syntax my-or (syntax-rules () ((_) #f) ((_ e) e) ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))", env_ref.clone());
+        run_with_env("(define-syntax for (syntax-rules (in) ((_ x in xs body ...) (map (lambda (x) (begin body ...)) xs))))", env_ref.clone());
+        // macros defined by one program are used by later ones
+        assert_eq!(Ok(Value::Integer(3)), run_with_env("(my-or #f #f 3)", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(false)), run_with_env("(my-or)", env_ref.clone()).value);
+        assert_eq!("'(2 4 6)", run_with_env("(for y in (list 1 2 3) (* y 2))", env_ref.clone()).value.unwrap().to_string());
+        assert_eq!(Err("no syntax rule matches the form"), run_with_env("(for y on (list 1) y)", env_ref.clone()).value);
+
+        assert_eq!(evaluate("'((1 2 3) ((b c a) (e d)))"), evaluate(r#"
+            (define-syntax my-let (syntax-rules () ((_ ((n v) ...) body) ((lambda (n ...) body) v ...))))
+            (define-syntax rotate (syntax-rules () ((_ (a b ...) ...) (quote ((b ... a) ...)))))
+            (list (my-let ((x 1) (y 2)) (list x y 3)) (rotate (a b c) (d e)))
+        "#));
+        assert_eq!(evaluate("'(1 (2 3))"), evaluate("(define-syntax rest (syntax-rules () ((_ a . b) (list a 'b)))) (rest 1 2 3)"));
+        assert_eq!(evaluate("'(1 2 3)"), evaluate("(define-syntax my-list (syntax-rules () ((_ . xs) (list . xs)))) (my-list 1 2 3)"));
+        assert_eq!(evaluate("'(1 ...)"), evaluate("(define-syntax dots (syntax-rules () ((_ a) '(a (... ...))))) (dots 1)"));
+        assert_eq!(evaluate("'(1 2)"), evaluate("(define-syntax my-list (syntax-rules ::: () ((_ a :::) (list a :::)))) (my-list 1 2)"));
+
+        // the variables the templates bind are not those of the forms the macro is used on
+        assert_eq!(Ok(Value::Integer(5)), run_with_env("(define t 5) (my-or #f t)", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Integer(5)), run_with_env("(let ((t 5)) (my-or #f t))", env_ref.clone()).value);
+        assert_eq!(evaluate("'(7 1 0)"), evaluate(r#"
+            (define-syntax rev (syntax-rules () ((_ a b) (let loop ((i 0) (tmp a)) (list b tmp i)))))
+            (define tmp 7)
+            (rev 1 tmp)
+        "#));
+        assert_eq!(evaluate("'(2 1)"), evaluate("(define x 1) (define-syntax m (syntax-rules () ((_ e) ((lambda (x) (list x e)) 2)))) (m x)"));
+        // a variable of the same name hides the macro
+        assert_eq!(Ok(Value::Integer(5)), run_with_env("((lambda (my-or) (my-or 5)) (lambda (x) (+ x 0)))", env_ref.clone()).value);
+        run_with_env("(define (my-or x) x)", env_ref.clone());
+        assert_eq!(Ok(Value::Integer(7)), run_with_env("(my-or 7)", env_ref.clone()).value);
     }
+
+    #[test]
+    fn expansion_errors() {
+        assert_eq!(Err("wrong syntax for let expression"), run("(let ((x)) x)").value);
+        assert_eq!(Err("wrong syntax for cond expression"), run("(cond (else 1) (#t 2))").value);
+        assert_eq!(Err("wrong syntax for define-syntax expression"), run("(define-syntax m 1)").value);
+        assert_eq!(Err("macro expansion does not terminate"), run("(define-syntax m (syntax-rules () ((_) (m)))) (m)").value);
+        assert_eq!(Err("expanded form nested too deeply"), run("(define-syntax m (syntax-rules () ((_ x) (m (x))))) (m 1)").value);
+        assert_eq!(Err("expanded form nested too deeply"), run("(define-syntax m (syntax-rules () ((_ x) (list (m x))))) (m 1)").value);
+        assert_eq!(Err("pattern variable used without an ellipsis"), run("(define-syntax m (syntax-rules () ((_ x ...) x))) (m 1)").value);
+        // nothing is evaluated when a form cannot be expanded
+        let env_ref = default_env();
+        assert!(run_with_env("(define x 1) (let ((y)) y)", env_ref.clone()).value.is_err());
+        assert_eq!(Err("symbol is not defined."), run_with_env("x", env_ref.clone()).value);
+
+        let source = "(define x 1)\n(cond (#t (car x)))";
+        let span = evaluate(source).unwrap_err().span().unwrap();
+        assert_eq!("(car x)", &source[span.start..span.end]);
+        // the body of a let is the body of a lambda, whose forms only compiled code places errors at
+        let source = "(define x 1)\n(when #t (let ((y 2))\n  (+ y (car x))))";
+        set_engine(Engine::Bytecode);
+        let span = evaluate(source).unwrap_err().span().unwrap();
+        set_engine(Engine::TreeWalker);
+        assert_eq!("(car x)", &source[span.start..span.end]);
+        let source = "(begin\n  (let ((y)) y))";
+        let span = evaluate(source).unwrap_err().span().unwrap();
+        assert_eq!("(let ((y)) y)", &source[span.start..span.end]);
+    }
+
+    #[test]
+    fn checked_after_expansion() {
+        let messages = |source: &str| check(source).into_iter().map(|d| d.message).collect::<Vec<String>>();
+        assert_eq!(vec!["unbound variable y"], messages("(let ((x 1)) (+ x y))"));
+        assert_eq!(Vec::<String>::new(), messages("(define-syntax m (syntax-rules () ((_ a) (list a)))) (m 1)"));
+        assert_eq!(vec!["wrong syntax for let expression"], messages("(let x)"));
+    }
+}
+
+mod statistics {
+    use super::*;
+
+    fn statistics_of(s: &str) -> EvalStatistics {
+        let test_result = run(s);
+        assert!(test_result.value.is_ok(), "{:?}", test_result.value);
+        last_eval_statistics()
+    }
+
+    #[test]
+    fn steps() {
+        let fact = "(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))";
+        let cheap = statistics_of(&format!("{} (fact 3)", fact));
+        let expensive = statistics_of(&format!("{} (fact 10)", fact));
+        assert!(cheap.steps > 0);
+        assert!(expensive.steps > cheap.steps);
+        assert!(expensive.allocations > cheap.allocations);
+
+        // the statistics cover one top-level evaluation only
+        assert_eq!(statistics_of("(+ 1 2)"), statistics_of("(+ 1 2)"));
+    }
+
+    #[test]
+    fn allocations() {
+        let base = statistics_of("(+ 1 2)").allocations;
+        assert_eq!(base, 0);
+        assert_eq!(2, statistics_of("(cons 1 (cons 2 3))").allocations);
+        // one pair for each element
+        assert_eq!(3, statistics_of("(list 1 2 3)").allocations);
+        // quoted data are given back as they were read, not copied
+        assert_eq!(0, statistics_of("(quote (1 2 3))").allocations);
+    }
+
+    #[test]
+    fn last_eval_statistics_builtin() {
+        let env_ref = default_env();
+        run_with_env("(cons 1 2)", env_ref.clone());
+        let stats = last_eval_statistics();
+        assert_eq!(Ok(Value::list(vec![
+            Value::cons(Value::Symbol("steps".into()), Value::Integer(stats.steps as i64)),
+            Value::cons(Value::Symbol("allocations".into()), Value::Integer(1)),
+        ])), run_with_env("(last-eval-statistics)", env_ref.clone()).value);
+        assert_eq!(Err("last-eval-statistics function requires no argument"), run("(last-eval-statistics 1)").value);
+    }
+
+    #[test]
+    fn fuel_limits() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.eval_str("(define (f) (f)) (define (fact n) (if (<= n 1) 1 (* n (fact (- n 1)))))").unwrap();
+        interpreter.enter(|| set_fuel(Some(10_000)));
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            interpreter.enter(|| set_engine(*engine));
+            let error = interpreter.eval_str("(f)").unwrap_err();
+            assert_eq!(SchemeError::budget_exceeded("evaluation ran out of fuel").with_span(error.span().unwrap()), error);
+            assert!(interpreter.enter(last_eval_statistics).steps <= 10_001);
+            // handlers do not catch it, and every evaluation has a budget of its own
+            assert!(matches!(interpreter.eval_str("(with-exception-handler (lambda (e) (+ 0)) (lambda () (f)))"), Err(SchemeError::BudgetExceeded { .. })));
+            assert_eq!(Ok(Value::Integer(3628800)), interpreter.eval_str("(fact 10)"));
+            assert!(matches!(interpreter.eval_str("(map (lambda (x) (f)) '(1))"), Err(SchemeError::BudgetExceeded { .. })));
+        }
+        interpreter.enter(|| set_fuel(None));
+        assert_eq!(None, interpreter.enter(fuel));
+        assert_eq!(Ok(Value::Integer(3628800)), interpreter.eval_str("(fact 10)"));
+    }
+
+    #[test]
+    fn interruptions() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.eval_str("(define (f) (f))").unwrap();
+        interpreter.enter(|| set_timeout(Some(std::time::Duration::from_millis(20))));
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            interpreter.enter(|| set_engine(*engine));
+            assert_eq!("evaluation timed out", interpreter.eval_str("(f)").unwrap_err().message());
+        }
+        // so do the builtins that can run long, between the parts of their work
+        interpreter.eval_str("(define pieces (vector->list (make-vector 1000000 \"piece\")))").unwrap();
+        for program in &["(expt 3 20000000)", "(factorial 2000000)", "(binomial 4000000 2000000)", "(string-join pieces \", \")"] {
+            let start = std::time::Instant::now();
+            assert_eq!(Err("evaluation timed out"), interpreter.eval_str(program).map_err(|e| e.message()), "{}", program);
+            assert!(start.elapsed() < std::time::Duration::from_secs(2), "{}: {:?}", program, start.elapsed());
+        }
+        interpreter.enter(|| {
+            set_engine(Engine::TreeWalker);
+            set_timeout(None);
+        });
+
+        let handle = interpreter.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.interrupt();
+        });
+        // handlers catch it
+        assert_eq!(Ok(Value::String("evaluation was interrupted".into())), interpreter.eval_str("(with-exception-handler error-object-message (lambda () (f)))"));
+        interrupter.join().unwrap();
+        // an interruption is reported once, to the evaluation running
+        let handle = interpreter.interrupt_handle();
+        interpreter.define_fn("interrupt", move |_| {
+            handle.interrupt();
+            Ok(Value::Nil)
+        });
+        let error = interpreter.eval_str("(begin (interrupt) (+ 1 2))").unwrap_err();
+        assert!(matches!(error, SchemeError::Interrupted { .. }));
+        assert_eq!("evaluation was interrupted", error.message());
+        assert_eq!(Ok(Value::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+        // one coming between evaluations does not stop the next
+        interpreter.interrupt_handle().interrupt();
+        assert_eq!(Ok(Value::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+        // nor does one of another interpreter on the thread
+        let other = Interpreter::new().unwrap();
+        let handle = other.interrupt_handle();
+        interpreter.define_fn("interrupt-other", move |_| {
+            handle.interrupt();
+            Ok(Value::Nil)
+        });
+        assert_eq!(Ok(Value::Integer(3)), interpreter.eval_str("(begin (interrupt-other) (+ 1 2))"));
+        assert_eq!(Ok(Value::Integer(3)), other.eval_str("(+ 1 2)"));
+    }
+
+    #[test]
+    fn allocation_limits() {
+        assert!(statistics_of("(list 1 2 3)").bytes >= 3 * std::mem::size_of::<Value>());
+        assert!(statistics_of("(make-vector 100)").bytes > statistics_of("(make-vector 10)").bytes);
+
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.eval_str("(define (grow l) (grow (cons l l))) (define (double s) (double (string-append s s)))").unwrap();
+        interpreter.enter(|| set_allocation_limit(Some(1 << 20)));
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            interpreter.enter(|| set_engine(*engine));
+            let error = interpreter.eval_str("(grow '())").unwrap_err();
+            assert!(matches!(error, SchemeError::BudgetExceeded { .. }));
+            assert_eq!("evaluation allocated more than its limit", error.message());
+            // the limit is checked as often as the fuel is
+            assert!(interpreter.enter(last_eval_statistics).bytes < (1 << 20) + 1024);
+            assert_eq!("evaluation allocated more than its limit", interpreter.eval_str("(double \"ab\")").unwrap_err().message());
+        }
+        interpreter.enter(|| set_engine(Engine::TreeWalker));
+        // objects of the size asked for are not made when they would go over the limit
+        assert_eq!("evaluation allocated more than its limit", interpreter.eval_str("(make-vector 100000000)").unwrap_err().message());
+        assert!(matches!(interpreter.eval_str("(with-exception-handler (lambda (e) (+ 0)) (lambda () (make-bytevector 2000000)))"), Err(SchemeError::BudgetExceeded { .. })));
+        // nor are strings and bignums, whose size is charged before they are built
+        interpreter.eval_str("(define (join s) (join (string-join (list s s) s)))").unwrap();
+        for program in &["(join \"ab\")", "(expt 3 10000000)", "(factorial 1000000)", "(let ((n (expt 2 4000000))) (* n n))"] {
+            assert_eq!(Err("evaluation allocated more than its limit"), interpreter.eval_str(program).map_err(|e| e.message()), "{}", program);
+            assert!(interpreter.enter(last_eval_statistics).bytes < 4 << 20, "{}", program);
+        }
+        // garbage counts as much as what is kept
+        interpreter.eval_str("(define (churn n) (if (= n 0) 0 (begin (list 1 2 3) (churn (- n 1)))))").unwrap();
+        assert!(matches!(interpreter.eval_str("(churn 100000)"), Err(SchemeError::BudgetExceeded { .. })));
+        // every evaluation has a limit of its own
+        assert_eq!(Ok(Value::Integer(1000)), interpreter.eval_str("(vector-length (make-vector 1000))"));
+        interpreter.enter(|| set_allocation_limit(None));
+        assert_eq!(None, interpreter.enter(allocation_limit));
+        assert_eq!(Ok(Value::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+    }
+}
+
+mod garbage_collection {
+    use super::*;
+
+    // Evaluates each step on its own, as `begin` would keep the values of the earlier
+    // ones alive, and collects the garbage they left.
+    fn gc_after(env_ref: &Rc<RefCell<Env>>, steps: &[&str]) -> Result<Value, &'static str> {
+        for step in steps {
+            assert!(run_with_env(step, env_ref.clone()).value.is_ok(), "{}", step);
+        }
+        run_with_env("(gc)", env_ref.clone()).value
+    }
+
+    #[test]
+    fn closures_in_their_own_frames() {
+        for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            set_engine(*engine);
+            let env_ref = default_env();
+            let make = "(define make (lambda () (begin (define self (lambda () (self))) self)))";
+            // the frame of the call and the closure made in it
+            assert_eq!(Ok(Value::Integer(2)), gc_after(&env_ref, &[make, "(make)"]), "{:?}", engine);
+            assert_eq!(Ok(Value::Integer(0)), gc_after(&env_ref, &[]), "{:?}", engine);
+
+            // a closure still referred to is kept, with its frame
+            assert_eq!(Ok(Value::Integer(0)), gc_after(&env_ref, &["(define kept (make))"]), "{:?}", engine);
+            assert_eq!(Ok(Value::Bool(true)), run_with_env("(procedure? kept)", env_ref.clone()).value, "{:?}", engine);
+
+            // the procedures of the prelude go with the environment they were defined in
+            drop(default_env());
+            let prelude = collect_garbage();
+            assert!(prelude > 0);
+
+            // the environment, which refers to itself through the closures in it, then
+            // goes with them and the frame of `kept`, and its prelude
+            drop(env_ref);
+            assert_eq!(4 + prelude, collect_garbage(), "{:?}", engine);
+        }
+        set_engine(Engine::TreeWalker);
+    }
+
+    #[test]
+    fn data_referring_to_themselves() {
+        let env_ref = default_env();
+        run_with_env("(define v (vector 1 2))", env_ref.clone());
+        let vector = match run_with_env("v", env_ref.clone()).value {
+            Ok(Value::Vector(v)) => Rc::downgrade(&v),
+            value => panic!("{:?}", value)
+        };
+        assert_eq!(Ok(Value::Integer(0)), gc_after(&env_ref, &["(vector-set! v 0 v)"]));
+        assert!(vector.upgrade().is_some());
+        assert_eq!(Ok(Value::Integer(1)), gc_after(&env_ref, &["(define v 0)"]));
+        assert!(vector.upgrade().is_none());
+
+        // the two pairs, and the table with the list in it
+        assert_eq!(Ok(Value::Integer(4)), gc_after(&env_ref, &[
+            "(define p (cons 1 (cons 2 3)))",
+            "(set-cdr! (cdr p) p)",
+            "(define t (make-hash-table))",
+            "(hash-table-set! t 1 (list t))",
+            "(define p 0)",
+            "(define t 0)"
+        ]));
+
+        // cycles read with labels, and the copies made of them for the quotes
+        assert_eq!(Ok(Value::Integer(6)), gc_after(&env_ref, &["(quote #0=(1 2 . #0#))", "(quote #0=#(#0#))"]));
+
+        // the vector and the pairs of the list in it are kept while the list is held
+        // from outside the cycle
+        assert_eq!(Ok(Value::Integer(0)), gc_after(&env_ref, &[
+            "(define w (vector 0))",
+            "(define l (list 1 w))",
+            "(define m (cdr l))",
+            "(vector-set! w 0 l)",
+            "(define w 0)",
+            "(define l 0)"
+        ]));
+        assert_eq!(Ok(Value::Integer(1)), run_with_env("(vector-length (car m))", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Integer(3)), gc_after(&env_ref, &["(define m 0)"]));
+    }
+
+    #[test]
+    fn heap_statistics_builtin() {
+        let env_ref = default_env();
+        for step in &["(define p (cons 1 2))", "(set-car! p p)", "(define p 0)"] {
+            run_with_env(step, env_ref.clone());
+        }
+        // the pair, the environment a pair was defined in, and the one the procedures
+        // of the prelude were defined in
+        assert_eq!(3, heap_statistics().tracked);
+        assert_eq!(1, collect_garbage());
+        let stats = heap_statistics();
+        assert_eq!((1, 1, 2), (stats.collections, stats.freed, stats.tracked));
+        assert_eq!(Ok(Value::list(vec![
+            Value::cons(Value::Symbol("collections".into()), Value::Integer(1)),
+            Value::cons(Value::Symbol("freed".into()), Value::Integer(1)),
+            Value::cons(Value::Symbol("traced".into()), Value::Integer(stats.traced as i64)),
+            Value::cons(Value::Symbol("tracked".into()), Value::Integer(2))
+        ])), run_with_env("(heap-statistics)", env_ref.clone()).value);
+        assert_eq!(Err("gc function requires no argument"), run("(gc 1)").value);
+    }
+
+    #[test]
+    fn automatic_collections() {
+        let env_ref = default_env();
+        run_with_env("(define make (lambda (n) (if (= n 0) 0 (begin (define v (vector 0)) (vector-set! v 0 v) (make (- n 1))))))", env_ref.clone());
+        set_gc_threshold(None);
+        run_with_env("(make 1000)", env_ref.clone());
+        assert_eq!(0, heap_statistics().collections);
+
+        // the vectors left by the first run are freed while the second one goes on
+        set_gc_threshold(Some(100));
+        run_with_env("(make 1000)", env_ref.clone());
+        let stats = heap_statistics();
+        assert!(stats.collections > 0);
+        assert!(stats.freed >= 1000, "{:?}", stats);
+    }
+}
+
+#[test]
+fn vector_test() {
+    let vector = |v: Vec<Value>| Value::Vector(Rc::new(RefCell::new(v)));
+    assert_eq!(Ok(vector(vec![Value::Integer(1), Value::Symbol("a".into()), vector(vec![])])), run("#(1 a #())").value);
+    assert_eq!(Ok(vector(vec![Value::Integer(2); 3])), run("(make-vector 3 2)").value);
+    assert_eq!(Ok(Value::Integer(3)), run("(vector-length (vector 1 2 (+ 1 2)))").value);
+    assert_eq!(Ok(Value::Symbol("b".into())), run("(vector-ref #(a b c) 1)").value);
+    assert_eq!(Err("vector-ref index out of range"), run("(vector-ref #(a b c) 3)").value);
+    assert_eq!(Err("vector-length function requires an argument of type 'vector'"), run("(vector-length (list 1))").value);
+    assert_eq!(Err("unexpected ."), run("#(1 . 2)").value);
+    assert_eq!(Ok(Value::Bool(true)), run("(vector? #(1))").value);
+    assert_eq!(Ok(Value::Bool(false)), run("(vector? (list 1))").value);
+    assert_eq!("#(1 \"a\" #\\b)", run("(vector 1 \"a\" #\\b)").value.unwrap().to_string());
+
+    // vectors are mutable and shared between the bindings that refer to them
+    let env_ref = default_env();
+    run_with_env("(define v (make-vector 2))(define w v)", env_ref.clone());
+    assert_eq!(Ok(Value::Unspecified), run_with_env("(vector-set! v 0 (quote x))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Symbol("x".into())), run_with_env("(vector-ref w 0)", env_ref.clone()).value);
+    assert_eq!(Err("vector-set! index out of range"), run_with_env("(vector-set! v 2 1)", env_ref.clone()).value);
+    assert_eq!("#('x 0)", run_with_env("w", env_ref.clone()).value.unwrap().to_string());
+}
+
+#[test]
+fn value_accessors_test() {
+    assert_eq!(Some(3), run("(+ 1 2)").value.unwrap().as_i64());
+    assert_eq!(None, run("(expt 2 64)").value.unwrap().as_i64());
+    assert_eq!(None, run("1.0").value.unwrap().as_i64());
+    assert_eq!(Some(0.5), run("(/ 1 2)").value.unwrap().as_f64());
+    assert_eq!(Some(2.0), run("2").value.unwrap().as_f64());
+    assert_eq!(None, run("\"2\"").value.unwrap().as_f64());
+    assert_eq!(Some("ab"), run("(string-append \"a\" \"b\")").value.unwrap().as_str());
+    assert_eq!(None, run("(quote ab)").value.unwrap().as_str());
+    assert_eq!(Some(SymbolId::intern("ab")), run("(quote ab)").value.unwrap().as_symbol());
+    assert_eq!(Some('a'), run("#\\a").value.unwrap().as_char());
+    assert_eq!(Some(false), run("(null? 1)").value.unwrap().as_bool());
+
+    // every value but #f is true, the empty list and 0 included
+    assert!(run("(quote ())").value.unwrap().is_truthy());
+    assert!(run("0").value.unwrap().is_truthy());
+    assert!(!run("#f").value.unwrap().is_truthy());
+
+    let elements = vec![Value::Integer(1), Value::Integer(2)];
+    assert_eq!(Some(elements.clone()), run("(list 1 2)").value.unwrap().as_list());
+    assert_eq!(Some(elements.clone()), run("(cons 1 (cons 2 (quote ())))").value.unwrap().as_list());
+    assert_eq!(Some(vec![]), run("(quote ())").value.unwrap().as_list());
+    assert_eq!(None, run("(cons 1 2)").value.unwrap().as_list());
+    assert_eq!(None, run("(vector 1 2)").value.unwrap().as_list());
+
+    assert_eq!(Ok(Value::string("a")), run("\"a\"").value);
+    assert_eq!(Ok(Value::symbol("a")), run("(quote a)").value);
+    assert_eq!(Ok(Value::vector(elements)), run("(vector 1 2)").value);
+    assert_eq!(Ok(Value::bytevector(vec![1, 2])), run("(bytevector 1 2)").value);
+}
+
+#[test]
+fn value_conversions_test() {
+    use std::convert::TryFrom;
+
+    assert_eq!(Ok(Value::from(7)), run("7").value);
+    assert_eq!(Ok(Value::from(2.5)), run("2.5").value);
+    assert_eq!(Ok(Value::from("hi")), run("\"hi\"").value);
+    assert_eq!(Ok(Value::from(String::from("hi"))), run("\"hi\"").value);
+    assert_eq!(Ok(Value::from('x')), run("#\\x").value);
+    assert_eq!(Ok(Value::from(vec![1u8, 2, 3])), run("(list 1 2 3)").value);
+    assert_eq!(Value::Nil, Value::from(Vec::<i64>::new()));
+    assert_eq!("18446744073709551615", Value::from(u64::MAX).to_string());
+
+    assert_eq!(Ok(7), i64::try_from(run("(+ 3 4)").value.unwrap()));
+    assert_eq!(Ok(0.5), f64::try_from(run("(/ 1 2)").value.unwrap()));
+    assert_eq!(Ok(String::from("ab")), String::try_from(run("(string-append \"a\" \"b\")").value.unwrap()));
+    assert_eq!(Ok(vec![1, 2]), Vec::<u32>::try_from(run("(vector 1 2)").value.unwrap()));
+    assert_eq!(Ok(vec![true, false]), Vec::<bool>::try_from(run("(cons #t (cons #f '()))").value.unwrap()));
+    assert_eq!(Err("expected an exact integer"), i64::try_from(Value::from(1.5)).map_err(|e| e.message()));
+    assert_eq!(Err("integer out of range"), u8::try_from(Value::from(256)).map_err(|e| e.message()));
+    assert_eq!(Err("integer out of range"), i64::try_from(run("(* 99999999999 99999999999)").value.unwrap()).map_err(|e| e.message()));
+    assert_eq!(Err("expected a list"), Vec::<i64>::try_from(Value::from(1)).map_err(|e| e.message()));
+
+    // association lists are vectors of tuples
+    let alist = vec![("a", 1), ("b", 2)].to_scheme();
+    let env = testing::fixture_env(&[("alist", alist.clone())]);
+    eval_ok!(env, "(cdr (assoc \"b\" alist))", 2);
+    assert_eq!(Ok(vec![(String::from("a"), 1), (String::from("b"), 2)]), Vec::<(String, i64)>::from_scheme(&alist));
+    assert_eq!(Ok((1, vec![2, 3])), <(i64, Vec<i64>)>::from_scheme(&run("(list 1 2 3)").value.unwrap()));
+}
+
+#[test]
+fn bytevector_test() {
+    let bytevector = |b: Vec<u8>| Value::Bytevector(Rc::new(RefCell::new(b)));
+    assert_eq!(Ok(bytevector(vec![1, 2, 255])), run("#u8(1 2 255)").value);
+    assert_eq!(Err("bytevector element must be a byte"), run("#u8(1 256)").value);
+    assert_eq!(Err("bytevector element must be a byte"), run("#u8(a)").value);
+    assert_eq!(Ok(bytevector(vec![7; 3])), run("(make-bytevector 3 7)").value);
+    assert_eq!(Err("make-bytevector function requires a fill of type 'byte'"), run("(make-bytevector 3 -1)").value);
+    assert_eq!(Ok(bytevector(vec![1, 2, 3])), run("(bytevector-append #u8(1) (bytevector 2) #u8() #u8(3))").value);
+    assert_eq!(Err("bytevector-append function requires arguments of type 'bytevector'"), run("(bytevector-append #u8(1) #(2))").value);
+    assert_eq!(Ok(Value::Integer(2)), run("(bytevector-length #u8(4 5))").value);
+    assert_eq!(Ok(Value::Integer(5)), run("(bytevector-u8-ref #u8(4 5) 1)").value);
+    assert_eq!(Err("bytevector-u8-ref index out of range"), run("(bytevector-u8-ref #u8(4 5) 2)").value);
+    assert_eq!(Ok(Value::Bool(true)), run("(bytevector? (make-bytevector 0))").value);
+    assert_eq!(Ok(Value::Bool(false)), run("(bytevector? #(1))").value);
+    assert_eq!("#u8(0 16)", run("(bytevector 0 16)").value.unwrap().to_string());
+
+    let env_ref = default_env();
+    run_with_env("(define b (make-bytevector 2))", env_ref.clone());
+    assert_eq!(Ok(Value::Unspecified), run_with_env("(bytevector-u8-set! b 1 200)", env_ref.clone()).value);
+    assert_eq!(Err("bytevector-u8-set! function requires a value of type 'byte'"), run_with_env("(bytevector-u8-set! b 1 300)", env_ref.clone()).value);
+    assert_eq!(Ok(bytevector(vec![0, 200])), run_with_env("b", env_ref.clone()).value);
+}
+
+#[test]
+fn watch_test() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let sink = events.clone();
+    set_watch_handler(Some(Rc::new(move |event: &WatchEvent| sink.borrow_mut().push(event.clone()))));
+
+    let env_ref = default_env();
+    run_with_env("(watch 'total)", env_ref.clone());
+    run_with_env(r#"
+    (define total 1)
+    (define bump (lambda (total) (+ total 1)))
+    (define other 2)
+    (bump (+ total other))
+    "#, env_ref.clone());
+    run_with_env("(unwatch 'total)", env_ref.clone());
+    run_with_env("(define total 5) total", env_ref.clone());
+    set_watch_handler(None);
+
+    // every program runs inside the `begin` that wraps it
+    let stack = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+    let accesses = events.borrow().iter()
+        .map(|e| (e.access, e.value.clone(), e.stack.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![
+        (WatchAccess::Write, Value::Integer(1), stack(&["begin"])),
+        (WatchAccess::Read, Value::Integer(1), stack(&["begin", "bump", "+"])),
+        (WatchAccess::Write, Value::Integer(3), stack(&["begin", "bump"])),
+        // the body of `bump` calls `+` in tail position, which takes its place
+        (WatchAccess::Read, Value::Integer(3), stack(&["begin", "+"])),
+    ], accesses);
+    assert!(events.borrow().iter().all(|e| e.symbol == "total"));
+    assert_eq!(Err("watch function requires a symbol or the start and end of a span"), run("(watch 1)").value);
+    assert_eq!(Err("watch function requires a symbol or the start and end of a span"), run("(watch 5 2)").value);
+
+    // a span watch reports the accesses the forms read from the span make, whatever
+    // the names, and not those of the procedures they call
+    let source = "(define total 1)\n(define (add x) (+ x total))\n(add (+ total 2))";
+    let call = Span { start: source.rfind("(add").unwrap(), end: source.len() };
+    for engine in [Engine::TreeWalker, Engine::Bytecode] {
+        set_engine(engine);
+        let events = Rc::new(RefCell::new(vec![]));
+        let sink = events.clone();
+        set_watch_handler(Some(Rc::new(move |event: &WatchEvent| sink.borrow_mut().push(event.clone()))));
+        watch_span(call);
+        run_with_env(source, default_env());
+        unwatch_span(call);
+        run_with_env(source, default_env());
+        set_watch_handler(None);
+
+        let accesses = events.borrow().iter().map(|e| (e.symbol.clone(), e.access, e.value.clone())).collect::<Vec<_>>();
+        assert_eq!(vec!["add", "+", "total"], accesses.iter().map(|(symbol, _, _)| symbol.as_str()).collect::<Vec<_>>(), "{:?}", engine);
+        assert!(accesses.iter().all(|(_, access, _)| *access == WatchAccess::Read));
+        assert_eq!(Value::Integer(1), accesses[2].2);
+        assert!(events.borrow().iter().all(|e| e.span.is_some_and(|span| call.start <= span.start && span.end <= call.end)));
+    }
+    set_engine(Engine::TreeWalker);
+}
+
+#[test]
+fn eval_observer_test() {
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<String>>);
+
+    impl EvalObserver for Recorder {
+        fn on_enter_form(&self, form: &Value) {
+            self.0.borrow_mut().push(format!("enter {}", form));
+        }
+
+        fn on_apply(&self, procedure: &Value, args: &[Value]) {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+            self.0.borrow_mut().push(format!("apply {} {}", procedure, args.join(" ")));
+        }
+
+        fn on_define(&self, name: &str, value: &Value) {
+            self.0.borrow_mut().push(format!("define {} {}", name, value));
+        }
+
+        fn on_error(&self, error: &SchemeError) {
+            self.0.borrow_mut().push(format!("error {}", error));
+        }
+    }
+
+    for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+        set_engine(*engine);
+        let recorder = Rc::new(Recorder::default());
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.set_eval_observer(Some(recorder.clone()));
+        interpreter.eval_str("(define (double x) (* 2 x)) (double 21)").unwrap();
+        interpreter.eval_str("(double missing)").unwrap_err();
+        interpreter.set_eval_observer(None);
+        interpreter.eval_str("(double 1)").unwrap();
+
+        let (entered, events): (Vec<String>, Vec<String>) = recorder.0.borrow().iter().cloned().partition(|event| event.starts_with("enter"));
+        assert_eq!(vec![
+            "define double #<procedure double (x)>",
+            "apply #<procedure double (x)> 21",
+            "apply #<procedure *> 2 21",
+            "apply #<procedure begin> #<unspecified> 42",
+            "error symbol is not defined: missing"
+        ], events);
+        // compiled code reports only the programs it runs
+        let forms = if *engine == Engine::TreeWalker { 11 } else { 2 };
+        assert_eq!(forms, entered.len());
+        assert!(entered[0].starts_with("enter '('begin '('define 'double"));
+    }
+    set_engine(Engine::TreeWalker);
+
+    // an interpreter's observer sees its evaluations alone, and a new interpreter
+    // starts with the observer of the thread
+    let (own, thread) = (Rc::new(Recorder::default()), Rc::new(Recorder::default()));
+    let observed = Interpreter::new().unwrap();
+    observed.set_eval_observer(Some(own.clone()));
+    set_eval_observer(Some(thread.clone()));
+    let other = Interpreter::new().unwrap();
+    observed.eval_str("(define x 1)").unwrap();
+    other.eval_str("(define y 2)").unwrap();
+    set_eval_observer(None);
+    other.eval_str("(define v 5)").unwrap();
+    observed.clone().eval_str("(define z 3)").unwrap();
+    let defined = |recorder: &Recorder| recorder.0.borrow().iter().filter(|event| event.starts_with("define")).cloned().collect::<Vec<String>>();
+    assert_eq!(vec!["define x 1", "define z 3"], defined(&own));
+    // changing the observer of the thread leaves the interpreters made already alone
+    assert_eq!(vec!["define y 2", "define v 5"], defined(&thread));
+    observed.set_eval_observer(None);
+    observed.eval_str("(define w 4)").unwrap();
+    assert_eq!(2, defined(&own).len());
+}
+
+#[test]
+fn error_environment_test() {
+    let env_ref = default_env();
+    run_with_env("(define f (lambda (x) (+ x missing)))", env_ref.clone());
+    assert_eq!(None, error_environment());
+
+    assert_eq!(Err("symbol is not defined."), run_with_env("(f 41)", env_ref.clone()).value);
+    let error_env = error_environment().unwrap();
+    assert_eq!(Ok(Value::Integer(42)), run_with_env("(+ x 1)", error_env.clone()).value);
+
+    // a successful evaluation forgets the failure
+    run_with_env("(f)", env_ref.clone());
+    assert!(error_environment().is_some());
+    run_with_env("1", env_ref.clone());
+    assert_eq!(None, error_environment());
+}
+
+#[test]
+fn environment_introspection_test() {
+    for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+        set_engine(*engine);
+        let env_ref = default_env();
+        run_with_env("(define x 5)(define f (lambda (x y) (environment-bindings)))", env_ref.clone());
+        assert_eq!(Ok(Value::Bool(true)), run_with_env("(bound? (quote car))", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(true)), run_with_env("(bound? (quote x))", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(false)), run_with_env("(bound? (quote y))", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(true)), run_with_env("((lambda (y) (bound? (quote y))) 1)", env_ref.clone()).value);
+
+        // the parameters come first, and hide the global `x`
+        let bindings = match run_with_env("(f 1 2)", env_ref.clone()).value {
+            Ok(bindings) => bindings.as_list().unwrap(),
+            value => panic!("{:?}", value)
+        };
+        let binding = |name: &str, value: Value, frame: i64| Value::list(vec![Value::Symbol(name.into()), value, Value::Integer(frame)]);
+        assert_eq!(binding("x", Value::Integer(1), 0), bindings[0]);
+        assert_eq!(binding("y", Value::Integer(2), 0), bindings[1]);
+        let f = run_with_env("f", env_ref.clone()).value.unwrap();
+        assert!(bindings.contains(&binding("f", f, 1)));
+        assert!(!bindings.contains(&binding("x", Value::Integer(5), 1)));
+        assert_eq!(Err("environment-bindings function requires no argument"), run_with_env("(environment-bindings 1)", env_ref.clone()).value);
+        assert_eq!(Err("bound? function requires an argument of type 'symbol'"), run_with_env("(bound? 1)", env_ref.clone()).value);
+    }
+    set_engine(Engine::TreeWalker);
+
+    let global = default_env();
+    let local = Rc::new(RefCell::new(Env::new(vec![(SymbolId::intern("car"), Value::Integer(1))].into_iter().collect(), Some(global.clone()))));
+    assert_eq!(Some(0), local.borrow().frame_of(SymbolId::intern("car")));
+    assert_eq!(Some(1), local.borrow().frame_of(SymbolId::intern("cdr")));
+    assert_eq!(None, local.borrow().frame_of(SymbolId::intern("nope")));
+    let visible = local.borrow().visible_bindings();
+    assert_eq!(Binding { name: SymbolId::intern("car"), value: Value::Integer(1), frame: 0 }, visible[0]);
+    assert_eq!(global.borrow().bindings().len(), visible.len());
+    assert!(visible[1..].iter().all(|binding| binding.frame == 1));
+}
+
+#[test]
+fn environment_snapshot_test() {
+    let env_ref = default_env();
+    run_with_env("(define x 1)(define v (vector 1))(define-syntax swap (syntax-rules () ((_ a b) (list b a))))", env_ref.clone());
+    let snapshot = env_ref.borrow().snapshot();
+    run_with_env("(vector-set! v 0 3)(+ x 1)", env_ref.clone());
+    assert!(!env_ref.borrow().changed_since(&snapshot));
+    run_with_env("(define x 2)(define y 3)(define car cdr)(define-syntax swap (syntax-rules () ((_ a b) (list a b))))(vector-set! v 0 2)", env_ref.clone());
+    assert_eq!(Ok(Value::Integer(2)), run_with_env("x", env_ref.clone()).value);
+    assert!(env_ref.borrow().changed_since(&snapshot));
+
+    env_ref.borrow_mut().restore(&snapshot);
+    assert!(!env_ref.borrow().changed_since(&snapshot));
+    assert_eq!(Ok(Value::Integer(1)), run_with_env("x", env_ref.clone()).value);
+    assert_eq!(Err("symbol is not defined."), run_with_env("y", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Integer(1)), run_with_env("(car (list 1 2))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::list(vec![Value::Integer(2), Value::Integer(1)])), run_with_env("(swap 1 2)", env_ref.clone()).value);
+    // the vector is the same one, changed in place
+    assert_eq!(Ok(Value::Integer(2)), run_with_env("(vector-ref v 0)", env_ref.clone()).value);
+
+    // a snapshot can be restored more than once
+    run_with_env("(define x 4)", env_ref.clone());
+    env_ref.borrow_mut().restore(&snapshot);
+    assert_eq!(Ok(Value::Integer(1)), run_with_env("x", env_ref.clone()).value);
+}
+
+#[test]
+fn persistent_environment_test() {
+    for engine in &[Engine::TreeWalker, Engine::Bytecode] {
+        set_engine(*engine);
+        let env_ref = Rc::new(RefCell::new(Env::persistent(builtins().into_iter().collect(), None)));
+        run_with_env("(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))(define x 5)", env_ref.clone());
+        assert_eq!(Ok(Value::Integer(120)), run_with_env("(fact x)", env_ref.clone()).value);
+        assert!(env_ref.borrow().local.borrow().is_empty());
+
+        // a fork sees the bindings made before it and none made after it, either way
+        let bindings = env_ref.borrow().persistent_bindings().unwrap();
+        let fork = Rc::new(RefCell::new(Env::persistent(bindings, None)));
+        run_with_env("(define x 6)(define y 1)", fork.clone());
+        run_with_env("(define z 1)", env_ref.clone());
+        assert_eq!(Ok(Value::Integer(720)), run_with_env("(fact x)", fork.clone()).value);
+        assert_eq!(Ok(Value::Integer(120)), run_with_env("(fact x)", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(false)), run_with_env("(bound? (quote y))", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Bool(false)), run_with_env("(bound? (quote z))", fork.clone()).value);
+
+        let snapshot = env_ref.borrow().snapshot();
+        run_with_env("(fact 3)", env_ref.clone());
+        assert!(!env_ref.borrow().changed_since(&snapshot));
+        run_with_env("(define x 7)", env_ref.clone());
+        assert!(env_ref.borrow().changed_since(&snapshot));
+        env_ref.borrow_mut().restore(&snapshot);
+        assert_eq!(Ok(Value::Integer(5)), run_with_env("x", env_ref.clone()).value);
+    }
+    set_engine(Engine::TreeWalker);
+
+    let interpreter = InterpreterBuilder::new().persistent_environment().build().unwrap();
+    interpreter.eval_str("(define x 1)").unwrap();
+    let bindings = interpreter.global_env().borrow().persistent_bindings().unwrap();
+    interpreter.eval_str("(define x 2)").unwrap();
+    assert_eq!(Some(&Value::Integer(1)), bindings.get(&SymbolId::intern("x")));
+    assert!(interpreter.global_env().borrow().local.borrow().is_empty());
+    assert!(Interpreter::new().unwrap().global_env().borrow().persistent_bindings().is_none());
+}
+
+#[test]
+fn persistent_map_test() {
+    // keys of the same hash, or of the same first bits of it, go down to the last level
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct Key(u32);
+    impl std::hash::Hash for Key {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            (self.0 % 7).hash(state);
+        }
+    }
+
+    let mut map = PersistentMap::new();
+    let mut model = std::collections::HashMap::new();
+    let mut versions = vec![];
+    let mut seed = 344u64;
+    for _ in 0..5000 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let key = (seed % 300) as u32;
+        if seed.is_multiple_of(3) {
+            map = map.remove(&key);
+            model.remove(&key);
+        } else {
+            map = map.insert(key, seed);
+            model.insert(key, seed);
+        }
+        if seed.is_multiple_of(100) {
+            versions.push((map.clone(), model.clone()));
+        }
+    }
+    versions.push((map, model));
+    // the old versions are left as they were
+    for (map, model) in &versions {
+        assert_eq!(model.len(), map.len());
+        assert!(model.iter().all(|(key, value)| map.get(key) == Some(value)));
+        assert_eq!(model.len(), map.iter().count());
+    }
+
+    let map = (0..50).map(|i| (Key(i), i)).collect::<PersistentMap<Key, u32>>();
+    assert_eq!(50, map.len());
+    assert!((0..50).all(|i| map.get(&Key(i)) == Some(&i)));
+    let smaller = (0..50).step_by(2).fold(map.clone(), |map, i| map.remove(&Key(i)));
+    assert_eq!(25, smaller.len());
+    assert!((0..50).all(|i| smaller.contains_key(&Key(i)) == (i % 2 == 1)));
+    assert_eq!(None, smaller.remove(&Key(51)).get(&Key(51)));
+    assert_eq!(50, map.len());
+    assert!(map.ptr_eq(&map.clone()));
+    assert!(!map.ptr_eq(&smaller));
+}
+
+#[test]
+fn error_dump_test() {
+    let env_ref = default_env();
+    run_with_env(r#"
+    (define limit 10)
+    (define check (lambda (x) (+ x missing)))
+    (define outer (lambda (y) (check (* y 2))))
+    "#, env_ref.clone());
+    assert_eq!(None, error_dump());
+
+    assert_eq!(Err("symbol is not defined."), run_with_env("(outer 20)", env_ref.clone()).value);
+    let dump = error_dump().unwrap();
+    assert_eq!("symbol is not defined.", dump.error);
+    // calls in tail position take the place of their caller on the stack, but not
+    // in the trace
+    assert_eq!(vec!["begin", "+"], dump.stack);
+    assert_eq!(vec![("x".to_string(), "40".to_string())], dump.frames[0]);
+    // builtins are left out of the global frame
+    assert!(dump.frames.last().unwrap().contains(&("limit".to_string(), "10".to_string())));
+    assert!(!dump.frames.last().unwrap().iter().any(|(name, _)| name == "car"));
+    assert!(dump.trace.ends_with(&["outer".to_string(), "check".to_string(), "*".to_string(), "+".to_string()]));
+    assert_eq!(Ok(dump.clone()), Dump::parse(&dump.to_string()));
+
+    run_with_env("1", env_ref.clone());
+    assert_eq!(None, error_dump());
+    assert_eq!(Err("malformed dump"), Dump::parse("(dump (stack 1))").map_err(|e| e.message()));
+}
+
+#[test]
+fn hash_table_test() {
+    let env_ref = default_env();
+    run_with_env(r#"
+    (define t (make-hash-table))
+    (hash-table-set! t "one" 1)
+    (hash-table-set! t (quote (a b)) 2)
+    (hash-table-set! t 3 (quote three))
+    (hash-table-set! t "one" 11)
+    "#, env_ref.clone());
+    let run_t = |s: &str| run_with_env(s, env_ref.clone()).value;
+
+    assert_eq!(Ok(Value::Integer(3)), run_t("(hash-table-count t)"));
+    assert_eq!(Ok(Value::Integer(11)), run_t("(hash-table-ref t \"one\")"));
+    // keys compare by value, whatever the list is built from
+    assert_eq!(Ok(Value::Integer(2)), run_t("(hash-table-ref t (cons (quote a) (list (quote b))))"));
+    assert_eq!(Ok(Value::Symbol("three".into())), run_t("(hash-table-ref t 3)"));
+    assert_eq!(Ok(Value::Symbol("three".into())), run_t("(hash-table-ref t (- (factorial 30) (- (factorial 30) 3)))"));
+    assert_eq!(Err("hash-table-ref key not found"), run_t("(hash-table-ref t 3.0)"));
+    assert_eq!(Err("hash-table-ref key not found"), run_t("(hash-table-ref t \"two\")"));
+    assert_eq!(Ok(Value::Integer(0)), run_t("(hash-table-ref/default t \"two\" 0)"));
+    assert_eq!(Ok(Value::Bool(false)), run_t("(hash-table-contains? t (factorial 3))"));
+
+    assert_eq!(Ok(Value::Unspecified), run_t("(hash-table-delete! t 3)"));
+    assert_eq!(Ok(Value::Unspecified), run_t("(hash-table-delete! t 3)"));
+    assert_eq!(Ok(Value::Bool(false)), run_t("(hash-table-contains? t 3)"));
+    assert_eq!(Ok(Value::Integer(2)), run_t("(length (hash-table->alist t))"));
+    assert_eq!(Ok(Value::Integer(13)), run_t("(apply + (hash-table-values t))"));
+    assert_eq!(Ok(Value::Integer(2)), run_t("(length (hash-table-keys t))"));
+    assert_eq!("#<hash-table 2>", run_t("t").unwrap().to_string());
+
+    // mutable values are keys by identity
+    assert_eq!(Ok(Value::Unspecified), run_t("(define v (vector 1)) (hash-table-set! t v 1)"));
+    assert_eq!(Ok(Value::Bool(true)), run_t("(hash-table-contains? t v)"));
+    assert_eq!(Ok(Value::Bool(false)), run_t("(hash-table-contains? t (vector 1))"));
+
+    assert_eq!(Ok(Value::Bool(true)), run_t("(hash-table? t)"));
+    assert_eq!(Err("hash-table-set! function requires a hash table, a key and a value"), run_t("(hash-table-set! t 1)"));
+}
+
+#[test]
+fn destructive_list_test() {
+    let env_ref = default_env();
+    let run_e = |s: &str| run_with_env(s, env_ref.clone()).value.map(|v| v.to_string());
+
+    run_with_env("(define xs (cons 1 (cons 2 (cons 3 (list)))))", env_ref.clone());
+    assert_eq!(Ok("'(3 2 1)".to_string()), run_e("(reverse! xs)"));
+    // the first pair became the last one
+    assert_eq!(Ok("'(1)".to_string()), run_e("xs"));
+
+    run_with_env("(define a (cons 1 (list))) (define b (cons 2 (cons 3 (list))))", env_ref.clone());
+    assert_eq!(Ok("'(1 2 3 4)".to_string()), run_e("(append! a b (list) (list 4))"));
+    assert_eq!(Ok("'(1 2 3 4)".to_string()), run_e("a"));
+    assert_eq!(Ok("'(2 3 4)".to_string()), run_e("b"));
+    assert_eq!(Ok("'(1 2 . 5)".to_string()), run_e("(append! (list 1) (list 2) 5)"));
+    assert_eq!(Ok("'(3 2 1)".to_string()), run_e("(reverse! (list 1 2 3))"));
+    assert_eq!(Ok("'(3 2 1)".to_string()), run_e("(reverse! (cons 1 (list 2 3)))"));
+    assert_eq!(Err("reverse! function requires an argument of type 'list'"), run_e("(reverse! (cons 1 2))"));
+    assert_eq!(Err("append! function requires arguments of type 'list'"), run_e("(append! (cons 1 2) (list 3))"));
+
+    run_with_env("(define p (cons 1 2))", env_ref.clone());
+    run_with_env("(set-car! p 10) (set-cdr! p (list 20))", env_ref.clone());
+    assert_eq!(Ok("'(10 20)".to_string()), run_e("p"));
+    assert_eq!(Err("set-car! function requires arguments of type 'pair' and a value"), run_e("(set-car! (list) 2)"));
+
+    // lists built by `list` and quoted ones are made of pairs as well
+    run_with_env("(define l (list 1 2)) (define q '(1 2))", env_ref.clone());
+    run_with_env("(set-car! l 10) (set-cdr! q 9)", env_ref.clone());
+    assert_eq!(Ok("'(10 2)".to_string()), run_e("l"));
+    assert_eq!(Ok("'(1 . 9)".to_string()), run_e("q"));
+    assert_eq!(Ok("'(1 2 3)".to_string()), run_e("(let ((a (list 1 2))) (begin (append! a (list 3)) a))"));
+    assert_eq!(Ok("'(1)".to_string()), run_e("(let ((a '(1 2 3))) (begin (reverse! a) a))"));
+    run_with_env("(define (f) '(1 2))", env_ref.clone());
+    run_with_env("(set-car! (f) 5)", env_ref.clone());
+    assert_eq!(Ok("'(5 2)".to_string()), run_e("(f)"));
+    // and so are those of vectors, which evaluate to themselves
+    run_with_env("(define v #(0 (1 2)))", env_ref.clone());
+    run_with_env("(set-car! (vector-ref v 1) 5)", env_ref.clone());
+    assert_eq!(Ok("'(5 2)".to_string()), run_e("(vector-ref v 1)"));
+    assert!(matches!(run_with_env("(vector-ref #((1)) 0)", env_ref.clone()).value, Ok(Value::Pair(_))));
+
+    // relinking allocates nothing, unlike append
+    run_with_env("(define ys (cons 1 (cons 2 (list))))", env_ref.clone());
+    run_with_env("(append! ys (cons 3 (list)))", env_ref.clone());
+    let linked = last_eval_statistics().allocations;
+    run_with_env("(append ys (cons 3 (list)))", env_ref.clone());
+    assert!(linked < last_eval_statistics().allocations);
+    run_with_env("(reverse! ys)", env_ref.clone());
+    assert_eq!(0, last_eval_statistics().allocations);
+}
+
+#[test]
+fn record_test() {
+    let env_ref = default_env();
+    let run_e = |s: &str| run_with_env(s, env_ref.clone()).value;
+    assert_eq!(Ok(Value::Unspecified), run_e(r#"
+    (define-record-type <point>
+        (make-point x y)
+        point?
+        (x point-x set-point-x!)
+        (y point-y)
+        (label point-label set-point-label!))
+    (define p (make-point 1 2))
+    "#));
+    assert_eq!(Ok(Value::Integer(1)), run_e("(point-x p)"));
+    assert_eq!(Ok(Value::Bool(false)), run_e("(point-label p)"));
+    assert_eq!(Ok(Value::Unspecified), run_e("(set-point-x! p 10)"));
+    assert_eq!(Ok(Value::Integer(12)), run_e("(+ (point-x p) (point-y p))"));
+    assert_eq!(Ok(Value::Bool(true)), run_e("(point? p)"));
+    assert_eq!(Ok(Value::Bool(false)), run_e("(point? (vector 10 2))"));
+    assert_eq!("#<record point x=10 y=2 label=false>", run_e("p").unwrap().to_string());
+    assert_eq!(Err("wrong number of arguments to record constructor"), run_e("(make-point 1)"));
+
+    // a second definition with the same shape is a different type
+    assert_eq!(Ok(Value::Unspecified), run_e("(define-record-type point (make-other x y) other? (x other-x) (y other-y))"));
+    assert_eq!(Ok(Value::Bool(false)), run_e("(other? p)"));
+    assert_eq!(Err("record procedure applied to a value of another type"), run_e("(other-x p)"));
+
+    assert_eq!(Err("record constructor argument is not a field of the record type"), run("(define-record-type r (make-r z) r? (x r-x))").value);
+    assert_eq!(Err("wrong syntax for define-record-type expression"), run("(define-record-type r (make-r))").value);
+}
+
+#[test]
+fn path_access_test() {
+    let env_ref = default_env();
+    let run_e = |s: &str| run_with_env(s, env_ref.clone()).value.map(|v| v.to_string());
+    assert_eq!(Ok("#<unspecified>".to_string()), run_e(r#"
+    (define-record-type user (make-user name tags) user? (name user-name) (tags user-tags))
+    (define data (make-hash-table))
+    (hash-table-set! data 'users (vector (make-user "ann" '(a b)) (make-user "bob" '(c))))
+    "#));
+    assert_eq!(Ok("\"bob\"".to_string()), run_e("(ref-in data '(users 1 name))"));
+    assert_eq!(Ok("'b".to_string()), run_e("(ref-in data '(users 0 tags 1))"));
+    assert_eq!(Ok("\"nobody\"".to_string()), run_e("(ref-in data '(users 3 name) \"nobody\")"));
+    assert_eq!(Err("ref-in path not found"), run_e("(ref-in data '(users 3 name))"));
+    assert_eq!(Err("ref-in path not found"), run_e("(ref-in data '(users 0 age))"));
+
+    run_e("(update-in data '(users 1 name) (lambda (n) (string-append n \"!\")))").unwrap();
+    assert_eq!(Ok("\"bob!\"".to_string()), run_e("(ref-in data '(users 1 name))"));
+    run_e("(update-in data '(users 0 tags 0) (lambda (t) (quote z)))").unwrap();
+    assert_eq!(Ok("'z".to_string()), run_e("(ref-in data '(users 0 tags 0))"));
+    assert_eq!(Ok("'(1 20 3)".to_string()), run_e("(update-in (list 1 2 3) '(1) (lambda (x) (* x 10)))"));
+    assert_eq!(Err("update-in path not found"), run_e("(update-in (list 1 2 3) '(5) (lambda (x) (+ x 1)))"));
+
+    // symbols look up association lists, as assv does
+    run_e("(define config (list (cons 'users (list (list (cons 'name \"cy\")) (list (cons 'name \"di\")))) (cons 'size 2)))").unwrap();
+    assert_eq!(Ok("\"di\"".to_string()), run_e("(ref-in config '(users 1 name))"));
+    assert_eq!(Ok("2".to_string()), run_e("(ref-in config '(size))"));
+    assert_eq!(Err("ref-in path not found"), run_e("(ref-in config '(users 3 name))"));
+    assert_eq!(Ok("'(1 2)".to_string()), run_e("(ref-in '((a 1 2) (b 3)) '(a))"));
+    run_e("(update-in config '(users 0 name) (lambda (n) (string-append n \"!\")))").unwrap();
+    assert_eq!(Ok("\"cy!\"".to_string()), run_e("(ref-in config '(users 0 name))"));
+    assert_eq!(Ok("10".to_string()), run_e("(ref-in (update-in '((a 1 2) (b 3)) '(a) (lambda (x) 10)) '(a))"));
+    assert_eq!(Err("update-in path not found"), run_e("(update-in config '(age) (lambda (x) x))"));
+    // indexing follows the pairs it needs, so circular lists can be indexed
+    run_e("(define ring (list 1 2 3)) (set-cdr! (cddr ring) ring)").unwrap();
+    assert_eq!(Ok("2".to_string()), run_e("(ref-in ring '(4))"));
+    assert_eq!(Err("ref-in path not found"), run_e("(ref-in ring '(x))"));
+}
+
+#[test]
+fn quasiquote_test() {
+    let env_ref = default_env();
+    let run_e = |s: &str| run_with_env(s, env_ref.clone()).value;
+    assert_eq!(Ok(Value::Unspecified), run_e("(begin (define name 'a) (define name1 'x) (define name2 'y))"));
+    // examples from section 4.2.8 of R7RS
+    let cases = [
+        ("`(list ,(+ 1 2) 4)", "'(list 3 4)"),
+        ("`(list ,name ',name)", "'(list a (quote a))"),
+        ("`(a ,(+ 1 2) ,@(map abs '(4 -5 6)) b)", "'(a 3 4 5 6 b)"),
+        ("`((foo ,(- 10 3)) ,@(cdr '(c)) . ,(car '(cons)))", "'((foo 7) . cons)"),
+        ("`#(10 5 ,(+ 1 1) ,@(map abs '(-4 3)) 8)", "'#(10 5 2 4 3 8)"),
+        ("`(1 ,@'() 2)", "'(1 2)"),
+        ("`(a `(b ,(+ 1 2) ,(foo ,(+ 1 3) d) e) f)", "'(a (quasiquote (b (unquote (+ 1 2)) (unquote (foo 4 d)) e)) f)"),
+        ("`(a `(b ,,name1 ,',name2 d) e)", "'(a (quasiquote (b (unquote x) (unquote (quote y)) d)) e)"),
+        ("`#(a `#(,@(list 1) ,,@(list 2 3)))", "'#(a (quasiquote #((unquote-splicing (list 1)) (unquote 2 3))))"),
+        ("(quasiquote (1 (unquote (+ 1 1))))", "'(1 2)")
+    ];
+    for &(template, expected) in cases.iter() {
+        assert_eq!(run_e(expected), run_e(template), "{}", template);
+    }
+    assert_eq!(Err("unquote-splicing is only allowed inside a list or vector"), run_e("`,@(list 1)"));
+    assert_eq!(Err("unquote-splicing requires a value of type 'list'"), run_e("`(1 ,@2)"));
+    // derived forms are expanded in unquoted tails of dotted templates
+    assert_eq!(run_e("'(1 . 3)"), run_e("`(1 . ,(let ((x 1)) (+ x 2)))"));
+    assert_eq!(Err("circular quasiquote template"), run_e("`#0=(1 . #0#)"));
+}
+
+#[test]
+fn check_test() {
+    let source = "(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))\n(display (fact 5))";
+    assert_eq!(Vec::<Diagnostic>::new(), check(source));
+
+    let source = "(define list 1)\n(define f (lambda (x) (+ x y)))\n  (if 1 2)";
+    let found = check(source).into_iter()
+        .map(|d| (line_column(source, d.span.start), d.severity, d.message))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![
+        ((1, 1), Severity::Warning, "definition of list shadows a builtin".to_string()),
+        ((2, 1), Severity::Error, "unbound variable y".to_string()),
+        ((3, 3), Severity::Error, "wrong syntax for if expression".to_string())
+    ], found);
+
+    // nothing is evaluated, the record type only introduces its procedure names
+    let source = "(display 1)\n(define-record-type point (make-point x) point? (x point-x))\n(point-x (make-point z))";
+    assert_eq!(vec!["unbound variable z".to_string()], check(source).into_iter().map(|d| d.message).collect::<Vec<_>>());
+    assert_eq!(vec![(Span { start: 12, end: 15 }, "unbound variable f".to_string()), (Span { start: 15, end: 16 }, "unexpected )".to_string())],
+        check("(display 1)\n(f))").into_iter().map(|d| (d.span, d.message)).collect::<Vec<_>>());
+
+    // imports bind the names of the libraries they name, which must be known
+    let messages = |source: &str| check(source).into_iter().map(|d| d.message).collect::<Vec<String>>();
+    assert_eq!(Vec::<String>::new(), messages("(import (scheme base) (scheme write))\n(display (car '(1)))"));
+    let source = "(define-library (util)\n  (export double (rename triple thrice))\n  (import (scheme base))\n          (begin (define double (lambda (x) (* 2 x))) (define triple (lambda (x) (* 3 x)))))\n        (import (prefix (util) u:) (rename (only (scheme base) car) (car first)))\n(first (list (u:double 1) (u:thrice 2)))";
+    assert_eq!(Vec::<String>::new(), messages(source));
+    assert_eq!(vec!["unknown library (srfi 1)".to_string(), "unbound variable fold".to_string()],
+        messages("(import (scheme base) (srfi 1))\n(fold + 0 '(1 2))"));
+    assert_eq!(vec!["unbound variable missing".to_string(), "unbound variable g".to_string()],
+        messages("(define-library (lib) (export f) (begin (define f (lambda () missing))))\n(import (only (lib) g))\n(import (prefix (scheme base) s:))\n(s:cdr (g))"));
+}
+
+#[test]
+fn immediate_values_test() {
+    // an exact integer has one representation whatever produced it
+    assert_eq!(run("5").value, run("(- (+ (factorial 30) 5) (factorial 30))").value);
+    assert_eq!(run("5").value, run("(* (/ 5 7) 7)").value);
+    assert_eq!(run("5").value, run("#e5.0").value);
+    assert_ne!(run("5").value, run("5.0").value);
+    assert_eq!(Ok(Value::Bool(true)), run("(= 5 5.0)").value);
+    assert_eq!(Ok(Value::Integer(i64::MIN)), run("(- -9223372036854775807 1)").value);
+
+    assert_eq!(run("#\\a").value, run("(integer->char 97)").value);
+    assert_eq!(run("#t").value, run("(< 1 2)").value);
+    let empty = Ok(Value::Nil);
+    assert_eq!(empty, run("(list)").value);
+    assert_eq!(empty, run("'()").value);
+    assert_eq!(empty, run("(cdr (list 1))").value);
+
+    let env_ref = default_env();
+    run_with_env("(define t (make-hash-table)) (hash-table-set! t 7 'seven) (hash-table-set! t #\\a 'a)", env_ref.clone());
+    assert_eq!(Ok(Value::Symbol("seven".into())), run_with_env("(hash-table-ref t (- (+ (factorial 25) 7) (factorial 25)))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Symbol("a".into())), run_with_env("(hash-table-ref t (integer->char 97))", env_ref.clone()).value);
+
+    // values are no bigger than a list, and copies of big numbers share their digits
+    assert!(std::mem::size_of::<Value>() <= std::mem::size_of::<Vec<Value>>());
+    assert_eq!(std::mem::size_of::<Value>(), std::mem::size_of::<Option<Value>>());
+    run_with_env("(define big (factorial 30)) (define half (/ 1 2)) (define numbers (list big half))", env_ref.clone());
+    match (run_with_env("big", env_ref.clone()).value, run_with_env("(car numbers)", env_ref.clone()).value) {
+        (Ok(Value::BigInt(a)), Ok(Value::BigInt(b))) => assert!(Rc::ptr_eq(&a, &b)),
+        values => panic!("{:?}", values)
+    }
+    match (run_with_env("half", env_ref.clone()).value, run_with_env("(car (cdr numbers))", env_ref.clone()).value) {
+        (Ok(Value::Rational(a)), Ok(Value::Rational(b))) => assert!(Rc::ptr_eq(&a, &b)),
+        values => panic!("{:?}", values)
+    }
+}
+
+#[test]
+fn port_test() {
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let captured = Rc::new(RefCell::new(vec![]));
+    set_current_output_port(Some(Rc::new(Port::output(Box::new(SharedBuffer(captured.clone()))))));
+    run("(display \"x = \") (display 42) (newline) (print (list 1 2))");
+    assert_eq!("x = 42\n'(1 2)\n", String::from_utf8(captured.borrow().clone()).unwrap());
+
+    let other = Rc::new(RefCell::new(vec![]));
+    let env_ref = default_env();
+    env_ref.borrow().local.borrow_mut().insert(SymbolId::intern("out"), Value::Port(Rc::new(Port::output(Box::new(SharedBuffer(other.clone()))))));
+    run_with_env("(display \"to port\" out) (newline out)", env_ref.clone());
+    assert_eq!("to port\n", String::from_utf8(other.borrow().clone()).unwrap());
+    assert_eq!("x = 42\n'(1 2)\n", String::from_utf8(captured.borrow().clone()).unwrap());
+
+    assert_eq!(Ok(Value::Bool(true)), run_with_env("(output-port? out)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Bool(false)), run_with_env("(input-port? out)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Bool(true)), run("(input-port? (current-input-port))").value);
+    assert_eq!(Ok(Value::Bool(false)), run("(output-port? 1)").value);
+    assert_eq!(Ok(Value::Unspecified), run_with_env("(close-port out)", env_ref.clone()).value);
+    assert_eq!(Err("port is closed"), run_with_env("(display 1 out)", env_ref.clone()).value);
+    assert_eq!(Err("port argument must be an output port"), run("(display 1 (current-input-port))").value);
+
+    // write puts down values as the reader reads them, and display as text
+    let written = Rc::new(RefCell::new(vec![]));
+    env_ref.borrow().local.borrow_mut().insert(SymbolId::intern("out"), Value::Port(Rc::new(Port::output(Box::new(SharedBuffer(written.clone()))))));
+    run_with_env(r#"(write "a \"b\"" out) (write-char #\space out) (write #\c out) (write-char #\λ out) (write (list 'x "y" 1.5) out) (display "a \"b\"" out)"#, env_ref.clone());
+    assert_eq!(r#""a \"b\"" #\cλ(x "y" 1.5)a "b""#, String::from_utf8(written.borrow().clone()).unwrap());
+    assert_eq!(Err("write-char function requires an argument of type 'char'"), run_with_env("(write-char \"c\" out)", env_ref.clone()).value);
+    assert_eq!(Err("write function requires one or two arguments"), run("(write)").value);
+
+    set_current_output_port(None);
+}
+
+#[test]
+fn read_test() {
+    let input = "(define x\n  (list 1 2))  #\\a\n3.5 'sym\n\n";
+    set_current_input_port(Some(Rc::new(Port::input(Box::new(std::io::Cursor::new(input))))));
+    let env_ref = default_env();
+    assert_eq!(Ok(Value::Integer(3)), run_with_env("(length (read))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Char('a')), run_with_env("(read)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Number(3.5)), run_with_env("(read (current-input-port))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::list(vec![Value::Symbol("quote".into()), Value::Symbol("sym".into())])),
+        run_with_env("(read)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Eof), run_with_env("(read)", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Bool(true)), run_with_env("(eof-object? (read))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Bool(true)), run("(eof-object? (eof-object))").value);
+    assert_eq!(Ok(Value::Bool(false)), run("(eof-object? '())").value);
+
+    set_current_input_port(Some(Rc::new(Port::input(Box::new(std::io::Cursor::new("(1 2"))))));
+    assert_eq!(Err("syntax error"), run("(read)").value);
+    run("(close-port (current-input-port))");
+    assert_eq!(Err("port is closed"), run("(read)").value);
+    assert_eq!(Err("port argument must be an input port"), run("(read (current-output-port))").value);
+    set_current_input_port(None);
+}
+
+#[test]
+fn streaming_reader() {
+    let input = "(define s \"two\nlines\") #| a comment\nover lines |# 'x\n#;\n(skipped)\n(1 2) (1 2";
+    let mut reader = Reader::new(std::io::BufReader::new(std::io::Cursor::new(input)));
+    assert_eq!(Ok(Some("(define s \"two\\nlines\")".to_string())), reader.read_datum().map(|d| d.map(|ast| unparse(&ast))));
+    assert_eq!(Ok(Some("(quote x)".to_string())), reader.read_datum().map(|d| d.map(|ast| unparse(&ast))));
+    assert_eq!(Ok(Some("(1 2)".to_string())), reader.read_datum().map(|d| d.map(|ast| unparse(&ast))));
+    assert_eq!(Err("syntax error"), reader.read_datum().map_err(|e| e.message()));
+    assert_eq!(Ok(None), reader.read_datum());
+
+    let forms = Reader::from_read("1 2\n3".as_bytes()).collect::<Result<Vec<Value>, _>>();
+    assert_eq!(Ok(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]), forms);
+
+    // a malformed datum is reported without reading on to the end of the input
+    struct Endless;
+    impl std::io::Read for Endless {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            panic!("read past the malformed datum")
+        }
+    }
+    let mut reader = Reader::new(std::io::BufReader::new(std::io::Read::chain("#z\n".as_bytes(), Endless)));
+    assert!(reader.read_datum().is_err());
+    let mut reader = Reader::new(std::io::BufReader::new(std::io::Read::chain("'\n(a\n\"b)\" #\\( #| c |#\n)\n".as_bytes(), Endless)));
+    assert_eq!(Ok(Some("(quote (a \"b)\" #\\())".to_string())), reader.read_datum().map(|d| d.map(|ast| unparse(&ast))));
+
+    // each line of a long datum is parsed once
+    let long = format!("(list\n{})", "1\n".repeat(20000));
+    let started = std::time::Instant::now();
+    assert_eq!(Some(40006), Reader::new(long.as_bytes()).read_datum().unwrap().map(|d| unparse(&d).len()));
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+}
+
+#[test]
+fn recovering_reader() {
+    let input = "(define x 1)\n(define y (+ x 1)))\n(display \"a\\q\")\n(define z (list 1 . 2 3))\n(define w\n(display w)\n; the end";
+    let mut reader = Reader::recovering(input.as_bytes());
+    let forms = reader.by_ref().map(|form| unparse(&form.unwrap())).collect::<Vec<String>>();
+    assert_eq!(vec!["(define x 1)", "(define y (+ x 1))", "(display w)"], forms);
+    assert_eq!(&[
+        SyntaxError { span: Span { start: 31, end: 32 }, message: "unexpected )" },
+        SyntaxError { span: Span { start: 44, end: 46 }, message: "unknown escape sequence in string" },
+        SyntaxError { span: Span { start: 49, end: 74 }, message: "unexpected ." },
+        SyntaxError { span: Span { start: 75, end: 84 }, message: "syntax error" },
+    ], reader.errors());
+    assert_eq!(parse_program(input).1, reader.errors());
+
+    let mut reader = Reader::new("(1 . ) 2 ; comment\n3 \"a\\q\"".as_bytes());
+    assert_eq!(Err("unexpected ."), reader.read_datum().map_err(|e| e.message()));
+    assert_eq!(Ok(Some(Value::Integer(2))), reader.read_datum());
+    assert_eq!(Ok(Some(Value::Integer(3))), reader.read_datum());
+    assert_eq!(Err("unknown escape sequence in string"), reader.read_datum().map_err(|e| e.message()));
+    assert_eq!(Ok(None), reader.read_datum());
+    assert!(reader.errors().is_empty());
+}
+
+#[test]
+fn nil_and_unspecified_test() {
+    let env_ref = default_env();
+    assert_eq!(Ok(Value::Unspecified), run_with_env("(define x 1)", env_ref.clone()).value);
+    assert_eq!("#<unspecified>", run("(define x 2)").value.unwrap().to_string());
+    assert_eq!(Ok(Value::Unspecified), run_with_env("(define y (define z 2))", env_ref.clone()).value);
+    assert_eq!(Ok(Value::Unspecified), run_with_env("y", env_ref.clone()).value);
+
+    assert_eq!(Ok(Value::Nil), run("'()").value);
+    assert_ne!(run("#f").value, run("'()").value);
+    assert_eq!("'()", run("(cdr (list 1))").value.unwrap().to_string());
+    assert_eq!(Ok(Value::Bool(true)), run("(list? '())").value);
+    assert_eq!(Ok(Value::Integer(0)), run("(length '())").value);
+    assert_eq!(Ok(Value::list(vec![Value::Integer(1), Value::Nil])), run("(list 1 '())").value);
+    assert_eq!("'(1 2)", run("(append '() (list 1) '() (list 2))").value.unwrap().to_string());
+    assert_eq!("'(1)", run("(cons 1 '())").value.unwrap().to_string());
+    assert_eq!(Err("car function requires a non-empty list"), run("(car '())").value);
+}
+
+#[test]
+fn symbol_interning_test() {
+    assert_eq!(SymbolId::intern("apple"), SymbolId::intern("apple"));
+    assert_ne!(SymbolId::intern("apple"), SymbolId::intern("orange"));
+    assert_eq!("apple", &*SymbolId::intern("apple").name());
+    assert_eq!("\"apple\"", format!("{:?}", SymbolId::intern("apple")));
+
+    let env_ref = default_env();
+    run_with_env("(define fruit (quote apple))", env_ref.clone());
+    assert_eq!(Ok(Value::Symbol(SymbolId::intern("apple"))), run_with_env("fruit", env_ref.clone()).value);
+    assert!(env_ref.borrow().local.borrow().contains_key(&SymbolId::intern("fruit")));
+}
+
+mod reader {
+    use super::*;
+
+    #[test]
+    fn parse_datum_span() {
+        assert_eq!(Ok((Value::Integer(42), Span { start: 2, end: 4 })), parse_datum("  42 (ignored)"));
+        let (ast, span) = parse_datum("(define s \"a (b)\") 1").unwrap();
+        assert_eq!(Value::list(vec![
+            Value::Symbol("define".into()),
+            Value::Symbol("s".into()),
+            Value::String("a (b)".into()),
+        ]), ast);
+        assert_eq!(Span { start: 0, end: 18 }, span);
+        assert_eq!(Ok((Value::String("x".into()), Span { start: 1, end: 4 })), parse_datum(" \"x\""));
+        assert_eq!(Err("unexpected EOF while reading"), parse_datum("   ").map_err(|e| e.message()));
+        assert_eq!(Err("syntax error"), parse_datum("(1 2").map_err(|e| e.message()));
+    }
+
+    #[test]
+    fn lists_read_at_their_size() {
+        fn exact(value: &Value) -> bool {
+            match value {
+                Value::List(list) => list.capacity() == list.len() && list.iter().all(exact),
+                Value::Vector(vector) => vector.borrow().capacity() == vector.borrow().len() && vector.borrow().iter().all(exact),
+                _ => true
+            }
+        }
+        let source = "(define (f x) (list 1 2 3 4 5 6 7 8 9 (quote (a b . c)) #(1 2 3 4 5) (g x x x x x)))";
+        let (ast, _) = parse_datum(source).unwrap();
+        assert!(exact(&ast));
+        assert_eq!(source, unparse(&ast));
+        // the lists of one read are not mixed up with those of the next
+        let (forms, errors) = parse_program(&format!("{} (1 . 2) #(x (y))", source));
+        assert!(errors.is_empty());
+        assert_eq!(vec![source, "(1 . 2)", "#(x (y))"], forms.iter().map(|(form, _)| unparse(form)).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn recovering_parse() {
+        let source = "(define x 1)\n(define y (+ x 1)))\n(display \"a\\q\")\n(define z (list 1 . 2 3))\n(define w\n(display w)\n";
+        let (forms, errors) = parse_program(source);
+        assert_eq!(vec![
+            SyntaxError { span: Span { start: 31, end: 32 }, message: "unexpected )" },
+            SyntaxError { span: Span { start: 44, end: 46 }, message: "unknown escape sequence in string" },
+            SyntaxError { span: Span { start: 49, end: 74 }, message: "unexpected ." },
+            SyntaxError { span: Span { start: 75, end: 84 }, message: "syntax error" },
+        ], errors);
+        assert_eq!(vec![
+            "(define x 1)".to_string(),
+            "(define y (+ x 1))".to_string(),
+            "(display \"a\")".to_string(),
+            "(display w)".to_string(),
+        ], forms.iter().map(|(ast, _)| unparse(ast)).collect::<Vec<String>>());
+        assert_eq!(Span { start: 85, end: 96 }, forms[3].1);
+    }
+
+    #[test]
+    fn reader_limits() {
+        let hostile = "(".repeat(10_000_000);
+        assert_eq!(Err("datum nested too deeply"), run(&hostile).value);
+        assert_eq!(Err("datum nested too deeply"), run(&"'".repeat(10_000_000)).value);
+        let nested = format!("(quote {}1{})", "(".repeat(500), ")".repeat(500));
+        assert!(run(&nested).value.is_ok());
+
+        set_reader_limits(ReaderLimits { max_depth: 3, max_datums: 8, max_token_length: 5 });
+        assert_eq!(Ok(Value::Integer(3)), run("(car (list 3))").value);
+        assert_eq!(Err("datum nested too deeply"), run("(car '(list 3))").value);
+        assert_eq!(Err("too many datums"), run("(list 1 2 3 4 5 6 7)").value);
+        assert_eq!(Err("token too long"), run("\"abcdef\"").value);
+        assert_eq!(vec![SyntaxError { span: Span { start: 4, end: 10 }, message: "token too long" }], parse_program("(f) 123456 (g)").1);
+        set_reader_limits(ReaderLimits::default());
+    }
+
+    #[test]
+    fn prefixed_literals() {
+        assert_eq!(Ok(Value::Integer(31)), run("#x1F").value);
+        assert_eq!(Ok(Value::Integer(-255)), run("#X-ff").value);
+        assert_eq!(Ok(Value::Integer(15)), run("#o17").value);
+        assert_eq!(Ok(Value::Integer(10)), run("#b1010").value);
+        assert_eq!(Ok(Value::Integer(10)), run("#d10").value);
+        assert_eq!(Ok(Value::Number(1.5)), run("#d1.5").value);
+        assert_eq!(Ok(Value::Number(5.0)), run("#i#b101").value);
+        assert_eq!(Ok(Value::Integer(16)), run("#x#e10").value);
+        assert_eq!(Ok(Value::Integer(42)), run("(+ #x20 #b1010)").value);
+        assert_eq!("1/2", run("#x1/2").value.unwrap().to_string());
+        assert_eq!("18446744073709551615", run("#xffffffffffffffff").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Bytevector(Rc::new(RefCell::new(vec![255, 8])))), parse_datum("#u8(#xff #o10)").map(|(ast, _)| ast));
+        assert_eq!(Ok(Value::Integer(31)), run("(string->number \"#x1F\")").value);
+
+        assert_eq!(Ok(Value::Bool(true)), run("#true").value);
+        assert_eq!(Ok(Value::Bool(false)), run("#false").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(if #false #f #true)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(car '(#false))").value);
+
+        // malformed literals are rejected while reading, not when evaluated
+        assert_eq!(Err("syntax error"), parse_datum("#tru").map(|(ast, _)| ast).map_err(|e| e.message()));
+        assert_eq!(Err("syntax error"), run("(if #f (#truth) 1)").value);
+        assert_eq!(Err("bad number literal"), run("(if #f #x1G 1)").value);
+        assert_eq!(Err("bad number literal"), run("#b102").value);
+        assert_eq!(Err("bad number literal"), run("#x1.5").value);
+        assert_eq!(Err("syntax error"), run("#x#x1").value);
+        assert_eq!(Err("syntax error"), run("#e#i1").value);
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(Ok(Value::Number(0.001)), run("1e-3").value);
+        assert_eq!(Ok(Value::Number(1500.0)), run("1.5E3").value);
+        assert_eq!(Ok(Value::Number(1.5)), run("+1.5").value);
+        assert_eq!(Ok(Value::Number(-0.5)), run("-.5").value);
+        assert_eq!(Ok(Value::Number(f64::INFINITY)), run("+inf.0").value);
+        assert_eq!(Ok(Value::Number(f64::NEG_INFINITY)), run("-inf.0").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(< -inf.0 0 +inf.0)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(= +nan.0 +nan.0)").value);
+
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        assert_eq!(Ok("+inf.0".to_string()), text("(/ 1.0 0)"));
+        assert_eq!(Ok("-inf.0".to_string()), text("(- +inf.0)"));
+        assert_eq!(Ok("+nan.0".to_string()), text("(/ 0.0 0)"));
+        assert_eq!(Ok("\"+inf.0\"".to_string()), text("(number->string +inf.0)"));
+        assert_eq!(Ok(Value::Number(f64::NEG_INFINITY)), run("(string->number \"-inf.0\")").value);
+        assert_eq!("(+inf.0 -inf.0 +nan.0 0.001)", unparse(&parse_datum("(+inf.0 -inf.0 +nan.0 1e-3)").unwrap().0));
+
+        // words that Rust reads as floats are symbols
+        assert_eq!(Err("symbol is not defined."), run("inf").value);
+        assert_eq!(Err("symbol is not defined."), run("NaN").value);
+        assert_eq!(Err("symbol is not defined."), run("infinity").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(string->number \"inf\")").value);
+    }
+
+    #[test]
+    fn number_printing() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        assert_eq!(Ok("1.0".to_string()), text("1.0"));
+        assert_eq!(Ok("-0.0".to_string()), text("-0.0"));
+        assert_eq!(Ok("0.1".to_string()), text("0.1"));
+        assert_eq!(Ok("0.30000000000000004".to_string()), text("(+ 0.1 0.2)"));
+        assert_eq!(Ok("1e300".to_string()), text("1e300"));
+        assert_eq!(Ok("1e-7".to_string()), text("0.0000001"));
+        assert_eq!(Ok("5.0".to_string()), text("(* 2.5 2 (/ 1 1))"));
+        assert_eq!(Ok("5".to_string()), text("(+ 2 3)"));
+        assert_eq!(Ok("\"2.0\"".to_string()), text("(number->string (exact->inexact 2))"));
+
+        // every float reads back as the same value
+        for &f in [1.0, -2.5, 0.1, 1.0 / 3.0, 123456789.125, 6.02e23, 1e-300, f64::MAX, f64::MIN_POSITIVE, 5e-324].iter() {
+            let printed = Value::Number(f).to_string();
+            assert_eq!(Ok(Value::Number(f)), run(&printed).value, "{}", printed);
+        }
+    }
+
+    #[test]
+    fn rational_literals() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        assert_eq!(Ok("3/4".to_string()), text("3/4"));
+        assert_eq!(Ok("-7/2".to_string()), text("-7/2"));
+        assert_eq!(Ok("1/2".to_string()), text("+2/4"));
+        assert_eq!(Ok(Value::Integer(2)), run("6/3").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(= (+ 1/3 2/3) 1)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(exact? 1/3)").value);
+        assert_eq!(Ok("'(1/2 3)".to_string()), text("'(1/2 3)"));
+        assert_eq!("(1/3 -5/7)", unparse(&parse_datum("(1/3 -5/7)").unwrap().0));
+        // integers too big for a fixnum are exact as well
+        assert_eq!(Ok("123456789012345678901234567890".to_string()), text("123456789012345678901234567890"));
+        assert_eq!(Ok(Value::Bool(true)), run("(exact? -9223372036854775809)").value);
+
+        assert_eq!(Err("bad number literal"), parse_datum("1/0").map(|(ast, _)| ast).map_err(|e| e.message()));
+        assert_eq!(Err("bad number literal"), run("1/2/3").value);
+        assert_eq!(Err("bad number literal"), run("(list 1/x)").value);
+        assert_eq!(Err("bad number literal"), run("1/-2").value);
+        assert_eq!(Err("bad number literal"), run("1.5/2").value);
+        // symbols may still contain slashes
+        assert_eq!(Ok(Value::Symbol("a/b".into())), run("'a/b").value);
+        assert_eq!(Ok(Value::Integer(2)), run("(/ 4 2)").value);
+    }
+
+    #[test]
+    fn comments() {
+        let source = r#"
+        ; a line comment
+        (define x 1) ; a comment after code
+        #| a block comment
+           #| that nests |#
+           (define x 2) |#
+        (define y #;(unused datum) 10)
+        (list x y #;'(1 2) #; #; a b ";" #\;)
+        "#;
+        assert_eq!("'(1 10 \";\" #\\;)", run(source).value.unwrap().to_string());
+        assert_eq!(Ok(Value::Integer(3)), run("(+ 1;comment\n2)").value);
+        assert_eq!(Ok(Value::Integer(3)), run("(+ 1 #|inline|#2)").value);
+        assert_eq!(Ok(Value::Integer(1)), run("(car '(1 #;(2 (3)) ))").value);
+        assert_eq!(Ok(Value::Integer(2)), run("#;1 2").value);
+        assert_eq!(Ok(Value::Integer(4)), run("(+ 2 2) ; no newline at the end").value);
+
+        assert_eq!(Err("unterminated block comment"), run("(+ 1 2) #| #| |#").value);
+        assert_eq!(Err("missing datum after #;"), run("(list 1 #;)").value);
+        assert_eq!(Err("missing datum after #;"), run("1 #;").value);
+
+        let (forms, errors) = parse_program("(a) #;(b)\n; (c)\n#|(d)|# (e)");
+        assert!(errors.is_empty());
+        assert_eq!(vec!["(a)".to_string(), "(e)".to_string()], forms.iter().map(|(ast, _)| unparse(ast)).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn large_programs() {
+        let definitions = (0..5000).map(|i| format!("(define x{} (+ {} 1))", i, i)).collect::<Vec<String>>().join("\n");
+        let env_ref = default_env();
+        run_with_env(&definitions, env_ref.clone()).value.unwrap();
+        assert_eq!(Ok(Value::Integer(5000)), run_with_env("x4999", env_ref.clone()).value);
+        assert_eq!(5000, parse_program(&definitions).0.len());
+
+        let elements = (0..50_000).map(|i| i.to_string()).collect::<Vec<String>>().join(" ");
+        assert_eq!(Ok(Value::Integer(50_000)), run(&format!("(length '({}))", elements)).value);
+        let (ast, span) = parse_datum(&format!("#({}) rest", elements)).unwrap();
+        assert_eq!(elements.len() + 3, span.end);
+        assert!(matches!(ast, Value::Vector(ref v) if v.borrow().len() == 50_000));
+    }
+
+    #[test]
+    fn datum_labels() {
+        assert_eq!("'('a 'a)", run("'(#0=a #0#)").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Bool(true)), run("(define l '(#0=(x) #0#)) (eq? (car l) (car (cdr l)))").value);
+        assert_eq!("'#0=('a 'b . #0#)", run("'#0=(a b . #0#)").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Symbol("a".into())), run("(car (cdr (cdr '#0=(a b . #0#))))").value);
+        assert_eq!("'('a . '#0=('b . #0#))", run("'#1=(a . #2=(b . #2#))").value.unwrap().to_string());
+        assert_eq!("#0=#(1 #0#)", run("'#5=#(1 #5#)").value.unwrap().to_string());
+        assert_eq!("'#0=(#0# . #0#)", run("'#0=(#0# . #0#)").value.unwrap().to_string());
+        // as they are for debugging, which the builtins log their arguments with
+        assert_eq!("Pair('#0=(1 . #0#))", format!("{:?}", run("'#0=(1 . #0#)").value.unwrap()));
+        assert_eq!("Vector(#0=#(1 #0#))", format!("{:?}", run("'#5=#(1 #5#)").value.unwrap()));
+        assert_eq!("Integer(1)", format!("{:?}", Value::Integer(1)));
+
+        let env_ref = default_env();
+        run_with_env("(define l (cons 1 (cons 2 (cons 3 '()))))", env_ref.clone());
+        run_with_env("(set-cdr! (cdr (cdr l)) l)", env_ref.clone());
+        assert_eq!("'#0=(1 2 3 . #0#)", run_with_env("l", env_ref.clone()).value.unwrap().to_string());
+        run_with_env("(define v (vector l 'x))", env_ref.clone());
+        run_with_env("(vector-set! v 1 v)", env_ref.clone());
+        assert_eq!("#0=#('#1=(1 2 3 . #1#) #0#)", run_with_env("v", env_ref.clone()).value.unwrap().to_string());
+        assert_eq!(Ok(Value::String("#0=(1 2 3 . #0#) (1 2)".into())), run_with_env("(format \"~a ~a\" l '(1 2))", env_ref.clone()).value);
+
+        assert_eq!(Ok(Value::Bool(true)), run("(define l '#0=(a #0#)) (eq? l (car (cdr l)))").value);
+        assert_eq!("#0=(a #0#)", unparse(&parse_datum("#0=(a #0#)").unwrap().0));
+        assert_eq!(Err("undefined datum label"), run("'(#0# #0=a)").value);
+        assert_eq!(Err("datum labels and dotted lists can only be used in quoted data"), run("#0=(+ 1 2)").value);
+        assert_eq!(Err("unexpected )"), run("'#0=").value);
+    }
+
+    #[test]
+    fn symbol_syntax() {
+        assert_eq!("'|a b|", run("'|a b|").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Symbol("a b".into())), run("'|a b|").value);
+        assert_eq!(Ok(Value::Symbol("xAy".into())), run(r"'|x\x41;y|").value);
+        assert_eq!(r"'|a\|b|", run(r"'|a\|b|").value.unwrap().to_string());
+        assert_eq!("'('a '|b c| 'd)", run("'(a|b c|d)").value.unwrap().to_string());
+        assert_eq!("'|1|", run("'|1|").value.unwrap().to_string());
+        assert_eq!(Ok(Value::String("λ".into())), run(r#""\x3bb;""#).value);
+        assert_eq!("'('... '-> '+a '.foo 'a.b '!$%&*/:<=>?^_~ 'a@b)", run("'(... -> +a .foo a.b !$%&*/:<=>?^_~ a@b)").value.unwrap().to_string());
+        assert_eq!("(quote |a b|)", unparse(&parse_datum("'|a b|").unwrap().0));
+
+        // strings, symbols between bars and single characters keep their case
+        assert_eq!("'('abc \"ABC\" 'ABC #\\A #\\space)", run("#!fold-case '(ABC \"ABC\" |ABC| #\\A #\\SPACE)").value.unwrap().to_string());
+        assert_eq!(Ok(Value::Bool(true)), run("(define abc 1) #!fold-case (= ABC Abc abc)").value);
+        assert_eq!("'('abc 'ABC #\\A)", run("#!fold-case '(ABC #!no-fold-case ABC #\\A)").value.unwrap().to_string());
+
+        assert_eq!(Err("can not find an end bar"), run("'|a b").value);
+        assert_eq!(Err("bad hexadecimal escape"), run(r"'|\xZZ;|").value);
+        assert_eq!(PartialParse::Incomplete { missing_parens: 0 }, parse_partial("'|a b"));
+    }
+
+    #[test]
+    fn partial_input() {
+        assert_eq!(PartialParse::Complete(vec![Value::Integer(1), Value::list(vec![Value::Symbol("f".into())])]), parse_partial("1 (f) ; done"));
+        assert_eq!(PartialParse::Complete(vec![]), parse_partial("  #| nothing |#"));
+        assert_eq!(PartialParse::Incomplete { missing_parens: 2 }, parse_partial("(define (f x)\n  (g x"));
+        assert_eq!(PartialParse::Incomplete { missing_parens: 1 }, parse_partial("(display \"a)"));
+        assert_eq!(PartialParse::Incomplete { missing_parens: 0 }, parse_partial("1 #| comment"));
+        assert_eq!(PartialParse::Incomplete { missing_parens: 0 }, parse_partial("(list) '"));
+        assert_eq!(PartialParse::Incomplete { missing_parens: 1 }, parse_partial("(list #;"));
+
+        assert_eq!(PartialParse::Invalid(SyntaxError { span: Span { start: 3, end: 4 }, message: "unexpected )" }), parse_partial("(f))(g"));
+        assert_eq!(PartialParse::Invalid(SyntaxError { span: Span { start: 0, end: 7 }, message: "unexpected ." }), parse_partial("(. a b) (c"));
+        assert_eq!(PartialParse::Invalid(SyntaxError { span: Span { start: 3, end: 5 }, message: "unknown escape sequence in string" }), parse_partial("(\"a\\q"));
+        assert_eq!(PartialParse::Invalid(SyntaxError { span: Span { start: 0, end: 4 }, message: "unexpected )" }), parse_partial("(' )"));
+    }
+
+    #[test]
+    fn error_positions() {
+        let source = "(define f (lambda (x)\n  (car x)))\n(+ 1 (f 5))";
+        let error = evaluate(source).unwrap_err();
+        assert_eq!("car function requires an argument of type 'list' / 'pair'", error.message());
+        let span = error.span().unwrap();
+        assert_eq!("(f 5)", &source[span.start..span.end]);
+        assert_eq!("error at 3:6: bad call", error_at(source, span, "bad call"));
+
+        // a parenthesis closed once too often is reported where it is
+        let source = "(define (f x)\n  (* x 2))\n(list (f 10))) (f 2)";
+        let error = parse(source).unwrap_err();
+        assert_eq!("error at 3:14: unexpected )", error_at(source, error.span().unwrap(), error.message()));
+
+        let source = "(list 1\n  'a #;(b) \"c\" ; d\n  (car #t))";
+        let span = evaluate(source).unwrap_err().span().unwrap();
+        assert_eq!("(car #t)", &source[span.start..span.end]);
+        assert_eq!((3, 3), line_column(source, span.start));
+
+        // values built at run time were not read from the source
+        assert_eq!(None, apply_procedure(&evaluate("car").unwrap(), vec![Value::Integer(1)], default_env()).unwrap_err().span());
+
+        let source = "(+ 1\n  2))";
+        let error = &parse_program(source).1[0];
+        assert_eq!("error at 2:5: unexpected )", error_at(source, error.span, error.message));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let span = |start, end| Some(Span { start, end });
+        assert_eq!(Err(SchemeError::UnboundVariable { name: "nope".to_string(), span: span(5, 9), backtrace: Backtrace::default() }), evaluate("(+ 1 nope)"));
+        assert_eq!("symbol is not defined: nope", evaluate("(nope 1)").unwrap_err().to_string());
+        assert_eq!(Err(SchemeError::WrongType { message: "car function requires an argument of type 'list' / 'pair'", span: span(0, 7), backtrace: Backtrace::default() }), evaluate("(car 1)"));
+        assert_eq!(Err(SchemeError::Arity { message: "car function requires one argument only", span: span(0, 5), backtrace: Backtrace::default() }), evaluate("(car)"));
+        assert_eq!(Err(SchemeError::OutOfRange { message: "vector-ref index out of range", span: span(0, 21), backtrace: Backtrace::default() }), evaluate("(vector-ref #(1 2) 2)"));
+        assert_eq!(Err(SchemeError::Syntax { message: "wrong syntax for if expression", span: span(0, 6), backtrace: Backtrace::default() }), evaluate("(if 1)"));
+        assert_eq!(Err(SchemeError::Other { message: "division by zero", span: span(0, 14), backtrace: Backtrace::default() }), evaluate("(quotient 1 0)"));
+        assert_eq!(Err(SchemeError::wrong_type("wrong type to apply").with_span(Span { start: 13, end: 18 })), evaluate("(define x 1) (x 2)"));
+
+        assert_eq!(Err(SchemeError::ReadError { message: "unexpected .", span: span(6, 15), backtrace: Backtrace::default() }), parse("(f 1)\n(g (. a))").map(|ast| ast.result));
+        assert_eq!(Err(SchemeError::ReadError { message: "can not find an end quote", span: span(3, 6), backtrace: Backtrace::default() }), parse_datum("(a \"bc"));
+        assert_eq!(Err(SchemeError::ReadError { message: "syntax error", span: span(6, 12), backtrace: Backtrace::default() }), Reader::new("(a b)\n(c (d)".as_bytes()).nth(1).unwrap());
+
+        // a handler sees the message of any kind of error
+        assert_eq!(Ok(Value::String("symbol is not defined.".into())), evaluate("(with-exception-handler error-object-message (lambda () (+ nope 1)))"));
+    }
+
+    // Host code returning any error type that `?` converts into.
+    fn sum_of_cars(interpreter: &Interpreter, source: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let list = interpreter.eval_str(source)?;
+        let cars = interpreter.eval_str("(lambda (l) (+ (car (car l)) (car (car (cdr l)))))")?;
+        Ok(i64::from_scheme(&interpreter.call(&cars, &[list])?)?)
+    }
+
+    #[test]
+    fn error_backtraces() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.eval_str("(define check (lambda (x) (car x))) (define outer (lambda (y) (check (+ y 1))))").unwrap();
+        let error = interpreter.eval_str("(outer 1)").unwrap_err();
+        // calls in tail position take the place of their caller
+        assert_eq!(vec!["begin", "car"], error.backtrace().frames());
+        assert_eq!("   0: car\n   1: begin\n", error.backtrace().to_string());
+        interpreter.enter(|| set_engine(Engine::Bytecode));
+        let compiled = interpreter.eval_str("(outer 1)").unwrap_err();
+        interpreter.enter(|| set_engine(Engine::TreeWalker));
+        // compiled code calls builtins without a frame
+        assert_eq!(vec!["begin", "check"], compiled.backtrace().frames());
+        // the backtrace is not part of what errors are compared by
+        assert_eq!(SchemeError::wrong_type("car function requires an argument of type 'list' / 'pair'").with_span(error.span().unwrap()), error);
+        assert!(SchemeError::other("division by zero").backtrace().is_empty());
+
+        assert_eq!(3, sum_of_cars(&interpreter, "'((1) (2))").unwrap());
+        let error = sum_of_cars(&interpreter, "'((1) 2)").unwrap_err();
+        assert_eq!("car function requires an argument of type 'list' / 'pair'", error.to_string());
+        assert!(error.downcast_ref::<SchemeError>().is_some());
+        assert_eq!("integer out of range", sum_of_cars(&interpreter, "'((1) (#xFFFFFFFFFFFFFFFFFF))").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn shared_string_literals() {
+        let env_ref = default_env();
+        run_with_env("(define a \"point\")", env_ref.clone());
+        run_with_env("(define b (car (list \"point\")))", env_ref.clone());
+        match (run_with_env("a", env_ref.clone()).value, run_with_env("b", env_ref.clone()).value) {
+            (Ok(Value::String(a)), Ok(Value::String(b))) => assert!(Rc::ptr_eq(&a, &b)),
+            other => panic!("expected two strings, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn interned_across_parses() {
+        let string = |source: &str| match parse_datum(source).unwrap().0 {
+            Value::String(s) => s,
+            other => panic!("expected a string, got {:?}", other)
+        };
+        let symbols = |source: &str| match parse_datum(source).unwrap().0 {
+            Value::List(list) => list.iter().map(|s| s.as_symbol().unwrap()).collect::<Vec<SymbolId>>(),
+            other => panic!("expected a list, got {:?}", other)
+        };
+        assert!(Rc::ptr_eq(&string("\"interned-across\""), &string(" \"interned-across\"")));
+        assert_eq!(symbols("(interned-x interned-y)"), symbols("(interned-x interned-y)"));
+
+        // a literal spelled like a symbol shares its name, whichever was read first
+        let id = symbols("(interned-first)")[0];
+        assert!(Rc::ptr_eq(&id.name(), &string("\"interned-first\"")));
+        let literal = string("\"interned-second\"");
+        assert!(Rc::ptr_eq(&literal, &symbols("(interned-second)")[0].name()));
+
+        // literals nothing holds any more are dropped; symbols keep their ids
+        for i in 0..5000 {
+            string(&format!("\"interned-{}\"", i));
+        }
+        let statistics = interner_statistics();
+        assert!(statistics.strings < 2000, "{:?}", statistics);
+        assert_eq!(id, symbols("(interned-first)")[0]);
+        assert_eq!(statistics.symbols, interner_statistics().symbols);
+        assert!(Rc::ptr_eq(&literal, &intern_string("interned-second")));
+    }
+
+    #[test]
+    fn unparse_round_trip() {
+        for source in &[
+            "(define (f x) (* x 2.5))",
+            "(a . b)",
+            "(string-append \"say \\\"hi\\\"\\n\" \"\")",
+            "(list #\\space #\\( #\\a #t)",
+            "#(1 #(2) \"x\")",
+            "(#u8(0 255) #u8())",
+            "-17",
+        ] {
+            let (ast, _) = parse_datum(source).unwrap();
+            assert_eq!(*source, unparse(&ast));
+            assert_eq!(ast, parse_datum(&unparse(&ast)).unwrap().0);
+        }
+        assert_eq!("(1.0 2)", unparse(&parse_datum("( 1.0   2 )").unwrap().0));
+    }
+
+    #[test]
+    fn syntax_tree() {
+        fn symbol(name: &str, start: usize, end: usize) -> Syntax {
+            Syntax { kind: SyntaxKind::Symbol(name.to_string()), span: Span { start, end } }
+        }
+
+        let forms = parse_all("(f 1.5 \"s\") ; comment\n#(#\\a #t) '() (a . b)").unwrap();
+        assert_eq!(4, forms.len());
+        assert_eq!(Syntax {
+            kind: SyntaxKind::List(vec![
+                symbol("f", 1, 2),
+                Syntax { kind: SyntaxKind::Number(Value::Number(1.5)), span: Span { start: 3, end: 6 } },
+                Syntax { kind: SyntaxKind::String("s".to_string()), span: Span { start: 7, end: 10 } },
+            ]),
+            span: Span { start: 0, end: 11 }
+        }, forms[0]);
+        assert_eq!(SyntaxKind::Vector(vec![
+            Syntax { kind: SyntaxKind::Character('a'), span: Span { start: 24, end: 27 } },
+            Syntax { kind: SyntaxKind::Boolean(true), span: Span { start: 28, end: 30 } },
+        ]), forms[1].kind);
+        // the quote of an abbreviation has the span of the whole datum
+        assert_eq!(SyntaxKind::List(vec![
+            symbol("quote", 32, 35),
+            Syntax { kind: SyntaxKind::List(vec![]), span: Span { start: 33, end: 35 } },
+        ]), forms[2].kind);
+        assert_eq!(SyntaxKind::Datum(parse_datum("(a . b)").unwrap().0), forms[3].kind);
+        assert_eq!(Span { start: 36, end: 43 }, forms[3].span);
+
+        // cyclic data are kept whole
+        let forms = parse_all("(x #0=(a . #0#))").unwrap();
+        match forms[0].kind {
+            SyntaxKind::List(ref elements) => match elements[1].kind {
+                SyntaxKind::Datum(Value::Pair(_)) => assert_eq!(Span { start: 3, end: 15 }, elements[1].span),
+                ref kind => panic!("{:?}", kind)
+            },
+            ref kind => panic!("{:?}", kind)
+        }
+
+        // rewritten syntax can be evaluated
+        let mut form = parse_all("(+ 1 2)").unwrap().remove(0);
+        if let SyntaxKind::List(ref mut elements) = form.kind {
+            elements[0] = symbol("*", 1, 2);
+        }
+        assert_eq!(Ok(Value::Integer(2)), Interpreter::new().unwrap().eval_str(&unparse(&form.to_value())));
+
+        let error = parse_all("(ok) (1 2\n(3 \"x)").unwrap_err();
+        assert_eq!("syntax error", error.message());
+        assert_eq!(Some(Span { start: 5, end: 9 }), error.span());
+    }
+}
+
+mod formatting {
+    use super::*;
+
+    #[test]
+    fn format_directives() {
+        assert_eq!(Ok(Value::String("x = 1.5, s = \"a\"\n~".into())), run(r#"(format "x = ~a, s = ~s~%~~" 1.5 "a")"#).value);
+        assert_eq!(Ok(Value::String("(1 b c) #\\d d".into())), run(r#"(format "~a ~s ~a" (list 1 (quote b) "c") #\d #\d)"#).value);
+        assert_eq!(Ok(Value::String("(a \"b\") #(1 x)".into())), run(r#"(format "~s ~s" '(a "b") (vector 1 'x))"#).value);
+        assert_eq!(Ok(Value::String(unparse(&run(r#"'(a "b" #\c)"#).value.unwrap()).into())), run(r#"(format "~s" '(a "b" #\c))"#).value);
+        assert_eq!(Err("format function has too few arguments"), run(r#"(format "~a ~a" 1)"#).value);
+        assert_eq!(Err("format function has too many arguments"), run(r#"(format "~a" 1 2)"#).value);
+        assert_eq!(Err("unknown format directive"), run(r#"(format "~x" 1)"#).value);
+        assert_eq!(Err("format function requires a format string"), run("(format 1)").value);
+    }
+
+    #[test]
+    fn number_locale() {
+        let german = NumberLocale { decimal_separator: ',', grouping_separator: Some('.') };
+        assert_eq!(Some("1.234.567,5".to_string()), german.format(&Value::Number(1234567.5)));
+        assert_eq!(Some("-123,25".to_string()), german.format(&Value::Number(-123.25)));
+        assert_eq!(Some("3.628.800".to_string()), german.format(&run("(factorial 10)").value.unwrap()));
+        assert_eq!(None, german.format(&Value::Number(f64::NAN)));
+        assert_eq!(None, german.format(&Value::String("1".into())));
+    }
+
+    #[test]
+    fn number_formatter_hook() {
+        set_number_formatter(Some(NumberLocale { decimal_separator: ',', grouping_separator: Some(' ') }.formatter()));
+        assert_eq!(Ok(Value::String("total: 12 345,5 (12345.5)".into())), run(r#"(format "total: ~a (~s)" 12345.5 12345.5)"#).value);
+        assert_eq!(Ok(Value::String("(1 000 2,5)".into())), run(r#"(format "~a" (list 1000 2.5))"#).value);
+
+        set_number_formatter(Some(Rc::new(|value: &Value| match value {
+            Value::Integer(i) if *i < 0 => Some(format!("({})", -i)),
+            _ => None
+        })));
+        assert_eq!(Ok(Value::String("(3) 4".into())), run(r#"(format "~a ~a" -3 4)"#).value);
+
+        set_number_formatter(None);
+        assert_eq!(Ok(Value::String("12345.5".into())), run(r#"(format "~a" 12345.5)"#).value);
+    }
+
+    #[test]
+    fn scheme_syntax() {
+        let value = run(r#"(list "a \"b\"" #\a (cons 1 2) (vector 'x 1.5) '())"#).value.unwrap();
+        assert_eq!(r#"("a \"b\"" #\a (1 . 2) #(x 1.5) ())"#, value.to_scheme_string());
+        let mut out = String::from("> ");
+        value.write_to(&mut out).unwrap();
+        assert_eq!(format!("> {}", value.to_scheme_string()), out);
+        assert_eq!(value, parse_datum(&value.to_scheme_string()).unwrap().0);
+
+        // forms keep their first argument on their line, data line up
+        let (value, _) = parse_datum("(let ((x 1) (y 2)) (vector x y \"long string\") (cons x (y . z)))").unwrap();
+        assert_eq!(unparse(&value), value.to_pretty_string(80));
+        assert_eq!([
+            "(let ((x 1) (y 2))",
+            "  (vector x",
+            "    y",
+            "    \"long string\")",
+            "  (cons x (y . z)))",
+        ].join("\n"), value.to_pretty_string(20));
+        assert_eq!(value, parse_datum(&value.to_pretty_string(1)).unwrap().0);
+        let mut out = String::new();
+        value.write_pretty_to(&mut out, 20).unwrap();
+        assert_eq!(value.to_pretty_string(20), out);
+        assert_eq!("#(1\n  2\n  3)", Value::vector(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]).to_pretty_string(4));
+
+        // cycles are written on one line with their labels
+        let cycle = run("(let ((v (vector 1 2))) (vector-set! v 1 v) v)").value.unwrap();
+        assert_eq!("#0=#(1 #0#)", cycle.to_pretty_string(4));
+    }
+}
+
+mod number_theory {
+    use super::*;
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+
+    fn exact(s: &str) -> Result<Value, &'static str> {
+        Ok(Value::integer(s.parse::<BigInt>().unwrap()))
+    }
+
+    #[test]
+    fn factorial() {
+        assert_eq!(exact("1"), run("(factorial 0)").value);
+        assert_eq!(exact("3628800"), run("(factorial 10)").value);
+        assert_eq!(exact("30414093201713378043612608166064768844377641568960512000000000000"), run("(factorial 50)").value);
+        assert_eq!(Err("factorial function requires one argument only"), run("(factorial)").value);
+        assert_eq!(Err("factorial function requires an argument of type 'non-negative integer'"), run("(factorial -1)").value);
+        assert_eq!(Err("factorial function requires an argument of type 'non-negative integer'"), run("(factorial 1.5)").value);
+    }
+
+    #[test]
+    fn binomial() {
+        assert_eq!(exact("10"), run("(binomial 5 2)").value);
+        assert_eq!(exact("0"), run("(binomial 2 5)").value);
+        assert_eq!(exact("100891344545564193334812497256"), run("(binomial 100 50)").value);
+        assert_eq!(Err("binomial function requires two arguments"), run("(binomial 5)").value);
+    }
+
+    #[test]
+    fn primes() {
+        assert_eq!(Ok(Value::Bool(true)), run("(prime? 2)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(prime? 1)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(prime? 561)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(prime? 7919)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(prime? (+ (factorial 11) 1))").value);
+        assert_eq!(exact("2"), run("(next-prime 0)").value);
+        assert_eq!(exact("11"), run("(next-prime 7)").value);
+        assert_eq!(exact("1000000000000000003"), run("(next-prime (* (factorial 1) 1000000000000000000))").value);
+    }
+
+    #[test]
+    fn modular_expt() {
+        assert_eq!(exact("445"), run("(modular-expt 4 13 497)").value);
+        assert_eq!(exact("2"), run("(modular-expt -4 1 3)").value);
+        assert_eq!(exact("1"), run("(modular-expt (factorial 30) 0 7)").value);
+        assert_eq!(Err("modular-expt function requires a non-negative exponent and a positive modulus"), run("(modular-expt 2 3 0)").value);
+        assert_eq!(Err("modular-expt function requires three arguments"), run("(modular-expt 2 3)").value);
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(exact("7257600"), run("(* (factorial 10) (factorial 2))").value);
+        assert_eq!(Ok(Value::Integer(7257600)), run("(* (factorial 10) 2)").value);
+        assert_eq!(Ok(Value::Integer(3628801)), run("(+ (factorial 10) 1)").value);
+        assert_eq!(exact("3628801"), run("(+ (factorial 10) (factorial 0))").value);
+        assert_eq!(exact("-3628800"), run("(- (factorial 10))").value);
+        assert_eq!(exact("105"), run("(/ (factorial 10) (factorial 3) (factorial 4) (factorial 5) (factorial 2))").value);
+        assert_eq!(Ok(Value::rational(BigRational::new(1.into(), 2.into()))), run("(/ (factorial 1) (factorial 2))").value);
+        assert_eq!(Err("division by zero"), run("(/ (factorial 1) (binomial 1 2))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(> (factorial 30) (factorial 29))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(= (factorial 5) 120)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(number? (factorial 5))").value);
+        assert_eq!(exact("120"), run("(max (factorial 5) (factorial 4))").value);
+        assert_eq!(exact("120"), run("(abs (- (factorial 5)))").value);
+    }
+
+    #[test]
+    fn exactness() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        let cases = [
+            ("(/ 1 3)", "1/3"),
+            ("(+ (/ 1 3) (/ 2 3))", "1"),
+            ("(* (/ 3 4) 4)", "3"),
+            ("(- (/ 1 2))", "-1/2"),
+            ("(/ 4)", "1/4"),
+            ("(/ 6 4 3)", "1/2"),
+            ("(+ (/ 1 2) 0.5)", "1.0"),
+            ("(max (/ 1 2) (/ 1 3))", "1/2"),
+            ("(abs (/ -7 2))", "7/2"),
+            ("#e1.5", "3/2"),
+            ("#e-0.125e1", "-5/4"),
+            ("#e1e20", "100000000000000000000"),
+            ("#e1/3", "1/3"),
+            ("#i3", "3.0"),
+            ("#i1/4", "0.25"),
+            ("(exact->inexact (/ 1 4))", "0.25"),
+            ("(inexact->exact 0.5)", "1/2"),
+            ("(inexact->exact 2.0)", "2"),
+            ("(inexact->exact 0.1)", "3602879701896397/36028797018963968"),
+            ("(inexact->exact (/ 1 3))", "1/3")
+        ];
+        for &(expression, expected) in cases.iter() {
+            assert_eq!(Ok(expected.to_string()), text(expression), "{}", expression);
+        }
+        assert_eq!(Ok(Value::Bool(true)), run("(exact? (/ 1 3))").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(exact? (factorial 30))").value);
+        assert_eq!(Ok(Value::list(vec![Value::Integer(0), Value::Integer(1)])), run("(list (+) (*))").value);
+        assert_eq!(Err("- function requires at least one argument"), run("((begin -))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(exact? 1.0)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(inexact? #i1)").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(inexact? #e1.0)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(< (/ 1 3) 0.34)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(= (/ 1 2) 0.5)").value);
+        assert_eq!(Err("exact? function requires an argument of type 'number'"), run("(exact? (quote a))").value);
+        assert_eq!(Err("inexact->exact function requires a finite number"), run("(inexact->exact (/ 1.0 0))").value);
+        assert_eq!(Err("division by zero"), run("(/ (/ 1 2) 0)").value);
+        assert_eq!(Err("division by zero"), run("(/ 0)").value);
+        assert_eq!(Err("bad number literal"), run("#e1.5.2").value);
+    }
+
+    #[test]
+    fn chained_comparisons() {
+        let t = Ok(Value::Bool(true));
+        let f = Ok(Value::Bool(false));
+        assert_eq!(t, run("(< 1 2 3)").value);
+        assert_eq!(f, run("(< 1 3 2)").value);
+        assert_eq!(t, run("(<= 1 1 2 2.5)").value);
+        assert_eq!(t, run("(> 3 (/ 5 2) 2 -1.5)").value);
+        assert_eq!(f, run("(>= 3 3 4)").value);
+        assert_eq!(t, run("(= 1 1.0 (/ 2 2) #e1.0)").value);
+        assert_eq!(f, run("(= 1 1 2)").value);
+        // exact integers are not rounded to a float before comparing
+        assert_eq!(f, run("(= 9007199254740993 9007199254740992.0)").value);
+        assert_eq!(t, run("(< 9007199254740992.0 9007199254740993)").value);
+        assert_eq!(f, run("(< (/ 1 3) 0.3333333333333333333 1)").value);
+        assert_eq!(t, run("(< (/ -1.0 0) (- (factorial 30)) 1)").value);
+        assert_eq!(t, run("(< (factorial 30) (/ 1.0 0))").value);
+        assert_eq!(f, run("(= (/ 0.0 0) (/ 0.0 0))").value);
+        assert_eq!(Err("function requires at least 2 arguments"), run("(< 1)").value);
+        // every argument is checked, even after the chain has failed
+        assert_eq!(Err("wrong argument datatype"), run("(< 2 1 \"3\")").value);
+    }
+
+    #[test]
+    fn integer_division() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        let cases = [
+            ("(quotient 17 5)", "3"),
+            ("(quotient -17 5)", "-3"),
+            ("(remainder 17 -5)", "2"),
+            ("(remainder -17 5)", "-2"),
+            ("(modulo 17 -5)", "-3"),
+            ("(modulo -17 5)", "3"),
+            ("(modulo -17 -5)", "-2"),
+            ("(modulo 17.0 5)", "2.0"),
+            ("(quotient (factorial 20) (factorial 18))", "380"),
+            ("(gcd 12 -18)", "6"),
+            ("(gcd)", "0"),
+            ("(gcd 0 5)", "5"),
+            ("(lcm 4 -6)", "12"),
+            ("(lcm)", "1"),
+            ("(lcm 3 0)", "0")
+        ];
+        for &(expression, expected) in cases.iter() {
+            assert_eq!(Ok(expected.to_string()), text(expression), "{}", expression);
+        }
+        assert_eq!(Ok(Value::Number(2.0)), run("(modulo 17.0 5)").value);
+        assert_eq!(Ok(Value::Number(6.0)), run("(gcd 12.0 18)").value);
+        assert_eq!(Err("division by zero"), run("(remainder 1 0)").value);
+        assert_eq!(Err("integer division requires two arguments"), run("(quotient 1)").value);
+        assert_eq!(Err("integer division requires arguments of type 'integer'"), run("(quotient 1.5 2)").value);
+        assert_eq!(Err("gcd function requires arguments of type 'integer'"), run("(gcd (/ 1 2) 2)").value);
+    }
+
+    #[test]
+    fn rounding() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        let cases = [
+            ("(floor (/ 7 2))", "3"),
+            ("(floor (/ -7 2))", "-4"),
+            ("(ceiling (/ 7 2))", "4"),
+            ("(ceiling (/ -7 2))", "-3"),
+            ("(truncate (/ -7 2))", "-3"),
+            ("(round (/ 7 2))", "4"),
+            ("(round (/ 5 2))", "2"),
+            ("(round (/ -5 2))", "-2"),
+            ("(round (/ 7 3))", "2"),
+            ("(round 5)", "5"),
+            ("(floor-quotient -7 2)", "-4"),
+            ("(floor-remainder -7 2)", "1"),
+            ("(truncate-quotient -7 2)", "-3"),
+            ("(truncate-remainder -7 2)", "-1"),
+            ("(floor/ -7 2)", "'(-4 1)"),
+            ("(truncate/ -7 2)", "'(-3 -1)")
+        ];
+        for &(expression, expected) in cases.iter() {
+            assert_eq!(Ok(expected.to_string()), text(expression), "{}", expression);
+        }
+        assert_eq!(Ok(Value::Number(-4.0)), run("(floor -3.5)").value);
+        assert_eq!(Ok(Value::Number(-3.0)), run("(ceiling -3.5)").value);
+        assert_eq!(Ok(Value::Number(-3.0)), run("(truncate -3.7)").value);
+        assert_eq!(Ok(Value::Number(2.0)), run("(round 2.5)").value);
+        assert_eq!(Ok(Value::Number(4.0)), run("(round 3.5)").value);
+        assert_eq!(Ok(Value::Number(-4.0)), run("(round -3.5)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(exact? (floor (/ 1 2)))").value);
+        assert_eq!(Err("rounding requires an argument of type 'number'"), run("(round \"1\")").value);
+        assert_eq!(Err("division by zero"), run("(floor/ 1 0)").value);
+        assert_eq!(Err("integer division requires two arguments"), run("(truncate/ 1)").value);
+        assert_eq!(Err("rounding requires one argument only"), run("(round)").value);
+    }
+
+    #[test]
+    fn transcendental() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        let cases = [
+            ("(sqrt 16)", "4"),
+            ("(sqrt (/ 9 4))", "3/2"),
+            ("(sqrt (expt 10 40))", "100000000000000000000"),
+            ("(sqrt 2.25)", "1.5"),
+            ("(exp 0)", "1"),
+            ("(log 1)", "0"),
+            ("(sin 0)", "0"),
+            ("(cos 0)", "1"),
+            ("(tan 0)", "0"),
+            ("(asin 0)", "0"),
+            ("(acos 1)", "0"),
+            ("(atan 0)", "0"),
+            ("(log 8 2)", "3.0")
+        ];
+        for &(expression, expected) in cases.iter() {
+            assert_eq!(Ok(expected.to_string()), text(expression), "{}", expression);
+        }
+        assert_eq!(Ok(Value::Integer(4)), run("(sqrt 16)").value);
+        assert_eq!(Ok(Value::Number(2f64.sqrt())), run("(sqrt 2)").value);
+        assert_eq!(Ok(Value::Number(1f64.exp())), run("(exp 1)").value);
+        assert_eq!(Ok(Value::Number(1.0)), run("(cos 0.0)").value);
+        assert_eq!(Ok(Value::Number(std::f64::consts::FRAC_PI_4)), run("(atan 1)").value);
+        assert_eq!(Ok(Value::Number(std::f64::consts::FRAC_PI_2)), run("(atan 1 0)").value);
+        assert_eq!(Ok(Value::Number(std::f64::consts::FRAC_PI_2)), run("(asin 1)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(< (abs (- (sin pi))) 0.000001)").value);
+        assert_eq!(Err("math function requires an argument of type 'number'"), run("(sqrt \"4\")").value);
+        assert_eq!(Err("math function requires one argument only"), run("(sqrt)").value);
+        assert_eq!(Err("math function requires one argument only"), run("(sin 1 2)").value);
+        assert_eq!(Err("math function requires an argument of type 'number'"), run("(log)").value);
+        assert_eq!(Err("math function requires an argument of type 'number'"), run("(atan 1 (quote a))").value);
+    }
+
+    #[test]
+    fn expt() {
+        let text = |s: &str| run(s).value.map(|v| v.to_string());
+        let cases = [
+            ("(expt 2 10)", "1024"),
+            ("(expt 2 100)", "1267650600228229401496703205376"),
+            ("(expt -3 3)", "-27"),
+            ("(expt 2 -2)", "1/4"),
+            ("(expt (/ 2 3) 2)", "4/9"),
+            ("(expt 0 0)", "1"),
+            ("(expt -1 (factorial 30))", "1"),
+            ("(expt 4 0.5)", "2.0"),
+            ("(expt 2.5 2)", "6.25")
+        ];
+        for &(expression, expected) in cases.iter() {
+            assert_eq!(Ok(expected.to_string()), text(expression), "{}", expression);
+        }
+        assert_eq!(Ok(Value::Number(2.0)), run("(expt 4 0.5)").value);
+        assert_eq!(Err("division by zero"), run("(expt 0 -1)").value);
+        assert_eq!(Err("expt function requires two arguments"), run("(expt 2)").value);
+        assert_eq!(Err("expt exponent is too large"), run("(expt 2 (factorial 30))").value);
+        assert_eq!(Err("expt function requires arguments of type 'number'"), run("(expt 2 (quote a))").value);
+    }
+
+    #[test]
+    fn fixnum_promotion() {
+        let env_ref = default_env();
+        run_with_env("(define fact (lambda (n) (if (<= n 1) 1 (* n (fact (- n 1))))))", env_ref.clone());
+        assert_eq!(exact("265252859812191058636308480000000"), run_with_env("(fact 30)", env_ref.clone()).value);
+        assert_eq!(Ok(Value::Integer(2432902008176640000)), run_with_env("(fact 20)", env_ref.clone()).value);
+        assert_eq!(exact("9223372036854775808"), run("(+ 9223372036854775807 1)").value);
+        assert_eq!(exact("-9223372036854775809"), run("(- -9223372036854775807 2)").value);
+        assert_eq!(exact("9223372036854775808"), run("(abs (- -9223372036854775807 1))").value);
+        // results that fit again become fixnums
+        assert_eq!(Ok(Value::Integer(9223372036854775807)), run("(- (+ 9223372036854775807 1) 1)").value);
+        assert_eq!(Ok(Value::Bool(true)), run("(< 9223372036854775807 (* 9223372036854775807 2))").value);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let handled = |body: &str| run(&format!("(with-exception-handler (lambda (e) (error-object-message e)) (lambda () {}))", body)).value;
+        let message = Ok(Value::String("division by zero".into()));
+        assert_eq!(message, handled("(/ 1 0)"));
+        assert_eq!(message, handled("(+ 1 (/ (factorial 20) 0))"));
+        assert_eq!(message, handled("(modulo 5 0)"));
+        assert_eq!(message, handled("(expt 0 -2)"));
+        assert_eq!(Ok(Value::Integer(3)), handled("(+ 1 2)"));
+        // only inexact division by zero gives an infinity
+        assert_eq!(Ok(Value::Number(f64::INFINITY)), handled("(/ 1.0 0)"));
+        assert_eq!(Err("division by zero"), run("(/ 1 0)").value);
+
+        assert_eq!(Ok(Value::Bool(true)), run("(with-exception-handler (lambda (e) (error-object? e)) (lambda () (/ 1 0)))").value);
+        assert_eq!(Ok(Value::Bool(false)), run("(error-object? \"division by zero\")").value);
+        // an error in the handler is not caught again
+        assert_eq!(Err("symbol is not defined."), run("(with-exception-handler (lambda (e) (undefined e)) (lambda () (/ 1 0)))").value);
+        assert_eq!(Err("with-exception-handler function requires two procedures"), run("(with-exception-handler 1 (lambda () (+ 1 1)))").value);
+        assert_eq!(Err("error-object-message function requires an argument of type 'error-object'"), run("(error-object-message 1)").value);
+    }
+
+    #[test]
+    fn overflow_mode() {
+        assert_eq!(OverflowMode::Promote, scheme_rs::overflow_mode());
+        set_overflow_mode(OverflowMode::Error);
+        assert_eq!(Err("integer overflow"), run("(+ 9223372036854775807 1)").value);
+        assert_eq!(Err("integer overflow"), run("(- -9223372036854775807 2)").value);
+        assert_eq!(Err("integer overflow"), run("(* 4294967296 4294967296)").value);
+        assert_eq!(Err("integer overflow"), run("(abs (- -9223372036854775807 1))").value);
+        assert_eq!(Ok(Value::Integer(9223372036854775807)), run("(+ 9223372036854775806 1)").value);
+        // bignums that do not come from an overflow are still exact
+        assert_eq!(exact("3628801"), run("(+ (factorial 10) 1)").value);
+        assert_eq!(exact("2432902008176640000"), run("(* (factorial 19) 20)").value);
+        assert_eq!(Ok(Value::Number(9223372036854775808.0)), run("(+ 9223372036854775807 1.0)").value);
+        set_overflow_mode(OverflowMode::Promote);
+        assert_eq!(exact("9223372036854775808"), run("(+ 9223372036854775807 1)").value);
+    }
+}
+
+#[test]
+fn procedure_metadata_test() {
+    let env_ref = default_env();
+    run_with_env(r#"
+    (define circle-area (lambda (r) (* pi (* r r))))
+    (define area circle-area)
+    (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+    "#, env_ref.clone());
+    let run_p = |s: &str| run_with_env(s, env_ref.clone()).value.unwrap().to_string();
+
+    assert_eq!("#<procedure circle-area (r)>", run_p("circle-area"));
+    // a procedure keeps the name it was first defined with
+    assert_eq!("#<procedure circle-area (r)>", run_p("area"));
+    assert_eq!("#<procedure (x y)>", run_p("(lambda (x y) (+ x y))"));
+    assert_eq!("#<procedure car>", run_p("car"));
+    assert_eq!("'circle-area", run_p("(procedure-name area)"));
+    assert_eq!(Ok(Value::Bool(false)), run_with_env("(procedure-name (lambda () (+ 1 2)))", env_ref.clone()).value);
+    assert_eq!("'make-point", run_p("(procedure-name make-point)"));
+
+    assert_eq!("'(1 . 1)", run_p("(procedure-arity circle-area)"));
+    assert_eq!("'(2 . 2)", run_p("(procedure-arity make-point)"));
+    assert_eq!(Ok(Value::cons(Value::Integer(0), Value::Bool(false))), run_with_env("(procedure-arity +)", env_ref.clone()).value);
+    assert_eq!("'(1 . 2)", run_p("(procedure-arity display)"));
+    assert_eq!(Err("procedure-arity function requires an argument of type 'procedure'"), run_with_env("(procedure-arity 1)", env_ref.clone()).value);
+}
+
+#[test]
+fn testing_helpers_test() {
+    eval_ok!("(+ 1 2)", 3);
+    eval_ok!("(/ 1.0 4)", 0.25);
+    eval_ok!("(string-append \"a\" \"b\")", "ab");
+    eval_ok!("(quote x)", Value::Symbol("x".into()));
+    eval_err!("(car 1)");
+    eval_err!("(car 1)", "car function requires an argument of type 'list' / 'pair'");
+
+    let env = testing::fixture_env(&[("limit", Value::Integer(10))]);
+    eval_ok!(env, "(* limit 2)", 20);
+    let env = testing::fixture_env_from_source("(define double (lambda (x) (* x 2)))");
+    let scope = testing::child_env(env.clone());
+    eval_ok!(scope, "(begin (define y 4) (double y))", 8);
+    eval_err!(env, "y", "symbol is not defined.");
+    eval_err!("(car 1)", SchemeError::WrongType { .. });
+    eval_err!(env, "(car (list 1) 2)", SchemeError::Arity { .. });
+    eval_err!(env, "(vector-ref (vector 1) 1)", SchemeError::OutOfRange { message: "vector-ref index out of range", .. });
+}
+
+#[cfg(feature = "serde")]
+mod serde_values {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rectangle { width: u32, height: u32 },
+        Segment(i64, i64)
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        layer: Option<u8>,
+        tags: BTreeMap<String, bool>
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut tags = BTreeMap::new();
+        tags.insert("draft".to_string(), true);
+        let drawing = Drawing {
+            name: "plan".to_string(),
+            shapes: vec![Shape::Point, Shape::Circle(1.5), Shape::Rectangle { width: 2, height: 3 }, Shape::Segment(-1, 1)],
+            layer: None,
+            tags
+        };
+        let value = to_value(&drawing).unwrap();
+        assert_eq!(Ok(drawing), from_value(&value));
+
+        // scripts see association lists, lists and symbols
+        let env = testing::fixture_env(&[("drawing", value)]);
+        eval_ok!(env, "(cdr (assq 'name drawing))", "plan");
+        eval_ok!(env, "(length (cdr (assq 'shapes drawing)))", 4);
+        eval_ok!(env, "(car (cdr (assq 'shapes drawing)))", Value::symbol("Point"));
+        eval_ok!(env, "(cdr (assq 'height (cdr (car (car (cdr (cdr (cdr (assq 'shapes drawing)))))))))", 3);
+        eval_ok!(env, "(null? (cdr (assq 'layer drawing)))", true);
+    }
+
+    #[test]
+    fn values_from_scripts() {
+        let interpreter = Interpreter::new().unwrap();
+        let value = interpreter.eval_str("(list (cons 'name \"sketch\") (cons 'shapes (vector 'Point (list (cons 'Circle 2)))) (cons 'layer 4) (cons 'tags '()))").unwrap();
+        assert_eq!(Ok(Drawing { name: "sketch".to_string(), shapes: vec![Shape::Point, Shape::Circle(2.0)], layer: Some(4), tags: BTreeMap::new() }), from_value(&value));
+
+        assert_eq!(Ok(vec![1, 2, 3]), from_value::<Vec<i64>>(&interpreter.eval_str("(list 1 2 3)").unwrap()));
+        assert_eq!(Ok((String::from("a"), 'b')), from_value(&interpreter.eval_str("(list 'a #\\b)").unwrap()));
+        assert_eq!(Ok(0.5), from_value::<f64>(&interpreter.eval_str("(/ 1 2)").unwrap()));
+        assert_eq!(Ok(u128::MAX), from_value(&to_value(&u128::MAX).unwrap()));
+        assert!(from_value::<(i64, i64)>(&interpreter.eval_str("(list 1 2 3)").unwrap()).is_err());
+
+        let error = from_value::<u8>(&Value::Integer(300)).unwrap_err();
+        assert_eq!("value cannot be converted", error.message());
+        assert_eq!("value cannot be converted: invalid value: integer `300`, expected u8", error.to_string());
+        let error = to_value(&interpreter.eval_str("car").unwrap()).unwrap_err();
+        assert_eq!("value cannot be converted: a procedure has no serde counterpart", error.to_string());
+        assert!(to_value(&interpreter.eval_str("(cons 1 2)").unwrap()).is_err());
+    }
+}
+
+#[test]
+fn interpreter_test() {
+    let interpreter = Interpreter::new().unwrap();
+    assert_eq!(Ok(Value::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+    assert_eq!(Ok(Value::Integer(12)), interpreter.eval_str("(define (twice x) (* x 2)) (twice 6)"));
+    // definitions stay for later evaluations, and are in the global environment
+    assert_eq!(Ok(Value::Integer(20)), interpreter.eval_str("(twice 10)"));
+    assert!(interpreter.global_env().borrow().is_bound(SymbolId::intern("twice")));
+    assert_eq!("symbol is not defined.", interpreter.eval_str("(missing)").unwrap_err().message());
+    assert_eq!("syntax error", interpreter.eval_str("(+ 1").unwrap_err().message());
+    // nothing after a parenthesis closed once too often is dropped unread
+    assert_eq!("unexpected )", interpreter.eval_str("(+ 1 2))").unwrap_err().message());
+    assert!(matches!(interpreter.eval_str("(define a 1)) (define b 2)"), Err(SchemeError::ReadError { .. })));
+    assert!(interpreter.eval_str("b").is_err());
+
+    // each interpreter has its own environment
+    assert!(Interpreter::new().unwrap().eval_str("twice").is_err());
+    interpreter.global_env().borrow().local.borrow_mut().insert(SymbolId::intern("limit"), Value::Integer(5));
+    assert_eq!(Ok(Value::Integer(10)), interpreter.eval_str("(twice limit)"));
+}
+
+#[test]
+fn native_functions_test() {
+    let interpreter = Interpreter::new().unwrap();
+    interpreter.define_fn("sum-all", |args: &[Value]| {
+        args.iter().try_fold(0, |sum, arg| match arg {
+            Value::Integer(n) => Ok(sum + n),
+            _ => Err(SchemeError::wrong_type("sum-all function requires integers"))
+        }).map(Value::Integer)
+    });
+    assert_eq!(Ok(Value::Integer(6)), interpreter.eval_str("(sum-all 1 2 3)"));
+    assert_eq!(Ok(Value::Integer(0)), interpreter.eval_str("(sum-all)"));
+    assert_eq!("sum-all function requires integers", interpreter.eval_str("(sum-all 1 'a)").unwrap_err().message());
+    assert_eq!(Ok(Value::list(vec![Value::Integer(3), Value::Integer(7)])), interpreter.eval_str("(map sum-all (list 1 3) (list 2 4))"));
+    assert_eq!("#<procedure sum-all>", interpreter.eval_str("sum-all").unwrap().to_string());
+
+    // functions keep state of the host, and replace builtins of the same name
+    let log = Rc::new(RefCell::new(vec![]));
+    let kept = log.clone();
+    interpreter.define_fn("display", move |args: &[Value]| {
+        kept.borrow_mut().extend(args.iter().map(Value::to_string));
+        Ok(Value::Unspecified)
+    });
+    assert!(interpreter.eval_str("(display \"a\") (display 1 2)").is_ok());
+    assert_eq!(vec!["\"a\"", "1", "2"], *log.borrow());
+}
+
+#[test]
+fn foreign_values_test() {
+    struct Entity { name: String, health: Cell<i64> }
+
+    let interpreter = Interpreter::new().unwrap();
+    interpreter.define_fn("make-entity", |args: &[Value]| {
+        let name = String::from_scheme(&args[0])?;
+        Ok(Value::foreign(Entity { name, health: Cell::new(10) }))
+    });
+    interpreter.define_fn("damage!", |args: &[Value]| {
+        let entity = Rc::<Entity>::from_scheme(&args[0])?;
+        entity.health.set(entity.health.get() - i64::from_scheme(&args[1])?);
+        Ok(Value::Integer(entity.health.get()))
+    });
+    interpreter.define_fn("entity-name", |args: &[Value]| match args[0].as_foreign::<Entity>() {
+        Some(entity) => Ok(Value::string(&entity.name)),
+        None => Err(SchemeError::wrong_type("entity-name function requires an entity"))
+    });
+
+    interpreter.eval_str("(define orc (make-entity \"orc\")) (define party (list orc (make-entity \"elf\")))").unwrap();
+    assert_eq!(Ok(Value::Integer(7)), interpreter.eval_str("(damage! (car party) 3)"));
+    assert_eq!(Ok(Value::Integer(5)), interpreter.eval_str("(damage! orc 2)"));
+    assert_eq!(Ok(Value::list(vec![Value::string("orc"), Value::string("elf")])), interpreter.eval_str("(map entity-name party)"));
+    assert_eq!(Ok(Value::Bool(true)), interpreter.eval_str("(eq? orc (car party))"));
+    assert_eq!(Ok(Value::Bool(false)), interpreter.eval_str("(equal? orc (car (cdr party)))"));
+    assert_eq!(Ok(Value::Integer(5)), interpreter.eval_str("(define table (make-hash-table)) (hash-table-set! table orc 1) (hash-table-ref table (car party)) (damage! orc 0)"));
+    assert_eq!("#<foreign>", interpreter.eval_str("orc").unwrap().to_string());
+
+    // back in Rust, with the changes the script made
+    let orc = interpreter.eval_str("orc").unwrap();
+    assert_eq!(5, orc.as_foreign::<Entity>().unwrap().health.get());
+    assert!(orc.as_foreign::<String>().is_none());
+    assert_eq!("expected foreign data of another type", Rc::<String>::from_scheme(&orc).err().unwrap().message());
+    assert_eq!("expected foreign data", interpreter.eval_str("(damage! 1 1)").unwrap_err().message());
+    assert_eq!("entity-name function requires an entity", interpreter.eval_str("(entity-name (list 1))").unwrap_err().message());
+    match orc {
+        Value::Foreign(f) => assert!(f.is::<Entity>() && f.downcast::<Entity>().is_some()),
+        other => panic!("expected foreign data, got {:?}", other)
+    }
+}
+
+#[test]
+fn calling_procedures_test() {
+    let interpreter = Interpreter::new().unwrap();
+    interpreter.eval_str("(define (greet name) (string-append \"hello \" name))").unwrap();
+    let greet = interpreter.eval_str("greet").unwrap();
+    assert_eq!(Ok(Value::string("hello you")), interpreter.call(&greet, &[Value::string("you")]));
+    let plus = interpreter.eval_str("+").unwrap();
+    assert_eq!(Ok(Value::Integer(6)), interpreter.call(&plus, &[Value::Integer(1), Value::Integer(2), Value::Integer(3)]));
+    assert_eq!("wrong type to apply", interpreter.call(&Value::Integer(1), &[]).unwrap_err().message());
+    assert!(interpreter.call(&greet, &[Value::Integer(1)]).is_err());
+
+    // from a native function, with a clone of the interpreter
+    let host = interpreter.clone();
+    interpreter.define_fn("twice", move |args: &[Value]| {
+        let once = host.call(&args[0], &args[1..])?;
+        host.call(&args[0], &[once])
+    });
+    assert_eq!(Ok(Value::Integer(12)), interpreter.eval_str("(twice (lambda (x) (* x 2)) 3)"));
+    assert_eq!(Ok(Value::string("hello hello x")), interpreter.eval_str("(twice greet \"x\")"));
+}
+
+#[test]
+fn sandbox_test() {
+    let unbound = |interpreter: &Interpreter, source: &str| matches!(interpreter.eval_str(source), Err(SchemeError::UnboundVariable { .. }));
+    let full = InterpreterBuilder::new().build().unwrap();
+    assert!(!unbound(&full, "(newline)") && !unbound(&full, "(gc)"));
+
+    let no_io = InterpreterBuilder::new().sandbox(Sandbox::NoIo).build().unwrap();
+    for source in &["(display 1)", "(newline)", "(print 1)", "(read)", "(current-output-port)", "(current-error-port)"] {
+        assert!(unbound(&no_io, source), "{}", source);
+    }
+    assert_eq!(Ok(Value::Integer(0)), no_io.eval_str("(gc)"));
+
+    let pure = InterpreterBuilder::new().sandbox(Sandbox::Pure).build().unwrap();
+    for source in &["(display 1)", "(gc)", "(watch 'x)", "(environment-bindings)", "(heap-statistics)"] {
+        assert!(unbound(&pure, source), "{}", source);
+    }
+    assert_eq!(Ok(Value::string("3 apples")), pure.eval_str("(define n 3) (format \"~a apples\" n)"));
+    assert_eq!(Ok(Value::Integer(6)), pure.eval_str("(let loop ((i 0) (acc 0)) (if (> i 3) acc (loop (+ i 1) (+ acc i))))"));
+    // the host can still hand a script what it chooses to
+    pure.define_fn("log", |args: &[Value]| Ok(args[0].clone()));
+    assert_eq!(Ok(Value::Integer(1)), pure.eval_str("(log 1)"));
+
+    // no builtin opens files or connections yet
+    for sandbox in &[Sandbox::NoFilesystem, Sandbox::NoNetwork] {
+        let interpreter = InterpreterBuilder::new().sandbox(*sandbox).build().unwrap();
+        assert!(!unbound(&interpreter, "(newline)"));
+    }
+    assert_eq!(Some(BuiltinGroup::Io), BuiltinGroup::of("display"));
+    assert_eq!(Some(BuiltinGroup::Core), BuiltinGroup::of("car"));
+    // a builtin of no group would only be left to the full sandbox
+    assert_eq!(None, BuiltinGroup::of("no-such-builtin"));
+    for name in setup().keys() {
+        assert!(BuiltinGroup::of(&name.name()).is_some(), "{}", name);
+    }
+    assert!(!Sandbox::NoIo.allows(BuiltinGroup::Filesystem) && Sandbox::NoNetwork.allows(BuiltinGroup::Filesystem));
+    // the builtins of other interpreters are not changed
+    assert!(!unbound(&Interpreter::new().unwrap(), "(newline)"));
+
+    // the prelude uses the builtins left, and its procedures are left out by name
+    let no_car = InterpreterBuilder::new().exclude("car").build().unwrap();
+    assert_eq!("symbol is not defined: car", no_car.eval_str("(cadr (list 1 2))").unwrap_err().to_string());
+    let no_cadr = InterpreterBuilder::new().exclude("cadr").build().unwrap();
+    assert!(unbound(&no_cadr, "(cadr (list 1 2))"));
+    assert_eq!(Ok(Value::Integer(3)), no_cadr.eval_str("(caddr (list 1 2 3))"));
+}
+
+#[test]
+fn interpreter_builder_test() {
+    let text = |output: &Rc<RefCell<Vec<u8>>>| String::from_utf8(output.borrow().clone()).unwrap();
+    let (stdout, stderr) = (Rc::new(RefCell::new(vec![])), Rc::new(RefCell::new(vec![])));
+    let interpreter = InterpreterBuilder::new()
+        .exclude("exit")
+        .exclude("gc")
+        .stdin(std::io::Cursor::new("(1 2) three"))
+        .stdout(Output(stdout.clone()))
+        .stderr(Output(stderr.clone()))
+        .build().unwrap();
+    assert!(matches!(interpreter.eval_str("(gc)"), Err(SchemeError::UnboundVariable { .. })));
+    assert_eq!(Ok(Value::list(vec![Value::Integer(1), Value::Integer(2)])), interpreter.eval_str("(read)"));
+    assert_eq!(Ok(Value::Symbol("three".into())), interpreter.eval_str("(display \"out\") (display \"err\" (current-error-port)) (read)"));
+    assert_eq!(("out".to_string(), "err".to_string()), (text(&stdout), text(&stderr)));
+
+    // the settings of the interpreter are its own, and the thread keeps those it has
+    let shallow = InterpreterBuilder::new().depth_limit(50).build().unwrap();
+    set_fuel(Some(1));
+    let limited = InterpreterBuilder::new().overflow_mode(OverflowMode::Error).fuel(1_000).build().unwrap();
+    let big = "(* 4611686018427387904 4)";
+    assert_eq!(Err("integer overflow"), limited.eval_str(big).map_err(|e| e.message()));
+    assert_eq!(OverflowMode::Promote, overflow_mode());
+    assert_eq!(Some(1), fuel());
+    assert_eq!(None, depth_limit());
+    assert_eq!(Err("evaluation ran out of fuel"), limited.eval_str("(define (forever) (forever)) (forever)").map_err(|e| e.message()));
+    assert_eq!(Err("evaluation ran out of fuel"), Interpreter::new().unwrap().eval_str(big).map_err(|e| e.message()));
+    set_fuel(None);
+    assert!(Interpreter::new().unwrap().eval_str(big).is_ok());
+    assert_eq!(Err("evaluation nested too deeply"), shallow.eval_str("(define (down n) (if (= n 0) 0 (+ 1 (down (- n 1))))) (down 100)").map_err(|e| e.message()));
+    assert_eq!(Ok(Value::Integer(5)), shallow.eval_str("(down 5)"));
+
+    let frugal = InterpreterBuilder::new().allocation_limit(1 << 20).build().unwrap();
+    assert_eq!(Err("evaluation allocated more than its limit"), frugal.eval_str("(make-vector 1000000)").map_err(|e| e.message()));
+    let hasty = InterpreterBuilder::new().timeout(std::time::Duration::from_millis(100)).build().unwrap();
+    assert_eq!(Err("evaluation timed out"), hasty.eval_str("(define (forever) (forever)) (forever)").map_err(|e| e.message()));
+    assert_eq!((None, None), (allocation_limit(), timeout()));
+
+    set_depth_limit(Some(20));
+    assert_eq!(Err("evaluation nested too deeply"), Interpreter::new().unwrap().eval_str("(define (down n) (if (= n 0) 0 (+ 1 (down (- n 1))))) (down 30)").map_err(|e| e.message()));
+    set_depth_limit(None);
+
+    // strict interpreters refuse programs using unbound variables before running them
+    let lenient = InterpreterBuilder::new().strictness(Strictness::Lenient).build().unwrap();
+    let strict = InterpreterBuilder::new().strictness(Strictness::Strict).stdout(std::io::sink()).build().unwrap();
+    let program = "(define shown (vector 0)) (display 1) (if #t 1 typo)";
+    assert_eq!(Ok(Value::Integer(1)), lenient.eval_str(program));
+    let error = strict.eval_str(program).unwrap_err();
+    assert_eq!("symbol is not defined: typo", error.to_string());
+    assert_eq!(Some(Span { start: 38, end: 52 }), error.span());
+    assert!(matches!(strict.eval_str("shown"), Err(SchemeError::UnboundVariable { .. })));
+    // names the interpreter or the program binds are fine, macros included
+    strict.eval_str("(define shown 0) (define-syntax twice (syntax-rules () ((_ e) (begin e e))))").unwrap();
+    assert_eq!(Ok(Value::Integer(2)), strict.eval_str("(define (f x) (twice (+ shown x))) (f 2)"));
+
+    let limits = ReaderLimits { max_depth: 4, max_datums: 100, max_token_length: 6 };
+    let compiled = InterpreterBuilder::new().engine(Engine::Bytecode).opt_level(OptLevel::Simple).reader_limits(limits).build().unwrap();
+    assert_eq!((Engine::Bytecode, OptLevel::Simple, limits), compiled.enter(|| (engine(), opt_level(), reader_limits())));
+    assert_eq!(Err("token too long"), compiled.eval_str("\"abcdefgh\"").map_err(|e| e.message()));
+    assert_eq!(Ok(Value::Integer(6)), compiled.eval_str("(define (f x) (* x 2)) (f 3)"));
+    assert_eq!((Engine::TreeWalker, OptLevel::None, ReaderLimits::default()), (engine(), opt_level(), reader_limits()));
+}
+
+#[test]
+fn interpreter_thread_test() {
+    let interpreter = InterpreterThread::spawn_with(|| {
+        let interpreter = Interpreter::new()?;
+        interpreter.define_fn("host-thread", |_: &[Value]| Ok(Value::string(std::thread::current().name().unwrap_or("unnamed"))));
+        Ok(interpreter)
+    }).unwrap();
+    interpreter.eval::<()>("(define counter (vector 0)) (define (bump n) (vector-set! counter 0 (+ (vector-ref counter 0) n)))").unwrap();
+    let workers = (1..=4).map(|n| {
+        let interpreter = interpreter.clone();
+        std::thread::spawn(move || interpreter.eval::<()>(&format!("(bump {})", n)).unwrap())
+    }).collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert_eq!(Ok(10), interpreter.eval::<i64>("(vector-ref counter 0)"));
+    // natives run on the thread of the interpreter, not of the caller
+    assert_eq!(Ok("unnamed".to_string()), interpreter.eval::<String>("(host-thread)"));
+
+    assert_eq!("symbol is not defined: nope", interpreter.eval::<()>("(bump nope)").unwrap_err().to_string());
+    assert_eq!("expected a string", interpreter.eval::<String>("(vector-ref counter 0)").unwrap_err().message());
+    assert_eq!(vec![2, 4], interpreter.run(|interpreter| {
+        let double = interpreter.eval_str("(lambda (x) (* 2 x))").unwrap();
+        [1, 2].iter().map(|&n| i64::from_scheme(&interpreter.call(&double, &[Value::Integer(n)]).unwrap()).unwrap()).collect::<Vec<i64>>()
+    }));
+
+    // a panic is raised in the caller, and the interpreter goes on
+    let caller = interpreter.clone();
+    assert!(std::thread::spawn(move || caller.run(|_| panic!("in the job"))).join().is_err());
+    assert_eq!(Ok(10), interpreter.eval::<i64>("(vector-ref counter 0)"));
+}
+
+#[test]
+fn isolated_instances_test() {
+    // 16 threads of 20 tenants each, every thread taking turns between its tenants
+    let threads = (0..16).map(|thread| std::thread::spawn(move || {
+        let thread_output = Rc::new(RefCell::new(vec![]));
+        set_current_output_port(Some(Rc::new(Port::output(Box::new(Output(thread_output.clone()))))));
+        let tenants = (0..20).map(|n| {
+            let interpreter = Interpreter::new().unwrap();
+            let output = Rc::new(RefCell::new(vec![]));
+            interpreter.set_output_port(Some(Rc::new(Port::output(Box::new(Output(output.clone()))))));
+            let tenant = thread * 100 + n;
+            interpreter.eval_str(&format!("(define tenant {}) (define total (vector 0))", tenant)).unwrap();
+            (interpreter, output, tenant)
+        }).collect::<Vec<_>>();
+        for round in 1..=5 {
+            for (interpreter, _, _) in &tenants {
+                interpreter.eval_str(&format!("(vector-set! total 0 (+ (vector-ref total 0) tenant {})) (display tenant)", round)).unwrap();
+            }
+        }
+        for &(ref interpreter, ref output, tenant) in &tenants {
+            assert_eq!(Ok(Value::Integer(5 * tenant + 15)), interpreter.eval_str("(vector-ref total 0)"));
+            assert_eq!(tenant.to_string().repeat(5), String::from_utf8(output.borrow().clone()).unwrap());
+        }
+        // the thread writes to its own port, which a new interpreter starts with
+        Interpreter::new().unwrap().eval_str("(display 'thread)").unwrap();
+        assert_eq!("thread", String::from_utf8(thread_output.borrow().clone()).unwrap());
+        set_current_output_port(None);
+        tenants.len()
+    })).collect::<Vec<_>>();
+    assert_eq!(320, threads.into_iter().map(|thread| thread.join().unwrap()).sum::<usize>());
+
+    // definitions, settings and failures of one interpreter are not seen by another
+    // on the same thread
+    let (first, second) = (Interpreter::new().unwrap(), Interpreter::new().unwrap());
+    first.eval_str("(define secret 42)").unwrap();
+    assert_eq!("symbol is not defined: secret", second.eval_str("secret").unwrap_err().to_string());
+    second.eval_str("(+ 1 2)").unwrap();
+    first.enter(|| set_fuel(Some(100)));
+    assert!(first.eval_str("(define (forever) (forever)) (forever)").is_err());
+    assert!(first.enter(error_dump).is_some());
+    assert_eq!(None, second.enter(fuel));
+    assert!(second.enter(error_dump).is_none());
+    assert_eq!(None, error_dump());
+
+    // nor are the symbols one interns, while the symbols both use are the same
+    let (before, thread) = (second.enter(interner_statistics), interner_statistics());
+    let names = (0..1000).map(|i| format!("first-{}", i)).collect::<Vec<_>>().join(" ");
+    first.eval_str(&format!("(define names '({}))", names)).unwrap();
+    assert!(first.enter(interner_statistics).symbols >= before.symbols + 1000);
+    assert_eq!((before, thread), (second.enter(interner_statistics), interner_statistics()));
+    assert_eq!(first.eval_str("'shared"), second.eval_str("'shared"));
+    assert_eq!(Ok(Value::Symbol("shared".into())), second.eval_str("'shared"));
+    drop(first);
+    assert_eq!(Ok(Value::Symbol("first-1".into())), second.eval_str("'first-1"));
+
+    // a symbol kept past its interpreter stays the same symbol, however many are
+    // interned after it
+    let kept = Interpreter::new().unwrap().eval_str("'kept-past-its-interpreter").unwrap();
+    for i in 0..5000 {
+        SymbolId::intern(&format!("fresh-{}", i));
+    }
+    assert_eq!("'kept-past-its-interpreter", kept.to_string());
+    assert_eq!(Value::Symbol("kept-past-its-interpreter".into()), kept);
+    assert_ne!(Value::Symbol("fresh-14".into()), kept);
+
+    // dropping the last handle of an interpreter frees its environment, with the
+    // procedures of the program and of the prelude on cycles through it, unless the
+    // host still holds it
+    let interpreter = Interpreter::new().unwrap();
+    let data = match interpreter.eval_str("(define data (vector 1 2)) (define (get) data) data") {
+        Ok(Value::Vector(data)) => Rc::downgrade(&data),
+        other => panic!("{:?}", other)
+    };
+    let env = Rc::downgrade(&interpreter.global_env());
+    let clone = interpreter.clone();
+    drop(interpreter);
+    assert!(env.upgrade().is_some());
+    drop(clone);
+    assert!(env.upgrade().is_none());
+    assert!(data.upgrade().is_none());
+    let interpreter = Interpreter::new().unwrap();
+    let held = interpreter.global_env();
+    drop(interpreter);
+    assert_eq!(Ok(Value::Integer(3)), run_with_env("(length (iota 3))", held).value);
+}
+
+// What a port writes, kept where the test can read it.
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl std::io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct TestResult {
+    // errors are compared by their message
+    value: Result<Value, &'static str>,
+    #[allow(dead_code)]
+    env: Rc<RefCell<Env>>
+}
+
+fn builtins() -> HashMap<SymbolId, Value> {
+    let mut builtins = setup();
+    define_prelude(&mut builtins).unwrap();
+    builtins
+}
+
+fn default_env() -> Rc<RefCell<Env>> {
+    let env = Env::new(builtins(), None);
+
+    Rc::new(RefCell::new(env))
+}
+
+fn run(s: &str) -> TestResult {
+    let _ = env_logger::init();
+    run_with_env(s, default_env().clone())
+}
+
+fn run_with_env(s: &str, env_ref: Rc<RefCell<Env>>) -> TestResult {
+    let _ = env_logger::init();
+    let result = parse(s)
+        .and_then(|ast| eval(Some(ast.result), env_ref.clone()));
+
+    TestResult {
+        value: result.map_err(|e| e.message()),
+        env: env_ref.clone()
+    }
+}
+
+fn evaluate(s: &str) -> Result<Value, SchemeError> {
+    parse(s).and_then(|ast| eval(Some(ast.result), default_env()))
 }